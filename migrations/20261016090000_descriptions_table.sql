@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS descriptions(
+env VARCHAR(50) NOT NULL,
+key TEXT NOT NULL,
+description TEXT NOT NULL,
+PRIMARY KEY(env,key)
+);
@@ -0,0 +1,5 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS locked_envs(
+env VARCHAR(50) NOT NULL PRIMARY KEY,
+created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
@@ -0,0 +1,5 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS settings(
+name VARCHAR(50) NOT NULL PRIMARY KEY,
+value TEXT NOT NULL
+);
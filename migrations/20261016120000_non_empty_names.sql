@@ -0,0 +1,60 @@
+-- Add migration script here
+-- sqlite cannot add a constraint to a table, the tables are rebuilt. An empty
+-- env is renamed to _empty and an empty key to _EMPTY, so that the rows that
+-- had one can still be found and renamed or dropped. When that name is taken
+-- underscores are appended until it is longer than every existing name, so
+-- that the histories of different variables are never merged
+CREATE TEMP TABLE placeholders AS
+WITH
+names(env, key) AS (
+    SELECT env, key FROM environments
+    UNION ALL
+    SELECT env, key FROM descriptions
+),
+longest(env, key) AS (
+    SELECT COALESCE(MAX(length(env)), 0), COALESCE(MAX(length(key)), 0) FROM names
+)
+SELECT
+    CASE WHEN EXISTS (SELECT 1 FROM names WHERE env = '_empty')
+        THEN '_empty' || replace(hex(zeroblob(longest.env)), '00', '_')
+        ELSE '_empty'
+    END AS env,
+    CASE WHEN EXISTS (SELECT 1 FROM names WHERE key = '_EMPTY')
+        THEN '_EMPTY' || replace(hex(zeroblob(longest.key)), '00', '_')
+        ELSE '_EMPTY'
+    END AS key
+FROM longest;
+
+CREATE TABLE environments_new(
+env VARCHAR(50) NOT NULL CONSTRAINT env_not_empty CHECK(length(env) > 0),
+key TEXT NOT NULL CONSTRAINT key_not_empty CHECK(length(key) > 0),
+value TEXT,
+created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+PRIMARY KEY(env,key,created_at)
+);
+INSERT INTO environments_new (env, key, value, created_at)
+SELECT
+    CASE WHEN length(environments.env) > 0 THEN environments.env ELSE placeholders.env END,
+    CASE WHEN length(environments.key) > 0 THEN environments.key ELSE placeholders.key END,
+    value,
+    created_at
+FROM environments, placeholders;
+DROP TABLE environments;
+ALTER TABLE environments_new RENAME TO environments;
+
+CREATE TABLE descriptions_new(
+env VARCHAR(50) NOT NULL CONSTRAINT env_not_empty CHECK(length(env) > 0),
+key TEXT NOT NULL CONSTRAINT key_not_empty CHECK(length(key) > 0),
+description TEXT NOT NULL,
+PRIMARY KEY(env,key)
+);
+INSERT INTO descriptions_new (env, key, description)
+SELECT
+    CASE WHEN length(descriptions.env) > 0 THEN descriptions.env ELSE placeholders.env END,
+    CASE WHEN length(descriptions.key) > 0 THEN descriptions.key ELSE placeholders.key END,
+    description
+FROM descriptions, placeholders;
+DROP TABLE descriptions;
+ALTER TABLE descriptions_new RENAME TO descriptions;
+
+DROP TABLE placeholders;
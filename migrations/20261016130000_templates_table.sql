@@ -0,0 +1,6 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS templates(
+name VARCHAR(50) NOT NULL CONSTRAINT name_not_empty CHECK(length(name) > 0),
+key TEXT NOT NULL CONSTRAINT key_not_empty CHECK(length(key) > 0),
+PRIMARY KEY(name,key)
+);
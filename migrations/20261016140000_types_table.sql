@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TABLE IF NOT EXISTS types(
+env VARCHAR(50) NOT NULL CONSTRAINT env_not_empty CHECK(length(env) > 0),
+key TEXT NOT NULL CONSTRAINT key_not_empty CHECK(length(key) > 0),
+type TEXT NOT NULL,
+PRIMARY KEY(env,key)
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+ALTER TABLE environments ADD COLUMN expires_at INTEGER;
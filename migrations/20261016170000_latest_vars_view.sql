@@ -0,0 +1,8 @@
+-- Latest version of each variable, deleted and expired ones included
+CREATE VIEW latest_vars AS
+SELECT env, key, value, created_at, expires_at
+FROM (
+    SELECT *, ROW_NUMBER() OVER (PARTITION BY env, key ORDER BY created_at DESC) AS version
+    FROM environments
+)
+WHERE version = 1;
@@ -0,0 +1,8 @@
+-- the environments table of the sqlite migrations, created at once
+CREATE TABLE IF NOT EXISTS environments(
+env VARCHAR(50) NOT NULL CONSTRAINT env_not_empty CHECK(length(env) > 0),
+key TEXT NOT NULL CONSTRAINT key_not_empty CHECK(length(key) > 0),
+value TEXT,
+created_at BIGINT NOT NULL DEFAULT (EXTRACT(EPOCH FROM now())::BIGINT),
+PRIMARY KEY(env,key,created_at)
+);
@@ -0,0 +1,180 @@
+//! A synchronous [`EnvelopeDb`] for the programs that do not run an async
+//! runtime, such as build scripts. It mirrors the common operations of
+//! [`crate::EnvelopeDb`] and runs them on a runtime of its own.
+//!
+//! ```
+//! use envelope::blocking::EnvelopeDb;
+//! use envelope::SortOrder;
+//!
+//! # fn main() -> envelope::EnvelopeResult<()> {
+//! # let path = std::env::temp_dir().join(format!("envelope-blocking-{}.db", std::process::id()));
+//! let db = EnvelopeDb::open(&path)?;
+//! db.insert("dev", "port", "8080")?;
+//!
+//! let vars = db.get_vars("dev", &["PORT".into(), "HOST".into()])?;
+//! assert_eq!(Some("8080"), vars["PORT"].as_deref());
+//! assert_eq!(None, vars["HOST"]);
+//! # drop(db);
+//! # std::fs::remove_file(&path)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::Path;
+
+use tokio::runtime::{Builder, Handle, Runtime};
+
+use crate::db::{
+    self, ConnectOptions, EnvDiff, Environment, EnvelopeResult, EnvironmentRow, InsertOutcome,
+};
+use crate::error::EnvelopeError;
+use crate::format::ImportMode;
+use crate::SortOrder;
+
+/// Blocking version of [`crate::EnvelopeDb`], each method waits for the
+/// operation of the same name to complete. Every method fails with
+/// [`EnvelopeError::InsideRuntime`] when called from within an async
+/// runtime, the async API must be used there.
+#[derive(Debug)]
+pub struct EnvelopeDb {
+    db: db::EnvelopeDb,
+    runtime: Runtime,
+}
+
+impl EnvelopeDb {
+    /// opens the database at `path`, see [`crate::EnvelopeDb::open`]
+    pub fn open(path: &Path) -> EnvelopeResult<Self> {
+        EnvelopeDb::open_with(path, &ConnectOptions::default())
+    }
+
+    /// opens the existing database at `path` without running the migrations,
+    /// see [`crate::EnvelopeDb::open_read_only`]
+    pub fn open_read_only(path: &Path) -> EnvelopeResult<Self> {
+        let runtime = runtime()?;
+        let db = runtime.block_on(db::EnvelopeDb::open_read_only(path))?;
+
+        Ok(EnvelopeDb { db, runtime })
+    }
+
+    /// opens the database at `path` as described by `options`
+    pub fn open_with(path: &Path, options: &ConnectOptions) -> EnvelopeResult<Self> {
+        let runtime = runtime()?;
+        let db = runtime.block_on(db::EnvelopeDb::open_with(path, options))?;
+
+        Ok(EnvelopeDb { db, runtime })
+    }
+
+    fn block_on<T>(&self, f: impl Future<Output = EnvelopeResult<T>>) -> EnvelopeResult<T> {
+        ensure_outside_runtime()?;
+        self.runtime.block_on(f)
+    }
+
+    /// see [`crate::EnvelopeDb::insert`]
+    pub fn insert(&self, env: &str, key: &str, value: &str) -> EnvelopeResult<InsertOutcome> {
+        self.block_on(self.db.insert(env, key, value))
+    }
+
+    /// see [`crate::EnvelopeDb::get_vars`]
+    pub fn get_vars(
+        &self,
+        env: &str,
+        keys: &[String],
+    ) -> EnvelopeResult<BTreeMap<String, Option<String>>> {
+        self.block_on(self.db.get_vars(env, keys))
+    }
+
+    pub fn list_var_in_env(
+        &self,
+        env: &str,
+        order: SortOrder,
+    ) -> EnvelopeResult<Vec<EnvironmentRow>> {
+        self.block_on(self.db.list_var_in_env(env, order))
+    }
+
+    pub fn list_environments(&self) -> EnvelopeResult<Vec<Environment>> {
+        self.block_on(self.db.list_environments())
+    }
+
+    pub fn delete_var_for_env(&self, env: &str, key: &str) -> EnvelopeResult<()> {
+        self.block_on(self.db.delete_var_for_env(env, key))
+    }
+
+    pub fn delete_env(&self, env: &str) -> EnvelopeResult<()> {
+        self.block_on(self.db.delete_env(env))
+    }
+
+    /// see [`crate::EnvelopeDb::apply_diff`]
+    pub fn apply_diff(&self, env: &str, diff: &EnvDiff) -> EnvelopeResult<()> {
+        self.block_on(self.db.apply_diff(env, diff))
+    }
+
+    /// see [`crate::EnvelopeDb::apply_dotenv_file`]
+    pub fn apply_dotenv_file(
+        &self,
+        env: &str,
+        path: &Path,
+        mode: ImportMode,
+    ) -> EnvelopeResult<EnvDiff> {
+        self.block_on(self.db.apply_dotenv_file(env, path, mode))
+    }
+
+    /// see [`crate::EnvelopeDb::fingerprint`]
+    pub fn fingerprint(&self, env: &str) -> EnvelopeResult<String> {
+        self.block_on(self.db.fingerprint(env))
+    }
+}
+
+/// Fails if the current thread runs an async runtime, blocking it would
+/// either panic or stall the tasks it runs
+fn ensure_outside_runtime() -> EnvelopeResult<()> {
+    match Handle::try_current() {
+        Ok(_) => Err(EnvelopeError::InsideRuntime),
+        Err(_) => Ok(()),
+    }
+}
+
+fn runtime() -> EnvelopeResult<Runtime> {
+    ensure_outside_runtime()?;
+    Ok(Builder::new_current_thread().enable_all().build()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_blocking() {
+        let path = std::env::temp_dir().join(format!("envelope-sync-{}.db", std::process::id()));
+        let db = EnvelopeDb::open(&path).unwrap();
+        db.insert("dev", "host", "localhost").unwrap();
+        db.insert("dev", "port", "80").unwrap();
+
+        let vars = db.list_var_in_env("dev", SortOrder::Asc).unwrap();
+        assert_eq!(
+            vec![("HOST", "localhost"), ("PORT", "80")],
+            vars.iter()
+                .map(|row| (row.key.as_str(), row.value.as_str()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, db.list_environments().unwrap().len());
+        drop(db);
+
+        let db = EnvelopeDb::open_read_only(&path).unwrap();
+        assert!(matches!(
+            db.insert("dev", "A", "1").unwrap_err(),
+            EnvelopeError::ReadOnly
+        ));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_inside_runtime() {
+        let path = std::env::temp_dir().join(format!("envelope-rt-{}.db", std::process::id()));
+        let err = EnvelopeDb::open(&path).unwrap_err();
+        assert!(matches!(err, EnvelopeError::InsideRuntime));
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,81 @@
+use clap::Parser;
+use crate::command::{CompleteCmd, EnvelopeCmd, GlobalArgs};
+use crate::error::EnvelopeError;
+use crate::{logging, ops, style};
+use std::error::Error;
+use std::io::{self, Write};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+static HELP_TEMPLATE: &str = "\
+{about}
+
+{usage-heading} {usage}
+
+{all-args}{after-help}";
+
+/// A modern environment variables manager
+#[derive(Parser)]
+#[command(
+    author = "Mattia Righetti <matt95.righetti@gmail.com>",
+    version = VERSION,
+    help_template(HELP_TEMPLATE),
+)]
+pub(crate) struct Envelope {
+    #[command(subcommand)]
+    envelope: Option<EnvelopeCmd>,
+
+    #[command(flatten)]
+    globals: GlobalArgs,
+}
+
+impl Envelope {
+    #[tokio::main(flavor = "current_thread")]
+    async fn run(self) -> std::io::Result<()> {
+        style::set_color(self.globals.color);
+        logging::init(self.globals.verbose);
+
+        match self.envelope {
+            Some(envelope) => {
+                envelope.run(&self.globals).await?;
+            }
+            None => {
+                ops::print_from_stdin().await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Entry point of the `envelope` binary
+pub fn main() -> io::Result<()> {
+    // not a subcommand, the completions would offer it otherwise
+    if std::env::args().nth(1).as_deref() == Some(CompleteCmd::NAME) {
+        return CompleteCmd::parse_from(std::env::args().skip(1)).run();
+    }
+
+    let envelope = Envelope::parse();
+    let verbose = envelope.globals.verbose > 0;
+    if let Err(err) = envelope.run() {
+        report(&mut io::stderr(), &err, verbose)?;
+        std::process::exit(EnvelopeError::from_io(&err).map_or(1, EnvelopeError::exit_code));
+    }
+
+    Ok(())
+}
+
+/// Writes `err` the way the CLI reports it, followed by the errors that
+/// caused it when `verbose`, such as the one returned by sqlite
+fn report<W: Write>(w: &mut W, err: &io::Error, verbose: bool) -> io::Result<()> {
+    writeln!(w, "error: {}", err)?;
+    if verbose {
+        let mut source = err.source();
+        while let Some(cause) = source {
+            writeln!(w, "caused by: {:?}", cause)?;
+            source = cause.source();
+        }
+    }
+
+    Ok(())
+}
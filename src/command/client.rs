@@ -1,28 +1,165 @@
-use clap::Subcommand;
-use std::io::Result;
+use clap::{Args, ColorChoice, Subcommand, ValueEnum};
+use std::future::Future;
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::std_err;
-use crate::{db::EnvelopeDb, ops};
+use crate::config::Config;
+use crate::db::{self, EnvelopeDb, SortOrder};
+use crate::err;
+use crate::error::EnvelopeError;
+use crate::store::{AnyStore, JsonStore, DATABASE_URL_VAR};
+use crate::{ops, tui};
 
+mod activate;
 mod add;
+mod audit;
+mod changes;
+mod check;
+mod check_runtime;
+mod complete;
+mod completions;
+mod convert;
+mod dedupe_case;
 mod delete;
+mod diff;
 mod drop;
 mod duplicate;
 mod edit;
+mod env;
 mod export;
+mod flatten;
+mod history;
+mod hook;
 mod import;
 mod list;
+mod lock;
+mod migrate;
+mod rename;
+mod run;
+mod scan;
+mod shell;
+mod template;
+
+pub use complete::Cmd as CompleteCmd;
+
+/// File of the json backend in the current directory
+const JSON_FILE: &str = ".envelope.json";
+
+/// Options shared by every command
+#[derive(Args)]
+pub struct GlobalArgs {
+    /// Allow changes to locked environments
+    #[arg(long, global = true)]
+    pub force: bool,
+
+    /// Run destructive commands without asking for confirmation
+    ///
+    /// Required when envelope is not run from a terminal, destructive
+    /// commands fail otherwise.
+    #[arg(short, long, global = true)]
+    pub yes: bool,
+
+    /// Print what add, import, delete, drop, duplicate and rename would
+    /// change without writing anything
+    ///
+    /// The changes are printed the way these commands print them once they
+    /// have been made. The other commands that write refuse to run with this
+    /// flag.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Log what envelope does on stderr, repeat to log the sql as well
+    ///
+    /// `-v` logs at info level and `-vv` at debug level, RUST_LOG overrides
+    /// the level, e.g. `RUST_LOG=sqlx=debug`. export and run also print which
+    /// environment provided each variable.
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Milliseconds a write waits for other writers before giving up
+    #[arg(long, global = true, value_name = "MS")]
+    pub write_timeout: Option<u64>,
+
+    /// Print the output of list, history, changes, diff and migrate --status
+    /// as JSON
+    ///
+    /// Variables are objects with the fields `env`, `key`, `value` and
+    /// `created_at` (unix seconds), `value` is null for the deleted versions
+    /// listed by history. Environments are `{"env": ...}` objects and diffs
+    /// are `{"added": {KEY: value}, "removed": {KEY: value}, "changed": {KEY:
+    /// {"old": value, "new": value}}}`. Changes are objects with the fields
+    /// `key`, `kind` (`added`, `changed` or `deleted`), `old`, `new` and
+    /// `created_at`.
+    #[arg(long, global = true)]
+    pub json: bool,
+
+    /// When to color the output
+    ///
+    /// `auto` colors the output that goes to a terminal, unless NO_COLOR is
+    /// set or CLICOLOR_FORCE asks for colors anyway. JSON and exports are
+    /// never colored.
+    #[arg(long, global = true, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// Where the variables are kept: the sqlite database .envelope or the
+    /// JSON file .envelope.json written by `convert`
+    ///
+    /// Only add, delete, history and list run on the json backend. Defaults
+    /// to json when ENVELOPE_PATH ends with .json, ENVELOPE_DATABASE_URL
+    /// takes precedence when it is set.
+    #[arg(long, global = true, value_enum)]
+    pub backend: Option<Backend>,
+}
+
+/// Backends of the command line, see [`crate::store`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Backend {
+    Sqlite,
+    Json,
+}
+
+impl GlobalArgs {
+    pub fn output(&self) -> ops::Output {
+        match self.json {
+            true => ops::Output::Json,
+            false => ops::Output::Text,
+        }
+    }
+}
 
 #[derive(Subcommand)]
 #[command(infer_subcommands = true)]
 pub enum EnvelopeCmd {
+    Activate(activate::Cmd),
+
     Add(add::Cmd),
 
-    /// Check which environment is currently exported
-    Check,
+    Audit(audit::Cmd),
+
+    Changes(changes::Cmd),
+
+    Check(check::Cmd),
+
+    CheckRuntime(check_runtime::Cmd),
+
+    Completions(completions::Cmd),
+
+    Convert(convert::Cmd),
+
+    /// Stop loading an environment with the shell hook
+    Deactivate,
+
+    DedupeCase(dedupe_case::Cmd),
+
+    /// Diagnose the database of the current directory, or the one of
+    /// ENVELOPE_PATH
+    Doctor,
 
     Delete(delete::Cmd),
 
+    Diff(diff::Cmd),
+
     Drop(drop::Cmd),
 
     Duplicate(duplicate::Cmd),
@@ -31,33 +168,283 @@ pub enum EnvelopeCmd {
 
     Edit(edit::Cmd),
 
+    Env(env::Cmd),
+
+    Flatten(flatten::Cmd),
+
+    History(history::Cmd),
+
+    Hook(hook::Cmd),
+
     /// Initialize envelope
     Init,
 
     Import(import::Cmd),
 
     List(list::Cmd),
+
+    Lock(lock::Cmd),
+
+    Migrate(migrate::Cmd),
+
+    Rename(rename::Cmd),
+
+    Run(run::Cmd),
+
+    Scan(scan::Cmd),
+
+    Shell(shell::Cmd),
+
+    Template(template::Cmd),
+
+    /// Browse the environments and variables in an interactive terminal UI
+    Tui,
+
+    Unlock(lock::UnlockCmd),
 }
 
 impl EnvelopeCmd {
-    pub async fn run(self) -> Result<()> {
-        let db = EnvelopeDb::load(matches!(self, Self::Init))
-            .await
-            .map_err(|e| std_err!("{}", e.to_string()))?;
+    pub async fn run(self, globals: &GlobalArgs) -> Result<()> {
+        if globals.dry_run && !self.supports_dry_run() {
+            return err!(
+                "--dry-run is only supported by add, import, delete, drop, duplicate and rename"
+            );
+        }
+
+        match &self {
+            Self::Completions(completions) => return completions.run(),
+            Self::Convert(convert) => return convert.run().await,
+            Self::Doctor => return ops::doctor(&mut anstream::stdout(), &db::db_path()?).await,
+            Self::Hook(hook) => return hook.run(),
+            Self::Migrate(migrate) => return migrate.run(globals.output()).await,
+            Self::Env(env) if env.for_hook() => return env.run_hook().await,
+            Self::Run(run) if run.uses_dotenv() => {
+                return run.run_dotenv(globals.verbose > 0).await
+            }
+            _ => {}
+        }
+
+        let mut db = match self.open_store(globals).await? {
+            AnyStore::Sqlite(db) => db,
+            store => return self.run_store(&store, globals).await,
+        };
+        db.set_force(globals.force);
+        if let Some(timeout) = globals.write_timeout {
+            db.set_write_timeout(Some(Duration::from_millis(timeout)));
+        }
 
         match self {
-            Self::Add(add) => add.run(&db).await?,
-            Self::Check => ops::check(&mut std::io::stdout(), &db).await?,
-            Self::Delete(delete) => delete.run(&db).await?,
-            Self::Drop(drop) => drop.run(&db).await?,
-            Self::Duplicate(duplicate) => duplicate.run(&db).await?,
-            Self::Export(export) => export.run(&db).await?,
+            Self::Activate(activate) => activate.run(&db).await?,
+            Self::Add(add) => add.run(&mut db, globals.dry_run).await?,
+            Self::Audit(audit) => audit.run(&db).await?,
+            Self::Changes(changes) => changes.run(&db, globals.output()).await?,
+            Self::Check(check) => {
+                check
+                    .run(&db, globals.output(), globals.yes, globals.dry_run)
+                    .await?
+            }
+            Self::CheckRuntime(check) => check.run(&db, globals.output()).await?,
+            Self::Deactivate => ops::deactivate(&db).await?,
+            Self::DedupeCase(dedupe) => dedupe.run(&db).await?,
+            Self::Delete(delete) => delete.run(&db, globals.yes, globals.dry_run).await?,
+            Self::Diff(diff) => diff.run(&db, globals.output()).await?,
+            Self::Drop(drop) => drop.run(&db, globals.yes, globals.dry_run).await?,
+            Self::Duplicate(duplicate) => duplicate.run(&db, globals.dry_run).await?,
+            Self::Export(export) => {
+                export
+                    .run(&db, globals.verbose > 0, globals.dry_run)
+                    .await?
+            }
             Self::Edit(edit) => edit.run(&db).await?,
-            Self::Import(import) => import.run(&db).await?,
-            Self::List(list) => list.run(&db).await?,
+            Self::Flatten(flatten) => flatten.run(&db, globals.yes).await?,
+            Self::History(history) => history.run(&db, globals.output()).await?,
+            Self::Env(env) => env.run(&db).await?,
+            Self::Import(import) => import.run(&mut db, globals.dry_run).await?,
+            Self::List(list) => list.run(&db, globals.output()).await?,
+            Self::Lock(lock) => lock.run(&db).await?,
+            Self::Rename(rename) => rename.run(&db, globals.dry_run).await?,
+            Self::Run(run) => run.run(&db, globals.verbose > 0).await?,
+            Self::Scan(scan) => scan.run(&db, globals.output()).await?,
+            Self::Shell(shell) => shell.run(&db).await?,
+            Self::Template(template) => template.run(&db, globals.output()).await?,
+            Self::Tui => {
+                if !ops::is_interactive() {
+                    return err!("tui requires a terminal");
+                }
+                tui::run(&db).await?
+            }
+            Self::Unlock(unlock) => unlock.run(&db).await?,
             _ => {}
         }
 
         Ok(())
     }
+
+    /// Opens the store of `ENVELOPE_DATABASE_URL` when it is set, the one of
+    /// `--backend` otherwise
+    async fn open_store(&self, globals: &GlobalArgs) -> Result<AnyStore> {
+        if let Ok(url) = std::env::var(DATABASE_URL_VAR) {
+            if !url.is_empty() {
+                return Ok(AnyStore::connect(&url).await?);
+            }
+        }
+
+        let init = matches!(self, Self::Init);
+        let path = std::env::var_os(db::PATH_VAR).filter(|path| !path.is_empty());
+        let backend = globals.backend.unwrap_or_else(|| {
+            match path.as_ref().map(Path::new).and_then(Path::extension) {
+                Some(extension) if extension == "json" => Backend::Json,
+                _ => Backend::Sqlite,
+            }
+        });
+        if backend == Backend::Sqlite {
+            return Ok(AnyStore::Sqlite(EnvelopeDb::load(init).await?));
+        }
+
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => std::env::current_dir()?.join(JSON_FILE),
+        };
+        let mut store = JsonStore::open(&path);
+        if let Some(timeout) = globals.write_timeout {
+            store.set_write_timeout(Some(Duration::from_millis(timeout)));
+        }
+        match path.exists() {
+            true => {}
+            false if init => store.write_versions(Vec::new()).await?,
+            false => return Err(EnvelopeError::NotInitialized.into()),
+        }
+
+        Ok(AnyStore::Json(store))
+    }
+
+    /// Runs the commands every backend provides on `store`, the other ones
+    /// need the sqlite database
+    async fn run_store(self, store: &AnyStore, globals: &GlobalArgs) -> Result<()> {
+        if globals.dry_run {
+            return err!("--dry-run needs the sqlite database");
+        }
+
+        match self {
+            Self::Add(add) => add.run_store(store).await,
+            Self::Delete(delete) => delete.run_store(store, globals.yes).await,
+            Self::History(history) => history.run_store(store, globals.output()).await,
+            Self::List(list) => list.run_store(store, globals.output()).await,
+            Self::Init => Ok(()),
+            _ => err!(
+                "only add, delete, history and list run on the {} backend, the other commands need the sqlite database",
+                store.backend()
+            ),
+        }
+    }
+
+    /// Whether the command can run with `--dry-run`, commands that do not
+    /// write anything ignore it
+    fn supports_dry_run(&self) -> bool {
+        if let Self::Export(export) = self {
+            return export.is_remote();
+        }
+
+        !matches!(
+            self,
+            Self::Activate(_)
+                | Self::Audit(_)
+                | Self::Convert(_)
+                | Self::Deactivate
+                | Self::DedupeCase(_)
+                | Self::Edit(_)
+                | Self::Flatten(_)
+                | Self::Init
+                | Self::Lock(_)
+                | Self::Migrate(_)
+                | Self::Template(_)
+                | Self::Tui
+                | Self::Unlock(_)
+        )
+    }
+}
+
+/// Runs `apply` then prints the `changes` it made, only prints them when
+/// `dry_run` is set
+async fn apply_changes<E: Into<std::io::Error>>(
+    changes: &ops::Changes,
+    dry_run: bool,
+    apply: impl Future<Output = std::result::Result<(), E>>,
+) -> Result<()> {
+    if dry_run {
+        return print_dry_run(changes);
+    }
+
+    apply.await.map_err(Into::into)?;
+    ops::print_changes(&mut anstream::stdout(), changes)
+}
+
+/// Fails if the values that `changes` sets break the `[schema]` of the
+/// project config, unless `no_verify` is set
+fn verify(changes: &ops::Changes, no_verify: bool) -> Result<()> {
+    if no_verify {
+        return Ok(());
+    }
+
+    let config = Config::load(&std::env::current_dir()?)?;
+    ops::verify_changes(&config.schema, changes)
+}
+
+/// Prints the `changes` a command would make and that nothing was written
+fn print_dry_run(changes: &ops::Changes) -> Result<()> {
+    ops::print_changes(&mut anstream::stdout(), changes)?;
+    writeln!(anstream::stderr(), "dry run, nothing has been written")
+}
+
+/// Asks the user of the terminal to type `answer` before running a destructive
+/// command, see [`ops::confirm`]
+fn confirm(yes: bool, message: &str, answer: &str) -> Result<()> {
+    ops::confirm(
+        &mut std::io::stdin().lock(),
+        &mut std::io::stdout(),
+        ops::is_interactive(),
+        yes,
+        message,
+        answer,
+    )
+}
+
+/// Returns `env`, or lets the user pick one when it is missing and `pick` is
+/// set, see [`tui::pick`]
+async fn pick_env(db: &EnvelopeDb, env: Option<&str>, pick: bool) -> Result<Option<String>> {
+    match env {
+        Some(env) => Ok(Some(env.to_string())),
+        None if pick => {
+            let envs: Vec<String> = db
+                .list_environments()
+                .await?
+                .into_iter()
+                .map(|e| e.env)
+                .collect();
+            tui::pick("environment", &envs)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Same as [`pick_env`] for a key of `env`
+async fn pick_key(
+    db: &EnvelopeDb,
+    env: &str,
+    key: Option<&str>,
+    pick: bool,
+) -> Result<Option<String>> {
+    match key {
+        Some(key) => Ok(Some(key.to_string())),
+        None if pick => {
+            let keys: Vec<String> = db
+                .list_var_in_env(env, SortOrder::Asc)
+                .await?
+                .into_iter()
+                .map(|row| row.key)
+                .collect();
+            tui::pick("key", &keys)
+        }
+        None => Ok(None),
+    }
 }
@@ -0,0 +1,18 @@
+use std::io::Result;
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// Set the environment loaded by the shell hook
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environment to load when entering this directory.
+    env: String,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
+        ops::activate(db, &self.env).await
+    }
+}
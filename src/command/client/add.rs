@@ -1,8 +1,11 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 use std::io::{BufRead, Result, Write};
 
-use crate::{db::EnvelopeDb, err, ops};
+use crate::db::{EnvDiff, EnvelopeDb};
+use crate::store::{AnyStore, Store};
+use crate::validate::ValueType;
+use crate::{err, ops};
 
 /// Add environment variables to a specific environment
 #[derive(Parser)]
@@ -19,10 +22,69 @@ pub struct Cmd {
 
     /// Value of the environment variable. Default to empty string if not provided.
     value: Option<String>,
+
+    /// Also set the variable in these environments, all of them are updated
+    /// at once.
+    #[arg(long, value_name = "ENV")]
+    also: Vec<String>,
+
+    /// Write the value even if it breaks the `[schema]` of envelope.toml.
+    #[arg(long)]
+    no_verify: bool,
+
+    /// Type the values of the variable must have from now on, checked on
+    /// every write. `none` removes the type.
+    #[arg(long = "type", value_name = "TYPE")]
+    value_type: Option<TypeArg>,
+
+    /// Store the key as given instead of uppercasing it.
+    ///
+    /// The other commands uppercase the keys they are given, they only find
+    /// this variable when listing the whole environment.
+    #[arg(long, conflicts_with_all = ["also", "value_type"])]
+    no_upper: bool,
+
+    /// Do not write the value to the environments where the variable
+    /// already has it, instead of adding a version to its history.
+    #[arg(long)]
+    dedupe: bool,
+
+    /// Read the secret the value points to in 1Password, a reference such
+    /// as op://vault/item/field, with `op read` and store it.
+    #[arg(long)]
+    resolve_op: bool,
+
+    /// Store the 1Password reference instead of the secret, `run` reads it
+    /// every time the command starts. The variable gets the type reference.
+    #[arg(long, requires = "resolve_op", conflicts_with_all = ["value_type", "no_upper"])]
+    dynamic: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TypeArg {
+    Int,
+    /// true, false, 1, 0, yes or no
+    Bool,
+    Url,
+    /// 1 to 65535
+    Port,
+    None,
+}
+
+impl TypeArg {
+    fn value_type(self) -> Option<ValueType> {
+        match self {
+            TypeArg::Int => Some(ValueType::Int),
+            TypeArg::Bool => Some(ValueType::Bool),
+            TypeArg::Url => Some(ValueType::Url),
+            TypeArg::Port => Some(ValueType::Port),
+            TypeArg::None => None,
+        }
+    }
 }
 
 impl Cmd {
-    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
+    fn read_value(&self) -> Result<String> {
         if self.stdin && self.value.is_some() {
             return err!("can't specify a value if you're reading from stdin");
         }
@@ -41,6 +103,109 @@ impl Cmd {
             }
         }
 
-        ops::add_var(db, &self.env, &self.key, value.trim_end()).await
+        Ok(value.trim_end().to_string())
+    }
+
+    /// sets the variable in `store`, the options that need the tables of
+    /// the sqlite database are refused
+    pub async fn run_store(&self, store: &AnyStore) -> Result<()> {
+        if !self.also.is_empty()
+            || self.value_type.is_some()
+            || self.no_upper
+            || self.dedupe
+            || self.resolve_op
+        {
+            return err!(
+                "--also, --type, --no-upper, --dedupe and --resolve-op need the sqlite database"
+            );
+        }
+
+        ops::check_key(&self.key)?;
+        let value = self.read_value()?;
+        let key = self.key.to_ascii_uppercase();
+        let previous = store
+            .get_vars(&self.env, std::slice::from_ref(&key))
+            .await?;
+        let mut diff = EnvDiff::default();
+        match previous.get(&key).cloned().flatten() {
+            Some(previous) if previous != value => {
+                diff.changed.insert(key.clone(), (previous, value.clone()));
+            }
+            Some(_) => {}
+            None => {
+                diff.added.insert(key.clone(), value.clone());
+            }
+        }
+        super::verify(
+            &ops::Changes::from([(self.env.clone(), diff)]),
+            self.no_verify,
+        )?;
+
+        let outcome = store.insert(&self.env, &key, &value).await?;
+        ops::print_outcome(&mut anstream::stdout(), &self.env, &key, &value, &outcome)
+    }
+
+    pub async fn run(&self, db: &mut EnvelopeDb, dry_run: bool) -> Result<()> {
+        db.set_dedupe(self.dedupe);
+        let db = &*db;
+
+        // checked before planning, a dry run would not fail otherwise
+        ops::check_key(&self.key)?;
+        let mut value = self.read_value()?;
+        if self.resolve_op {
+            ops::check_op_reference(&value)?;
+            if !self.dynamic {
+                value = ops::read_op(&ops::OpBinary::default(), &value).await?;
+            }
+        }
+        let value = value.as_str();
+        let envs: Vec<String> = std::iter::once(&self.env)
+            .chain(&self.also)
+            .cloned()
+            .collect();
+        let mut changes = ops::Changes::new();
+        for env in &envs {
+            let diff = match self.no_upper {
+                true => ops::plan_set_exact(db, env, &self.key, value).await?,
+                false => ops::plan_set(db, env, [(self.key.clone(), value.into())]).await?,
+            };
+            changes.insert(env.clone(), diff);
+        }
+        super::verify(&changes, self.no_verify)?;
+        let value_type = match self.dynamic {
+            true => Some(ValueType::Reference),
+            false => self.value_type.and_then(TypeArg::value_type),
+        };
+        ops::check_type(&self.key, value, value_type)?;
+
+        // the type is set first, the value could break the previous one
+        let set_type = async {
+            match self.value_type.is_some() || self.dynamic {
+                true => ops::set_var_type(db, &envs, &self.key, value_type).await,
+                false => Ok(()),
+            }
+        };
+
+        if dry_run {
+            return super::print_dry_run(&changes);
+        }
+
+        if self.also.is_empty() {
+            set_type.await?;
+            let (key, outcome) = match self.no_upper {
+                true => {
+                    let outcome = ops::add_var_exact(db, &self.env, &self.key, value).await?;
+                    (self.key.clone(), outcome)
+                }
+                false => {
+                    let outcome = ops::add_var(db, &self.env, &self.key, value).await?;
+                    (self.key.to_ascii_uppercase(), outcome)
+                }
+            };
+            return ops::print_outcome(&mut anstream::stdout(), &self.env, &key, value, &outcome);
+        }
+
+        set_type.await?;
+        ops::add_var_in_envs(&mut anstream::stdout(), db, &envs, &self.key, value).await
     }
 }
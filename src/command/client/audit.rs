@@ -0,0 +1,24 @@
+use std::io::Result;
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// Show or enable the audit mode, where history can no longer be removed
+#[derive(Parser)]
+pub struct Cmd {
+    /// Enable the audit mode, drop and flatten fail from then on. It cannot
+    /// be disabled
+    #[arg(long)]
+    enable: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
+        if self.enable {
+            ops::enable_audit(db).await?;
+        }
+
+        ops::audit_status(&mut anstream::stdout(), db).await
+    }
+}
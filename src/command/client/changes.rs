@@ -0,0 +1,38 @@
+use std::io::{self, Result};
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// Seconds in a day, the default window of the changes listed
+const DAY: i64 = 24 * 60 * 60;
+
+/// Show the variables set, changed and deleted in an environment over a
+/// period of time, the last day by default
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environment whose changes you wish to see
+    env: String,
+
+    /// Only show the changes made at or after this unix timestamp
+    #[arg(long, value_name = "SECS")]
+    since: Option<i64>,
+
+    /// Only show the changes made before this unix timestamp
+    #[arg(long, value_name = "SECS")]
+    until: Option<i64>,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb, output: ops::Output) -> Result<()> {
+        let until = self.until.unwrap_or(i64::MAX);
+        let since = match self.since {
+            Some(since) => since,
+            None => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs() as i64 - DAY),
+        };
+
+        ops::changes(&mut io::stdout(), db, &self.env, since, until, output).await
+    }
+}
@@ -0,0 +1,148 @@
+use std::io::{ErrorKind, Result};
+use std::path::Path;
+use std::{env, fs};
+
+use clap::Parser;
+
+use crate::config::Config;
+use crate::{db::EnvelopeDb, ops};
+
+/// Check which environment is currently exported, or validate environments
+/// against the schema of envelope.toml, an example file or a template
+///
+/// With `--unused`, reports the variables of the environments that neither
+/// the files of the current directory nor the example file reference.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environments to validate against the `[schema]` of envelope.toml, or
+    /// against the example file or template if given.
+    envs: Vec<String>,
+
+    /// Dotenv file listing the required variables, such as `.env.example`.
+    /// Only its keys are read.
+    #[arg(long, value_name = "PATH", requires = "envs")]
+    against: Option<String>,
+
+    /// Template whose keys the environments must set, see `envelope template`.
+    #[arg(
+        long,
+        value_name = "NAME",
+        requires = "envs",
+        conflicts_with = "against"
+    )]
+    template: Option<String>,
+
+    /// Also report the variables that are not in the example file.
+    #[arg(long, requires = "against", conflicts_with = "unused")]
+    strict: bool,
+
+    /// Report the variables that neither the sources of the current directory
+    /// nor the example file, `.env.example` by default, reference. The keys
+    /// matching `[unused] allow` of envelope.toml are left out. Does not fail
+    /// when some are found.
+    #[arg(long, requires = "envs", conflicts_with = "template")]
+    unused: bool,
+
+    /// Delete the unused variables, after confirmation.
+    #[arg(long, requires = "unused")]
+    delete_unused: bool,
+}
+
+/// Example file read by `--unused` when `--against` is not given
+const DEFAULT_EXAMPLE: &str = ".env.example";
+
+impl Cmd {
+    pub async fn run(
+        &self,
+        db: &EnvelopeDb,
+        output: ops::Output,
+        yes: bool,
+        dry_run: bool,
+    ) -> Result<()> {
+        if self.unused {
+            return self.run_unused(db, output, yes, dry_run).await;
+        }
+
+        if let Some(template) = &self.template {
+            return ops::check_template(&mut anstream::stdout(), db, &self.envs, template, output)
+                .await;
+        }
+
+        let Some(against) = &self.against else {
+            if self.envs.is_empty() {
+                return ops::check(&mut anstream::stdout(), db).await;
+            }
+
+            let config = Config::load(&env::current_dir()?)?;
+            return ops::check_schema(
+                &mut anstream::stdout(),
+                db,
+                &self.envs,
+                &config.schema,
+                output,
+            )
+            .await;
+        };
+
+        let example = fs::read_to_string(against)?;
+        ops::check_example(
+            &mut anstream::stdout(),
+            db,
+            &self.envs,
+            &example,
+            self.strict,
+            output,
+        )
+        .await
+    }
+
+    async fn run_unused(
+        &self,
+        db: &EnvelopeDb,
+        output: ops::Output,
+        yes: bool,
+        dry_run: bool,
+    ) -> Result<()> {
+        let example = match &self.against {
+            Some(against) => Some(fs::read_to_string(against)?),
+            None => match fs::read_to_string(DEFAULT_EXAMPLE) {
+                Ok(example) => Some(example),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            },
+        };
+        let dir = env::current_dir()?;
+        let config = Config::load(&dir)?;
+        let reports = ops::check_unused(
+            &mut anstream::stdout(),
+            db,
+            &self.envs,
+            Path::new("."),
+            example.as_deref(),
+            &config.unused,
+            output,
+        )
+        .await?;
+        if !self.delete_unused {
+            return Ok(());
+        }
+
+        for report in reports.iter().filter(|r| !r.unused.is_empty()) {
+            let keys: Vec<&str> = report.unused.iter().map(String::as_str).collect();
+            let diff = ops::plan_delete(db, &report.env, Some(&keys)).await?;
+            if !dry_run {
+                let message = format!(
+                    "this will remove {} unused variables from '{}'",
+                    keys.len(),
+                    report.env
+                );
+                super::confirm(yes, &message, &report.env)?;
+            }
+            let changes = ops::Changes::from([(report.env.clone(), diff)]);
+            let diff = &changes[&report.env];
+            super::apply_changes(&changes, dry_run, db.apply_diff(&report.env, diff)).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use std::env;
+use std::io::Result;
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// Compare the variables of the current process with stored environments
+///
+/// Reports the variables whose value differs and the stored ones that are
+/// not set. Values of variables that look like secrets are masked.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environments to compare with, later ones take precedence.
+    #[arg(required = true)]
+    envs: Vec<String>,
+
+    /// Also report the variables only set in the process whose name starts
+    /// with PREFIX.
+    #[arg(long)]
+    prefix: Option<String>,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb, output: ops::Output) -> Result<()> {
+        // variables that are not valid unicode cannot be stored
+        let runtime = env::vars_os()
+            .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)));
+
+        ops::check_runtime(
+            &mut anstream::stdout(),
+            db,
+            &self.envs,
+            runtime,
+            self.prefix.as_deref(),
+            output,
+        )
+        .await
+    }
+}
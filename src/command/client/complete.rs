@@ -0,0 +1,39 @@
+use std::io::{self, Result};
+
+use clap::Parser;
+
+use crate::db::{self, EnvelopeDb};
+use crate::ops;
+
+/// List environment names or keys for the completion scripts
+///
+/// Nothing is printed when no database can be read.
+#[derive(Parser)]
+#[command(name = Cmd::NAME)]
+pub struct Cmd {
+    #[arg(value_enum)]
+    kind: ops::Completion,
+
+    /// Environment whose keys are listed.
+    env: Option<String>,
+}
+
+impl Cmd {
+    /// First argument that runs the helper
+    pub const NAME: &'static str = "__complete";
+
+    #[tokio::main(flavor = "current_thread")]
+    pub async fn run(self) -> Result<()> {
+        let Some(path) = std::env::current_dir().ok().and_then(|d| db::find_db(&d)) else {
+            return Ok(());
+        };
+        let Ok(db) = EnvelopeDb::open_read_only(&path).await else {
+            return Ok(());
+        };
+
+        // completions must never print errors in the middle of the prompt
+        let _ = ops::complete(&mut io::stdout(), &db, self.kind, self.env.as_deref()).await;
+
+        Ok(())
+    }
+}
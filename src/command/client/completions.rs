@@ -0,0 +1,31 @@
+use std::io::{self, Result, Write};
+
+use clap::{CommandFactory, Parser};
+use clap_complete::Shell;
+
+use crate::ops;
+
+/// Print the completion script of a shell
+///
+/// Add `source <(envelope completions bash)` to your shell configuration,
+/// environment names and keys are completed from the database of the current
+/// directory in bash, zsh and fish.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Shell to generate the completions for.
+    #[arg(value_enum)]
+    shell: Shell,
+}
+
+impl Cmd {
+    pub fn run(&self) -> Result<()> {
+        let mut cmd = crate::cli::Envelope::command();
+        let mut stdout = io::stdout();
+        clap_complete::generate(self.shell, &mut cmd, "envelope", &mut stdout);
+
+        match ops::dynamic_completion(self.shell, &cmd) {
+            Some(script) => stdout.write_all(script.as_bytes()),
+            None => Ok(()),
+        }
+    }
+}
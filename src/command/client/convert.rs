@@ -0,0 +1,60 @@
+use std::env;
+use std::io::Result;
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::db::EnvelopeDb;
+use crate::store::JsonStore;
+use crate::{err, ops};
+
+use super::Backend;
+
+/// Copy the database of the current directory to another backend
+///
+/// Every version of every variable is copied, deletions and expiry
+/// included. The JSON file cannot hold descriptions, types, locks nor
+/// templates, a database that has them is not converted to it.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Backend to convert to, from the other one
+    #[arg(long, value_enum)]
+    to: Backend,
+
+    /// Database to convert, defaults to .envelope when converting to json
+    /// and to .envelope.json when converting to sqlite
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    /// File to create, defaults to .envelope.json when converting to json
+    /// and to .envelope when converting to sqlite
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+impl Cmd {
+    pub async fn run(&self) -> Result<()> {
+        let dir = env::current_dir()?;
+        let (input, output) = match self.to {
+            Backend::Json => (".envelope", super::JSON_FILE),
+            Backend::Sqlite => (super::JSON_FILE, ".envelope"),
+        };
+        let input = self.input.clone().unwrap_or_else(|| dir.join(input));
+        let output = self.output.clone().unwrap_or_else(|| dir.join(output));
+        if !input.exists() {
+            return err!("{} does not exist", input.display());
+        }
+
+        let mut stdout = anstream::stdout();
+        match self.to {
+            Backend::Json => {
+                let db = EnvelopeDb::open(&input).await?;
+                ops::convert_to_json(&mut stdout, &db, &output).await
+            }
+            Backend::Sqlite => {
+                let store = JsonStore::open(&input);
+                ops::convert_to_sqlite(&mut stdout, &store, &output).await
+            }
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::io::Result;
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// Merge the keys of an environment that differ only by case
+///
+/// Older versions of envelope could store both `ApiKey` and `APIKEY`. Each
+/// set of such keys is merged into its uppercased key, which takes the value
+/// of the most recently set one. The other keys are deleted, their history
+/// is kept.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environment to merge the keys of
+    env: String,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
+        ops::dedupe_case(&mut anstream::stdout(), db, &self.env).await
+    }
+}
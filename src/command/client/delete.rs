@@ -1,7 +1,9 @@
 use clap::Parser;
 use std::io::Result;
 
-use crate::{db::EnvelopeDb, ops};
+use crate::db::{EnvDiff, EnvelopeDb};
+use crate::store::{AnyStore, Store};
+use crate::{err, ops};
 
 /// Delete environment variables
 #[derive(Parser)]
@@ -13,19 +15,122 @@ pub struct Cmd {
     /// Environment variable name that you wish to delete.
     #[arg(short, long)]
     key: Option<String>,
+
+    /// Pick the environment, then the variable, with a fuzzy finder when they
+    /// are not given
+    #[arg(long)]
+    pick: bool,
 }
 
 impl Cmd {
-    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
-        match (&self.env, &self.key) {
+    /// deletes the variables of `store` like [`Cmd::run`] does with the
+    /// sqlite database
+    pub async fn run_store(&self, store: &AnyStore, yes: bool) -> Result<()> {
+        if self.pick {
+            return err!("--pick needs the sqlite database");
+        }
+        let key = self.key.as_deref().map(str::to_ascii_uppercase);
+        if let Some(env) = &self.env {
+            store.check_env_exists(env).await?;
+        }
+
+        let envs = match &self.env {
+            Some(env) => vec![env.clone()],
+            None => store
+                .list_environments()
+                .await?
+                .into_iter()
+                .map(|e| e.env)
+                .collect(),
+        };
+        let mut changes = ops::Changes::new();
+        for env in envs {
+            let removed = store
+                .list_var_in_env(&env, crate::SortOrder::Asc)
+                .await?
+                .into_iter()
+                .filter(|row| key.as_ref().is_none_or(|key| &row.key == key))
+                .map(|row| (row.key, row.value))
+                .collect();
+            let diff = EnvDiff {
+                removed,
+                ..Default::default()
+            };
+            if !diff.is_empty() {
+                changes.insert(env, diff);
+            }
+        }
+
+        match (&self.env, &key) {
+            (None, None) => return Ok(()),
+            (None, Some(key)) if !changes.is_empty() => {
+                let message = format!(
+                    "this will remove {} from {} environments",
+                    key,
+                    changes.len()
+                );
+                super::confirm(yes, &message, key)?;
+            }
+            (Some(env), None) if !changes.is_empty() => {
+                let message = format!(
+                    "this will remove {} variables from '{}'",
+                    changes[env].removed.len(),
+                    env
+                );
+                super::confirm(yes, &message, env)?;
+            }
+            _ => {}
+        }
+
+        let delete = async {
+            for env in changes.keys() {
+                match &key {
+                    Some(key) => store.delete_var_for_env(env, key).await?,
+                    None => store.delete_env(env).await?,
+                }
+            }
+            Ok::<_, crate::EnvelopeError>(())
+        };
+        super::apply_changes(&changes, false, delete).await
+    }
+
+    pub async fn run(&self, db: &EnvelopeDb, yes: bool, dry_run: bool) -> Result<()> {
+        let env = super::pick_env(db, self.env.as_deref(), self.pick).await?;
+        let key = match &env {
+            Some(env) => super::pick_key(db, env, self.key.as_deref(), self.pick).await?,
+            None => self.key.clone(),
+        };
+
+        match (&env, &key) {
             (Some(e), Some(k)) => {
-                ops::delete_var_in_env(db, e, k).await?;
+                let diff = ops::plan_delete(db, e, Some(&[k])).await?;
+                let changes = ops::Changes::from([(e.clone(), diff)]);
+                super::apply_changes(&changes, dry_run, ops::delete_var_in_env(db, e, k)).await?;
             }
             (None, Some(k)) => {
-                ops::delete_var_globally(db, k).await?;
+                let mut changes = ops::Changes::new();
+                for env in db.list_environments().await? {
+                    let diff = ops::plan_delete(db, &env.env, Some(&[k])).await?;
+                    if !diff.is_empty() {
+                        changes.insert(env.env, diff);
+                    }
+                }
+                if !changes.is_empty() && !dry_run {
+                    let message =
+                        format!("this will remove {} from {} environments", k, changes.len());
+                    super::confirm(yes, &message, k)?;
+                }
+                super::apply_changes(&changes, dry_run, ops::delete_var_globally(db, k)).await?;
             }
             (Some(e), None) => {
-                ops::delete_env(db, e).await?;
+                let diff = ops::plan_delete(db, e, None).await?;
+                let count = diff.removed.len();
+                if count > 0 && !dry_run {
+                    let message = format!("this will remove {} variables from '{}'", count, e);
+                    super::confirm(yes, &message, e)?;
+                }
+                let changes = ops::Changes::from([(e.clone(), diff)]);
+                super::apply_changes(&changes, dry_run, ops::delete_env(db, e)).await?;
             }
             _ => {}
         }
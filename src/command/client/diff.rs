@@ -0,0 +1,32 @@
+use std::fs;
+use std::io::{self, Read, Result};
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// Show what importing a dotenv file would change in an environment
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environment to compare.
+    env: String,
+
+    /// Path of the dotenv file to compare with.
+    /// Defaults to stdin if not provided.
+    path: Option<String>,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb, output: ops::Output) -> Result<()> {
+        let contents = match &self.path {
+            Some(path) => fs::read_to_string(path)?,
+            None => {
+                let mut contents = String::new();
+                io::stdin().read_to_string(&mut contents)?;
+                contents
+            }
+        };
+
+        ops::diff_dotenv(&mut anstream::stdout(), db, &self.env, &contents, output).await
+    }
+}
@@ -2,7 +2,8 @@ use std::io::Result;
 
 use clap::Parser;
 
-use crate::{db::EnvelopeDb, ops};
+use crate::db::EnvelopeDb;
+use crate::ops;
 
 /// Drop environment
 #[derive(Parser)]
@@ -12,7 +13,20 @@ pub struct Cmd {
 }
 
 impl Cmd {
-    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
-        ops::drop(db, &self.env).await
+    pub async fn run(&self, db: &EnvelopeDb, yes: bool, dry_run: bool) -> Result<()> {
+        db.check_env_exists(&self.env).await?;
+
+        let diff = ops::plan_delete(db, &self.env, None).await?;
+        if !dry_run {
+            let message = format!(
+                "this will permanently remove {} variables and their history from '{}'",
+                diff.removed.len(),
+                self.env
+            );
+            super::confirm(yes, &message, &self.env)?;
+        }
+
+        let changes = ops::Changes::from([(self.env.clone(), diff)]);
+        super::apply_changes(&changes, dry_run, ops::drop(db, &self.env)).await
     }
 }
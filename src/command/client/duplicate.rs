@@ -2,7 +2,7 @@ use clap::Parser;
 
 use std::io::Result;
 
-use crate::{db::EnvelopeDb, err, ops};
+use crate::{db::EnvelopeDb, err, ops, std_err};
 
 /// Create a copy of another environment
 #[derive(Parser)]
@@ -11,15 +11,55 @@ pub struct Cmd {
     source: String,
 
     /// New environment name
-    target: String,
+    #[arg(required_unless_present = "pick")]
+    target: Option<String>,
+
+    /// Copy into the target environment even if it already exists, the
+    /// copied values are added to its history
+    #[arg(long)]
+    append: bool,
+
+    /// Only copy the keys matching this glob, case insensitive. `*` matches
+    /// any characters and `?` a single one. Can be repeated.
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Pick the environment to duplicate with a fuzzy finder, the only
+    /// environment given is then the new one
+    #[arg(long)]
+    pick: bool,
 }
 
 impl Cmd {
-    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
-        if self.source == self.target {
+    pub async fn run(&self, db: &EnvelopeDb, dry_run: bool) -> Result<()> {
+        let (source, target) = match &self.target {
+            Some(target) => (self.source.clone(), target.clone()),
+            None => {
+                let source = super::pick_env(db, None, self.pick)
+                    .await?
+                    .ok_or(std_err!("no environment to duplicate"))?;
+                (source, self.source.clone())
+            }
+        };
+        if source == target {
             return err!("cannot duplicate to same environment");
         }
 
-        ops::duplicate(db, &self.source, &self.target).await
+        if !self.append {
+            db.ensure_new_env(&target).await?;
+        }
+
+        let diff = ops::plan_duplicate(db, &source, &target, &self.include).await?;
+        let changes = ops::Changes::from([(target.clone(), diff)]);
+        let duplicate = async {
+            match self.include.is_empty() {
+                true => ops::duplicate(db, &source, &target, self.append).await,
+                false => {
+                    ops::duplicate_filtered(db, &source, &target, &self.include, self.append).await
+                }
+            }
+        };
+
+        super::apply_changes(&changes, dry_run, duplicate).await
     }
 }
@@ -13,7 +13,7 @@ pub struct Cmd {
 
 impl Cmd {
     pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
-        ops::edit(db, &self.env).await?;
+        ops::edit(&mut anstream::stdout(), db, &self.env).await?;
         Ok(())
     }
 }
@@ -0,0 +1,71 @@
+use std::io::{self, Result};
+
+use clap::Parser;
+
+use crate::db::{self, EnvelopeDb};
+use crate::{ops, std_err};
+
+/// Print the statements that load an environment in the current shell
+///
+/// Meant to be evaluated by the shell, e.g. `eval "$(envelope env dev)"`, or
+/// `envelope env dev | source` in fish.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environment that you wish to load.
+    #[arg(required_unless_present = "for_hook")]
+    env: Option<String>,
+
+    /// Shell syntax to use, detected from $SHELL if not provided.
+    #[arg(long, value_enum)]
+    shell: Option<ops::Shell>,
+
+    /// Load the active environment of the closest database, used by the hook.
+    #[arg(long, hide = true, conflicts_with = "env")]
+    for_hook: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
+        let env = self.env.as_deref().unwrap_or_default();
+        let loaded = std::env::var(ops::ENVELOPE_LOADED_VARS).ok();
+
+        let skipped =
+            ops::print_env(&mut io::stdout(), db, env, self.shell(), loaded.as_deref()).await?;
+        ops::warn_skipped_keys(&mut io::stderr(), &skipped)
+    }
+
+    pub fn for_hook(&self) -> bool {
+        self.for_hook
+    }
+
+    /// Runs without a database in the current directory, the closest one is
+    /// looked up in the parent directories instead
+    pub async fn run_hook(&self) -> Result<()> {
+        let loaded = std::env::var(ops::ENVELOPE_LOADED_VARS).ok();
+        let state = std::env::var(ops::ENVELOPE_HOOK_STATE).ok();
+
+        let path = db::find_db(&std::env::current_dir()?);
+        let db = match &path {
+            Some(path) => Some(
+                EnvelopeDb::open(path)
+                    .await
+                    .map_err(|e| std_err!("{}", e))?,
+            ),
+            None => None,
+        };
+
+        let skipped = ops::print_hook_env(
+            &mut io::stdout(),
+            path.as_deref().zip(db.as_ref()),
+            self.shell(),
+            loaded.as_deref(),
+            state.as_deref(),
+        )
+        .await?;
+        ops::warn_skipped_keys(&mut io::stderr(), &skipped)
+    }
+
+    fn shell(&self) -> ops::Shell {
+        self.shell.unwrap_or_else(ops::Shell::detect)
+    }
+}
@@ -1,42 +1,267 @@
 use std::env;
-use std::fs;
 use std::fs::OpenOptions;
-use std::io::{BufWriter, Result};
+use std::io::{self, BufWriter, Result, Write};
 
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 use crate::db::EnvelopeDb;
-use crate::ops;
+use crate::format::K8sKind;
+use crate::{err, ops};
 
 /// Export environment variables
 #[derive(Parser)]
 pub struct Cmd {
     /// Environment that you wish to export.
-    env: String,
+    env: Option<String>,
+
+    /// Additional environments layered on top of the previous ones, later
+    /// environments take precedence.
+    #[arg(short = 'e', long = "env")]
+    envs: Vec<String>,
 
     /// Custom output file path.
+    /// Defaults to .env for dotenv and to stdout for the other formats.
     #[arg(long, short)]
     output: Option<String>,
+
+    /// Format of the exported variables.
+    #[arg(long, short, value_enum, default_value_t = Format::Dotenv)]
+    format: Format,
+
+    /// Name of the Kubernetes manifest, defaults to the last environment.
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Namespace of the Kubernetes manifest.
+    #[arg(long)]
+    namespace: Option<String>,
+
+    /// Write the variables in the order their keys were first set in this
+    /// environment, the keys it does not have come last alphabetically.
+    /// Only for the dotenv and csv formats.
+    #[arg(long, value_name = "ENV")]
+    order_like: Option<String>,
+
+    /// Write the variables of the environment to an external store instead
+    /// of a file, only the values that differ are written
+    #[cfg(any(feature = "aws", feature = "azure", feature = "vault"))]
+    #[arg(
+        long,
+        value_name = "STORE",
+        requires = "env",
+        conflicts_with_all = ["envs", "output", "format", "name", "namespace", "order_like"]
+    )]
+    to: Option<Destination>,
+
+    /// Path of the variables in the store. For SSM the path of the
+    /// parameters, such as /myapp/prod/, each variable is written to the
+    /// SecureString parameter of its key under it. For Vault the path of
+    /// the secret under its mount, such as myapp/prod.
+    #[cfg(any(feature = "aws", feature = "vault"))]
+    #[arg(
+        long = "path",
+        value_name = "STORE_PATH",
+        required_if_eq_any([("to", "aws-ssm"), ("to", "vault")])
+    )]
+    #[cfg_attr(feature = "aws", arg(conflicts_with = "secret_name"))]
+    remote_path: Option<String>,
+
+    /// Secrets Manager secret holding the variables as a JSON object
+    #[cfg(feature = "aws")]
+    #[arg(long, required_if_eq("to", "aws-secretsmanager"))]
+    secret_name: Option<String>,
+
+    /// KMS key encrypting the parameters or the secret, the default key of
+    /// the account otherwise
+    #[cfg(feature = "aws")]
+    #[arg(long, requires = "to")]
+    kms_key_id: Option<String>,
+
+    /// Mount of the Vault KV v2 secrets engine
+    #[cfg(feature = "vault")]
+    #[arg(long, default_value = ops::DEFAULT_VAULT_MOUNT, requires = "to")]
+    mount: String,
+
+    /// Write one Vault secret per key under the path, holding the value in
+    /// its `value` field, instead of a single secret for the environment
+    #[cfg(feature = "vault")]
+    #[arg(long, requires = "to")]
+    per_key: bool,
+
+    /// Name of the Azure Key Vault, such as myapp-kv, or its URL in another
+    /// cloud. Each variable is written to the secret of its key with _
+    /// replaced by -.
+    #[cfg(feature = "azure")]
+    #[arg(long, requires = "to", required_if_eq("to", "azure-keyvault"))]
+    vault_name: Option<String>,
+}
+
+/// External stores the variables can be exported to
+#[cfg(any(feature = "aws", feature = "azure", feature = "vault"))]
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Destination {
+    /// SSM Parameter Store, one parameter per variable
+    #[cfg(feature = "aws")]
+    AwsSsm,
+    /// Secrets Manager, one secret for the environment
+    #[cfg(feature = "aws")]
+    #[value(name = "aws-secretsmanager")]
+    AwsSecretsManager,
+    /// HashiCorp Vault KV v2 secrets engine, configured by VAULT_ADDR,
+    /// VAULT_TOKEN and VAULT_NAMESPACE
+    #[cfg(feature = "vault")]
+    Vault,
+    /// Azure Key Vault, one secret per variable, authenticated by
+    /// AZURE_TENANT_ID, AZURE_CLIENT_ID and AZURE_CLIENT_SECRET or by the
+    /// az CLI
+    #[cfg(feature = "azure")]
+    AzureKeyvault,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Dotenv,
+    /// env,key,value records, env is the environment providing the variable
+    Csv,
+    /// Object mapping each key to its value, environment, type and
+    /// description
+    Json,
+    /// Kubernetes Secret, values are base64 encoded
+    #[value(name = "k8s-secret")]
+    K8sSecret,
+    /// Kubernetes ConfigMap
+    #[value(name = "k8s-configmap")]
+    K8sConfigMap,
 }
 
 impl Cmd {
-    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
+    /// Whether the variables are written to a store, which supports
+    /// `--dry-run`
+    pub fn is_remote(&self) -> bool {
+        #[cfg(any(feature = "aws", feature = "azure", feature = "vault"))]
+        return self.to.is_some();
+        #[cfg(not(any(feature = "aws", feature = "azure", feature = "vault")))]
+        false
+    }
+
+    /// `dry_run` only applies to `--to`
+    #[cfg_attr(
+        not(any(feature = "aws", feature = "azure", feature = "vault")),
+        allow(unused_variables)
+    )]
+    pub async fn run(&self, db: &EnvelopeDb, verbose: bool, dry_run: bool) -> Result<()> {
+        #[cfg(any(feature = "aws", feature = "azure", feature = "vault"))]
+        if let (Some(to), Some(env)) = (self.to, &self.env) {
+            return self.run_remote(db, to, env, dry_run).await;
+        }
+
+        let envs: Vec<String> = self.env.iter().chain(&self.envs).cloned().collect();
+        if envs.is_empty() {
+            return err!("at least one environment is required");
+        }
+        if self.order_like.is_some() && !matches!(self.format, Format::Dotenv | Format::Csv) {
+            return err!("--order-like only applies to the dotenv and csv formats");
+        }
+
+        if verbose {
+            let layers = ops::get_env(db, &envs).await?;
+            ops::print_provenance(&mut io::stderr(), &layers)?;
+        }
+
         let mut opts = OpenOptions::new();
         opts.create(true);
         opts.write(true);
 
-        let out: fs::File = match &self.output {
-            Some(out) => opts.open(out)?,
-            None => {
+        let out: Box<dyn Write> = match (&self.output, self.format) {
+            (Some(out), _) => Box::new(opts.open(out)?),
+            (None, Format::Dotenv) => {
                 let dotenv = env::current_dir()?.join(".env");
-                opts.open(dotenv)?
+                Box::new(opts.open(dotenv)?)
             }
+            (None, _) => Box::new(io::stdout()),
         };
 
         let mut buf = BufWriter::new(out);
 
-        ops::export_dotenv(db, &self.env, &mut buf).await?;
+        let name = self.name.as_ref().unwrap_or(&envs[envs.len() - 1]);
+        let namespace = self.namespace.as_deref();
+        let order_like = self.order_like.as_deref();
+        match self.format {
+            Format::Dotenv => ops::export_dotenv(db, &envs, order_like, &mut buf).await?,
+            Format::Csv => ops::export_csv(db, &envs, order_like, &mut buf).await?,
+            Format::Json => ops::export_json(db, &envs, &mut buf).await?,
+            Format::K8sSecret => {
+                let kind = K8sKind::Secret;
+                ops::export_k8s(db, &envs, kind, name, namespace, &mut buf).await?
+            }
+            Format::K8sConfigMap => {
+                let kind = K8sKind::ConfigMap;
+                ops::export_k8s(db, &envs, kind, name, namespace, &mut buf).await?
+            }
+        }
+
+        buf.flush()
+    }
+
+    /// Writes the variables of `env` to the store `to`, a dry run prints
+    /// what would be written without writing anything
+    #[cfg(any(feature = "aws", feature = "azure", feature = "vault"))]
+    async fn run_remote(
+        &self,
+        db: &EnvelopeDb,
+        to: Destination,
+        env: &str,
+        dry_run: bool,
+    ) -> Result<()> {
+        db.check_env_exists(env).await?;
+        let vars = ops::current_vars(db, env).await?;
+        let stdout = &mut io::stdout();
+        // clap requires the path or the secret name of the destination
+        #[cfg(any(feature = "aws", feature = "vault"))]
+        let path = self.remote_path.as_deref().unwrap_or_default();
+
+        match to {
+            #[cfg(feature = "aws")]
+            Destination::AwsSsm => {
+                let client = aws_sdk_ssm::Client::new(&ops::aws_config().await);
+                let plan = ops::plan_ssm_export(&client, path, &vars).await?;
+                let changes = ops::Changes::from([(path.to_string(), plan.diff.clone())]);
+                let kms_key_id = self.kms_key_id.as_deref();
+                let export = ops::export_ssm(stdout, &client, path, &plan, kms_key_id);
+                super::apply_changes(&changes, dry_run, export).await
+            }
+            #[cfg(feature = "aws")]
+            Destination::AwsSecretsManager => {
+                let client = aws_sdk_secretsmanager::Client::new(&ops::aws_config().await);
+                let name = self.secret_name.as_deref().unwrap_or_default();
+                let (plan, exists) = ops::plan_secret_export(&client, name, &vars).await?;
+                let changes = ops::Changes::from([(name.to_string(), plan.diff.clone())]);
+                let kms_key_id = self.kms_key_id.as_deref();
+                let export =
+                    ops::export_secret(stdout, &client, name, &plan, exists, &vars, kms_key_id);
+                super::apply_changes(&changes, dry_run, export).await
+            }
+            #[cfg(feature = "vault")]
+            Destination::Vault => {
+                use ops::KvStore;
+
+                let client = ops::VaultClient::from_env(&self.mount)?;
+                let plan = ops::plan_vault_export(&client, path, &vars, self.per_key).await?;
+                let changes = ops::Changes::from([(client.display(path), plan.plan.diff.clone())]);
+                let export = ops::export_vault(stdout, &client, path, &plan, &vars, self.per_key);
+                super::apply_changes(&changes, dry_run, export).await
+            }
+            #[cfg(feature = "azure")]
+            Destination::AzureKeyvault => {
+                use ops::KeyVaultStore;
 
-        Ok(())
+                let name = self.vault_name.as_deref().unwrap_or_default();
+                let client = ops::KeyVaultClient::from_env(name).await?;
+                let plan = ops::plan_azure_export(&client, &vars).await?;
+                let changes = ops::Changes::from([(client.display(), plan.plan.diff.clone())]);
+                let export = ops::export_azure(stdout, &client, plan);
+                super::apply_changes(&changes, dry_run, export).await
+            }
+        }
     }
 }
@@ -0,0 +1,40 @@
+use std::io::Result;
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// Drop the history of an environment, keeping its current variables
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environment to flatten
+    env: String,
+
+    /// Only drop the versions created before this unix timestamp, the
+    /// current value of every variable is kept
+    #[arg(long, value_name = "SECS")]
+    before: Option<i64>,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb, yes: bool) -> Result<()> {
+        db.check_env_exists(&self.env).await?;
+
+        let message = match self.before {
+            Some(ts) => format!(
+                "this will permanently remove the versions of '{}' created before {}",
+                self.env, ts
+            ),
+            None => format!(
+                "this will permanently remove the history of '{}', keeping its current variables",
+                self.env
+            ),
+        };
+        super::confirm(yes, &message, &self.env)?;
+
+        match self.before {
+            Some(ts) => ops::purge(&mut anstream::stdout(), db, &self.env, ts).await,
+            None => ops::flatten(db, &self.env).await,
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::io::{self, Result};
+
+use clap::Parser;
+
+use crate::store::AnyStore;
+use crate::{db::EnvelopeDb, err, ops, std_err};
+
+/// Show every version of the variables of an environment
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environment whose history you wish to see
+    #[arg(required_unless_present_any = ["pick", "all"])]
+    env: Option<String>,
+
+    /// Only show the history of this variable
+    key: Option<String>,
+
+    /// Only show the versions created at or after this unix timestamp
+    #[arg(long, value_name = "SECS")]
+    since: Option<i64>,
+
+    /// Only show the versions created before this unix timestamp
+    #[arg(long, value_name = "SECS")]
+    until: Option<i64>,
+
+    /// Pick the environment with a fuzzy finder when it is not given, or the
+    /// variable when only the environment is given
+    #[arg(long)]
+    pick: bool,
+
+    /// Show every row of the database, all the versions of every variable of
+    /// every environment, for troubleshooting
+    #[arg(long, conflicts_with_all = ["env", "since", "until", "pick"])]
+    all: bool,
+}
+
+impl Cmd {
+    /// shows the history of an environment of `store`
+    pub async fn run_store(&self, store: &AnyStore, output: ops::Output) -> Result<()> {
+        let Some(env) = &self.env else {
+            return err!("--pick and --all need the sqlite database");
+        };
+        if self.pick {
+            return err!("--pick needs the sqlite database");
+        }
+
+        ops::store_history(
+            &mut io::stdout(),
+            store,
+            env,
+            self.key.as_deref(),
+            self.since,
+            self.until,
+            output,
+        )
+        .await
+    }
+
+    pub async fn run(&self, db: &EnvelopeDb, output: ops::Output) -> Result<()> {
+        if self.all {
+            return ops::dump(&mut io::stdout(), db, output).await;
+        }
+
+        let key = match &self.env {
+            Some(env) => super::pick_key(db, env, self.key.as_deref(), self.pick).await?,
+            None => self.key.clone(),
+        };
+        let env = super::pick_env(db, self.env.as_deref(), self.pick)
+            .await?
+            .ok_or(std_err!("no environment to show the history of"))?;
+
+        ops::history(
+            &mut io::stdout(),
+            db,
+            &env,
+            key.as_deref(),
+            self.since,
+            self.until,
+            output,
+        )
+        .await
+    }
+}
@@ -0,0 +1,25 @@
+use std::io::{self, Result, Write};
+
+use clap::Parser;
+
+use crate::ops;
+
+/// Print the snippet that loads the active environment on every prompt
+///
+/// Add `eval "$(envelope hook bash)"` to your shell configuration, the
+/// environment set with `envelope activate` is loaded when entering the
+/// directory of the database and unloaded when leaving it.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Shell to generate the hook for.
+    #[arg(value_enum)]
+    shell: ops::Shell,
+}
+
+impl Cmd {
+    pub fn run(&self) -> Result<()> {
+        let exe = std::env::current_exe()?;
+        let snippet = ops::hook_snippet(self.shell, &exe.to_string_lossy())?;
+        io::stdout().write_all(snippet.as_bytes())
+    }
+}
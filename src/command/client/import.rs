@@ -1,36 +1,322 @@
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs;
 
 use std::io;
-use std::io::{BufRead, BufReader, Result};
+use std::io::{Read, Result};
 
 use clap::Parser;
 
 use crate::db::EnvelopeDb;
+use crate::dotenv::from_dotenv;
+use crate::format::{from_csv, ImportMode, DEFAULT_IMPORT_SUFFIX};
 use crate::ops;
+use crate::secret::MaskSecrets;
 
 /// Import environment variables
 #[derive(Parser)]
 pub struct Cmd {
     /// Environment that you wish to assign to the imported environment variables.
-    env: String,
+    #[arg(required_unless_present = "csv")]
+    env: Option<String>,
 
     /// Path of the file from which you want to import environment variables.
     /// Defaults to stdin if not provided.
     path: Option<String>,
+
+    /// Import env,key,value records from a CSV file, `-` reads stdin.
+    /// Every variable is added to the environment of its record.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["env", "path"])]
+    csv: Option<String>,
+
+    /// Keep the current value of the keys already set to another value and
+    /// import the new one under the key followed by SUFFIX
+    #[arg(
+        long,
+        value_name = "SUFFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = DEFAULT_IMPORT_SUFFIX,
+        conflicts_with = "csv"
+    )]
+    suffix_on_conflict: Option<String>,
+
+    /// Import the values even if they break the `[schema]` of envelope.toml.
+    #[arg(long)]
+    no_verify: bool,
+
+    /// Do not wait for the imported variables to reach the disk. Faster on
+    /// slow disks, but a power loss during the import can corrupt the
+    /// database.
+    #[arg(long)]
+    fast_import: bool,
+
+    /// Import the variables from an external store instead of a dotenv file
+    #[arg(
+        long,
+        value_name = "STORE",
+        requires = "env",
+        conflicts_with_all = ["csv", "suffix_on_conflict"]
+    )]
+    from: Option<Source>,
+
+    /// Path of the variables in the store. For SSM the path of the
+    /// parameters, such as /myapp/prod/, the last segment of the name of
+    /// each parameter is its key. For Vault the path of the secret under
+    /// its mount, such as myapp/prod, each field is a variable.
+    #[cfg(any(feature = "aws", feature = "vault"))]
+    #[arg(
+        long = "path",
+        value_name = "STORE_PATH",
+        requires = "from",
+        required_if_eq_any([("from", "aws-ssm"), ("from", "vault")]),
+        conflicts_with = "path"
+    )]
+    remote_path: Option<String>,
+
+    /// Also import the parameters nested deeper under the SSM path
+    #[cfg(feature = "aws")]
+    #[arg(long, requires = "from")]
+    recursive: bool,
+
+    /// Decrypt the SecureString parameters, they are imported encrypted
+    /// otherwise
+    #[cfg(feature = "aws")]
+    #[arg(long, requires = "from")]
+    with_decryption: bool,
+
+    /// How the name of a parameter becomes a key
+    #[cfg(feature = "aws")]
+    #[arg(long, value_enum, default_value_t, requires = "from")]
+    key_transform: ops::KeyTransform,
+
+    /// Mount of the Vault KV v2 secrets engine
+    #[cfg(feature = "vault")]
+    #[arg(long, default_value = ops::DEFAULT_VAULT_MOUNT, requires = "from")]
+    mount: String,
+
+    /// Read one secret per key under the Vault path, holding the value in
+    /// its `value` field
+    #[cfg(feature = "vault")]
+    #[arg(long, requires = "from")]
+    per_key: bool,
+
+    /// Doppler project to download the secrets of, they are read from the
+    /// file or stdin otherwise
+    #[cfg(feature = "doppler")]
+    #[arg(long, requires_all = ["from", "config"], conflicts_with = "path")]
+    project: Option<String>,
+
+    /// Config of the Doppler project, such as prd
+    #[cfg(feature = "doppler")]
+    #[arg(long, requires = "project")]
+    config: Option<String>,
+
+    /// Name of the Azure Key Vault, such as myapp-kv, or its URL in another
+    /// cloud
+    #[cfg(feature = "azure")]
+    #[arg(
+        long,
+        requires = "from",
+        required_if_eq("from", "azure-keyvault"),
+        conflicts_with = "path"
+    )]
+    vault_name: Option<String>,
+
+    /// 1Password vault holding the item to import
+    #[arg(
+        long,
+        value_name = "VAULT",
+        requires = "from",
+        required_if_eq("from", "op"),
+        conflicts_with = "path"
+    )]
+    vault: Option<String>,
+
+    /// 1Password item to import, each field is a variable named after its
+    /// label
+    #[arg(long, requires = "vault", required_if_eq("from", "op"))]
+    item: Option<String>,
+}
+
+/// External stores the variables can be imported from
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Source {
+    /// Doppler secrets, the JSON written by `doppler secrets download
+    /// --format json` read from the file or stdin, or downloaded with
+    /// --project and --config
+    Doppler,
+    /// The fields of a 1Password item, read with the `op` CLI from --vault
+    /// and --item
+    Op,
+    /// AWS SSM Parameter Store
+    #[cfg(feature = "aws")]
+    AwsSsm,
+    /// HashiCorp Vault KV v2 secrets engine, configured by VAULT_ADDR,
+    /// VAULT_TOKEN and VAULT_NAMESPACE
+    #[cfg(feature = "vault")]
+    Vault,
+    /// Azure Key Vault, authenticated by AZURE_TENANT_ID, AZURE_CLIENT_ID
+    /// and AZURE_CLIENT_SECRET or by the az CLI. The secret names become
+    /// keys with - replaced by _.
+    #[cfg(feature = "azure")]
+    AzureKeyvault,
 }
 
 impl Cmd {
-    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
-        let reader: Box<dyn BufRead> = match &self.path {
-            None => Box::new(BufReader::new(io::stdin())),
-            Some(path) => {
-                let f = File::open(path)?;
-                Box::new(BufReader::new(f))
+    pub async fn run(&self, db: &mut EnvelopeDb, dry_run: bool) -> Result<()> {
+        db.set_fast_import(self.fast_import);
+        let db = &*db;
+
+        if let Some(csv) = &self.csv {
+            let contents = match csv.as_str() {
+                "-" => read(None)?,
+                path => read(Some(path))?,
+            };
+
+            let mut vars_by_env: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+            for (env, key, value) in from_csv(&contents)? {
+                ops::check_key(&key)?;
+                vars_by_env.entry(env).or_default().push((key, value));
+            }
+
+            let mut changes = ops::Changes::new();
+            for (env, vars) in vars_by_env {
+                let diff = ops::plan_set(db, &env, vars).await?;
+                changes.insert(env, diff);
             }
+            super::verify(&changes, self.no_verify)?;
+            return super::apply_changes(
+                &changes,
+                dry_run,
+                ops::import_csv(&mut io::stdout(), db, &contents),
+            )
+            .await;
+        }
+
+        // clap requires env unless --csv is given
+        let env = self.env.as_deref().unwrap_or_default();
+
+        if let Some(Source::Doppler) = self.from {
+            #[cfg(feature = "doppler")]
+            let json = match (&self.project, &self.config) {
+                (Some(project), Some(config)) => ops::download_doppler(project, config).await?,
+                _ => read(self.path.as_deref())?,
+            };
+            #[cfg(not(feature = "doppler"))]
+            let json = read(self.path.as_deref())?;
+
+            let secrets = ops::doppler_vars(&json)?;
+            let stdout = &mut io::stdout();
+            let import = ops::import_doppler(stdout, db, env, &secrets);
+            return self
+                .import_remote(db, env, &secrets.vars, dry_run, import)
+                .await;
+        }
+
+        if let (Some(Source::Op), Some(vault), Some(item)) = (self.from, &self.vault, &self.item) {
+            let fields = ops::op_item_fields(&ops::OpBinary::default(), vault, item).await?;
+            let vars: Vec<(String, String)> = fields
+                .iter()
+                .map(|f| (f.key.clone(), f.value.clone()))
+                .collect();
+            let diff = ops::plan_set(db, env, vars).await?;
+            let mut changes = ops::Changes::from([(env.to_string(), diff)]);
+            super::verify(&changes, self.no_verify)?;
+            // the concealed values are not printed, envelope only knows
+            // secrets from their key
+            for diff in changes.values_mut() {
+                ops::mask_concealed(diff, &fields);
+                diff.mask_secrets();
+            }
+
+            let source = format!("{}/{}", vault, item);
+            let stdout = &mut io::stdout();
+            let import = ops::import_op(stdout, db, env, &source, &fields);
+            return super::apply_changes(&changes, dry_run, import).await;
+        }
+
+        #[cfg(feature = "aws")]
+        if let (Some(Source::AwsSsm), Some(path)) = (self.from, &self.remote_path) {
+            let query = ops::SsmQuery {
+                path: path.clone(),
+                recursive: self.recursive,
+                with_decryption: self.with_decryption,
+            };
+            let client = aws_sdk_ssm::Client::new(&ops::aws_config().await);
+            let vars = ops::ssm_vars(&client, &query, self.key_transform).await?;
+            let stdout = &mut io::stdout();
+            let import = ops::import_ssm(stdout, db, env, path, &vars);
+            return self.import_remote(db, env, &vars, dry_run, import).await;
+        }
+
+        #[cfg(feature = "vault")]
+        if let (Some(Source::Vault), Some(path)) = (self.from, &self.remote_path) {
+            let client = ops::VaultClient::from_env(&self.mount)?;
+            let vars = ops::vault_vars(&client, path, self.per_key).await?;
+            let stdout = &mut io::stdout();
+            let import = ops::import_vault(stdout, db, env, &client, path, &vars);
+            return self.import_remote(db, env, &vars, dry_run, import).await;
+        }
+
+        #[cfg(feature = "azure")]
+        if let (Some(Source::AzureKeyvault), Some(name)) = (self.from, &self.vault_name) {
+            let client = ops::KeyVaultClient::from_env(name).await?;
+            let secrets = ops::azure_vars(&client).await?;
+            let stdout = &mut io::stdout();
+            let import = ops::import_azure(stdout, db, env, &client, &secrets);
+            return self
+                .import_remote(db, env, &secrets.vars, dry_run, import)
+                .await;
+        }
+
+        let contents = read(self.path.as_deref())?;
+        let mode = match &self.suffix_on_conflict {
+            Some(suffix) => ImportMode::SuffixOnConflict(suffix.clone()),
+            None => ImportMode::Overwrite,
         };
 
-        ops::import(reader, &mut io::stdout(), db, &self.env).await?;
+        let current = ops::current_vars(db, env).await?;
+        let vars = from_dotenv(&contents)
+            .into_iter()
+            .map(|e| (mode.key_for(&current, &e.key, &e.value), e.value));
+        let changes = ops::Changes::from([(env.to_string(), ops::plan_set(db, env, vars).await?)]);
+        super::verify(&changes, self.no_verify)?;
+
+        super::apply_changes(
+            &changes,
+            dry_run,
+            ops::import(contents.as_bytes(), &mut io::stdout(), db, env, &mode),
+        )
+        .await
+    }
+
+    /// Checks and prints the changes the `vars` read from a store make to
+    /// `env`, then runs `import` unless it is a dry run
+    async fn import_remote(
+        &self,
+        db: &EnvelopeDb,
+        env: &str,
+        vars: &[(String, String)],
+        dry_run: bool,
+        import: impl std::future::Future<Output = Result<()>>,
+    ) -> Result<()> {
+        let changes = ops::Changes::from([(
+            env.to_string(),
+            ops::plan_set(db, env, vars.to_vec()).await?,
+        )]);
+        super::verify(&changes, self.no_verify)?;
+        super::apply_changes(&changes, dry_run, import).await
+    }
+}
 
-        Ok(())
+/// Reads the file at `path`, or stdin if not provided
+fn read(path: Option<&str>) -> Result<String> {
+    match path {
+        None => {
+            let mut contents = String::new();
+            io::stdin().read_to_string(&mut contents)?;
+            Ok(contents)
+        }
+        Some(path) => fs::read_to_string(path),
     }
 }
@@ -1,9 +1,9 @@
 use clap::Parser;
-use std::io;
-use std::io::Result;
+use std::io::{self, Result};
 
 use crate::db::{self, EnvelopeDb};
-use crate::ops;
+use crate::store::AnyStore;
+use crate::{err, ops, table};
 
 /// List saved environments and/or their variables
 #[derive(Parser)]
@@ -15,27 +15,93 @@ pub struct Cmd {
     #[arg(long, short)]
     pretty_print: bool,
 
-    #[arg(long, short)]
+    /// Kept for compatibility, long values are always fitted to the width of
+    /// the terminal when pretty printing.
+    #[arg(long, short, hide = true)]
     truncate: bool,
+
+    /// Width the pretty printed table must fit in, defaults to the width of
+    /// the terminal.
+    #[arg(long, requires = "pretty_print")]
+    width: Option<usize>,
+
+    /// Wrap long values on multiple lines instead of truncating them.
+    #[arg(long, requires = "pretty_print")]
+    wrap: bool,
+
+    /// Pretty print the type of each variable, how many versions it has and
+    /// when it was last modified, in unix seconds.
+    #[arg(long, visible_alias = "long", conflicts_with = "resolve")]
+    detailed: bool,
+
+    /// Sort the variables in descending order.
+    #[arg(long)]
+    desc: bool,
+
+    /// Replace `@file:PATH` values with the contents of PATH
+    #[arg(long, short, conflicts_with = "pretty_print")]
+    resolve: bool,
+
+    /// Show the size in bytes of each value instead of the value itself
+    #[arg(long, conflicts_with_all = ["pretty_print", "detailed", "resolve"])]
+    sizes: bool,
+
+    /// Pick the environment to list with a fuzzy finder when it is not given
+    #[arg(long)]
+    pick: bool,
 }
 
 impl Cmd {
-    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
-        match &self.env {
-            None => ops::list_envs(&mut io::stdout(), db).await?,
-            Some(env) => {
-                if !self.pretty_print {
-                    ops::list_raw(&mut io::stdout(), db, env).await?;
-                } else {
-                    let truncate = match self.truncate {
-                        true => db::Truncate::Range(0, 60),
-                        false => db::Truncate::None,
-                    };
-                    ops::list(db, env, truncate).await?;
-                }
-            }
+    /// lists the environments or the variables of one in `store`, as
+    /// `KEY=value` lines only
+    pub async fn run_store(&self, store: &AnyStore, output: ops::Output) -> Result<()> {
+        if self.pretty_print || self.detailed || self.sizes || self.pick {
+            return err!("--pretty-print, --detailed, --sizes and --pick need the sqlite database");
+        }
+        let Some(env) = &self.env else {
+            return ops::list_envs(&mut io::stdout(), store, output).await;
+        };
+
+        let order = match self.desc {
+            true => db::SortOrder::Desc,
+            false => db::SortOrder::Asc,
+        };
+        ops::list_raw(&mut io::stdout(), store, env, order, self.resolve, output).await
+    }
+
+    pub async fn run(&self, db: &EnvelopeDb, output: ops::Output) -> Result<()> {
+        let env = super::pick_env(db, self.env.as_deref(), self.pick).await?;
+        let Some(env) = &env else {
+            return ops::list_envs(&mut io::stdout(), db, output).await;
+        };
+
+        let order = match self.desc {
+            true => db::SortOrder::Desc,
+            false => db::SortOrder::Asc,
+        };
+
+        if self.sizes {
+            return ops::list_sizes(&mut io::stdout(), db, env, order, output).await;
+        }
+
+        if !(self.pretty_print || self.detailed) || output == ops::Output::Json {
+            return ops::list_raw(&mut io::stdout(), db, env, order, self.resolve, output).await;
         }
 
-        Ok(())
+        let width = self.width.or_else(table::terminal_width);
+        let overflow = match self.wrap {
+            true => table::Overflow::Wrap,
+            false => table::Overflow::Truncate,
+        };
+        ops::list(
+            &mut io::stdout(),
+            db,
+            env,
+            order,
+            width,
+            overflow,
+            self.detailed,
+        )
+        .await
     }
 }
@@ -0,0 +1,31 @@
+use std::io::Result;
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// Lock an environment, changing it will require --force
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environment to lock
+    env: String,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
+        ops::lock(db, &self.env).await
+    }
+}
+
+/// Unlock a locked environment
+#[derive(Parser)]
+pub struct UnlockCmd {
+    /// Environment to unlock
+    env: String,
+}
+
+impl UnlockCmd {
+    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
+        ops::unlock(db, &self.env).await
+    }
+}
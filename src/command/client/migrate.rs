@@ -0,0 +1,42 @@
+use std::io::Result;
+
+use clap::Parser;
+
+use crate::db::{self, ConnectOptions, EnvelopeDb};
+use crate::error::EnvelopeError;
+use crate::ops;
+
+/// Apply the migrations the database of the current directory lacks
+///
+/// Envelope applies them when it opens a database whose schema is older
+/// than its own, this command shows which ones. It also checks that the
+/// migrations already applied are the ones envelope knows.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Only show the schema version of the database and of envelope, and the
+    /// migrations to apply
+    #[arg(long)]
+    status: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, output: ops::Output) -> Result<()> {
+        if !db::is_present() {
+            return Err(EnvelopeError::NotInitialized.into());
+        }
+
+        let path = db::db_path()?;
+        let mut stdout = anstream::stdout();
+        if self.status {
+            let db = EnvelopeDb::open_read_only(&path).await?;
+            return ops::migration_status(&mut stdout, &db, output).await;
+        }
+
+        let options = ConnectOptions {
+            run_migrations: false,
+            ..Default::default()
+        };
+        let db = EnvelopeDb::open_with(&path, &options).await?;
+        ops::migrate(&mut stdout, &db).await
+    }
+}
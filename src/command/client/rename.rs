@@ -0,0 +1,49 @@
+use std::io::Result;
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// Rename a variable in every environment where it is set
+///
+/// The variables are renamed at once, and not at all if the new key is
+/// already set in one of the environments. The history of the variable stays
+/// under its old key.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Current key of the variable.
+    old_key: String,
+
+    /// New key of the variable.
+    new_key: String,
+
+    /// Only rename the variable in this environment.
+    #[arg(long, short)]
+    env: Option<String>,
+
+    /// Rename the variable even if the new key breaks the `[schema]` of
+    /// envelope.toml.
+    #[arg(long)]
+    no_verify: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb, dry_run: bool) -> Result<()> {
+        ops::check_key(&self.new_key)?;
+        let changes =
+            ops::plan_rename(db, self.env.as_deref(), &self.old_key, &self.new_key).await?;
+        super::verify(&changes, self.no_verify)?;
+        if dry_run {
+            return super::print_dry_run(&changes);
+        }
+
+        ops::rename(
+            &mut anstream::stdout(),
+            db,
+            self.env.as_deref(),
+            &self.old_key,
+            &self.new_key,
+        )
+        .await
+    }
+}
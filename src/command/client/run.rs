@@ -0,0 +1,153 @@
+use std::io::{self, Result, Write};
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use clap::{builder::BoolishValueParser, Parser};
+
+use crate::db::{self, EnvelopeDb};
+use crate::{err, ops, std_err, subproc};
+
+/// Run a command with the environment variables loaded
+///
+/// On unix envelope is replaced by the command, so signals and the exit code
+/// reach it directly.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environment that you wish to load.
+    env: Option<String>,
+
+    /// Additional environments layered on top of the previous ones, later
+    /// environments take precedence.
+    #[arg(short = 'e', long = "env")]
+    envs: Vec<String>,
+
+    /// Set a variable for this run only, it takes precedence over the stored
+    /// ones and is never saved.
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_val)]
+    set: Vec<(String, String)>,
+
+    /// Remove a variable from the environment of the command.
+    #[arg(long = "unset", value_name = "KEY")]
+    unset: Vec<String>,
+
+    /// Start from an empty environment instead of inheriting the current one,
+    /// only PATH, HOME, TERM and the variables passed to --keep are inherited.
+    #[arg(long)]
+    pristine: bool,
+
+    /// Inherited variable to keep in a pristine environment.
+    #[arg(long, value_name = "VAR", requires = "pristine")]
+    keep: Vec<String>,
+
+    /// Restart the command every time its variables change in the database.
+    #[arg(long)]
+    watch: bool,
+
+    /// Seconds between two checks for changes in watch mode.
+    #[arg(long, value_name = "SECS", default_value_t = 2, requires = "watch")]
+    interval: u64,
+
+    /// Load the variables of ./.env instead when there is no envelope
+    /// database in the current directory.
+    #[arg(long, env = "ENVELOPE_FALLBACK_DOTENV", value_parser = BoolishValueParser::new())]
+    fallback_dotenv: bool,
+
+    /// Load the variables of ./.env even if there is an envelope database,
+    /// which is ignored.
+    #[arg(long, conflicts_with_all = ["env", "envs", "watch"])]
+    dotenv_only: bool,
+
+    /// Command to run, followed by its arguments.
+    #[arg(last = true, required = true)]
+    command: Vec<String>,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb, verbose: bool) -> Result<()> {
+        let envs: Vec<String> = self.env.iter().chain(&self.envs).cloned().collect();
+        if envs.is_empty() {
+            return err!("at least one environment is required");
+        }
+
+        let opts = self.options(envs, verbose);
+        let status = match self.watch {
+            true => {
+                let interval = Duration::from_secs(self.interval);
+                ops::watch(&mut io::stderr(), db, &opts, &self.command, interval).await?
+            }
+            false => {
+                let child = ops::prepare_run(&mut io::stderr(), db, &opts, &self.command).await?;
+                child
+                    .exec()
+                    .map_err(|e| std_err!("error running {}: {}", child.cmd(), e))?
+            }
+        };
+
+        exit_on_failure(status)
+    }
+
+    /// Whether the variables are read from the dotenv file, the database
+    /// always takes precedence unless --dotenv-only is given
+    pub fn uses_dotenv(&self) -> bool {
+        self.dotenv_only || (self.fallback_dotenv && !db::is_present())
+    }
+
+    /// Runs the command with the variables of the dotenv file, no database
+    /// is needed
+    pub async fn run_dotenv(&self, verbose: bool) -> Result<()> {
+        let path = Path::new(ops::DOTENV_FILE);
+        if !self.dotenv_only {
+            if self.watch {
+                return err!("--watch requires an envelope database");
+            }
+            if !path.is_file() {
+                return err!(
+                    "envelope is not initialized in current directory and no {} file was found",
+                    ops::DOTENV_FILE
+                );
+            }
+            writeln!(
+                io::stderr(),
+                "envelope: no database found, loading variables from {}",
+                ops::DOTENV_FILE
+            )?;
+        }
+
+        let opts = self.options(Vec::new(), verbose);
+        let child = ops::prepare_run_dotenv(&mut io::stderr(), path, &opts, &self.command)?;
+        let status = child
+            .exec()
+            .map_err(|e| std_err!("error running {}: {}", child.cmd(), e))?;
+
+        exit_on_failure(status)
+    }
+
+    fn options(&self, envs: Vec<String>, verbose: bool) -> ops::RunOptions {
+        ops::RunOptions {
+            envs,
+            overrides: ops::Overrides {
+                set: self.set.clone(),
+                unset: self.unset.clone(),
+            },
+            pristine: self.pristine,
+            keep: self.keep.clone(),
+            verbose,
+        }
+    }
+}
+
+fn exit_on_failure(status: ExitStatus) -> Result<()> {
+    if !status.success() {
+        std::process::exit(subproc::exit_code(status));
+    }
+
+    Ok(())
+}
+
+fn parse_key_val(s: &str) -> std::result::Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.is_empty() => Ok((k.into(), v.into())),
+        _ => Err(format!("invalid KEY=VALUE: `{}`", s)),
+    }
+}
@@ -0,0 +1,39 @@
+use std::io::Result;
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// Compare the variables read by the sources with those of an environment
+///
+/// Reports the variables referenced by the sources of a project that the
+/// environment does not set, and the ones it sets that are never referenced.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Directory to scan, files ignored by git are skipped.
+    #[arg(default_value = ".")]
+    path: PathBuf,
+
+    /// Environment to compare the references with.
+    #[arg(long, short)]
+    env: String,
+
+    /// Fail if a referenced variable is not set in the environment.
+    #[arg(long)]
+    strict: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb, output: ops::Output) -> Result<()> {
+        ops::print_scan(
+            &mut anstream::stdout(),
+            db,
+            &self.env,
+            &self.path,
+            self.strict,
+            output,
+        )
+        .await
+    }
+}
@@ -0,0 +1,39 @@
+use std::io::Result;
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops, subproc};
+
+/// Spawn an interactive shell with the environment variables loaded
+#[derive(Parser)]
+pub struct Cmd {
+    /// Environment that you wish to load in the shell.
+    env: String,
+
+    /// Start from an empty environment instead of inheriting the current one,
+    /// only PATH, HOME, TERM and the variables passed to --keep are inherited.
+    #[arg(long)]
+    pristine: bool,
+
+    /// Inherited variable to keep in a pristine environment.
+    #[arg(long, value_name = "VAR", requires = "pristine")]
+    keep: Vec<String>,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb) -> Result<()> {
+        let status = ops::shell(
+            &mut anstream::stderr(),
+            db,
+            &self.env,
+            self.pristine,
+            &self.keep,
+        )
+        .await?;
+        if !status.success() {
+            std::process::exit(subproc::exit_code(status));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use std::io::Result;
+
+use clap::Parser;
+
+use crate::{db::EnvelopeDb, ops};
+
+/// List, extend or remove templates, the keys environments must set
+///
+/// Use `envelope check ENV --template NAME` to find the keys of a template
+/// that an environment does not set.
+#[derive(Parser)]
+pub struct Cmd {
+    /// Template to show or change, every template is listed if not provided.
+    name: Option<String>,
+
+    /// Keys to add to the template.
+    #[arg(requires = "name")]
+    keys: Vec<String>,
+
+    /// Remove the keys from the template, or the whole template if no key is
+    /// given.
+    #[arg(long, requires = "name")]
+    remove: bool,
+}
+
+impl Cmd {
+    pub async fn run(&self, db: &EnvelopeDb, output: ops::Output) -> Result<()> {
+        let Some(name) = &self.name else {
+            return ops::list_templates(&mut anstream::stdout(), db, None, output).await;
+        };
+
+        if self.remove {
+            return ops::remove_template(db, name, &self.keys).await;
+        }
+        if self.keys.is_empty() {
+            return ops::list_templates(&mut anstream::stdout(), db, Some(name), output).await;
+        }
+
+        for key in &self.keys {
+            ops::check_key(key)?;
+        }
+        Ok(db.add_template_keys(name, &self.keys).await?)
+    }
+}
@@ -1,3 +1,3 @@
 mod client;
 
-pub use client::EnvelopeCmd;
+pub use client::{CompleteCmd, EnvelopeCmd, GlobalArgs};
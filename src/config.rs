@@ -0,0 +1,149 @@
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::error::EnvelopeError;
+use crate::std_err;
+use crate::validate::Schema;
+
+/// Project configuration, next to the `.envelope` database
+pub const CONFIG_FILE: &str = "envelope.toml";
+
+/// Settings of a project read from [`CONFIG_FILE`], every section is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// keys required by each environment and rules their values follow
+    #[serde(default)]
+    pub schema: Schema,
+    /// keys `envelope check --unused` never reports
+    #[serde(default)]
+    pub unused: Unused,
+}
+
+/// The `[unused]` section of the config
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Unused {
+    /// globs of the keys that are used even though nothing in the project
+    /// reads them, such as the ones only read by the platform at runtime.
+    /// `*` matches any sequence of characters and `?` a single one.
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl Unused {
+    /// Whether `key` matches one of the globs of [`Unused::allow`], case
+    /// insensitively like keys are stored
+    pub fn allows(&self, key: &str) -> bool {
+        let key: Vec<char> = key.to_uppercase().chars().collect();
+        self.allow.iter().any(|glob| {
+            let glob: Vec<char> = glob.to_uppercase().chars().collect();
+            glob_matches(&glob, &key)
+        })
+    }
+}
+
+fn glob_matches(glob: &[char], key: &[char]) -> bool {
+    match (glob.split_first(), key.split_first()) {
+        (None, _) => key.is_empty(),
+        (Some(('*', rest)), _) => {
+            glob_matches(rest, key) || (!key.is_empty() && glob_matches(glob, &key[1..]))
+        }
+        (Some(_), None) => false,
+        (Some(('?', rest)), Some((_, key_rest))) => glob_matches(rest, key_rest),
+        (Some((g, rest)), Some((k, key_rest))) => g == k && glob_matches(rest, key_rest),
+    }
+}
+
+impl Config {
+    /// Reads the [`CONFIG_FILE`] of `dir`, a missing file is an empty config
+    pub fn load(dir: &Path) -> Result<Self> {
+        match fs::read_to_string(dir.join(CONFIG_FILE)) {
+            Ok(contents) => Config::parse(&contents),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(std_err!("cannot read {}: {}", CONFIG_FILE, e)),
+        }
+    }
+
+    /// Parses a config, keys are uppercased like they are on insert. Fails
+    /// with [`EnvelopeError::Parse`] pointing at the offending line.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut config: Config = toml::from_str(contents).map_err(|e| {
+            let start = e.span().map_or(0, |span| span.start);
+            EnvelopeError::Parse {
+                file: CONFIG_FILE.to_string(),
+                line: contents[..start].matches('\n').count() + 1,
+                message: e.message().to_string(),
+            }
+        })?;
+        config.schema.normalize();
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::validate::Rule;
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r#"
+            [schema.required]
+            "*" = ["database_url"]
+            prod = ["SENTRY_DSN"]
+
+            [schema.rules]
+            database_url = "url"
+            PORT = "integer"
+            API_KEY = "non-empty"
+            LOG_LEVEL = { one-of = ["debug", "info"] }
+            "#,
+        )
+        .unwrap();
+
+        let schema = config.schema;
+        assert_eq!(vec!["DATABASE_URL"], schema.required["*"]);
+        assert_eq!(Rule::Url, schema.rules["DATABASE_URL"]);
+        assert_eq!(Rule::Integer, schema.rules["PORT"]);
+        assert_eq!(Rule::NonEmpty, schema.rules["API_KEY"]);
+        assert_eq!(
+            Rule::OneOf(vec!["debug".into(), "info".into()]),
+            schema.rules["LOG_LEVEL"]
+        );
+    }
+
+    #[test]
+    fn test_unused_allows() {
+        let config = Config::parse("[unused]\nallow = [\"port\", \"AWS_*\", \"K?Y\"]").unwrap();
+        let unused = config.unused;
+        assert!(unused.allows("PORT"));
+        assert!(unused.allows("aws_region"));
+        assert!(unused.allows("AWS_"));
+        assert!(unused.allows("KEY"));
+        assert!(!unused.allows("PORTS"));
+        assert!(!unused.allows("MY_AWS_KEY"));
+        assert!(!unused.allows("KY"));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Config::parse("").unwrap().schema.is_empty());
+
+        let err = Config::parse("[schema.rules]\nPORT = \"number\"").unwrap_err();
+        assert!(err.to_string().starts_with("envelope.toml:2: "));
+        assert_eq!(8, EnvelopeError::from_io(&err).unwrap().exit_code());
+        assert!(Config::parse("[schemas]").is_err());
+    }
+
+    #[test]
+    fn test_load_missing() {
+        let dir = std::env::temp_dir().join(format!("envelope-config-{}", std::process::id()));
+        assert!(Config::load(&dir).unwrap().schema.is_empty());
+    }
+}
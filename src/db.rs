@@ -1,375 +1,7379 @@
-use sea_query::{Alias, Asterisk, Expr, Func, Order, Query, SqliteQueryBuilder};
-use sea_query_binder::SqlxBinder;
-use sqlx::SqlitePool;
-use std::{env, io};
+use async_stream::try_stream;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, TryStreamExt};
+use regex::Regex;
+use sea_query::{
+    any, Alias, Asterisk, Condition, Expr, Func, Iden, JoinType, LikeExpr, OnConflict, Order,
+    Query, SelectStatement, SimpleExpr, SqliteQueryBuilder, UnionType,
+};
+use sea_query_binder::{SqlxBinder, SqlxValues};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::error::ErrorKind;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqliteRow};
+use sqlx::{Connection, Executor, Row, SqlitePool};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, MutexGuard};
+use tracing::{debug, info, Span};
 
-use crate::std_err;
+use crate::dotenv::{from_dotenv, DotenvLine, DotenvParser};
+use crate::error::EnvelopeError;
+use crate::format::{from_canonical, to_canonical, ImportMode};
+use crate::reference::{resolve_value, SecretResolver, OP_PREFIX};
+use crate::store::Store;
+use crate::validate::ValueType;
 
-pub(crate) type EnvelopeResult<T> = Result<T, Box<dyn std::error::Error>>;
+/// How long a write waits for the other writers by default
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the reads of a read-only database wait for a lock
+const READ_ONLY_BUSY_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Number of variables written by each statement of
+/// [`EnvelopeDb::insert_many`]
+const INSERT_BATCH: usize = 500;
+
+/// Counter of the calls to the [`EnvelopeDb`] methods that write variables,
+/// labelled with the `operation`, with the `metrics` feature
+pub const INSERTS_COUNTER: &str = "envelope_inserts_total";
+
+/// Counter of the calls to the [`EnvelopeDb`] methods that delete
+/// variables or environments, see [`INSERTS_COUNTER`]
+pub const DELETES_COUNTER: &str = "envelope_deletes_total";
+
+/// Counter of the calls to the [`EnvelopeDb`] methods that read variables,
+/// environments or history, see [`INSERTS_COUNTER`]
+pub const READS_COUNTER: &str = "envelope_reads_total";
+
+/// increments `counter` of the `metrics` crate for `operation`, expands to
+/// nothing without the `metrics` feature
+macro_rules! count {
+    ($counter:ident, $operation:literal) => {
+        #[cfg(feature = "metrics")]
+        metrics::counter!($counter, "operation" => $operation).increment(1);
+    };
+}
+
+/// Result of the operations of the library
+pub type EnvelopeResult<T> = Result<T, EnvelopeError>;
 
 #[derive(Debug, sea_query::Iden)]
-pub enum Environments {
+enum Environments {
     Table,
     Env,
     Key,
     Value,
     CreatedAt,
+    ExpiresAt,
+}
+
+/// View of the latest version of each variable of the environments table,
+/// which has the same columns
+#[derive(Debug, sea_query::Iden)]
+enum LatestVars {
+    Table,
+}
+
+#[derive(Debug, sea_query::Iden)]
+enum LockedEnvs {
+    Table,
+    Env,
+}
+
+#[derive(Debug, sea_query::Iden)]
+enum Descriptions {
+    Table,
+    Env,
+    Key,
+    Description,
+}
+
+#[derive(Debug, sea_query::Iden)]
+enum Templates {
+    Table,
+    Name,
+    Key,
+}
+
+#[derive(Debug, sea_query::Iden)]
+enum Types {
+    Table,
+    Env,
+    Key,
+    Type,
+}
+
+#[derive(Debug, sea_query::Iden)]
+enum Settings {
+    Table,
+    Name,
+    Value,
 }
 
-#[derive(Debug, Clone, sqlx::FromRow)]
+/// Setting holding the environment loaded by the shell hook
+const ACTIVE_ENV: &str = "active_env";
+
+/// Setting present once the database is in audit mode, see
+/// [`EnvelopeDb::enable_audit_mode`]
+const AUDIT_MODE: &str = "audit_mode";
+
+/// Setting holding the newest migration envelope applied, see
+/// [`check_schema_version`]
+const SCHEMA_VERSION: &str = "schema_version";
+
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow, Serialize, Deserialize)]
 pub struct Environment {
     pub env: String,
 }
 
-#[derive(Debug, Clone, sqlx::FromRow)]
+/// A current variable of an environment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct EnvironmentRow {
     pub env: String,
     pub key: String,
     pub value: String,
-    pub created_at: i32,
+    /// time the value was set at, serialized as unix seconds
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl EnvironmentRow {
+    /// time the value was set at in unix seconds, as it is stored
+    pub fn created_at_unix(&self) -> i64 {
+        self.created_at.timestamp()
+    }
+}
+
+/// `created_at` is stored as unix seconds, `strftime('%s', 'now')` by
+/// default, and decoded to a UTC time here
+impl<'r> sqlx::FromRow<'r, SqliteRow> for EnvironmentRow {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        Ok(EnvironmentRow {
+            env: row.try_get("env")?,
+            key: row.try_get("key")?,
+            value: row.try_get("value")?,
+            created_at: decode_created_at(row.try_get("created_at")?)?,
+        })
+    }
+}
+
+/// Decodes the `created_at` column, stored as unix seconds
+pub(crate) fn decode_created_at(created_at: i64) -> sqlx::Result<DateTime<Utc>> {
+    DateTime::from_timestamp(created_at, 0).ok_or_else(|| sqlx::Error::ColumnDecode {
+        index: "created_at".to_string(),
+        source: format!("{} is out of the range of a date", created_at).into(),
+    })
+}
+
+/// A current variable along with how often it changed
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow, Serialize, Deserialize)]
+pub struct DetailedRow {
+    pub env: String,
+    pub key: String,
+    pub value: String,
+    /// number of versions of the variable, deletions included
+    pub version_count: i64,
+    /// time the current value was set at, in unix seconds
+    pub last_modified: i64,
+}
+
+/// A named set of keys that the environments following it must set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Template {
+    pub name: String,
+    /// sorted and uppercased
+    pub keys: Vec<String>,
+}
+
+/// A version of a variable, `value` is `None` if the variable has been deleted
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow, Serialize, Deserialize)]
+pub struct HistoryRow {
+    pub env: String,
+    pub key: String,
+    pub value: Option<String>,
+    pub created_at: i64,
+}
+
+/// A row of the environments table as it is stored, what the backends copy
+/// between each other. `value` is `None` for a deletion.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow, Serialize, Deserialize)]
+pub struct Version {
+    pub env: String,
+    pub key: String,
+    pub value: Option<String>,
+    pub created_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+/// What a [`ChangeEvent`] did to its variable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// the variable was set while it was not
+    Added,
+    /// the variable was set to another value
+    Changed,
+    /// the variable was deleted
+    Deleted,
+}
+
+/// A set or a delete of a variable, see [`EnvelopeDb::changes_between`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub key: String,
+    pub kind: ChangeKind,
+    /// value before the change, `None` if the variable was not set
+    pub old: Option<String>,
+    /// value after the change, `None` if the variable was deleted
+    pub new: Option<String>,
+    pub created_at: i64,
+}
+
+/// Schema of a database compared with the one of this version of envelope,
+/// see [`EnvelopeDb::migration_status`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MigrationStatus {
+    /// newest migration applied to the database, `None` if it was never
+    /// migrated
+    pub current: Option<i64>,
+    /// newest migration of this version of envelope
+    pub available: i64,
+    /// migrations of this version of envelope not applied to the database,
+    /// oldest first
+    pub pending: Vec<MigrationInfo>,
+}
+
+/// A migration of the schema, its version is the time it was written at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MigrationInfo {
+    pub version: i64,
+    pub description: String,
+}
+
+/// Health of a database, see [`EnvelopeDb::diagnose`]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Diagnostics {
+    /// problems reported by sqlite, `["ok"]` when there are none
+    pub integrity: Vec<String>,
+    /// migrations of this version of envelope not applied to the database
+    pub pending_migrations: Vec<i64>,
+    /// migrations applied to the database that this version does not know of
+    pub unknown_migrations: Vec<i64>,
+    /// indexes of the environments table
+    pub indexes: Vec<String>,
+    /// deletions of variables that never had a value
+    pub orphaned_tombstones: i64,
+    /// creation time of the newest version, in unix seconds
+    pub latest_created_at: Option<i64>,
+    /// keys of an environment that differ only by case
+    pub case_duplicates: Vec<CaseDuplicate>,
+}
+
+/// Current keys of an environment that differ only by case, such as `ApiKey`
+/// and `APIKEY`, left by versions of envelope that did not uppercase keys
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaseDuplicate {
+    pub env: String,
+    /// the uppercased key they merge into
+    pub key: String,
+    /// newest first, the first one's value is kept by a merge
+    pub variants: Vec<String>,
 }
 
+/// Variable holding the path of the database, which replaces `.envelope` in
+/// the current directory
+pub const PATH_VAR: &str = "ENVELOPE_PATH";
+
+/// Returns the path of the database: the one of `ENVELOPE_PATH` when it is
+/// set, `.envelope` in the current directory otherwise
+pub fn db_path() -> io::Result<PathBuf> {
+    match env::var_os(PATH_VAR) {
+        Some(path) if !path.is_empty() => Ok(PathBuf::from(path)),
+        _ => Ok(env::current_dir()?.join(".envelope")),
+    }
+}
+
+/// Checks if the database of [`db_path`] exists
 pub fn is_present() -> bool {
-    if let Ok(current_dir) = env::current_dir() {
-        let envelope_fs = current_dir.join(".envelope");
-        return envelope_fs.is_file();
+    match db_path() {
+        Ok(path) => path.is_file(),
+        Err(_) => false,
+    }
+}
+
+/// Looks for an `.envelope` file in `dir` and in its parents, returns the
+/// path of the closest one
+pub fn find_db(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(".envelope"))
+        .find(|path| path.is_file())
+}
+
+/// How [`EnvelopeDb::open_with`] connects to a database. Set the fields that
+/// matter and leave the others to `..Default::default()`, which are the ones
+/// [`EnvelopeDb::open`] uses, so that adding an option breaks no caller.
+///
+/// ```
+/// use std::time::Duration;
+/// use envelope::{ConnectOptions, EnvelopeDb};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() -> envelope::EnvelopeResult<()> {
+/// # let path = std::env::temp_dir().join(format!("envelope-options-{}.db", std::process::id()));
+/// let options = ConnectOptions {
+///     busy_timeout: Duration::from_secs(30),
+///     max_connections: 4,
+///     ..Default::default()
+/// };
+/// let db = EnvelopeDb::open_with(&path, &options).await?;
+/// # drop(db);
+/// # std::fs::remove_file(&path)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectOptions {
+    /// journal mode of the database, the current one is kept when `None`
+    pub journal_mode: Option<SqliteJournalMode>,
+    /// how long a statement waits for a lock held by another connection
+    pub busy_timeout: Duration,
+    pub foreign_keys: bool,
+    /// number of connections kept in the pool
+    pub max_connections: u32,
+    /// creates the database file if it does not exist, ignored when read-only
+    pub create_if_missing: bool,
+    /// opens the database without running the migrations, every write fails
+    /// with [`EnvelopeError::ReadOnly`] before any query is sent
+    pub read_only: bool,
+    /// creates or updates the envelope tables, see [`migrate`]. Turn it off
+    /// when the migrations are run separately, the tables must exist then.
+    pub run_migrations: bool,
+    /// names the tables `{prefix}_environments` and so on, so that several
+    /// isolated stores can share a database. Made of letters, digits and `_`.
+    pub table_prefix: Option<String>,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions {
+            journal_mode: None,
+            busy_timeout: Duration::from_secs(5),
+            foreign_keys: true,
+            max_connections: 1,
+            create_if_missing: true,
+            read_only: false,
+            run_migrations: true,
+            table_prefix: None,
+        }
     }
+}
+
+/// How [`EnvelopeDb`] retries a write that failed because another
+/// connection held the database, see [`EnvelopeDb::set_retry`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// number of times a write is tried, 1 never retries it
+    pub attempts: u32,
+    /// wait before the second attempt, doubled before each of the next ones
+    /// up to the busy timeout of the connection
+    pub base_delay: Duration,
+}
 
-    false
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 5,
+            base_delay: Duration::from_millis(20),
+        }
+    }
 }
 
-/// Checks if an `.envelope` file is present in the current directory,
-/// if it is nothing is done and an error in returned, otherwise a new envelope
-/// database will get created
+/// Opens the database of [`db_path`], which is created if it does not exist
 pub async fn init() -> EnvelopeResult<SqlitePool> {
-    let envelope_fs = env::current_dir()?.join(".envelope");
-    let db_path = envelope_fs.into_os_string().into_string().unwrap();
+    connect(&db_path()?, &ConnectOptions::default()).await
+}
+
+/// Opens the database at `path` according to `options`, runs the migrations
+/// if asked to unless it is read-only
+async fn connect(path: &Path, options: &ConnectOptions) -> EnvelopeResult<SqlitePool> {
+    info!(path = %path.display(), read_only = options.read_only, "opening database");
+    if let Some(prefix) = &options.table_prefix {
+        check_table_prefix(prefix)?;
+    }
+    let mut sqlite = SqliteConnectOptions::new()
+        .filename(path)
+        .create_if_missing(options.create_if_missing && !options.read_only)
+        .read_only(options.read_only)
+        .foreign_keys(options.foreign_keys)
+        .busy_timeout(options.busy_timeout);
+    if let Some(mode) = options.journal_mode {
+        sqlite = sqlite.journal_mode(mode);
+    }
+
     let pool = sqlx::sqlite::SqlitePoolOptions::new()
-        .max_connections(1)
-        .connect(&format!("sqlite://{}?mode=rwc", db_path))
+        .max_connections(options.max_connections)
+        .connect_with(sqlite)
         .await
-        .map_err(|err| format!("{}\nfile: {}", err, db_path))?;
+        .map_err(|source| EnvelopeError::Open {
+            path: path.to_path_buf(),
+            source,
+        })?;
 
-    sqlx::migrate!("./migrations").run(&pool).await?;
+    if options.run_migrations && !options.read_only {
+        match &options.table_prefix {
+            Some(prefix) => migrate_with_prefix(&pool, prefix).await?,
+            None => migrate(&pool).await?,
+        }
+    }
 
     Ok(pool)
 }
 
+/// Creates or updates the envelope tables in `pool`, the tables of other
+/// applications sharing the database are left alone. Fails with
+/// [`EnvelopeError::MigrationMismatch`] if a newer envelope migrated it.
+///
+/// Nothing more is done when the newest migration is already applied, which
+/// spares every invocation the migrator. [`EnvelopeDb::apply_migrations`]
+/// runs it anyway.
+pub async fn migrate(pool: &SqlitePool) -> EnvelopeResult<()> {
+    if check_schema_version(pool, SQLX_MIGRATIONS).await? == Some(embedded_version()) {
+        debug!("schema is up to date");
+        return Ok(());
+    }
+
+    run_migrator(pool).await
+}
+
+async fn run_migrator(pool: &SqlitePool) -> EnvelopeResult<()> {
+    let mut migrator = sqlx::migrate!("./migrations");
+    // other applications may keep their migrations in the same table
+    migrator.set_ignore_missing(true);
+    info!(migrations = migrator.iter().count(), "running migrations");
+    migrator.run(pool).await?;
+
+    sqlx::query(
+        "INSERT INTO settings (name, value) VALUES (?, ?)
+        ON CONFLICT (name) DO UPDATE SET value = excluded.value",
+    )
+    .bind(SCHEMA_VERSION)
+    .bind(embedded_version().to_string())
+    .execute(pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(())
+}
+
+/// Creates or updates the envelope tables named with `prefix` in `pool`, see
+/// [`ConnectOptions::table_prefix`]. The migrations are run with the tables
+/// renamed, the versions applied are kept in `{prefix}_migrations`. Like
+/// [`migrate`], nothing is done when the newest migration is applied.
+pub async fn migrate_with_prefix(pool: &SqlitePool, prefix: &str) -> EnvelopeResult<()> {
+    check_table_prefix(prefix)?;
+    let migrations = format!("{}_migrations", prefix);
+    if check_schema_version(pool, &migrations).await? == Some(embedded_version()) {
+        debug!(prefix, "schema is up to date");
+        return Ok(());
+    }
+
+    run_prefixed_migrations(pool, prefix).await
+}
+
+async fn run_prefixed_migrations(pool: &SqlitePool, prefix: &str) -> EnvelopeResult<()> {
+    let migrations = format!("{}_migrations", prefix);
+    sqlx::query(&format!(
+        "CREATE TABLE IF NOT EXISTS {}(version INTEGER NOT NULL PRIMARY KEY)",
+        migrations
+    ))
+    .execute(pool)
+    .await
+    .map_err(db_error)?;
+    let applied: Vec<i64> = sqlx::query_scalar(&format!("SELECT version FROM {}", migrations))
+        .fetch_all(pool)
+        .await
+        .map_err(db_error)?;
+
+    let tables = Regex::new(&format!(r"\b({})\b", TABLES.join("|"))).unwrap();
+    let migrator = sqlx::migrate!("./migrations");
+    info!(
+        prefix,
+        migrations = migrator.iter().count(),
+        "running migrations"
+    );
+    for migration in migrator.iter().filter(|m| !applied.contains(&m.version)) {
+        let sql = tables.replace_all(&migration.sql, format!("{}_${{1}}", prefix));
+        let mut tx = pool.begin().await.map_err(db_error)?;
+        tx.execute(sql.as_ref()).await.map_err(db_error)?;
+        sqlx::query(&format!("INSERT INTO {} (version) VALUES (?)", migrations))
+            .bind(migration.version)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+    }
+
+    Ok(())
+}
+
+/// Table in which sqlx keeps the versions of the migrations it applied
+const SQLX_MIGRATIONS: &str = "_sqlx_migrations";
+
+/// versions of the migrations envelope embeds
+fn embedded_versions() -> Vec<i64> {
+    sqlx::migrate!("./migrations")
+        .iter()
+        .map(|migration| migration.version)
+        .collect()
+}
+
+/// newest migration envelope embeds, the schema version it expects
+fn embedded_version() -> i64 {
+    embedded_versions().into_iter().max().unwrap_or_default()
+}
+
+async fn has_table(pool: &SqlitePool, name: &str) -> EnvelopeResult<bool> {
+    sqlx::query_scalar("SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?")
+        .bind(name)
+        .fetch_one(pool)
+        .await
+        .map_err(db_error)
+}
+
+/// schema version recorded by the newest envelope that migrated the shared
+/// `_sqlx_migrations` table, `None` before any did
+async fn recorded_schema_version(pool: &SqlitePool) -> EnvelopeResult<Option<i64>> {
+    if !has_table(pool, "settings").await? {
+        return Ok(None);
+    }
+
+    let version: Option<String> = sqlx::query_scalar("SELECT value FROM settings WHERE name = ?")
+        .bind(SCHEMA_VERSION)
+        .fetch_optional(pool)
+        .await
+        .map_err(db_error)?;
+    Ok(version.and_then(|version| version.parse().ok()))
+}
+
+/// Fails with [`EnvelopeError::MigrationMismatch`] if the database was
+/// migrated by a newer envelope, which sqlx would otherwise report as a
+/// missing migration. Returns the newest migration envelope knows of that is
+/// applied, `None` for a database never migrated.
+///
+/// `_sqlx_migrations` may also list the migrations of other applications
+/// sharing the database, so only the version recorded in the settings tells
+/// a newer envelope apart. Every version of a prefixed `migrations` table is
+/// one of envelope.
+async fn check_schema_version(pool: &SqlitePool, migrations: &str) -> EnvelopeResult<Option<i64>> {
+    if !has_table(pool, migrations).await? {
+        return Ok(None);
+    }
+
+    let applied: Vec<i64> = sqlx::query_scalar(&format!("SELECT version FROM {}", migrations))
+        .fetch_all(pool)
+        .await
+        .map_err(db_error)?;
+    let database = match migrations {
+        SQLX_MIGRATIONS => recorded_schema_version(pool).await?,
+        _ => applied.iter().max().copied(),
+    };
+    let known = embedded_version();
+    if let Some(database) = database.filter(|database| *database > known) {
+        return Err(EnvelopeError::MigrationMismatch { database, known });
+    }
+
+    let embedded = embedded_versions();
+    Ok(applied
+        .into_iter()
+        .filter(|version| embedded.contains(version))
+        .max())
+}
+
+/// Names of the envelope tables, renamed by a table prefix
+const TABLES: &[&str] = &[
+    "environments",
+    "latest_vars",
+    "descriptions",
+    "locked_envs",
+    "settings",
+    "templates",
+    "types",
+];
+
+/// Table prefixes end up in the sql of the migrations, only plain identifiers
+/// are accepted
+fn check_table_prefix(prefix: &str) -> EnvelopeResult<()> {
+    let valid = prefix.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    match valid {
+        true => Ok(()),
+        false => Err(EnvelopeError::Constraint(format!(
+            "table prefix {:?} must be made of letters, digits and _",
+            prefix
+        ))),
+    }
+}
+
 #[derive(Debug)]
 pub struct EnvelopeDb {
     db: SqlitePool,
+    force: bool,
+    /// serializes writers so that contention surfaces as
+    /// [`EnvelopeError::Busy`] instead of an opaque sqlite error
+    writer: Mutex<()>,
+    write_timeout: Option<Duration>,
+    /// writes fail with [`EnvelopeError::ReadOnly`] without reaching sqlite
+    read_only: bool,
+    /// reads list the variables whose ttl has passed
+    include_expired: bool,
+    /// bulk inserts run with sqlite's `synchronous` pragma off
+    fast_import: bool,
+    /// single inserts skip the values a variable already has
+    dedupe: bool,
+    /// how the writes are retried when sqlite reports the database busy
+    retry: RetryPolicy,
+    /// how long sqlite waits for the lock of another connection, which
+    /// bounds the waits between retries
+    busy_timeout: Duration,
+    /// prepended to the names of the tables, empty or ending with `_`
+    prefix: String,
 }
 
 #[cfg(test)]
 impl EnvelopeDb {
-    pub(crate) fn with(pool: SqlitePool) -> Self {
-        EnvelopeDb { db: pool }
-    }
-
     pub fn get_pool(&self) -> &SqlitePool {
         &self.db
     }
 }
 
+impl From<SqlitePool> for EnvelopeDb {
+    fn from(db: SqlitePool) -> Self {
+        EnvelopeDb::from_pool(db)
+    }
+}
+
 impl EnvelopeDb {
+    /// uses `pool` as the database, which lets applications keep the envelope
+    /// tables next to their own in a pool they manage. The tables must have
+    /// been created beforehand with [`migrate`].
+    pub fn from_pool(pool: SqlitePool) -> Self {
+        EnvelopeDb {
+            db: pool,
+            force: false,
+            writer: Mutex::new(()),
+            write_timeout: Some(DEFAULT_WRITE_TIMEOUT),
+            read_only: false,
+            include_expired: false,
+            fast_import: false,
+            dedupe: false,
+            retry: RetryPolicy::default(),
+            busy_timeout: ConnectOptions::default().busy_timeout,
+            prefix: String::new(),
+        }
+    }
+
+    /// uses the tables named with `prefix` in `pool`, see
+    /// [`ConnectOptions::table_prefix`]. They must have been created
+    /// beforehand with [`migrate_with_prefix`].
+    pub fn from_pool_with_prefix(pool: SqlitePool, prefix: &str) -> EnvelopeResult<Self> {
+        check_table_prefix(prefix)?;
+
+        Ok(EnvelopeDb {
+            prefix: format!("{}_", prefix),
+            ..EnvelopeDb::from_pool(pool)
+        })
+    }
+
     pub async fn init() -> EnvelopeResult<Self> {
         let db = init().await?;
 
-        Ok(EnvelopeDb { db })
+        Ok(EnvelopeDb::from_pool(db))
+    }
+
+    /// opens the database at `path`
+    pub async fn open(path: &Path) -> EnvelopeResult<Self> {
+        EnvelopeDb::open_with(path, &ConnectOptions::default()).await
+    }
+
+    /// opens the existing database at `path` without running the migrations,
+    /// every write fails with [`EnvelopeError::ReadOnly`] before any query is
+    /// sent and reads give up quickly if the database is locked
+    pub async fn open_read_only(path: &Path) -> EnvelopeResult<Self> {
+        let options = ConnectOptions {
+            read_only: true,
+            busy_timeout: READ_ONLY_BUSY_TIMEOUT,
+            ..Default::default()
+        };
+
+        EnvelopeDb::open_with(path, &options).await
+    }
+
+    /// opens the database at `path` as described by `options`
+    pub async fn open_with(path: &Path, options: &ConnectOptions) -> EnvelopeResult<Self> {
+        let db = connect(path, options).await?;
+
+        Ok(EnvelopeDb {
+            read_only: options.read_only,
+            busy_timeout: options.busy_timeout,
+            prefix: options
+                .table_prefix
+                .as_ref()
+                .map(|prefix| format!("{}_", prefix))
+                .unwrap_or_default(),
+            ..EnvelopeDb::from_pool(db)
+        })
     }
 
+    /// opens the database of [`db_path`], which must exist unless `init` is
+    /// set
     pub async fn load(init: bool) -> EnvelopeResult<Self> {
         if !is_present() && !init {
-            return Err("envelope is not initialized in current directory".into());
+            return Err(EnvelopeError::NotInitialized);
         }
 
         EnvelopeDb::init().await
     }
 
-    /// checks if an environment exists in the database
-    pub async fn check_env_exists(&self, env: &str) -> io::Result<()> {
-        let (sql, value) = Query::select()
-            .from(Environments::Table)
-            .column(Environments::Env)
-            .distinct()
-            .and_where(Expr::col(Environments::Env).eq(env))
-            .build_sqlx(SqliteQueryBuilder);
-
-        sqlx::query_as_with(&sql, value)
-            .fetch_one(&self.db)
-            .await
-            .map_err(|e| std_err!("db error: {}", e))
+    /// sets how long a write waits for the other writers before failing with
+    /// [`EnvelopeError::Busy`], `None` waits forever
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
     }
 
-    pub async fn get_all_env_vars(&self) -> io::Result<Vec<EnvironmentRow>> {
-        let (sql, _) = Query::select()
-            .from(Environments::Table)
-            .column(Asterisk)
-            .group_by_columns([Environments::Env, Environments::Key])
-            .and_having(Expr::col(Environments::CreatedAt).max())
-            .build_sqlx(SqliteQueryBuilder);
-
-        let rows = sqlx::query_as::<_, EnvironmentRow>(&sql)
-            .fetch_all(&self.db)
-            .await
-            .map_err(|e| std_err!("db error: {}", e))?;
+    /// waits for the ongoing write, if any, to complete. Every write goes
+    /// through here first, which is where a read-only database rejects it.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn write_guard(&self) -> EnvelopeResult<MutexGuard<'_, ()>> {
+        if self.read_only {
+            return Err(EnvelopeError::ReadOnly);
+        }
 
-        Ok(rows)
+        match self.write_timeout {
+            None => Ok(self.writer.lock().await),
+            Some(timeout) => tokio::time::timeout(timeout, self.writer.lock())
+                .await
+                .map_err(|_| EnvelopeError::Busy(timeout)),
+        }
     }
 
-    /// inserts `key` and `value` to environment `env`
-    pub async fn insert(&self, env: &str, key: &str, var: &str) -> io::Result<()> {
-        let (sql, values) = Query::insert()
-            .into_table(Environments::Table)
-            .columns([Environments::Env, Environments::Key, Environments::Value])
-            .values([env.into(), Func::upper(key).into(), var.into()])
-            .unwrap()
-            .build_sqlx(SqliteQueryBuilder);
+    /// runs the write `op` again while it fails with a transient error, see
+    /// [`EnvelopeError::is_transient`], waiting longer before each attempt.
+    /// `op` runs in a transaction or as a single statement, a failed attempt
+    /// wrote nothing.
+    ///
+    /// The waits never exceed the busy timeout of the connection, sqlite
+    /// already waited that long when another process held the lock. Once
+    /// the attempts are exhausted the write fails with
+    /// [`EnvelopeError::Blocked`], naming `operation`.
+    async fn retry<T, F, Fut>(&self, operation: &'static str, mut op: F) -> EnvelopeResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = EnvelopeResult<T>>,
+    {
+        let started = Instant::now();
+        let mut delay = self.retry.base_delay.min(self.busy_timeout);
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(err) if err.is_transient() && attempt < self.retry.attempts => {
+                    debug!(operation, attempt, ?delay, %err, "retrying write");
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(self.busy_timeout);
+                    attempt += 1;
+                }
+                Err(err) if err.is_transient() => {
+                    let waited = started.elapsed();
+                    debug!(operation, attempts = attempt, ?waited, %err, "giving up write");
+                    return Err(EnvelopeError::Blocked { operation, waited });
+                }
+                result => return result,
+            }
+        }
+    }
 
-        sqlx::query_with(&sql, values)
-            .execute(&self.db)
-            .await
-            .map_err(|e| std_err!("db error: {}", e))?;
+    /// sets how the writes are retried when sqlite reports the database busy
+    /// or locked by another connection
+    pub fn set_retry(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
 
-        Ok(())
+    /// allows write operations on locked environments
+    pub fn set_force(&mut self, force: bool) {
+        self.force = force;
     }
 
-    /// soft deletes all variables in an environment by setting all their
-    /// values to NULL
-    pub async fn delete_env(&self, env: &str) -> io::Result<()> {
-        let select = Query::select()
-            .from(Environments::Table)
-            .column(Environments::Env)
-            .column(Environments::Key)
-            .expr(Expr::val(Option::<i32>::None))
-            .and_where(Expr::col(Environments::Env).eq(env))
-            .and_where(Expr::col(Environments::Value).is_not_null())
-            .group_by_columns([Environments::Env, Environments::Key])
-            .to_owned();
+    /// lists the variables whose ttl has passed, see
+    /// [`EnvelopeDb::insert_with_ttl`], which the reads treat as deleted
+    /// otherwise
+    pub fn set_include_expired(&mut self, include_expired: bool) {
+        self.include_expired = include_expired;
+    }
 
-        let (sql, values) = Query::insert()
-            .into_table(Environments::Table)
-            .columns([Environments::Env, Environments::Key, Environments::Value])
-            .select_from(select)
-            .unwrap()
-            .build_sqlx(SqliteQueryBuilder);
+    /// turns sqlite's `synchronous` pragma off for the duration of
+    /// [`EnvelopeDb::insert_many`], which no longer waits for the data to
+    /// reach the disk. Much faster on slow disks, but a power loss during
+    /// the import can corrupt the database.
+    pub fn set_fast_import(&mut self, fast_import: bool) {
+        self.fast_import = fast_import;
+    }
 
-        sqlx::query_with(&sql, values)
-            .execute(&self.db)
-            .await
-            .map_err(|e| std_err!("db error: {}", e))?;
+    /// skips the writes of [`EnvelopeDb::insert`] and
+    /// [`EnvelopeDb::set_in_envs`] that would set a variable to the value it
+    /// already has, instead of adding a version to its history.
+    /// [`EnvelopeDb::insert_many`] always skips them.
+    pub fn set_dedupe(&mut self, dedupe: bool) {
+        self.dedupe = dedupe;
+    }
 
-        Ok(())
+    /// `table` named with the prefix of the store
+    fn table(&self, table: impl Iden) -> Alias {
+        Alias::new(format!("{}{}", self.prefix, table.to_string()))
     }
 
-    /// soft deletes all variables with key `key`
-    pub async fn delete_var_all(&self, key: &str) -> io::Result<()> {
-        let select = Query::select()
-            .from(Environments::Table)
-            .column(Environments::Env)
-            .column(Environments::Key)
-            .expr(Expr::val(Option::<i32>::None))
-            .and_where(Expr::col(Environments::Key).eq(key))
-            .and_where(Expr::col(Environments::Value).is_not_null())
-            .group_by_columns([Environments::Env, Environments::Key])
-            .to_owned();
+    /// matches the current versions that have not expired, or all of them
+    /// when the expired ones are included
+    fn unexpired(&self) -> Condition {
+        match self.include_expired {
+            true => Condition::all(),
+            false => any![
+                Expr::col(Environments::ExpiresAt).is_null(),
+                Expr::col(Environments::ExpiresAt).gt(unix_now()),
+            ],
+        }
+    }
 
-        let (sql, values) = Query::insert()
-            .into_table(Environments::Table)
-            .columns([Environments::Env, Environments::Key, Environments::Value])
-            .select_from(select)
-            .unwrap()
-            .build_sqlx(SqliteQueryBuilder);
+    /// locks `env`, every write operation on it will fail unless forced
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn lock_env(&self, env: &str) -> EnvelopeResult<()> {
+        self.retry("lock_env", || async {
+            let _guard = self.write_guard().await?;
+            let (sql, values) = Query::insert()
+                .into_table(self.table(LockedEnvs::Table))
+                .columns([LockedEnvs::Env])
+                .values([env.into()])
+                .unwrap()
+                .on_conflict(OnConflict::column(LockedEnvs::Env).do_nothing().to_owned())
+                .to_sqlite();
 
-        sqlx::query_with(&sql, values)
-            .execute(&self.db)
-            .await
-            .map_err(|e| std_err!("db error: {}", e))?;
+            sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    pub async fn delete_var_for_env(&self, env: &str, key: &str) -> io::Result<()> {
-        let select = Query::select()
-            .from(Environments::Table)
-            .column(Environments::Env)
-            .column(Environments::Key)
-            .expr(Expr::val(Option::<i32>::None))
-            .and_where(Expr::col(Environments::Env).eq(env))
-            .and_where(Expr::col(Environments::Key).eq(key))
-            .and_where(Expr::col(Environments::Value).is_not_null())
-            .group_by_columns([Environments::Env, Environments::Key])
-            .to_owned();
-
-        let (sql, values) = Query::insert()
-            .into_table(Environments::Table)
-            .columns([Environments::Env, Environments::Key, Environments::Value])
-            .select_from(select)
-            .unwrap()
-            .build_sqlx(SqliteQueryBuilder);
+    /// unlocks `env`
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn unlock_env(&self, env: &str) -> EnvelopeResult<()> {
+        self.retry("unlock_env", || async {
+            let _guard = self.write_guard().await?;
+            let (sql, values) = Query::delete()
+                .from_table(self.table(LockedEnvs::Table))
+                .and_where(Expr::col(LockedEnvs::Env).eq(env))
+                .to_sqlite();
 
-        sqlx::query_with(&sql, values)
-            .execute(&self.db)
-            .await
-            .map_err(|e| std_err!("db error: {}", e))?;
+            sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
 
-        Ok(())
+            Ok(())
+        })
+        .await
     }
 
-    /// deletes environment from database entirely
-    pub async fn drop_env(&self, env: &str) -> io::Result<()> {
-        let (sql, values) = Query::delete()
-            .from_table(Environments::Table)
-            .and_where(Expr::col(Environments::Env).eq(env))
-            .build_sqlx(SqliteQueryBuilder);
+    /// returns the locked environments among `envs`
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn locked_among(&self, envs: &[String]) -> EnvelopeResult<Vec<String>> {
+        let (sql, values) = Query::select()
+            .from(self.table(LockedEnvs::Table))
+            .column(LockedEnvs::Env)
+            .and_where(Expr::col(LockedEnvs::Env).is_in(envs.iter().map(String::as_str)))
+            .order_by(LockedEnvs::Env, Order::Asc)
+            .to_sqlite();
 
-        sqlx::query_with(&sql, values)
-            .execute(&self.db)
+        let locked: Vec<(String,)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
             .await
-            .map_err(|e| std_err!("db error: {}", e))?;
+            .map_err(db_error)?;
 
-        Ok(())
+        Ok(locked.into_iter().map(|(env,)| env).collect())
     }
 
-    /// duplicates `src_env` in a new environment `tgt_env`
-    pub async fn duplicate(&self, src_env: &str, tgt_env: &str) -> io::Result<()> {
-        let select = Query::select()
-            .column(Asterisk)
-            .from(Environments::Table)
-            .and_where(Expr::col(Environments::Env).eq(src_env))
-            .group_by_columns([Environments::Env, Environments::Key])
-            .and_having(Expr::col(Environments::CreatedAt).max())
-            .to_owned();
+    /// returns an error if any of `envs` is locked and writes are not forced
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn ensure_unlocked(&self, envs: &[String]) -> EnvelopeResult<()> {
+        if self.force {
+            return Ok(());
+        }
 
-        let select = Query::select()
-            .from_subquery(select, Alias::new("T"))
-            .expr(Expr::val(tgt_env))
-            .column(Environments::Key)
-            .column(Environments::Value)
-            .and_where(Expr::col(Environments::Env).eq(src_env))
-            .and_where(Expr::col(Environments::Value).is_not_null())
-            .group_by_columns([Environments::Env, Environments::Key])
-            .and_having(Expr::col(Environments::CreatedAt).max())
-            .order_by_columns([
-                (Environments::Env, Order::Desc),
-                (Environments::Key, Order::Desc),
-            ])
-            .to_owned();
+        match self.locked_among(envs).await?.as_slice() {
+            [] => Ok(()),
+            locked => Err(EnvelopeError::Locked(locked.to_vec())),
+        }
+    }
 
-        let (sql, values) = Query::insert()
-            .into_table(Environments::Table)
-            .columns([Environments::Env, Environments::Key, Environments::Value])
-            .select_from(select)
-            .unwrap()
-            .build_sqlx(SqliteQueryBuilder);
+    /// fails with [`EnvelopeError::AuditModeEnabled`] if the database is in
+    /// audit mode, where `operation` would remove history for good
+    async fn ensure_hard_deletes(&self, operation: &'static str) -> EnvelopeResult<()> {
+        match self.audit_mode().await? {
+            true => Err(EnvelopeError::AuditModeEnabled(operation)),
+            false => Ok(()),
+        }
+    }
 
-        sqlx::query_with(&sql, values)
-            .execute(&self.db)
+    /// whether the database is in audit mode, see [`Self::enable_audit_mode`]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn audit_mode(&self) -> EnvelopeResult<bool> {
+        let (sql, values) = Query::select()
+            .from(self.table(Settings::Table))
+            .column(Settings::Value)
+            .and_where(Expr::col(Settings::Name).eq(AUDIT_MODE))
+            .to_sqlite();
+
+        let row: Option<(String,)> = sqlx::query_as_with(&sql, values)
+            .fetch_optional(&self.db)
             .await
-            .map_err(|e| std_err!("db error: {}", e))?;
+            .map_err(db_error)?;
 
-        Ok(())
+        Ok(row.is_some())
     }
 
-    pub async fn list_var_in_env(&self, env: &str) -> io::Result<Vec<EnvironmentRow>> {
-        let select = Query::select()
-            .column(Asterisk)
-            .from(Environments::Table)
+    /// makes the history of the database append-only: [`Self::drop_env`],
+    /// [`Self::flatten_env`], [`Self::purge_older_than`] and
+    /// [`Self::purge_expired`] fail with [`EnvelopeError::AuditModeEnabled`]
+    /// from then on, deleting a variable or an environment still records a
+    /// deletion. The mode is stored in the database and cannot be disabled.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn enable_audit_mode(&self) -> EnvelopeResult<()> {
+        self.retry("enable_audit_mode", || async {
+            let _guard = self.write_guard().await?;
+            let (sql, values) = Query::insert()
+                .into_table(self.table(Settings::Table))
+                .columns([Settings::Name, Settings::Value])
+                .values([AUDIT_MODE.into(), unix_now().to_string().into()])
+                .unwrap()
+                .on_conflict(OnConflict::column(Settings::Name).do_nothing().to_owned())
+                .to_sqlite();
+
+            sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// returns the environment loaded by the shell hook, if any
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn active_env(&self) -> EnvelopeResult<Option<String>> {
+        let (sql, values) = Query::select()
+            .from(self.table(Settings::Table))
+            .column(Settings::Value)
+            .and_where(Expr::col(Settings::Name).eq(ACTIVE_ENV))
+            .to_sqlite();
+
+        let env: Option<(String,)> = sqlx::query_as_with(&sql, values)
+            .fetch_optional(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        Ok(env.map(|(env,)| env))
+    }
+
+    /// sets the environment loaded by the shell hook, `None` disables it
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn set_active_env(&self, env: Option<&str>) -> EnvelopeResult<()> {
+        self.retry("set_active_env", || async {
+            let _guard = self.write_guard().await?;
+            let (sql, values) = match env {
+                Some(env) => Query::insert()
+                    .into_table(self.table(Settings::Table))
+                    .columns([Settings::Name, Settings::Value])
+                    .values([ACTIVE_ENV.into(), env.into()])
+                    .unwrap()
+                    .on_conflict(
+                        OnConflict::column(Settings::Name)
+                            .update_column(Settings::Value)
+                            .to_owned(),
+                    )
+                    .to_sqlite(),
+                None => Query::delete()
+                    .from_table(self.table(Settings::Table))
+                    .and_where(Expr::col(Settings::Name).eq(ACTIVE_ENV))
+                    .to_sqlite(),
+            };
+
+            sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// returns a SHA-256 hex digest of the current variables of `env`, it
+    /// only changes when a variable is added, modified or deleted. Deleted
+    /// variables are not part of it.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn fingerprint(&self, env: &str) -> EnvelopeResult<String> {
+        count!(READS_COUNTER, "fingerprint");
+        Store::fingerprint(self, env).await
+    }
+
+    /// groups the environments whose current variables are identical, that
+    /// is with the same [`EnvelopeDb::fingerprint`]. Every environment is in
+    /// one group, alone if nothing duplicates it, and the environments whose
+    /// variables are all deleted are grouped together. Groups and the
+    /// environments in them are sorted by name.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn group_identical_envs(&self) -> EnvelopeResult<Vec<Vec<String>>> {
+        let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for env in self.list_environments().await? {
+            let fingerprint = self.fingerprint(&env.env).await?;
+            groups.entry(fingerprint).or_default().push(env.env);
+        }
+
+        let mut groups: Vec<Vec<String>> = groups
+            .into_values()
+            .map(|mut envs| {
+                envs.sort();
+                envs
+            })
+            .collect();
+        groups.sort();
+
+        Ok(groups)
+    }
+
+    /// lists the environments whose variables have all been deleted, sorted
+    /// by name. They are still listed by [`EnvelopeDb::list_environments`]
+    /// and can be dropped to clean up, environments that never existed are
+    /// not part of them. Variables whose ttl has passed still count as set.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn empty_environments(&self) -> EnvelopeResult<Vec<String>> {
+        count!(READS_COUNTER, "empty_environments");
+        let (sql, values) = Query::select()
+            .from(self.table(LatestVars::Table))
+            .column(Environments::Env)
+            .group_by_col(Environments::Env)
+            .and_having(Expr::col(Environments::Value).count().eq(0))
+            .order_by(Environments::Env, Order::Asc)
+            .to_sqlite();
+
+        sqlx::query_scalar_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)
+    }
+
+    /// checks if an environment exists in the database
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn check_env_exists(&self, env: &str) -> EnvelopeResult<()> {
+        let (sql, value) = Query::select()
+            .from(self.table(Environments::Table))
+            .column(Environments::Env)
+            .distinct()
             .and_where(Expr::col(Environments::Env).eq(env))
-            .group_by_columns([Environments::Env, Environments::Key])
-            .and_having(Expr::col(Environments::CreatedAt).max())
-            .to_owned();
+            .to_sqlite();
+
+        let existing: Option<(String,)> = sqlx::query_as_with(&sql, value)
+            .fetch_optional(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        match existing {
+            Some(_) => Ok(()),
+            None => Err(EnvelopeError::EnvNotFound(env.to_string())),
+        }
+    }
 
+    /// returns an error if `env` has any row, deleted variables included
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn ensure_new_env(&self, env: &str) -> EnvelopeResult<()> {
         let (sql, values) = Query::select()
-            .from_subquery(select, Alias::new("T"))
+            .from(self.table(Environments::Table))
+            .column(Environments::Env)
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .limit(1)
+            .to_sqlite();
+
+        let existing: Option<(String,)> = sqlx::query_as_with(&sql, values)
+            .fetch_optional(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        match existing {
+            Some(_) => Err(EnvelopeError::Constraint(format!(
+                "env {} already exists, use --append to copy into it",
+                env
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn get_all_env_vars(&self) -> EnvelopeResult<Vec<EnvironmentRow>> {
+        self.stream_all_env_vars().try_collect().await
+    }
+
+    /// same as [`EnvelopeDb::get_all_env_vars`], the rows are yielded as
+    /// they are read instead of being collected first
+    pub fn stream_all_env_vars(
+        &self,
+    ) -> impl Stream<Item = EnvelopeResult<EnvironmentRow>> + Send + '_ {
+        count!(READS_COUNTER, "stream_all_env_vars");
+        let query = Query::select()
+            .from(self.table(LatestVars::Table))
             .column(Asterisk)
-            .and_where(Expr::col(Environments::Value).is_not_null())
-            .order_by_columns([
-                (Environments::Env, Order::Desc),
-                (Environments::Key, Order::Desc),
-            ])
-            .build_sqlx(SqliteQueryBuilder);
+            .to_sqlite();
 
-        sqlx::query_as_with(&sql, values)
-            .fetch_all(&self.db)
+        self.fetch_stream(query)
+    }
+
+    /// runs the query made of `sql` and its `values`, yielding its rows as
+    /// they are read
+    fn fetch_stream<T>(
+        &self,
+        (sql, values): (String, SqlxValues),
+    ) -> impl Stream<Item = EnvelopeResult<T>> + Send + '_
+    where
+        T: for<'r> sqlx::FromRow<'r, SqliteRow> + Send + Unpin + 'static,
+    {
+        try_stream! {
+            let mut rows = sqlx::query_as_with::<_, T, _>(&sql, values).fetch(&self.db);
+            while let Some(row) = rows.try_next().await.map_err(db_error)? {
+                yield row;
+            }
+        }
+    }
+
+    /// inserts `key` and `value` to environment `env`, the value is stored
+    /// as is and fails with [`EnvelopeError::Constraint`] if it contains a
+    /// NUL byte. Returns the value it replaced, read in the same transaction.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, var), fields(rows))
+    )]
+    pub async fn insert(&self, env: &str, key: &str, var: &str) -> EnvelopeResult<InsertOutcome> {
+        count!(INSERTS_COUNTER, "insert");
+        self.insert_as(env, key, Func::upper(key).into(), var, None)
             .await
-            .map_err(|e| std_err!("db error: {}", e))
     }
 
-    pub async fn list_all_var_in_env(
+    /// inserts `key` and `value` to environment `env` for `ttl` only, the
+    /// reads treat the variable as deleted once it has expired until it is
+    /// set again. [`EnvelopeDb::purge_expired`] removes it for good.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, var), fields(rows))
+    )]
+    pub async fn insert_with_ttl(
         &self,
         env: &str,
-        truncate: Truncate,
-    ) -> io::Result<Vec<EnvironmentRow>> {
-        let select = Query::select()
-            .column(Asterisk)
-            .from(Environments::Table)
+        key: &str,
+        var: &str,
+        ttl: Duration,
+    ) -> EnvelopeResult<InsertOutcome> {
+        count!(INSERTS_COUNTER, "insert_with_ttl");
+        let expires_at = unix_now().saturating_add(ttl.as_secs() as i64);
+        self.insert_as(env, key, Func::upper(key).into(), var, Some(expires_at))
+            .await
+    }
+
+    /// inserts `key` and `value` to environment `env` without uppercasing
+    /// `key`. The other methods uppercase the keys they are given, so the
+    /// variable is only found by its exact key through
+    /// [`EnvelopeDb::get_var_exact`], while it is listed and exported along
+    /// the others. Its type is the one of its uppercased key.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, var), fields(rows))
+    )]
+    pub async fn insert_exact(
+        &self,
+        env: &str,
+        key: &str,
+        var: &str,
+    ) -> EnvelopeResult<InsertOutcome> {
+        count!(INSERTS_COUNTER, "insert_exact");
+        self.insert_as(env, key, key.into(), var, None).await
+    }
+
+    /// sets `key` to `value` in `env` only if the key has no current value,
+    /// a deleted or expired key is set again. Returns whether it wrote, which
+    /// makes seeding default values idempotent.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, value), fields(rows))
+    )]
+    pub async fn set_if_absent(&self, env: &str, key: &str, value: &str) -> EnvelopeResult<bool> {
+        count!(INSERTS_COUNTER, "set_if_absent");
+        self.retry("set_if_absent", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+            self.check_values(env, [(key, value)]).await?;
+
+            // a single statement, the key cannot be set in between by another
+            // process
+            let present = self
+                .live_in(env)
+                .and_where(Expr::col(Environments::Key).eq(Func::upper(key)))
+                .to_owned();
+            let select = Query::select()
+                .expr(Expr::val(env))
+                .expr(Func::upper(key))
+                .expr(Expr::val(value))
+                .expr(Expr::val(Option::<i64>::None))
+                .and_where(Expr::exists(present).not())
+                .to_owned();
+
+            Ok(self.insert_selected(select).await? > 0)
+        })
+        .await
+    }
+
+    /// appends `suffix` to the current value of `key` in `env` after `sep`,
+    /// for list-like values such as `PATH`, or sets it to `suffix` when the
+    /// key has no current value. Returns the new value.
+    ///
+    /// The current value is read and the new version written in a single
+    /// transaction, a value set by another process in between makes the
+    /// attempt fail and be retried, see [`EnvelopeDb::retry`], so that it is
+    /// appended to instead of lost. The new version keeps the ttl of the
+    /// current one. Versions are told apart by the second they were created
+    /// in: appending to a key already written in the current second fails
+    /// with [`EnvelopeError::Conflict`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, suffix), fields(rows))
+    )]
+    pub async fn append_var(
+        &self,
+        env: &str,
+        key: &str,
+        suffix: &str,
+        sep: &str,
+    ) -> EnvelopeResult<String> {
+        count!(INSERTS_COUNTER, "append_var");
+        self.retry("append_var", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+            let types = self.list_types(env).await?;
+
+            let mut tx = self.db.begin().await.map_err(db_error)?;
+
+            let (sql, values) = self
+                .live_in(env)
+                .column(Environments::ExpiresAt)
+                .and_where(Expr::col(Environments::Key).eq(Func::upper(key)))
+                .to_sqlite();
+            let current: Option<(String, String, Option<i64>)> = sqlx::query_as_with(&sql, values)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(db_error)?;
+            // the new version keeps the ttl of the current one
+            let (value, expires_at) = match current {
+                Some((_, current, expires_at)) => {
+                    (format!("{}{}{}", current, sep, suffix), expires_at)
+                }
+                None => (suffix.to_string(), None),
+            };
+            check_nul(env, [(key, value.as_str())])?;
+            check_types(env, &types, [(key, value.as_str())])?;
+
+            let (sql, values) = Query::insert()
+                .into_table(self.table(Environments::Table))
+                .columns([
+                    Environments::Env,
+                    Environments::Key,
+                    Environments::Value,
+                    Environments::ExpiresAt,
+                ])
+                .values([
+                    env.into(),
+                    Func::upper(key).into(),
+                    value.as_str().into(),
+                    expires_at.into(),
+                ])
+                .unwrap()
+                .to_sqlite();
+            let result = sqlx::query_with(&sql, values)
+                .execute(&mut *tx)
+                .await
+                .map_err(|err| match &err {
+                    sqlx::Error::Database(db_err)
+                        if db_err.kind() == ErrorKind::UniqueViolation =>
+                    {
+                        EnvelopeError::Conflict(format!(
+                            "{} in {} already has a version from this second, append again later",
+                            key.to_ascii_uppercase(),
+                            env
+                        ))
+                    }
+                    _ => db_error(err),
+                })?;
+            record_rows(result.rows_affected());
+
+            tx.commit().await.map_err(db_error)?;
+
+            Ok(value)
+        })
+        .await
+    }
+
+    async fn insert_as(
+        &self,
+        env: &str,
+        key: &str,
+        stored: SimpleExpr,
+        var: &str,
+        expires_at: Option<i64>,
+    ) -> EnvelopeResult<InsertOutcome> {
+        self.retry("insert", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+            self.check_values(env, [(key, var)]).await?;
+
+            let mut tx = self.db.begin().await.map_err(db_error)?;
+
+            let (sql, values) = Query::select()
+                .from(self.table(Environments::Table))
+                .columns([Environments::Value, Environments::ExpiresAt])
+                .and_where(Expr::col(Environments::Env).eq(env))
+                .and_where(Expr::col(Environments::Key).eq(stored.clone()))
+                .order_by(Environments::CreatedAt, Order::Desc)
+                .limit(1)
+                .to_sqlite();
+            let latest: Option<(Option<String>, Option<i64>)> = sqlx::query_as_with(&sql, values)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(db_error)?;
+            // an expired value is replaced like a deleted one
+            let previous = latest.and_then(|(value, expires_at)| match expires_at {
+                Some(at) if !self.include_expired && at <= unix_now() => None,
+                _ => value,
+            });
+            if self.dedupe && previous.as_deref() == Some(var) {
+                return Ok(InsertOutcome {
+                    changed: false,
+                    previous,
+                });
+            }
+
+            let (sql, values) = Query::insert()
+                .into_table(self.table(Environments::Table))
+                .columns([
+                    Environments::Env,
+                    Environments::Key,
+                    Environments::Value,
+                    Environments::ExpiresAt,
+                ])
+                .values([env.into(), stored.clone(), var.into(), expires_at.into()])
+                .unwrap()
+                .to_sqlite();
+
+            let result = sqlx::query_with(&sql, values)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+            tx.commit().await.map_err(db_error)?;
+
+            record_rows(result.rows_affected());
+
+            Ok(InsertOutcome {
+                changed: previous.as_deref() != Some(var),
+                previous,
+            })
+        })
+        .await
+    }
+
+    /// sets each `(key, value)` of `vars` in `env` in a single transaction,
+    /// as [`EnvelopeDb::insert`] would one by one. The rows are written by
+    /// batches of [`INSERT_BATCH`], which share one prepared statement.
+    /// When a key is repeated the last value wins, see
+    /// [`EnvelopeDb::set_fast_import`].
+    ///
+    /// A variable already set to its value is not written again, so that
+    /// importing the same file twice does not fill its history. A deleted or
+    /// expired variable is written, even with an empty value.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, vars), fields(rows))
+    )]
+    pub async fn insert_many(
+        &self,
+        env: &str,
+        vars: &[(String, String)],
+    ) -> EnvelopeResult<InsertManyOutcome> {
+        count!(INSERTS_COUNTER, "insert_many");
+        self.retry("insert_many", || async {
+            // uppercased like sqlite's upper() does for the other inserts
+            let vars: BTreeMap<String, &str> = vars
+                .iter()
+                .map(|(key, value)| (key.to_ascii_uppercase(), value.as_str()))
+                .collect();
+            if vars.is_empty() {
+                return Ok(InsertManyOutcome::default());
+            }
+
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+            self.check_values(env, vars.iter().map(|(key, value)| (key.as_str(), *value)))
+                .await?;
+
+            let mut conn = self.db.acquire().await.map_err(db_error)?;
+            let synchronous: Option<i64> = match self.fast_import {
+                true => {
+                    let level = sqlx::query_scalar("PRAGMA synchronous")
+                        .fetch_one(&mut *conn)
+                        .await
+                        .map_err(db_error)?;
+                    conn.execute("PRAGMA synchronous = OFF")
+                        .await
+                        .map_err(db_error)?;
+                    Some(level)
+                }
+                false => None,
+            };
+
+            let outcome = self.insert_batches(&mut conn, env, &vars).await;
+            // the connection goes back to the pool, it gets its level back even
+            // when the import failed
+            if let Some(level) = synchronous {
+                conn.execute(format!("PRAGMA synchronous = {}", level).as_str())
+                    .await
+                    .map_err(db_error)?;
+            }
+            let outcome = outcome?;
+            record_rows(outcome.written);
+
+            Ok(outcome)
+        })
+        .await
+    }
+
+    async fn insert_batches(
+        &self,
+        conn: &mut SqliteConnection,
+        env: &str,
+        vars: &BTreeMap<String, &str>,
+    ) -> EnvelopeResult<InsertManyOutcome> {
+        let mut tx = conn.begin().await.map_err(db_error)?;
+
+        let vars: Vec<_> = vars.iter().collect();
+        let mut outcome = InsertManyOutcome::default();
+        for batch in vars.chunks(INSERT_BATCH) {
+            // compared inside the transaction, no write can come in between
+            let (sql, values) = self
+                .live_in(env)
+                .and_where(
+                    Expr::col(Environments::Key).is_in(batch.iter().map(|(key, _)| key.as_str())),
+                )
+                .to_sqlite();
+            let current: BTreeMap<String, String> = sqlx::query_as_with(&sql, values)
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(db_error)?
+                .into_iter()
+                .collect();
+            let changed: Vec<_> = batch
+                .iter()
+                .filter(|(key, value)| current.get(key.as_str()).map(String::as_str) != Some(value))
+                .collect();
+            outcome.unchanged += (batch.len() - changed.len()) as u64;
+            if changed.is_empty() {
+                continue;
+            }
+
+            let mut insert = Query::insert()
+                .into_table(self.table(Environments::Table))
+                .columns([Environments::Env, Environments::Key, Environments::Value])
+                .to_owned();
+            for (key, value) in changed {
+                insert
+                    .values([env.into(), key.as_str().into(), (**value).into()])
+                    .unwrap();
+            }
+
+            let (sql, values) = insert.to_sqlite();
+            let result = sqlx::query_with(&sql, values)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+            outcome.written += result.rows_affected();
+        }
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(outcome)
+    }
+
+    /// returns the current value of the variable of `env` whose key is
+    /// exactly `key`, None if it is not set
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn get_var_exact(&self, env: &str, key: &str) -> EnvelopeResult<Option<String>> {
+        count!(READS_COUNTER, "get_var_exact");
+        let latest = Query::select()
+            .from(self.table(Environments::Table))
+            .columns([Environments::Value, Environments::ExpiresAt])
             .and_where(Expr::col(Environments::Env).eq(env))
-            .group_by_columns([Environments::Env, Environments::Key])
-            .and_having(Expr::col(Environments::CreatedAt).max())
+            .and_where(Expr::col(Environments::Key).eq(key))
+            .order_by(Environments::CreatedAt, Order::Desc)
+            .limit(1)
             .to_owned();
+        let (sql, values) = Query::select()
+            .from_subquery(latest, Alias::new("T"))
+            .column(Environments::Value)
+            .cond_where(self.unexpired())
+            .to_sqlite();
 
-        let mut select = Query::select()
-            .from_subquery(select, Alias::new("T"))
+        let latest: Option<(Option<String>,)> = sqlx::query_as_with(&sql, values)
+            .fetch_optional(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        Ok(latest.and_then(|(value,)| value))
+    }
+
+    /// sets the description of `key` in environment `env`, replacing the
+    /// previous one if present
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, description))
+    )]
+    pub async fn set_description(
+        &self,
+        env: &str,
+        key: &str,
+        description: &str,
+    ) -> EnvelopeResult<()> {
+        self.retry("set_description", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+
+            let (sql, values) = Query::insert()
+                .into_table(self.table(Descriptions::Table))
+                .columns([
+                    Descriptions::Env,
+                    Descriptions::Key,
+                    Descriptions::Description,
+                ])
+                .values([env.into(), Func::upper(key).into(), description.into()])
+                .unwrap()
+                .on_conflict(
+                    OnConflict::columns([Descriptions::Env, Descriptions::Key])
+                        .update_column(Descriptions::Description)
+                        .to_owned(),
+                )
+                .to_sqlite();
+
+            sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// returns the descriptions of the variables in environment `env`
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn list_descriptions(&self, env: &str) -> EnvelopeResult<BTreeMap<String, String>> {
+        count!(READS_COUNTER, "list_descriptions");
+        let (sql, values) = Query::select()
+            .from(self.table(Descriptions::Table))
+            .columns([Descriptions::Key, Descriptions::Description])
+            .and_where(Expr::col(Descriptions::Env).eq(env))
+            .to_sqlite();
+
+        let descriptions: Vec<(String, String)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        Ok(descriptions.into_iter().collect())
+    }
+
+    /// annotates `key` in `env` with the type its values must have, writes
+    /// of a value of another type fail from then on. `None` removes the
+    /// annotation.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn set_var_type(
+        &self,
+        env: &str,
+        key: &str,
+        value_type: Option<ValueType>,
+    ) -> EnvelopeResult<()> {
+        self.retry("set_var_type", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+
+            let (sql, values) = match value_type {
+                Some(value_type) => Query::insert()
+                    .into_table(self.table(Types::Table))
+                    .columns([Types::Env, Types::Key, Types::Type])
+                    .values([
+                        env.into(),
+                        Func::upper(key).into(),
+                        value_type.as_str().into(),
+                    ])
+                    .unwrap()
+                    .on_conflict(
+                        OnConflict::columns([Types::Env, Types::Key])
+                            .update_column(Types::Type)
+                            .to_owned(),
+                    )
+                    .to_sqlite(),
+                None => Query::delete()
+                    .from_table(self.table(Types::Table))
+                    .and_where(Expr::col(Types::Env).eq(env))
+                    .and_where(Expr::col(Types::Key).eq(key.to_ascii_uppercase()))
+                    .to_sqlite(),
+            };
+
+            sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// returns the types the variables of `env` are annotated with
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn list_types(&self, env: &str) -> EnvelopeResult<BTreeMap<String, ValueType>> {
+        let (sql, values) = Query::select()
+            .from(self.table(Types::Table))
+            .columns([Types::Key, Types::Type])
+            .and_where(Expr::col(Types::Env).eq(env))
+            .to_sqlite();
+
+        let types: Vec<(String, String)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        types
+            .into_iter()
+            .map(|(key, value_type)| {
+                let value_type = value_type.parse().map_err(|e| {
+                    EnvelopeError::Constraint(format!("{} of {} in {}", e, key, env))
+                })?;
+                Ok((key, value_type))
+            })
+            .collect()
+    }
+
+    /// fails with [`EnvelopeError::Constraint`] if one of `vars` contains a
+    /// NUL byte or is not of the type its key is annotated with in `env`.
+    ///
+    /// Values are stored as they are given, multi-byte UTF-8 included, and
+    /// read back unchanged. NUL bytes are rejected rather than stored since
+    /// an environment variable cannot hold them: the value could never be
+    /// exported, and sqlite's own string functions stop at the first one.
+    async fn check_values<'a, I>(&self, env: &str, vars: I) -> EnvelopeResult<()>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)> + Clone,
+    {
+        check_nul(env, vars.clone())?;
+
+        let types = self.list_types(env).await?;
+        check_types(env, &types, vars)
+    }
+
+    /// fails like [`EnvelopeDb::check_values`] if a row of `select` does
+    /// not match the type of its key in `env`. `select` lists the
+    /// `(env, key, value, expires_at)` an insert-select writes to `env`, its
+    /// rows are only read when `env` has typed keys, and only theirs.
+    async fn check_selected(&self, env: &str, mut select: SelectStatement) -> EnvelopeResult<()> {
+        let types = self.list_types(env).await?;
+        if types.is_empty() {
+            return Ok(());
+        }
+
+        let (sql, values) = select
+            .and_where(Expr::col(Environments::Key).is_in(types.keys().map(String::as_str)))
+            .to_sqlite();
+        let rows: Vec<(String, String, String, Option<i64>)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        check_types(
+            env,
+            &types,
+            rows.iter()
+                .map(|(_, key, value, _)| (key.as_str(), value.as_str())),
+        )
+    }
+
+    /// inserts the `(env, key, value, expires_at)` rows of `select`, returns
+    /// how many there were
+    async fn insert_selected(&self, select: SelectStatement) -> EnvelopeResult<u64> {
+        let (sql, values) = Query::insert()
+            .into_table(self.table(Environments::Table))
             .columns([
                 Environments::Env,
                 Environments::Key,
-                Environments::CreatedAt,
-            ])
-            .and_where(Expr::col(Environments::Value).is_not_null())
-            .and_where(Expr::col(Environments::Env).eq(env))
-            .group_by_col(Environments::Key)
-            .and_having(Expr::col(Environments::CreatedAt).max())
-            .order_by_columns([
-                (Environments::Env, Order::Desc),
-                (Environments::Key, Order::Desc),
+                Environments::Value,
+                Environments::ExpiresAt,
             ])
-            .to_owned();
+            .select_from(select)
+            .unwrap()
+            .to_sqlite();
 
-        match truncate {
-            Truncate::None => select.column(Environments::Value),
-            Truncate::Range(x, y) => select.expr(
-                Expr::cust(format!("substr(value, {}, {}) as value", x, y))
-                    .cast_as(Alias::new("value")),
-            ),
-        };
+        let result = sqlx::query_with(&sql, values)
+            .execute(&self.db)
+            .await
+            .map_err(db_error)?;
+        record_rows(result.rows_affected());
 
-        let (sql, values) = select.build_sqlx(SqliteQueryBuilder);
-        sqlx::query_as_with(&sql, values)
+        Ok(result.rows_affected())
+    }
+
+    /// adds `keys` to the template `name`, creating it if needed. Keys are
+    /// uppercased like variables are.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn add_template_keys(&self, name: &str, keys: &[String]) -> EnvelopeResult<()> {
+        self.retry("add_template_keys", || async {
+            let _guard = self.write_guard().await?;
+            if keys.is_empty() {
+                return Ok(());
+            }
+
+            let mut insert = Query::insert()
+                .into_table(self.table(Templates::Table))
+                .columns([Templates::Name, Templates::Key])
+                .on_conflict(
+                    OnConflict::columns([Templates::Name, Templates::Key])
+                        .do_nothing()
+                        .to_owned(),
+                )
+                .to_owned();
+            for key in keys {
+                insert
+                    .values([name.into(), key.to_ascii_uppercase().into()])
+                    .unwrap();
+            }
+            let (sql, values) = insert.to_sqlite();
+
+            sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// removes `keys` from the template `name`, the whole template when `keys`
+    /// is empty. Returns how many keys have been removed.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn remove_template_keys(&self, name: &str, keys: &[String]) -> EnvelopeResult<u64> {
+        self.retry("remove_template_keys", || async {
+            let _guard = self.write_guard().await?;
+            let mut delete = Query::delete()
+                .from_table(self.table(Templates::Table))
+                .and_where(Expr::col(Templates::Name).eq(name))
+                .to_owned();
+            if !keys.is_empty() {
+                delete.and_where(
+                    Expr::col(Templates::Key).is_in(keys.iter().map(|k| k.to_ascii_uppercase())),
+                );
+            }
+            let (sql, values) = delete.to_sqlite();
+
+            let result = sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+            record_rows(result.rows_affected());
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    /// lists the templates sorted by name, a template exists as long as it
+    /// has a key
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn list_templates(&self) -> EnvelopeResult<Vec<Template>> {
+        count!(READS_COUNTER, "list_templates");
+        let (sql, values) = Query::select()
+            .from(self.table(Templates::Table))
+            .columns([Templates::Name, Templates::Key])
+            .order_by(Templates::Name, Order::Asc)
+            .order_by(Templates::Key, Order::Asc)
+            .to_sqlite();
+
+        let rows: Vec<(String, String)> = sqlx::query_as_with(&sql, values)
             .fetch_all(&self.db)
             .await
-            .map_err(|e| std_err!("db error: {}", e))
+            .map_err(db_error)?;
+
+        let mut templates: Vec<Template> = Vec::new();
+        for (name, key) in rows {
+            match templates.last_mut() {
+                Some(template) if template.name == name => template.keys.push(key),
+                _ => templates.push(Template {
+                    name,
+                    keys: vec![key],
+                }),
+            }
+        }
+
+        Ok(templates)
     }
 
-    // lists environments present in the database. Environments that only contain deletes variables
-    // will be listed as well.
-    pub async fn list_environments(&self) -> io::Result<Vec<Environment>> {
-        let (sql, _) = Query::select()
-            .from(Environments::Table)
-            .column(Environments::Env)
-            .distinct()
-            .build_sqlx(SqliteQueryBuilder);
+    /// returns the keys of `template` that `env` does not set, sorted. Fails
+    /// if the template does not exist.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn validate_against_template(
+        &self,
+        env: &str,
+        template: &str,
+    ) -> EnvelopeResult<Vec<String>> {
+        let (sql, values) = Query::select()
+            .from(self.table(Templates::Table))
+            .column(Templates::Key)
+            .and_where(Expr::col(Templates::Name).eq(template))
+            .order_by(Templates::Key, Order::Asc)
+            .to_sqlite();
 
-        sqlx::query_as(&sql)
+        let required: Vec<(String,)> = sqlx::query_as_with(&sql, values)
             .fetch_all(&self.db)
             .await
-            .map_err(|e| std_err!("db error: {}", e))
+            .map_err(db_error)?;
+        if required.is_empty() {
+            return Err(EnvelopeError::TemplateNotFound(template.to_string()));
+        }
+
+        let set: BTreeSet<String> = self
+            .list_var_in_env(env, SortOrder::Asc)
+            .await?
+            .into_iter()
+            .map(|row| row.key)
+            .collect();
+
+        Ok(required
+            .into_iter()
+            .map(|(key,)| key)
+            .filter(|key| !set.contains(key))
+            .collect())
     }
-}
 
-pub enum Truncate {
-    None,
-    Range(u32, u32),
-}
+    /// soft deletes all variables in an environment by setting all their
+    /// values to NULL
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn delete_env(&self, env: &str) -> EnvelopeResult<()> {
+        count!(DELETES_COUNTER, "delete_env");
+        self.retry("delete_env", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
 
-#[cfg(test)]
-pub async fn test_db() -> EnvelopeDb {
-    let pool = sqlx::sqlite::SqlitePoolOptions::new()
-        .connect(":memory:")
+            let select = Query::select()
+                .from(self.table(Environments::Table))
+                .column(Environments::Env)
+                .column(Environments::Key)
+                .expr(Expr::val(Option::<i32>::None))
+                .and_where(Expr::col(Environments::Env).eq(env))
+                .and_where(Expr::col(Environments::Value).is_not_null())
+                .group_by_columns([Environments::Env, Environments::Key])
+                .to_owned();
+
+            let (sql, values) = Query::insert()
+                .into_table(self.table(Environments::Table))
+                .columns([Environments::Env, Environments::Key, Environments::Value])
+                .select_from(select)
+                .unwrap()
+                .to_sqlite();
+
+            let result = sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+
+            record_rows(result.rows_affected());
+
+            Ok(())
+        })
         .await
-        .expect("cannot connect to db");
+    }
+
+    /// soft deletes all variables with key `key`
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn delete_var_all(&self, key: &str) -> EnvelopeResult<()> {
+        count!(DELETES_COUNTER, "delete_var_all");
+        self.retry("delete_var_all", || async {
+            let _guard = self.write_guard().await?;
+            // only the environments where `key` is still set are written to,
+            // one that deleted it already may be locked since
+            let (sql, values) = Query::select()
+                .from(self.table(LatestVars::Table))
+                .column(Environments::Env)
+                .and_where(Expr::col(Environments::Key).eq(key))
+                .and_where(Expr::col(Environments::Value).is_not_null())
+                .to_sqlite();
+
+            let envs: Vec<(String,)> = sqlx::query_as_with(&sql, values)
+                .fetch_all(&self.db)
+                .await
+                .map_err(db_error)?;
+            let envs: Vec<String> = envs.into_iter().map(|(env,)| env).collect();
+            self.ensure_unlocked(&envs).await?;
+
+            let select = Query::select()
+                .from(self.table(LatestVars::Table))
+                .column(Environments::Env)
+                .column(Environments::Key)
+                .expr(Expr::val(Option::<i32>::None))
+                .and_where(Expr::col(Environments::Key).eq(key))
+                .and_where(Expr::col(Environments::Value).is_not_null())
+                .to_owned();
+
+            let (sql, values) = Query::insert()
+                .into_table(self.table(Environments::Table))
+                .columns([Environments::Env, Environments::Key, Environments::Value])
+                .select_from(select)
+                .unwrap()
+                .to_sqlite();
+
+            let result = sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
 
-    sqlx::migrate!("./migrations").run(&pool).await.unwrap();
+            record_rows(result.rows_affected());
 
-    EnvelopeDb::with(pool)
+            Ok(())
+        })
+        .await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn delete_var_for_env(&self, env: &str, key: &str) -> EnvelopeResult<()> {
+        count!(DELETES_COUNTER, "delete_var_for_env");
+        self.retry("delete_var_for_env", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+
+            let select = Query::select()
+                .from(self.table(Environments::Table))
+                .column(Environments::Env)
+                .column(Environments::Key)
+                .expr(Expr::val(Option::<i32>::None))
+                .and_where(Expr::col(Environments::Env).eq(env))
+                .and_where(Expr::col(Environments::Key).eq(key))
+                .and_where(Expr::col(Environments::Value).is_not_null())
+                .group_by_columns([Environments::Env, Environments::Key])
+                .to_owned();
+
+            let (sql, values) = Query::insert()
+                .into_table(self.table(Environments::Table))
+                .columns([Environments::Env, Environments::Key, Environments::Value])
+                .select_from(select)
+                .unwrap()
+                .to_sqlite();
+
+            let result = sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+
+            record_rows(result.rows_affected());
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// deletes environment from database entirely, along with its
+    /// description and lock, in a single transaction
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn drop_env(&self, env: &str) -> EnvelopeResult<()> {
+        count!(DELETES_COUNTER, "drop_env");
+        self.retry("drop_env", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_hard_deletes("drop_env").await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+
+            let mut tx = self.db.begin().await.map_err(db_error)?;
+
+            let (sql, values) = Query::delete()
+                .from_table(self.table(Environments::Table))
+                .and_where(Expr::col(Environments::Env).eq(env))
+                .to_sqlite();
+
+            let result = sqlx::query_with(&sql, values)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+
+            record_rows(result.rows_affected());
+
+            let (sql, values) = Query::delete()
+                .from_table(self.table(Descriptions::Table))
+                .and_where(Expr::col(Descriptions::Env).eq(env))
+                .to_sqlite();
+
+            sqlx::query_with(&sql, values)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+
+            let (sql, values) = Query::delete()
+                .from_table(self.table(LockedEnvs::Table))
+                .and_where(Expr::col(LockedEnvs::Env).eq(env))
+                .to_sqlite();
+
+            sqlx::query_with(&sql, values)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+
+            tx.commit().await.map_err(db_error)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// collapses the history of `env` so that only the current value of each
+    /// variable is kept, older versions and deleted variables are removed in a
+    /// single transaction
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn flatten_env(&self, env: &str) -> EnvelopeResult<()> {
+        count!(DELETES_COUNTER, "flatten_env");
+        self.retry("flatten_env", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_hard_deletes("flatten_env").await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+
+            let mut tx = self.db.begin().await.map_err(db_error)?;
+
+            let (sql, values) = Query::delete()
+                .from_table(self.table(Environments::Table))
+                .and_where(Expr::col(Environments::Env).eq(env))
+                .cond_where(any![
+                    Expr::col(Environments::CreatedAt).lt(self.latest_version()),
+                    Expr::col(Environments::Value).is_null(),
+                ])
+                .to_sqlite();
+
+            let result = sqlx::query_with(&sql, values)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+
+            record_rows(result.rows_affected());
+
+            tx.commit().await.map_err(db_error)?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// deletes the versions of the variables of `env` created before the unix
+    /// timestamp `ts`, the current value of a variable is kept however old it
+    /// is. Returns how many versions were removed.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn purge_older_than(&self, env: &str, ts: i64) -> EnvelopeResult<u64> {
+        count!(DELETES_COUNTER, "purge_older_than");
+        self.retry("purge_older_than", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_hard_deletes("purge_older_than").await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+
+            // a deletion older than `ts` goes along with every version before it
+            let (sql, values) = Query::delete()
+                .from_table(self.table(Environments::Table))
+                .and_where(Expr::col(Environments::Env).eq(env))
+                .and_where(Expr::col(Environments::CreatedAt).lt(ts))
+                .cond_where(any![
+                    Expr::col(Environments::CreatedAt).lt(self.latest_version()),
+                    Expr::col(Environments::Value).is_null(),
+                ])
+                .to_sqlite();
+
+            let result = sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+            record_rows(result.rows_affected());
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    /// removes for good the variables whose ttl has passed along with their
+    /// history, and the expired versions that have been replaced since.
+    /// Returns how many versions were removed.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn purge_expired(&self) -> EnvelopeResult<u64> {
+        count!(DELETES_COUNTER, "purge_expired");
+        self.retry("purge_expired", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_hard_deletes("purge_expired").await?;
+            let now = unix_now();
+
+            let expired = Query::select()
+                .columns([Environments::Env, Environments::Key])
+                .from(self.table(LatestVars::Table))
+                .and_where(Expr::col(Environments::ExpiresAt).lte(now))
+                .to_owned();
+            let (sql, values) = Query::delete()
+                .from_table(self.table(Environments::Table))
+                .cond_where(any![
+                    Expr::tuple([
+                        Expr::col(Environments::Env).into(),
+                        Expr::col(Environments::Key).into(),
+                    ])
+                    .in_subquery(expired),
+                    Expr::col(Environments::ExpiresAt).lte(now),
+                ])
+                .to_sqlite();
+
+            let result = sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+            record_rows(result.rows_affected());
+
+            Ok(result.rows_affected())
+        })
+        .await
+    }
+
+    /// sets `key` to `value` in every environment of `envs` in a single
+    /// transaction, returns the outcome of the operation for each environment
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, value), fields(rows))
+    )]
+    pub async fn set_in_envs(
+        &self,
+        envs: &[String],
+        key: &str,
+        value: &str,
+    ) -> EnvelopeResult<Vec<(String, SetOutcome)>> {
+        count!(INSERTS_COUNTER, "set_in_envs");
+        self.retry("set_in_envs", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(envs).await?;
+            for env in envs {
+                self.check_values(env, [(key, value)]).await?;
+            }
+
+            let mut tx = self.db.begin().await.map_err(db_error)?;
+
+            let mut outcomes = Vec::with_capacity(envs.len());
+            for env in envs {
+                let (sql, values) = Query::select()
+                    .from(self.table(Environments::Table))
+                    .columns([Environments::Value, Environments::ExpiresAt])
+                    .and_where(Expr::col(Environments::Env).eq(env.as_str()))
+                    .and_where(Expr::col(Environments::Key).eq(Func::upper(key)))
+                    .order_by(Environments::CreatedAt, Order::Desc)
+                    .limit(1)
+                    .to_sqlite();
+
+                let previous: Option<(Option<String>, Option<i64>)> =
+                    sqlx::query_as_with(&sql, values)
+                        .fetch_optional(&mut *tx)
+                        .await
+                        .map_err(db_error)?;
+                let unchanged = matches!(&previous, Some((Some(previous), expires_at))
+                    if previous == value
+                        && expires_at.is_none_or(|at| self.include_expired || at > unix_now()));
+                if self.dedupe && unchanged {
+                    outcomes.push((env.clone(), SetOutcome::Unchanged));
+                    continue;
+                }
+
+                let (sql, values) = Query::insert()
+                    .into_table(self.table(Environments::Table))
+                    .columns([Environments::Env, Environments::Key, Environments::Value])
+                    .values([env.into(), Func::upper(key).into(), value.into()])
+                    .unwrap()
+                    .to_sqlite();
+
+                sqlx::query_with(&sql, values)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(db_error)?;
+
+                let outcome = match previous.and_then(|(v, _)| v) {
+                    Some(previous) => SetOutcome::Updated { previous },
+                    None => SetOutcome::Created,
+                };
+                outcomes.push((env.clone(), outcome));
+            }
+
+            tx.commit().await.map_err(db_error)?;
+            let written = outcomes
+                .iter()
+                .filter(|(_, outcome)| *outcome != SetOutcome::Unchanged)
+                .count();
+            record_rows(written as u64);
+
+            Ok(outcomes)
+        })
+        .await
+    }
+
+    /// renames `old_key` to `new_key` in `env`, see
+    /// [`EnvelopeDb::rename_var_everywhere`]. Fails if `old_key` is not set in
+    /// `env`.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn rename_var(&self, env: &str, old_key: &str, new_key: &str) -> EnvelopeResult<()> {
+        count!(INSERTS_COUNTER, "rename_var");
+        self.rename(Some(env), old_key, new_key).await.map(|_| ())
+    }
+
+    /// renames `old_key` to `new_key` in every environment where it is set,
+    /// at once, and returns these environments. The value is set under the
+    /// new key and the old key is deleted, its history is kept. Nothing is
+    /// renamed if `new_key` is already set in any of these environments.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn rename_var_everywhere(
+        &self,
+        old_key: &str,
+        new_key: &str,
+    ) -> EnvelopeResult<Vec<String>> {
+        count!(INSERTS_COUNTER, "rename_var_everywhere");
+        self.rename(None, old_key, new_key).await
+    }
+
+    /// merges the keys of `env` that differ only by case into their
+    /// uppercased key, at once. The value of the newest variant is kept and
+    /// the other variants are deleted, their history is kept. Returns the
+    /// merged keys.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn dedupe_case(&self, env: &str) -> EnvelopeResult<Vec<CaseDuplicate>> {
+        count!(INSERTS_COUNTER, "dedupe_case");
+        self.retry("dedupe_case", || async {
+            let _guard = self.write_guard().await?;
+            let duplicates = self.case_duplicates(Some(env)).await?;
+            if duplicates.is_empty() {
+                return Ok(Vec::new());
+            }
+            self.ensure_unlocked(&[env.into()]).await?;
+            self.check_values(
+                env,
+                duplicates
+                    .iter()
+                    .map(|(duplicate, value)| (duplicate.key.as_str(), value.as_str())),
+            )
+            .await?;
+
+            let mut insert = Query::insert()
+                .into_table(self.table(Environments::Table))
+                .columns([Environments::Env, Environments::Key, Environments::Value])
+                .to_owned();
+            for (duplicate, value) in &duplicates {
+                if duplicate.variants[0] != duplicate.key {
+                    insert
+                        .values([env.into(), duplicate.key.as_str().into(), value.into()])
+                        .unwrap();
+                }
+                for variant in duplicate.variants.iter().filter(|v| **v != duplicate.key) {
+                    insert
+                        .values([
+                            env.into(),
+                            variant.as_str().into(),
+                            Option::<String>::None.into(),
+                        ])
+                        .unwrap();
+                }
+            }
+
+            let (sql, values) = insert.to_sqlite();
+            let result = sqlx::query_with(&sql, values)
+                .execute(&self.db)
+                .await
+                .map_err(db_error)?;
+            record_rows(result.rows_affected());
+
+            Ok(duplicates
+                .into_iter()
+                .map(|(duplicate, _)| duplicate)
+                .collect())
+        })
+        .await
+    }
+
+    async fn rename(
+        &self,
+        env: Option<&str>,
+        old_key: &str,
+        new_key: &str,
+    ) -> EnvelopeResult<Vec<String>> {
+        self.retry("rename", || async {
+            let old_key = old_key.to_ascii_uppercase();
+            let new_key = new_key.to_ascii_uppercase();
+            if old_key == new_key {
+                return Err(EnvelopeError::Constraint(format!(
+                    "cannot rename {} to itself",
+                    old_key
+                )));
+            }
+
+            let _guard = self.write_guard().await?;
+            let (sql, values) = self.live_values_of(&old_key, env).to_sqlite();
+            let renamed: Vec<(String, String)> = sqlx::query_as_with(&sql, values)
+                .fetch_all(&self.db)
+                .await
+                .map_err(db_error)?;
+            match env {
+                _ if renamed.is_empty() => {
+                    return Err(EnvelopeError::KeyNotFound {
+                        env: env.map(str::to_string),
+                        key: old_key,
+                    })
+                }
+                _ => {}
+            }
+
+            let envs: Vec<String> = renamed.iter().map(|(env, _)| env.clone()).collect();
+            self.ensure_unlocked(&envs).await?;
+            for (env, value) in &renamed {
+                self.check_values(env, [(new_key.as_str(), value.as_str())])
+                    .await?;
+            }
+
+            let mut tx = self.db.begin().await.map_err(db_error)?;
+
+            let (sql, values) = Query::select()
+                .from_subquery(self.live_values_of(&new_key, None), Alias::new("T"))
+                .column(Environments::Env)
+                .and_where(Expr::col(Environments::Env).is_in(envs.iter().map(String::as_str)))
+                .to_sqlite();
+            let conflicts: Vec<(String,)> = sqlx::query_as_with(&sql, values)
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(db_error)?;
+            if !conflicts.is_empty() {
+                let conflicts: Vec<String> = conflicts.into_iter().map(|(env,)| env).collect();
+                return Err(EnvelopeError::Conflict(format!(
+                    "key {} already exists in {}",
+                    new_key,
+                    conflicts.join(", ")
+                )));
+            }
+
+            let mut insert = Query::insert()
+                .into_table(self.table(Environments::Table))
+                .columns([Environments::Env, Environments::Key, Environments::Value])
+                .to_owned();
+            for (env, value) in renamed {
+                insert
+                    .values([env.as_str().into(), new_key.as_str().into(), value.into()])
+                    .unwrap();
+                insert
+                    .values([
+                        env.into(),
+                        old_key.as_str().into(),
+                        Option::<String>::None.into(),
+                    ])
+                    .unwrap();
+            }
+            let (sql, values) = insert.to_sqlite();
+            let result = sqlx::query_with(&sql, values)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+            record_rows(result.rows_affected());
+
+            tx.commit().await.map_err(db_error)?;
+
+            Ok(envs)
+        })
+        .await
+    }
+
+    /// applies `diff` to `env` at once: the added and changed variables are
+    /// set to their new value and the removed ones are deleted
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, diff), fields(rows))
+    )]
+    pub async fn apply_diff(&self, env: &str, diff: &EnvDiff) -> EnvelopeResult<()> {
+        count!(INSERTS_COUNTER, "apply_diff");
+        self.retry("apply_diff", || async {
+            if diff.is_empty() {
+                return Ok(());
+            }
+
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+
+            self.write_diff(env, diff).await
+        })
+        .await
+    }
+
+    /// makes `desired` the variables of `env`, writing only what differs: a
+    /// new version for the added and changed variables and a deletion for
+    /// the ones missing from `desired`. The variables already set to their
+    /// desired value keep their history untouched. The state is read and
+    /// written while holding the write lock, see [`EnvelopeDb::apply_diff`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, desired), fields(rows))
+    )]
+    pub async fn reconcile(
+        &self,
+        env: &str,
+        desired: &BTreeMap<String, String>,
+    ) -> EnvelopeResult<ReconcileReport> {
+        count!(INSERTS_COUNTER, "reconcile");
+        self.retry("reconcile", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+
+            let current = self.live_vars(env).await?;
+            // uppercased like sqlite's upper() does for the stored keys
+            let desired: BTreeMap<String, String> = desired
+                .iter()
+                .map(|(key, value)| (key.to_ascii_uppercase(), value.clone()))
+                .collect();
+            let unchanged = desired
+                .iter()
+                .filter(|(key, value)| current.get(*key) == Some(*value))
+                .count();
+
+            let diff = EnvDiff::between(current, desired);
+            if !diff.is_empty() {
+                self.write_diff(env, &diff).await?;
+            }
+
+            Ok(ReconcileReport { diff, unchanged })
+        })
+        .await
+    }
+
+    /// describes what turns `base_env` into `target_env` as a JSON patch, the
+    /// [`EnvDiff`] between their variables. [`EnvelopeDb::apply_patch`]
+    /// replays it on another environment, of this database or another one.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn export_diff_patch(
+        &self,
+        base_env: &str,
+        target_env: &str,
+    ) -> EnvelopeResult<String> {
+        self.check_env_exists(base_env).await?;
+        self.check_env_exists(target_env).await?;
+
+        let diff = self.diff_envs(base_env, target_env).await?;
+
+        Ok(serde_json::to_string_pretty(&diff).map_err(io::Error::from)?)
+    }
+
+    /// compares the current variables of `base_env` and `target_env` in a
+    /// single query: sqlite has no full outer join, the variables of
+    /// `base_env` left joined to those of `target_env` are completed by the
+    /// ones only `target_env` has. A missing environment has no variables.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn diff_envs(&self, base_env: &str, target_env: &str) -> EnvelopeResult<EnvDiff> {
+        count!(READS_COUNTER, "diff_envs");
+        let (base, target) = (Alias::new("B"), Alias::new("T"));
+        let same_key = |left: &Alias, right: &Alias| {
+            Expr::col((left.clone(), Environments::Key)).equals((right.clone(), Environments::Key))
+        };
+
+        let only_target = Query::select()
+            .column((target.clone(), Environments::Key))
+            .expr(Expr::val(Option::<String>::None))
+            .column((target.clone(), Environments::Value))
+            .from_subquery(self.live_in(target_env), target.clone())
+            .join_subquery(
+                JoinType::LeftJoin,
+                self.live_in(base_env),
+                base.clone(),
+                same_key(&base, &target),
+            )
+            .and_where(Expr::col((base.clone(), Environments::Key)).is_null())
+            .to_owned();
+        let (sql, values) = Query::select()
+            .column((base.clone(), Environments::Key))
+            .column((base.clone(), Environments::Value))
+            .column((target.clone(), Environments::Value))
+            .from_subquery(self.live_in(base_env), base.clone())
+            .join_subquery(
+                JoinType::LeftJoin,
+                self.live_in(target_env),
+                target.clone(),
+                same_key(&target, &base),
+            )
+            .cond_where(any![
+                Expr::col((target.clone(), Environments::Value)).is_null(),
+                Expr::col((target, Environments::Value)).ne(Expr::col((base, Environments::Value))),
+            ])
+            .union(UnionType::All, only_target)
+            .to_sqlite();
+
+        let rows: Vec<(String, Option<String>, Option<String>)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        let mut diff = EnvDiff::default();
+        for (key, old, new) in rows {
+            match (old, new) {
+                (Some(old), Some(new)) => {
+                    diff.changed.insert(key, (old, new));
+                }
+                (Some(old), None) => {
+                    diff.removed.insert(key, old);
+                }
+                (None, Some(new)) => {
+                    diff.added.insert(key, new);
+                }
+                (None, None) => {}
+            }
+        }
+
+        Ok(diff)
+    }
+
+    /// applies the `patch` written by [`EnvelopeDb::export_diff_patch`] to
+    /// `env` and returns it. The patch only applies to the state it was made
+    /// from: it fails with [`EnvelopeError::Conflict`], writing nothing, if a
+    /// changed or removed variable does not have its old value in `env`, or
+    /// if an added one is already set.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, patch), fields(rows))
+    )]
+    pub async fn apply_patch(&self, env: &str, patch: &str) -> EnvelopeResult<EnvDiff> {
+        count!(INSERTS_COUNTER, "apply_patch");
+        self.retry("apply_patch", || async {
+            let diff: EnvDiff = serde_json::from_str(patch).map_err(|e| EnvelopeError::Parse {
+                file: "patch".to_string(),
+                line: e.line(),
+                message: e.to_string(),
+            })?;
+
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[env.into()]).await?;
+
+            let current = self.live_vars(env).await?;
+            let expected = diff
+                .changed
+                .iter()
+                .map(|(key, (old, _))| (key, Some(old)))
+                .chain(diff.removed.iter().map(|(key, old)| (key, Some(old))))
+                .chain(diff.added.keys().map(|key| (key, None)));
+            let conflicts: Vec<&str> = expected
+                .filter(|(key, old)| current.get(*key) != *old)
+                .map(|(key, _)| key.as_str())
+                .collect();
+            if !conflicts.is_empty() {
+                return Err(EnvelopeError::Conflict(format!(
+                    "patch does not apply to {}, {} changed since it was made",
+                    env,
+                    conflicts.join(", ")
+                )));
+            }
+
+            if !diff.is_empty() {
+                self.write_diff(env, &diff).await?;
+            }
+
+            Ok(diff)
+        })
+        .await
+    }
+
+    /// returns the current variables of every environment as the canonical
+    /// text of [`to_canonical`], which only depends on the variables and not
+    /// on the order they were set in. Meant to be committed to git and
+    /// restored with [`EnvelopeDb::import_canonical`].
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn export_canonical(&self) -> EnvelopeResult<String> {
+        count!(READS_COUNTER, "export_canonical");
+        let (sql, values) = Query::select()
+            .from(self.table(LatestVars::Table))
+            .columns([Environments::Env, Environments::Key, Environments::Value])
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .cond_where(self.unexpired())
+            .to_sqlite();
+
+        let rows: Vec<(String, String, String)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        let mut vars: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+        for (env, key, value) in rows {
+            vars.entry(env).or_default().insert(key, value);
+        }
+
+        Ok(to_canonical(&vars))
+    }
+
+    /// makes the environments found in the canonical text `contents`, as
+    /// written by [`EnvelopeDb::export_canonical`], hold exactly its
+    /// variables, like [`EnvelopeDb::reconcile`] does for each of them. The
+    /// keys are uppercased and the environments missing from the text are
+    /// left alone. Returns what was written to each environment.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, contents), fields(rows))
+    )]
+    pub async fn import_canonical(
+        &self,
+        contents: &str,
+    ) -> EnvelopeResult<BTreeMap<String, EnvDiff>> {
+        count!(INSERTS_COUNTER, "import_canonical");
+        let envs = from_canonical(contents)?;
+
+        self.retry("import_canonical", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&envs.keys().cloned().collect::<Vec<_>>())
+                .await?;
+
+            let mut diffs = BTreeMap::new();
+            for (env, desired) in &envs {
+                let current = self.live_vars(env).await?;
+                let desired = desired
+                    .iter()
+                    .map(|(key, value)| (key.to_ascii_uppercase(), value.clone()))
+                    .collect();
+
+                let diff = EnvDiff::between(current, desired);
+                if !diff.is_empty() {
+                    self.write_diff(env, &diff).await?;
+                }
+                diffs.insert(env.clone(), diff);
+            }
+
+            Ok(diffs)
+        })
+        .await
+    }
+
+    /// variables of `env` mapped to their value
+    async fn live_vars(&self, env: &str) -> EnvelopeResult<BTreeMap<String, String>> {
+        Ok(self
+            .list_var_in_env(env, SortOrder::Asc)
+            .await?
+            .into_iter()
+            .map(|row| (row.key, row.value))
+            .collect())
+    }
+
+    /// writes `diff` to `env`, the caller holds the write lock
+    async fn write_diff(&self, env: &str, diff: &EnvDiff) -> EnvelopeResult<()> {
+        let set = diff
+            .added
+            .iter()
+            .chain(diff.changed.iter().map(|(key, (_, new))| (key, new)));
+        self.check_values(env, set.clone().map(|(k, v)| (k.as_str(), v.as_str())))
+            .await?;
+
+        let mut insert = Query::insert()
+            .into_table(self.table(Environments::Table))
+            .columns([Environments::Env, Environments::Key, Environments::Value])
+            .to_owned();
+        for (key, value) in set {
+            insert
+                .values([env.into(), Func::upper(key).into(), value.into()])
+                .unwrap();
+        }
+        for key in diff.removed.keys() {
+            insert
+                .values([env.into(), key.into(), Option::<String>::None.into()])
+                .unwrap();
+        }
+
+        let (sql, values) = insert.to_sqlite();
+        let result = sqlx::query_with(&sql, values)
+            .execute(&self.db)
+            .await
+            .map_err(db_error)?;
+        record_rows(result.rows_affected());
+
+        Ok(())
+    }
+
+    /// compares the variables of `env` with the ones found in the dotenv
+    /// `contents`, showing what importing them would change
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, contents))
+    )]
+    pub async fn diff_with_dotenv(&self, env: &str, contents: &str) -> EnvelopeResult<EnvDiff> {
+        count!(READS_COUNTER, "diff_with_dotenv");
+        let current: BTreeMap<String, String> = self
+            .list_var_in_env(env, SortOrder::Asc)
+            .await?
+            .into_iter()
+            .map(|row| (row.key, row.value))
+            .collect();
+
+        let incoming: BTreeMap<String, String> = from_dotenv(contents)
+            .into_iter()
+            .map(|entry| (entry.key.to_ascii_uppercase(), entry.value))
+            .collect();
+
+        Ok(EnvDiff::between(current, incoming))
+    }
+
+    /// reads the dotenv file at `path` and imports its variables in `env` at
+    /// once, the keys already set in `env` are handled according to `mode`.
+    /// A comment right above a variable becomes its description.
+    ///
+    /// Unlike `envelope import`, which skips them, an invalid line fails
+    /// with [`EnvelopeError::Parse`] and nothing is imported. Returns what the
+    /// import changed, the variables of `env` missing from the file are kept.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn apply_dotenv_file(
+        &self,
+        env: &str,
+        path: &Path,
+        mode: ImportMode,
+    ) -> EnvelopeResult<EnvDiff> {
+        let contents = fs::read_to_string(path).map_err(|source| EnvelopeError::File {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let mut parser = DotenvParser::new();
+        let mut entries = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            match parser.parse_line(line) {
+                DotenvLine::Entry(entry) => entries.push(entry),
+                DotenvLine::Invalid(line) => {
+                    return Err(EnvelopeError::Parse {
+                        file: path.display().to_string(),
+                        line: n + 1,
+                        message: format!("invalid line {:?}", line),
+                    })
+                }
+                DotenvLine::Comment(_) | DotenvLine::Blank => {}
+            }
+        }
+
+        let current: BTreeMap<String, String> = self
+            .list_var_in_env(env, SortOrder::Asc)
+            .await?
+            .into_iter()
+            .map(|row| (row.key, row.value))
+            .collect();
+
+        let mut incoming = BTreeMap::new();
+        let mut descriptions = Vec::new();
+        for entry in entries {
+            let key = mode.key_for(&current, &entry.key, &entry.value);
+            if let Some(description) = entry.description {
+                descriptions.push((key.clone(), description));
+            }
+            incoming.insert(key.to_ascii_uppercase(), entry.value);
+        }
+
+        let replaced = current
+            .into_iter()
+            .filter(|(key, _)| incoming.contains_key(key))
+            .collect();
+        let diff = EnvDiff::between(replaced, incoming);
+        self.apply_diff(env, &diff).await?;
+        for (key, description) in descriptions {
+            self.set_description(env, &key, &description).await?;
+        }
+
+        Ok(diff)
+    }
+
+    /// duplicates `src_env` in a new environment `tgt_env`, see
+    /// [`EnvelopeDb::duplicate_filtered`]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn duplicate(
+        &self,
+        src_env: &str,
+        tgt_env: &str,
+        append: bool,
+    ) -> EnvelopeResult<()> {
+        self.duplicate_filtered(src_env, tgt_env, &["*"], append)
+            .await
+    }
+
+    /// copies the variables of `src_env` whose key matches one of the globs
+    /// of `include` to `tgt_env`. Globs support `*` and `?` and ignore case
+    /// like sql `LIKE`, nothing is copied if `include` is empty. Fails if
+    /// `tgt_env` already has rows, even deleted ones, unless `append` is set,
+    /// the copied values are then added to its history.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn duplicate_filtered(
+        &self,
+        src_env: &str,
+        tgt_env: &str,
+        include: &[&str],
+        append: bool,
+    ) -> EnvelopeResult<()> {
+        count!(INSERTS_COUNTER, "duplicate_filtered");
+        self.retry("duplicate_filtered", || async {
+            if include.is_empty() {
+                return Ok(());
+            }
+
+            let filter = key_filter(include);
+
+            let _guard = self.write_guard().await?;
+            if !append {
+                self.ensure_new_env(tgt_env).await?;
+            }
+            self.ensure_unlocked(&[tgt_env.into()]).await?;
+
+            let select = Query::select()
+                .from(self.table(LatestVars::Table))
+                .expr(Expr::val(tgt_env))
+                .column(Environments::Key)
+                .column(Environments::Value)
+                .column(Environments::ExpiresAt)
+                .and_where(Expr::col(Environments::Env).eq(src_env))
+                .and_where(Expr::col(Environments::Value).is_not_null())
+                .cond_where(self.unexpired())
+                .cond_where(filter)
+                .order_by_columns([
+                    (Environments::Env, Order::Desc),
+                    (Environments::Key, Order::Desc),
+                ])
+                .to_owned();
+            self.check_selected(tgt_env, select.clone()).await?;
+            self.insert_selected(select).await?;
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// sets the current variables of `src_env` in `tgt_env` with a single
+    /// insert-select, `strategy` decides which of the variables `tgt_env`
+    /// already has are replaced. A variable already set to the same value is
+    /// never written again. Returns the number of variables written.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn merge_env(
+        &self,
+        src_env: &str,
+        tgt_env: &str,
+        strategy: MergeStrategy,
+    ) -> EnvelopeResult<u64> {
+        count!(INSERTS_COUNTER, "merge_env");
+        self.retry("merge_env", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[tgt_env.into()]).await?;
+
+            // the variables of tgt_env a source variable must not be written over
+            let source = Alias::new("S");
+            let mut kept = self
+                .live_in(tgt_env)
+                .and_where(Expr::col(Environments::Key).equals((source.clone(), Environments::Key)))
+                .to_owned();
+            if strategy == MergeStrategy::Overwrite {
+                kept.and_where(
+                    Expr::col(Environments::Value).equals((source.clone(), Environments::Value)),
+                );
+            }
+
+            let select = Query::select()
+                .expr(Expr::val(tgt_env))
+                .columns([
+                    (source.clone(), Environments::Key),
+                    (source.clone(), Environments::Value),
+                    (source.clone(), Environments::ExpiresAt),
+                ])
+                .from_as(self.table(LatestVars::Table), source.clone())
+                .and_where(Expr::col((source.clone(), Environments::Env)).eq(src_env))
+                .and_where(Expr::col((source, Environments::Value)).is_not_null())
+                .cond_where(self.unexpired())
+                .and_where(Expr::exists(kept).not())
+                .to_owned();
+            self.check_selected(tgt_env, select.clone()).await?;
+
+            self.insert_selected(select).await
+        })
+        .await
+    }
+
+    /// sets `key` in `tgt_env` to its current value in `src_env` with a
+    /// single insert-select, its expiration included. Fails with
+    /// [`EnvelopeError::KeyNotFound`] if `src_env` does not have it.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self), fields(rows))
+    )]
+    pub async fn copy_var(&self, src_env: &str, tgt_env: &str, key: &str) -> EnvelopeResult<()> {
+        count!(INSERTS_COUNTER, "copy_var");
+        self.retry("copy_var", || async {
+            let _guard = self.write_guard().await?;
+            self.ensure_unlocked(&[tgt_env.into()]).await?;
+
+            let select = Query::select()
+                .from(self.table(LatestVars::Table))
+                .expr(Expr::val(tgt_env))
+                .columns([
+                    Environments::Key,
+                    Environments::Value,
+                    Environments::ExpiresAt,
+                ])
+                .and_where(Expr::col(Environments::Env).eq(src_env))
+                .and_where(Expr::col(Environments::Key).eq(Func::upper(key)))
+                .and_where(Expr::col(Environments::Value).is_not_null())
+                .cond_where(self.unexpired())
+                .to_owned();
+            self.check_selected(tgt_env, select.clone()).await?;
+
+            match self.insert_selected(select).await? {
+                0 => Err(EnvelopeError::KeyNotFound {
+                    env: Some(src_env.to_string()),
+                    key: key.to_ascii_uppercase(),
+                }),
+                _ => Ok(()),
+            }
+        })
+        .await
+    }
+
+    /// lists the current variables of `env` whose key matches one of the
+    /// globs of `include`, the variables [`EnvelopeDb::duplicate_filtered`]
+    /// copies
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn list_var_matching(
+        &self,
+        env: &str,
+        include: &[&str],
+    ) -> EnvelopeResult<Vec<EnvironmentRow>> {
+        count!(READS_COUNTER, "list_var_matching");
+        if include.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (sql, values) = Query::select()
+            .from(self.table(LatestVars::Table))
+            .column(Asterisk)
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .cond_where(self.unexpired())
+            .cond_where(key_filter(include))
+            .order_by(Environments::Key, Order::Asc)
+            .to_sqlite();
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)
+    }
+
+    /// lists the current variables of `env` sorted by key in `order`
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn list_var_in_env(
+        &self,
+        env: &str,
+        order: SortOrder,
+    ) -> EnvelopeResult<Vec<EnvironmentRow>> {
+        self.stream_var_in_env(env, order).try_collect().await
+    }
+
+    /// lists the current variables of `env` like [`Self::list_var_in_env`],
+    /// with the values that are secret references, such as `ssm:///my/param`,
+    /// replaced by the secret `resolver` returns for them. `op://` values are
+    /// only references for the variables of type [`ValueType::Reference`].
+    /// See [`crate::reference`].
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, resolver))
+    )]
+    pub async fn list_var_in_env_resolved<R: SecretResolver>(
+        &self,
+        env: &str,
+        order: SortOrder,
+        resolver: &R,
+    ) -> EnvelopeResult<Vec<EnvironmentRow>> {
+        let mut vars = self.list_var_in_env(env, order).await?;
+        let types = self.list_types(env).await?;
+        for var in &mut vars {
+            let marked = types.get(&var.key) == Some(&ValueType::Reference);
+            if !marked && var.value.starts_with(OP_PREFIX) {
+                continue;
+            }
+            var.value = resolve_value(resolver, &var.value).await?;
+        }
+
+        Ok(vars)
+    }
+
+    /// same as [`EnvelopeDb::list_var_in_env`], the rows are yielded as they
+    /// are read instead of being collected first
+    pub fn stream_var_in_env(
+        &self,
+        env: &str,
+        order: SortOrder,
+    ) -> impl Stream<Item = EnvelopeResult<EnvironmentRow>> + Send + '_ {
+        count!(READS_COUNTER, "stream_var_in_env");
+        let (sql, values) = Query::select()
+            .from(self.table(LatestVars::Table))
+            .column(Asterisk)
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .cond_where(self.unexpired())
+            .order_by(Environments::Key, order.to_order())
+            .to_sqlite();
+
+        self.fetch_stream((sql, values))
+    }
+
+    /// lists the keys of `env`, deleted ones included, in the order they were
+    /// first set in. Keys first set at the same time are sorted.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn key_order(&self, env: &str) -> EnvelopeResult<Vec<String>> {
+        count!(READS_COUNTER, "key_order");
+        let (sql, values) = Query::select()
+            .from(self.table(Environments::Table))
+            .column(Environments::Key)
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .group_by_col(Environments::Key)
+            .order_by_expr(Expr::col(Environments::CreatedAt).min(), Order::Asc)
+            .order_by(Environments::Key, Order::Asc)
+            .to_sqlite();
+
+        sqlx::query_scalar_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)
+    }
+
+    /// returns the time `key` was first set in `env` at, None if it never
+    /// was. A key deleted and set again keeps the time it was first set at.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn key_first_seen(
+        &self,
+        env: &str,
+        key: &str,
+    ) -> EnvelopeResult<Option<DateTime<Utc>>> {
+        count!(READS_COUNTER, "key_first_seen");
+        let select = Query::select()
+            .expr(Expr::col(Environments::CreatedAt).min())
+            .to_owned();
+
+        self.key_time(env, key, select).await
+    }
+
+    /// returns the time the value of `key` in `env` was last set at, None if
+    /// it was never set. Deletions do not count as modifications, the time
+    /// of the last value is kept.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn key_last_modified(
+        &self,
+        env: &str,
+        key: &str,
+    ) -> EnvelopeResult<Option<DateTime<Utc>>> {
+        count!(READS_COUNTER, "key_last_modified");
+        let select = Query::select()
+            .expr(Expr::col(Environments::CreatedAt).max())
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .to_owned();
+
+        self.key_time(env, key, select).await
+    }
+
+    /// runs `select`, which aggregates the creation times of the versions of
+    /// `key` in `env`
+    async fn key_time(
+        &self,
+        env: &str,
+        key: &str,
+        mut select: SelectStatement,
+    ) -> EnvelopeResult<Option<DateTime<Utc>>> {
+        let (sql, values) = select
+            .from(self.table(Environments::Table))
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .and_where(Expr::col(Environments::Key).eq(Func::upper(key)))
+            .to_sqlite();
+
+        let time: Option<i64> = sqlx::query_scalar_with(&sql, values)
+            .fetch_one(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        time.map(decode_created_at)
+            .transpose()
+            .map_err(EnvelopeError::Sqlx)
+    }
+
+    /// returns the current value of each of `keys` in `env` in a single
+    /// query, None if the key is not set or has been deleted. Keys are
+    /// uppercased like they are on insert, and so are the keys of the map.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn get_vars(
+        &self,
+        env: &str,
+        keys: &[String],
+    ) -> EnvelopeResult<BTreeMap<String, Option<String>>> {
+        count!(READS_COUNTER, "get_vars");
+        let mut vars: BTreeMap<String, Option<String>> = keys
+            .iter()
+            .map(|key| (key.to_ascii_uppercase(), None))
+            .collect();
+        if vars.is_empty() {
+            return Ok(vars);
+        }
+
+        let (sql, values) = Query::select()
+            .from(self.table(LatestVars::Table))
+            .columns([Environments::Key, Environments::Value])
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .and_where(Expr::col(Environments::Key).is_in(vars.keys().map(String::as_str)))
+            .cond_where(self.unexpired())
+            .to_sqlite();
+
+        let rows: Vec<(String, Option<String>)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+        vars.extend(rows);
+
+        Ok(vars)
+    }
+
+    /// lists the current variables of `env` sorted by key, along with their
+    /// number of versions and the time they were last modified at
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn list_var_detailed(&self, env: &str) -> EnvelopeResult<Vec<DetailedRow>> {
+        count!(READS_COUNTER, "list_var_detailed");
+        // sqlite takes the bare columns from the row holding the max
+        let select = Query::select()
+            .columns([
+                Environments::Env,
+                Environments::Key,
+                Environments::Value,
+                Environments::ExpiresAt,
+            ])
+            .expr_as(Expr::col(Asterisk).count(), Alias::new("version_count"))
+            .expr_as(
+                Expr::col(Environments::CreatedAt).max(),
+                Alias::new("last_modified"),
+            )
+            .from(self.table(Environments::Table))
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .group_by_columns([Environments::Env, Environments::Key])
+            .to_owned();
+
+        let (sql, values) = Query::select()
+            .from_subquery(select, Alias::new("T"))
+            .column(Asterisk)
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .cond_where(self.unexpired())
+            .order_by(Environments::Key, Order::Asc)
+            .to_sqlite();
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)
+    }
+
+    /// lists the size in bytes of the current value of each variable of `env`
+    /// sorted by key, sqlite computes it so the values are not read
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn value_sizes(&self, env: &str) -> EnvelopeResult<Vec<(String, i64)>> {
+        count!(READS_COUNTER, "value_sizes");
+        // length() counts characters of text, blobs are counted in bytes
+        let size = Func::cust(Alias::new("length"))
+            .arg(Expr::col(Environments::Value).cast_as(Alias::new("BLOB")));
+        let (sql, values) = Query::select()
+            .from(self.table(LatestVars::Table))
+            .column(Environments::Key)
+            .expr(size)
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .cond_where(self.unexpired())
+            .order_by(Environments::Key, Order::Asc)
+            .to_sqlite();
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)
+    }
+
+    /// lists the versions of the variables of `env`, or of `key` only, ordered
+    /// by key and from the oldest to the newest. Only the versions created
+    /// from `since` included up to `until` excluded are listed, both are unix
+    /// timestamps and unbounded if `None`
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn history_between(
+        &self,
+        env: &str,
+        key: Option<&str>,
+        since: Option<i64>,
+        until: Option<i64>,
+    ) -> EnvelopeResult<Vec<HistoryRow>> {
+        self.stream_history_between(env, key, since, until)
+            .try_collect()
+            .await
+    }
+
+    /// same as [`EnvelopeDb::history_between`], the rows are yielded as they
+    /// are read instead of being collected first
+    pub fn stream_history_between(
+        &self,
+        env: &str,
+        key: Option<&str>,
+        since: Option<i64>,
+        until: Option<i64>,
+    ) -> impl Stream<Item = EnvelopeResult<HistoryRow>> + Send + '_ {
+        count!(READS_COUNTER, "stream_history_between");
+        let mut select = Query::select()
+            .from(self.table(Environments::Table))
+            .columns([
+                Environments::Env,
+                Environments::Key,
+                Environments::Value,
+                Environments::CreatedAt,
+            ])
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .order_by_columns([
+                (Environments::Key, Order::Asc),
+                (Environments::CreatedAt, Order::Asc),
+            ])
+            .to_owned();
+
+        if let Some(key) = key {
+            select.and_where(Expr::col(Environments::Key).eq(Func::upper(key)));
+        }
+        if let Some(since) = since {
+            select.and_where(Expr::col(Environments::CreatedAt).gte(since));
+        }
+        if let Some(until) = until {
+            select.and_where(Expr::col(Environments::CreatedAt).lt(until));
+        }
+
+        self.fetch_stream(select.to_sqlite())
+    }
+
+    /// reconstructs the sets and deletes of the variables of `env` made from
+    /// `from` included up to `to` excluded, both unix timestamps, from the
+    /// oldest to the newest. A version setting the value a variable already
+    /// had is not a change.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn changes_between(
+        &self,
+        env: &str,
+        from: i64,
+        to: i64,
+    ) -> EnvelopeResult<Vec<ChangeEvent>> {
+        // the versions before the window give the value each change replaced
+        let versions = self.history_between(env, None, None, Some(to)).await?;
+
+        let mut changes = Vec::new();
+        let mut previous: Option<HistoryRow> = None;
+        for row in versions {
+            let old = match previous.take() {
+                Some(previous) if previous.key == row.key => previous.value,
+                _ => None,
+            };
+            if row.created_at >= from && old != row.value {
+                let kind = match (&old, &row.value) {
+                    (None, _) => ChangeKind::Added,
+                    (_, None) => ChangeKind::Deleted,
+                    _ => ChangeKind::Changed,
+                };
+                changes.push(ChangeEvent {
+                    key: row.key.clone(),
+                    kind,
+                    old,
+                    new: row.value.clone(),
+                    created_at: row.created_at,
+                });
+            }
+            previous = Some(row);
+        }
+        changes.sort_by_key(|change| change.created_at);
+
+        Ok(changes)
+    }
+
+    /// lists every row of the table, all the versions and deletions of every
+    /// env, ordered by env, key then creation time. Meant for troubleshooting,
+    /// nothing is grouped nor filtered out
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn dump_raw(&self) -> EnvelopeResult<Vec<HistoryRow>> {
+        self.stream_raw().try_collect().await
+    }
+
+    /// same as [`EnvelopeDb::dump_raw`], the rows are yielded as they are
+    /// read instead of being collected first
+    pub fn stream_raw(&self) -> impl Stream<Item = EnvelopeResult<HistoryRow>> + Send + '_ {
+        count!(READS_COUNTER, "stream_raw");
+        let query = Query::select()
+            .from(self.table(Environments::Table))
+            .columns([
+                Environments::Env,
+                Environments::Key,
+                Environments::Value,
+                Environments::CreatedAt,
+            ])
+            .order_by_columns([
+                (Environments::Env, Order::Asc),
+                (Environments::Key, Order::Asc),
+                (Environments::CreatedAt, Order::Asc),
+            ])
+            .to_sqlite();
+
+        self.fetch_stream(query)
+    }
+
+    /// lists every stored version, expiry included, ordered by env, key then
+    /// creation time. [`EnvelopeDb::import_versions`] writes them back.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn versions(&self) -> EnvelopeResult<Vec<Version>> {
+        count!(READS_COUNTER, "versions");
+        let query = Query::select()
+            .from(self.table(Environments::Table))
+            .column(Asterisk)
+            .order_by_columns([
+                (Environments::Env, Order::Asc),
+                (Environments::Key, Order::Asc),
+                (Environments::CreatedAt, Order::Asc),
+            ])
+            .to_sqlite();
+
+        self.fetch_stream(query).try_collect().await
+    }
+
+    /// stores `versions` as they are, in a single transaction. Locks are
+    /// ignored, it restores rows rather than setting variables.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, versions), fields(rows))
+    )]
+    pub async fn import_versions(&self, versions: &[Version]) -> EnvelopeResult<()> {
+        count!(INSERTS_COUNTER, "import_versions");
+        self.retry("import_versions", || async {
+            let _guard = self.write_guard().await?;
+            let mut tx = self.db.begin().await.map_err(db_error)?;
+
+            let mut rows = 0;
+            // sqlite caps the number of values bound to a statement
+            for chunk in versions.chunks(1000) {
+                let mut insert = Query::insert()
+                    .into_table(self.table(Environments::Table))
+                    .columns([
+                        Environments::Env,
+                        Environments::Key,
+                        Environments::Value,
+                        Environments::CreatedAt,
+                        Environments::ExpiresAt,
+                    ])
+                    .to_owned();
+                for version in chunk {
+                    insert
+                        .values([
+                            version.env.as_str().into(),
+                            version.key.as_str().into(),
+                            version.value.clone().into(),
+                            version.created_at.into(),
+                            version.expires_at.into(),
+                        ])
+                        .unwrap();
+                }
+
+                let (sql, values) = insert.to_sqlite();
+                let result = sqlx::query_with(&sql, values)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(db_error)?;
+                rows += result.rows_affected();
+            }
+            tx.commit().await.map_err(db_error)?;
+            record_rows(rows);
+
+            Ok(())
+        })
+        .await
+    }
+
+    /// names what the database holds besides the versions of the variables:
+    /// descriptions, types, locks and templates, which the other backends
+    /// cannot store
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn sqlite_only_data(&self) -> EnvelopeResult<Vec<&'static str>> {
+        let tables = [
+            ("descriptions", self.table(Descriptions::Table)),
+            ("types", self.table(Types::Table)),
+            ("locks", self.table(LockedEnvs::Table)),
+            ("templates", self.table(Templates::Table)),
+        ];
+
+        let mut found = Vec::new();
+        for (name, table) in tables {
+            let (sql, values) = Query::select()
+                .expr(Expr::value(1))
+                .from(table)
+                .limit(1)
+                .to_sqlite();
+            let row: Option<(i32,)> = sqlx::query_as_with(&sql, values)
+                .fetch_optional(&self.db)
+                .await
+                .map_err(db_error)?;
+            if row.is_some() {
+                found.push(name);
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// versions of the migrations envelope applied to the database, empty
+    /// when it was never migrated. The migrations other applications keep in
+    /// `_sqlx_migrations` are left out, see [`check_schema_version`].
+    async fn applied_migrations(&self) -> EnvelopeResult<Vec<i64>> {
+        // the migrations of a prefixed store are all successful
+        let (migrations, applied) = match self.prefix.is_empty() {
+            true => (SQLX_MIGRATIONS.to_string(), "WHERE success"),
+            false => (format!("{}migrations", self.prefix), ""),
+        };
+        if !has_table(&self.db, &migrations).await? {
+            return Ok(Vec::new());
+        }
+
+        let versions: Vec<i64> = sqlx::query_scalar(&format!(
+            "SELECT version FROM {} {} ORDER BY version",
+            migrations, applied
+        ))
+        .fetch_all(&self.db)
+        .await
+        .map_err(db_error)?;
+        if !self.prefix.is_empty() {
+            return Ok(versions);
+        }
+
+        // the unknown versions up to the one a newer envelope recorded are its
+        let known = embedded_versions();
+        let recorded = recorded_schema_version(&self.db).await?;
+        Ok(versions
+            .into_iter()
+            .filter(|version| {
+                known.contains(version)
+                    || (*version > embedded_version()
+                        && recorded.is_some_and(|recorded| *version <= recorded))
+            })
+            .collect())
+    }
+
+    /// compares the schema of the database with the one of this version of
+    /// envelope, nothing is written
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn migration_status(&self) -> EnvelopeResult<MigrationStatus> {
+        let applied = self.applied_migrations().await?;
+        let pending = sqlx::migrate!("./migrations")
+            .iter()
+            .filter(|migration| !applied.contains(&migration.version))
+            .map(|migration| MigrationInfo {
+                version: migration.version,
+                description: migration.description.to_string(),
+            })
+            .collect();
+
+        Ok(MigrationStatus {
+            current: applied.last().copied(),
+            available: embedded_version(),
+            pending,
+        })
+    }
+
+    /// applies the migrations the database lacks and returns them. Unlike
+    /// opening the database, the migrator always runs: it also checks that
+    /// the migrations applied are the ones envelope embeds. Fails with
+    /// [`EnvelopeError::MigrationMismatch`] if a newer envelope migrated it.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn apply_migrations(&self) -> EnvelopeResult<Vec<MigrationInfo>> {
+        let _guard = self.write_guard().await?;
+        let status = self.migration_status().await?;
+        match self.prefix.strip_suffix('_') {
+            Some(prefix) => {
+                check_schema_version(&self.db, &format!("{}_migrations", prefix)).await?;
+                run_prefixed_migrations(&self.db, prefix).await?;
+            }
+            None => {
+                check_schema_version(&self.db, SQLX_MIGRATIONS).await?;
+                run_migrator(&self.db).await?;
+            }
+        }
+
+        Ok(status.pending)
+    }
+
+    /// inspects the database for `envelope doctor`, nothing is written
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn diagnose(&self) -> EnvelopeResult<Diagnostics> {
+        let integrity: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check")
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        let applied = self.applied_migrations().await?;
+        let known = embedded_versions();
+
+        let table = self.table(Environments::Table).to_string();
+        let indexes = sqlx::query_scalar("SELECT name FROM pragma_index_list(?)")
+            .bind(&table)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        let orphaned_tombstones = sqlx::query_scalar(&format!(
+            r"SELECT COUNT(*) FROM {table} t
+            WHERE t.value IS NULL AND NOT EXISTS (
+                SELECT 1 FROM {table} p
+                WHERE p.env = t.env AND p.key = t.key
+                AND p.created_at < t.created_at AND p.value IS NOT NULL
+            )",
+        ))
+        .fetch_one(&self.db)
+        .await
+        .map_err(db_error)?;
+
+        let latest_created_at =
+            sqlx::query_scalar(&format!("SELECT MAX(created_at) FROM {}", table))
+                .fetch_one(&self.db)
+                .await
+                .map_err(db_error)?;
+
+        let case_duplicates = self
+            .case_duplicates(None)
+            .await?
+            .into_iter()
+            .map(|(duplicate, _)| duplicate)
+            .collect();
+
+        Ok(Diagnostics {
+            integrity,
+            pending_migrations: known
+                .iter()
+                .filter(|v| !applied.contains(v))
+                .copied()
+                .collect(),
+            unknown_migrations: applied
+                .iter()
+                .filter(|v| !known.contains(v))
+                .copied()
+                .collect(),
+            indexes,
+            orphaned_tombstones,
+            latest_created_at,
+            case_duplicates,
+        })
+    }
+
+    /// finds the current keys of `env`, or of every environment, that differ
+    /// only by case, along with the value of their newest variant
+    async fn case_duplicates(
+        &self,
+        env: Option<&str>,
+    ) -> EnvelopeResult<Vec<(CaseDuplicate, String)>> {
+        let mut select = Query::select()
+            .from(self.table(LatestVars::Table))
+            .column(Asterisk)
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .to_owned();
+        if let Some(env) = env {
+            select.and_where(Expr::col(Environments::Env).eq(env));
+        }
+
+        let (sql, values) = select.to_sqlite();
+
+        let rows: Vec<EnvironmentRow> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        let mut groups: BTreeMap<(String, String), Vec<EnvironmentRow>> = BTreeMap::new();
+        for row in rows {
+            groups
+                .entry((row.env.clone(), row.key.to_ascii_uppercase()))
+                .or_default()
+                .push(row);
+        }
+
+        Ok(groups
+            .into_iter()
+            .filter(|(_, rows)| rows.len() > 1)
+            .map(|((env, key), mut rows)| {
+                // within the same second the uppercased key wins, as it is the
+                // one envelope writes
+                rows.sort_by(|a, b| {
+                    b.created_at
+                        .cmp(&a.created_at)
+                        .then_with(|| (b.key == key).cmp(&(a.key == key)))
+                        .then_with(|| a.key.cmp(&b.key))
+                });
+                let value = rows[0].value.clone();
+                let duplicate = CaseDuplicate {
+                    env,
+                    key,
+                    variants: rows.into_iter().map(|row| row.key).collect(),
+                };
+                (duplicate, value)
+            })
+            .collect())
+    }
+
+    /// lists keys of `env` whose latest version has been soft deleted, or has
+    /// expired unless the expired versions are included
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn list_deleted_var_in_env(&self, env: &str) -> EnvelopeResult<Vec<String>> {
+        count!(READS_COUNTER, "list_deleted_var_in_env");
+        let mut removed = Condition::any().add(Expr::col(Environments::Value).is_null());
+        if !self.include_expired {
+            removed = removed.add(Expr::col(Environments::ExpiresAt).lte(unix_now()));
+        }
+        let (sql, values) = Query::select()
+            .from(self.table(LatestVars::Table))
+            .column(Environments::Key)
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .cond_where(removed)
+            .order_by(Environments::Key, Order::Asc)
+            .to_sqlite();
+
+        let keys: Vec<(String,)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        Ok(keys.into_iter().map(|(k,)| k).collect())
+    }
+
+    // lists environments present in the database. Environments that only contain deletes variables
+    // will be listed as well.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn list_environments(&self) -> EnvelopeResult<Vec<Environment>> {
+        count!(READS_COUNTER, "list_environments");
+        let (sql, _) = Query::select()
+            .from(self.table(Environments::Table))
+            .column(Environments::Env)
+            .distinct()
+            .to_sqlite();
+
+        sqlx::query_as(&sql)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)
+    }
+
+    /// maps each environment with variables to their keys in a single query,
+    /// both sorted. The deleted and expired variables are left out, and so
+    /// are the environments that only have such variables.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub async fn list_envs_with_keys(&self) -> EnvelopeResult<BTreeMap<String, Vec<String>>> {
+        count!(READS_COUNTER, "list_envs_with_keys");
+        let (sql, values) = Query::select()
+            .from(self.table(LatestVars::Table))
+            .columns([Environments::Env, Environments::Key])
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .cond_where(self.unexpired())
+            .order_by_columns([
+                (Environments::Env, Order::Asc),
+                (Environments::Key, Order::Asc),
+            ])
+            .to_sqlite();
+
+        let rows: Vec<(String, String)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        let mut envs: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (env, key) in rows {
+            envs.entry(env).or_default().push(key);
+        }
+
+        Ok(envs)
+    }
+
+    /// key and current value of the variables of `env`
+    fn live_in(&self, env: &str) -> SelectStatement {
+        Query::select()
+            .from(self.table(LatestVars::Table))
+            .columns([Environments::Key, Environments::Value])
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .cond_where(self.unexpired())
+            .to_owned()
+    }
+
+    /// environment and current value of `key` in every environment where it is
+    /// set, or in `env` only
+    fn live_values_of(&self, key: &str, env: Option<&str>) -> SelectStatement {
+        let mut select = Query::select()
+            .from(self.table(LatestVars::Table))
+            .columns([Environments::Env, Environments::Value])
+            .and_where(Expr::col(Environments::Key).eq(key))
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .order_by(Environments::Env, Order::Asc)
+            .to_owned();
+        if let Some(env) = env {
+            select.and_where(Expr::col(Environments::Env).eq(env));
+        }
+
+        select
+    }
+
+    /// creation time of the latest version of the variable of the current row of
+    /// the environments table, to compare rows against in a where clause
+    fn latest_version(&self) -> SimpleExpr {
+        let latest = Alias::new("L");
+        let select = Query::select()
+            .expr(Expr::col((latest.clone(), Environments::CreatedAt)).max())
+            .from_as(self.table(Environments::Table), latest.clone())
+            .and_where(
+                Expr::col((latest.clone(), Environments::Env))
+                    .equals((self.table(Environments::Table), Environments::Env)),
+            )
+            .and_where(
+                Expr::col((latest, Environments::Key))
+                    .equals((self.table(Environments::Table), Environments::Key)),
+            )
+            .to_owned();
+
+        SimpleExpr::SubQuery(None, Box::new(select.into_sub_query_statement()))
+    }
+}
+
+/// Records the rows a write affected on the span of the method doing it
+fn record_rows(rows: u64) {
+    Span::current().record("rows", rows);
+}
+
+/// Builds sea-query statements for sqlite
+trait ToSqlite {
+    /// returns the sql of the statement and its values, the sql is logged at
+    /// debug level
+    fn to_sqlite(&self) -> (String, SqlxValues);
+}
+
+impl<T: SqlxBinder> ToSqlite for T {
+    fn to_sqlite(&self) -> (String, SqlxValues) {
+        let (sql, values) = self.build_sqlx(SqliteQueryBuilder);
+        debug!(%sql, "query");
+        (sql, values)
+    }
+}
+
+/// Order in which variables are listed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub(crate) fn to_order(self) -> Order {
+        match self {
+            SortOrder::Asc => Order::Asc,
+            SortOrder::Desc => Order::Desc,
+        }
+    }
+}
+
+/// Differences between two sets of variables
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvDiff {
+    /// variables only present in the new set
+    pub added: BTreeMap<String, String>,
+    /// variables only present in the old set
+    pub removed: BTreeMap<String, String>,
+    /// variables present in both sets with a different value, mapped to
+    /// their old and new value
+    #[serde(
+        serialize_with = "serialize_changed",
+        deserialize_with = "deserialize_changed"
+    )]
+    pub changed: BTreeMap<String, (String, String)>,
+}
+
+/// current time in unix seconds, the unit of `created_at` and `expires_at`
+pub(crate) fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+/// Turns a database error into an [`EnvelopeError`], the constraint
+/// violations are reported as [`EnvelopeError::Constraint`]
+pub(crate) fn db_error(err: sqlx::Error) -> EnvelopeError {
+    let sqlx::Error::Database(db_err) = &err else {
+        return EnvelopeError::Sqlx(err);
+    };
+
+    // postgres names the constraint, sqlite ends its message with it
+    let constraint = db_err.constraint().unwrap_or(db_err.message());
+    let message = match db_err.kind() {
+        ErrorKind::CheckViolation if constraint.ends_with("name_not_empty") => {
+            "template name cannot be empty".to_string()
+        }
+        ErrorKind::CheckViolation if constraint.ends_with("env_not_empty") => {
+            "env name cannot be empty".to_string()
+        }
+        ErrorKind::CheckViolation if constraint.ends_with("key_not_empty") => {
+            "key name cannot be empty".to_string()
+        }
+        ErrorKind::CheckViolation | ErrorKind::NotNullViolation => db_err.message().to_string(),
+        _ => return EnvelopeError::Sqlx(err),
+    };
+
+    EnvelopeError::Constraint(message)
+}
+
+/// Fails with [`EnvelopeError::Constraint`] if one of `vars` contains a NUL
+/// byte, see [`EnvelopeDb::check_values`]
+pub(crate) fn check_nul<'a>(
+    env: &str,
+    vars: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> EnvelopeResult<()> {
+    for (key, value) in vars {
+        if key.contains('\0') || value.contains('\0') {
+            let message = format!(
+                "{} in {} contains a NUL byte",
+                key.to_ascii_uppercase(),
+                env
+            );
+            return Err(EnvelopeError::Constraint(message));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails with [`EnvelopeError::Constraint`] if one of `vars` is not of the
+/// type its key has in `types`, the types of `env`
+fn check_types<'a>(
+    env: &str,
+    types: &BTreeMap<String, ValueType>,
+    vars: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> EnvelopeResult<()> {
+    for (key, value) in vars {
+        let key = key.to_ascii_uppercase();
+        match types.get(&key) {
+            Some(value_type) if !value_type.accepts(value) => {
+                let message = format!(
+                    "{} in {} must be of type {}, got {:?}",
+                    key, env, value_type, value
+                );
+                return Err(EnvelopeError::Constraint(message));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Matches the keys matching one of the globs of `include`
+fn key_filter(include: &[&str]) -> Condition {
+    include.iter().fold(Condition::any(), |filter, glob| {
+        let pattern = LikeExpr::new(glob_to_like(glob)).escape('\\');
+        filter.add(Expr::col(Environments::Key).like(pattern))
+    })
+}
+
+/// Translates a glob into a `LIKE` pattern escaped with `\`, `*` matches any
+/// sequence of characters and `?` a single one
+fn glob_to_like(glob: &str) -> String {
+    let mut pattern = String::with_capacity(glob.len());
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push('%'),
+            '?' => pattern.push('_'),
+            '%' | '_' | '\\' => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            c => pattern.push(c),
+        }
+    }
+
+    pattern
+}
+
+/// Serializes the changed variables as `{KEY: {"old": .., "new": ..}}`
+fn serialize_changed<S: Serializer>(
+    changed: &BTreeMap<String, (String, String)>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    #[derive(Serialize)]
+    struct Change<'a> {
+        old: &'a str,
+        new: &'a str,
+    }
+
+    serializer.collect_map(
+        changed
+            .iter()
+            .map(|(key, (old, new))| (key, Change { old, new })),
+    )
+}
+
+/// reads the changes written by [`serialize_changed`]
+fn deserialize_changed<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, (String, String)>, D::Error> {
+    #[derive(Deserialize)]
+    struct Change {
+        old: String,
+        new: String,
+    }
+
+    let changed = BTreeMap::<String, Change>::deserialize(deserializer)?;
+    Ok(changed
+        .into_iter()
+        .map(|(key, change)| (key, (change.old, change.new)))
+        .collect())
+}
+
+impl EnvDiff {
+    pub fn between(old: BTreeMap<String, String>, mut new: BTreeMap<String, String>) -> Self {
+        let mut diff = EnvDiff::default();
+        for (key, old_value) in old {
+            match new.remove(&key) {
+                None => {
+                    diff.removed.insert(key, old_value);
+                }
+                Some(new_value) if new_value != old_value => {
+                    diff.changed.insert(key, (old_value, new_value));
+                }
+                Some(_) => {}
+            }
+        }
+        diff.added = new;
+
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Outcome of [`EnvelopeDb::insert`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InsertOutcome {
+    /// value the variable had, None if it was not set, had been deleted or
+    /// had expired
+    pub previous: Option<String>,
+    /// whether the value differs from the previous one
+    pub changed: bool,
+}
+
+/// Outcome of [`EnvelopeDb::insert_many`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InsertManyOutcome {
+    /// number of variables written
+    pub written: u64,
+    /// number of variables skipped, they were already set to their value
+    pub unchanged: u64,
+}
+
+/// Outcome of [`EnvelopeDb::reconcile`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// what was written: the added and changed variables got a new version
+    /// and the removed ones a deletion
+    pub diff: EnvDiff,
+    /// number of variables already set to their desired value
+    pub unchanged: usize,
+}
+
+/// Which variables [`EnvelopeDb::merge_env`] writes over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// the variables the target already has keep their value
+    Keep,
+    /// the source values replace those of the target
+    Overwrite,
+}
+
+/// Outcome of setting a variable in an environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetOutcome {
+    /// the variable did not exist or had been deleted
+    Created,
+    /// the variable replaced a previous value
+    Updated { previous: String },
+    /// the variable already had the value and nothing was written, see
+    /// [`EnvelopeDb::set_dedupe`]
+    Unchanged,
+}
+
+#[cfg(test)]
+pub async fn test_db() -> EnvelopeDb {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect(":memory:")
+        .await
+        .expect("cannot connect to db");
+
+    migrate(&pool).await.unwrap();
+
+    EnvelopeDb::from_pool(pool)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::reference::test::MockResolver;
+    use std::io;
+
+    #[tokio::test]
+    async fn test_envelope_path() {
+        let path = env::temp_dir().join(format!("envelope-path-{}.db", std::process::id()));
+        env::set_var(PATH_VAR, &path);
+        assert_eq!(path, db_path().unwrap());
+        assert!(!is_present());
+        assert!(matches!(
+            EnvelopeDb::load(false).await.unwrap_err(),
+            EnvelopeError::NotInitialized
+        ));
+
+        let db = EnvelopeDb::load(true).await.unwrap();
+        db.insert("dev", "A", "1").await.unwrap();
+        drop(db);
+        assert!(is_present());
+        let db = EnvelopeDb::load(false).await.unwrap();
+        assert_eq!(
+            1,
+            db.list_var_in_env("dev", SortOrder::Asc)
+                .await
+                .unwrap()
+                .len()
+        );
+        drop(db);
+
+        // an empty variable is ignored
+        env::set_var(PATH_VAR, "");
+        assert_eq!(
+            env::current_dir().unwrap().join(".envelope"),
+            db_path().unwrap()
+        );
+        env::remove_var(PATH_VAR);
+        assert_eq!(
+            env::current_dir().unwrap().join(".envelope"),
+            db_path().unwrap()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_from_pool() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE users(name TEXT NOT NULL)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        migrate(&pool).await.unwrap();
+
+        let db = EnvelopeDb::from_pool(pool.clone());
+        db.insert("dev", "A", "1").await.unwrap();
+        assert_eq!(
+            1,
+            db.list_var_in_env("dev", SortOrder::Asc)
+                .await
+                .unwrap()
+                .len()
+        );
+
+        // the tables of the application are still usable
+        sqlx::query("INSERT INTO users (name) VALUES ('me')")
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migration_mismatch() {
+        let path = env::temp_dir().join(format!("envelope-future-{}.db", std::process::id()));
+        let options = ConnectOptions {
+            table_prefix: Some("tenant".into()),
+            ..Default::default()
+        };
+        let db = EnvelopeDb::open(&path).await.unwrap();
+        drop(EnvelopeDb::open_with(&path, &options).await.unwrap());
+        // a newer envelope migrated the database
+        sqlx::query(
+            r"INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES (99990101000000, 'future', 1, x'00', 0);
+            UPDATE settings SET value = '99990101000000' WHERE name = 'schema_version';
+            INSERT INTO tenant_migrations (version) VALUES (99990101000000);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        drop(db);
+
+        let known = sqlx::migrate!("./migrations")
+            .iter()
+            .last()
+            .unwrap()
+            .version;
+        let err = EnvelopeDb::open(&path).await.unwrap_err();
+        assert!(matches!(
+            err,
+            EnvelopeError::MigrationMismatch { database: 99990101000000, known: k } if k == known
+        ));
+        assert_eq!(7, err.exit_code());
+        assert!(err.to_string().contains("upgrade envelope"));
+        let err = EnvelopeDb::open_with(&path, &options).await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::MigrationMismatch { .. }));
+
+        // doctor still opens it read-only to report the unknown migration
+        let db = EnvelopeDb::open_read_only(&path).await.unwrap();
+        assert_eq!(
+            vec![99990101000000],
+            db.diagnose().await.unwrap().unknown_migrations
+        );
+        drop(db);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_foreign_migrations() {
+        let path = env::temp_dir().join(format!("envelope-foreign-{}.db", std::process::id()));
+        let db = EnvelopeDb::open(&path).await.unwrap();
+        // another application keeps its migrations in the same table
+        sqlx::query(
+            r"INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES (99990101000000, 'users table', 1, x'00', 0)",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        drop(db);
+
+        let db = EnvelopeDb::open(&path).await.unwrap();
+        assert!(db.apply_migrations().await.unwrap().is_empty());
+        let status = db.migration_status().await.unwrap();
+        assert_eq!(Some(embedded_version()), status.current);
+        assert!(db.diagnose().await.unwrap().unknown_migrations.is_empty());
+        drop(db);
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// runs the migrations on an empty database, `seed` before the one that
+    /// forbids empty names
+    async fn migrate_seeded(seed: &str) -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        let migrator = sqlx::migrate!("./migrations");
+        let (before, after): (Vec<_>, Vec<_>) =
+            migrator.iter().partition(|m| m.version < 20261016120000);
+        for migration in before {
+            pool.execute(migration.sql.as_ref()).await.unwrap();
+        }
+        pool.execute(seed).await.unwrap();
+        for migration in after {
+            pool.execute(migration.sql.as_ref()).await.unwrap();
+        }
+
+        pool
+    }
+
+    async fn rows_of(pool: &SqlitePool) -> Vec<(String, String, Option<String>)> {
+        sqlx::query_as("SELECT env, key, value FROM environments ORDER BY env, key")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_non_empty_names_migration() {
+        let pool = migrate_seeded(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES ('', 'A', 'a', 1), ('dev', '', 'b', 1), ('dev', 'C', 'c', 1);
+            INSERT INTO descriptions (env, key, description) VALUES ('dev', '', 'd');",
+        )
+        .await;
+
+        // the rows with an empty name are renamed rather than lost
+        assert_eq!(
+            vec![
+                ("_empty".to_string(), "A".to_string(), Some("a".to_string())),
+                ("dev".into(), "C".into(), Some("c".into())),
+                ("dev".into(), "_EMPTY".into(), Some("b".into())),
+            ],
+            rows_of(&pool).await
+        );
+        let key: String = sqlx::query_scalar("SELECT key FROM descriptions")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!("_EMPTY", key);
+    }
+
+    #[tokio::test]
+    async fn test_non_empty_names_taken() {
+        let pool = migrate_seeded(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', '', 'b', 1),
+            ('dev', '_EMPTY', 'taken', 1),
+            ('dev', 'LONGEST_KEY', 'x', 1);",
+        )
+        .await;
+
+        // the placeholder is longer than every key, it cannot be taken
+        assert_eq!(
+            vec![
+                (
+                    "dev".to_string(),
+                    "LONGEST_KEY".to_string(),
+                    Some("x".to_string())
+                ),
+                ("dev".into(), "_EMPTY".into(), Some("taken".into())),
+                ("dev".into(), "_EMPTY___________".into(), Some("b".into())),
+            ],
+            rows_of(&pool).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migration_status() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        let db = EnvelopeDb::from_pool(pool.clone());
+        let all: Vec<MigrationInfo> = sqlx::migrate!("./migrations")
+            .iter()
+            .map(|m| MigrationInfo {
+                version: m.version,
+                description: m.description.to_string(),
+            })
+            .collect();
+
+        let status = db.migration_status().await.unwrap();
+        assert_eq!(None, status.current);
+        assert_eq!(embedded_version(), status.available);
+        assert_eq!(all, status.pending);
+
+        assert_eq!(all, db.apply_migrations().await.unwrap());
+        let status = db.migration_status().await.unwrap();
+        assert_eq!(Some(status.available), status.current);
+        assert!(status.pending.is_empty());
+        assert!(db.apply_migrations().await.unwrap().is_empty());
+
+        // an up to date database is not migrated again, the missing view
+        // shows the migrator did not run
+        sqlx::query("DROP VIEW latest_vars")
+            .execute(&pool)
+            .await
+            .unwrap();
+        migrate(&pool).await.unwrap();
+        let views: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE name = 'latest_vars'")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(0, views);
+
+        // it is once the newest migration is missing
+        sqlx::query("DELETE FROM _sqlx_migrations WHERE version = ?")
+            .bind(embedded_version())
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![all.last().unwrap().clone()],
+            db.migration_status().await.unwrap().pending
+        );
+        migrate(&pool).await.unwrap();
+        db.list_envs_with_keys().await.unwrap();
+
+        let prefixed = EnvelopeDb::from_pool_with_prefix(pool, "tenant").unwrap();
+        assert_eq!(all, prefixed.migration_status().await.unwrap().pending);
+        assert_eq!(all, prefixed.apply_migrations().await.unwrap());
+        assert!(prefixed
+            .migration_status()
+            .await
+            .unwrap()
+            .pending
+            .is_empty());
+        prefixed.insert("dev", "A", "1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_table_prefix() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        migrate(&pool).await.unwrap();
+        migrate_with_prefix(&pool, "tenant1").await.unwrap();
+        migrate_with_prefix(&pool, "tenant2").await.unwrap();
+        // applied migrations are not run again
+        migrate_with_prefix(&pool, "tenant1").await.unwrap();
+
+        let db = EnvelopeDb::from_pool(pool.clone());
+        let tenant1 = EnvelopeDb::from_pool_with_prefix(pool.clone(), "tenant1").unwrap();
+        let tenant2 = EnvelopeDb::from_pool_with_prefix(pool.clone(), "tenant2").unwrap();
+        db.insert("dev", "A", "0").await.unwrap();
+        tenant1.insert("dev", "A", "1").await.unwrap();
+        tenant1.set_description("dev", "A", "first").await.unwrap();
+        tenant1.lock_env("dev").await.unwrap();
+        tenant2.insert("prod", "B", "2").await.unwrap();
+
+        for (db, expected) in [
+            (&db, ("dev", "A", "0")),
+            (&tenant1, ("dev", "A", "1")),
+            (&tenant2, ("prod", "B", "2")),
+        ] {
+            let vars = db.get_all_env_vars().await.unwrap();
+            assert_eq!(
+                vec![expected],
+                vars.iter()
+                    .map(|row| (row.env.as_str(), row.key.as_str(), row.value.as_str()))
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        // the lock and the description only apply to the store they were set in
+        db.insert("dev", "B", "3").await.unwrap();
+        assert!(db.list_descriptions("dev").await.unwrap().is_empty());
+        assert_eq!(
+            "first",
+            tenant1.list_descriptions("dev").await.unwrap()["A"]
+        );
+        assert!(matches!(
+            tenant1.insert("dev", "A", "3").await.unwrap_err(),
+            EnvelopeError::Locked(_)
+        ));
+
+        let tables: Vec<String> = sqlx::query_scalar(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE 'tenant1%' ORDER BY name",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(
+            vec![
+                "tenant1_descriptions",
+                "tenant1_environments",
+                "tenant1_locked_envs",
+                "tenant1_migrations",
+                "tenant1_settings",
+                "tenant1_templates",
+                "tenant1_types",
+            ],
+            tables
+        );
+        assert!(tenant1
+            .diagnose()
+            .await
+            .unwrap()
+            .pending_migrations
+            .is_empty());
+
+        for prefix in ["", "1tenant", "tenant-1", "tenant; DROP TABLE environments"] {
+            assert!(matches!(
+                EnvelopeDb::from_pool_with_prefix(pool.clone(), prefix).unwrap_err(),
+                EnvelopeError::Constraint(_)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_with_table_prefix() {
+        let path = std::env::temp_dir().join(format!("envelope-prefix-{}.db", std::process::id()));
+        let options = ConnectOptions {
+            table_prefix: Some("app".into()),
+            ..Default::default()
+        };
+        let db = EnvelopeDb::open_with(&path, &options).await.unwrap();
+        db.insert("dev", "A", "1").await.unwrap();
+        drop(db);
+
+        // the unprefixed tables are those of another store
+        let db = EnvelopeDb::open(&path).await.unwrap();
+        assert!(db.list_environments().await.unwrap().is_empty());
+        drop(db);
+        let db = EnvelopeDb::open_with(&path, &options).await.unwrap();
+        assert_eq!(1, db.list_environments().await.unwrap().len());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_in_envs() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'TOKEN', 'old-dev', 1),
+            ('prod', 'TOKEN', 'old-prod', 1),
+            ('prod', 'TOKEN', NULL, 2),
+            ('test', 'OTHER', 'x', 1);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let envs = ["dev".to_string(), "prod".to_string(), "test".to_string()];
+        let outcomes = db.set_in_envs(&envs, "token", "new").await.unwrap();
+        assert_eq!(
+            vec![
+                (
+                    "dev".to_string(),
+                    SetOutcome::Updated {
+                        previous: "old-dev".into()
+                    }
+                ),
+                ("prod".to_string(), SetOutcome::Created),
+                ("test".to_string(), SetOutcome::Created),
+            ],
+            outcomes
+        );
+
+        for env in envs {
+            let rows = db.list_var_in_env(&env, SortOrder::Asc).await.unwrap();
+            let row = rows.iter().find(|r| r.key == "TOKEN").unwrap();
+            assert_eq!("new", row.value);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_diff() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES ('dev', 'A', '1', 1), ('dev', 'B', '2', 1), ('dev', 'C', '3', 1);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let diff = EnvDiff {
+            added: BTreeMap::from([("d".into(), "4".into())]),
+            removed: BTreeMap::from([("A".into(), "1".into())]),
+            changed: BTreeMap::from([("B".into(), ("2".into(), "x".into()))]),
+        };
+        db.apply_diff("dev", &diff).await.unwrap();
+
+        let vars: Vec<(String, String)> = db
+            .list_var_in_env("dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.key, r.value))
+            .collect();
+        assert_eq!(
+            vec![
+                ("B".to_string(), "x".to_string()),
+                ("C".to_string(), "3".to_string()),
+                ("D".to_string(), "4".to_string()),
+            ],
+            vars
+        );
+
+        db.lock_env("dev").await.unwrap();
+        assert!(db.apply_diff("dev", &diff).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reconcile() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES ('dev', 'A', '1', 1), ('dev', 'B', '2', 1), ('dev', 'C', '3', 1);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let desired = BTreeMap::from([
+            ("a".to_string(), "1".to_string()),
+            ("B".to_string(), "x".to_string()),
+            ("D".to_string(), "4".to_string()),
+        ]);
+        let report = db.reconcile("dev", &desired).await.unwrap();
+        assert_eq!(1, report.unchanged);
+        assert_eq!(
+            EnvDiff {
+                added: BTreeMap::from([("D".into(), "4".into())]),
+                removed: BTreeMap::from([("C".into(), "3".into())]),
+                changed: BTreeMap::from([("B".into(), ("2".into(), "x".into()))]),
+            },
+            report.diff
+        );
+
+        let versions = |key: &'static str| {
+            let db = &db;
+            async move {
+                db.history_between("dev", Some(key), None, None)
+                    .await
+                    .unwrap()
+                    .len()
+            }
+        };
+        // only the actual changes got a new version
+        assert_eq!(1, versions("A").await);
+        assert_eq!(2, versions("B").await);
+        assert_eq!(2, versions("C").await);
+        assert_eq!(1, versions("D").await);
+
+        let report = db.reconcile("dev", &desired).await.unwrap();
+        assert!(report.diff.is_empty());
+        assert_eq!(3, report.unchanged);
+        let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM environments")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(6, rows);
+
+        db.lock_env("dev").await.unwrap();
+        let err = db.reconcile("dev", &BTreeMap::new()).await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::Locked(_)));
+    }
+
+    #[tokio::test]
+    async fn test_canonical_stable_across_insert_orders() {
+        let vars = [
+            ("prod", "URL", "https://example.com"),
+            ("dev", "MOTD", "multi\r\nline"),
+            ("prod", "DEBUG", "false"),
+            ("dev", "URL", "http://localhost"),
+        ];
+
+        let first = test_db().await;
+        for (env, key, value) in vars {
+            first.insert(env, key, value).await.unwrap();
+        }
+        let second = test_db().await;
+        for (env, key, value) in vars.iter().rev() {
+            second.insert(env, key, value).await.unwrap();
+        }
+        // replaced and deleted values leave no trace
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES ('prod', 'DEBUG', 'true', 1), ('dev', 'GONE', 'x', 1),
+                ('dev', 'GONE', NULL, 2);",
+        )
+        .execute(second.get_pool())
+        .await
+        .unwrap();
+
+        let text = first.export_canonical().await.unwrap();
+        assert_eq!(
+            "[dev]\nMOTD=\"multi\\r\\nline\"\nURL=\"http://localhost\"\n\n\
+            [prod]\nDEBUG=\"false\"\nURL=\"https://example.com\"\n",
+            text
+        );
+        assert_eq!(text, second.export_canonical().await.unwrap());
+
+        let restored = test_db().await;
+        sqlx::query("INSERT INTO environments (env, key, value, created_at) VALUES ('prod', 'STALE', '1', 1);")
+            .execute(restored.get_pool())
+            .await
+            .unwrap();
+        let diffs = restored.import_canonical(&text).await.unwrap();
+        assert_eq!(2, diffs["dev"].added.len());
+        assert_eq!(
+            BTreeMap::from([("STALE".to_string(), "1".to_string())]),
+            diffs["prod"].removed
+        );
+        assert_eq!(text, restored.export_canonical().await.unwrap());
+
+        // importing the same text again writes nothing
+        let diffs = first.import_canonical(&text).await.unwrap();
+        assert!(diffs.values().all(EnvDiff::is_empty));
+    }
+
+    #[tokio::test]
+    async fn test_diff_patch() {
+        let db = test_db().await;
+        // versions are keyed by second, the copies of a are made beforehand
+        let seed = r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('a', 'SAME', '1', 1), ('a', 'CHANGED', 'old', 1), ('a', 'REMOVED', 'x', 1),
+            ('copy', 'SAME', '1', 1), ('copy', 'CHANGED', 'old', 1), ('copy', 'REMOVED', 'x', 1),
+            ('b', 'SAME', '1', 1), ('b', 'CHANGED', 'new', 1), ('b', 'ADDED', 'y', 1);";
+        sqlx::query(seed).execute(db.get_pool()).await.unwrap();
+        let other = test_db().await;
+        sqlx::query(seed).execute(other.get_pool()).await.unwrap();
+
+        let patch = db.export_diff_patch("a", "b").await.unwrap();
+        let diff: EnvDiff = serde_json::from_str(&patch).unwrap();
+        assert_eq!(
+            EnvDiff {
+                added: BTreeMap::from([("ADDED".into(), "y".into())]),
+                removed: BTreeMap::from([("REMOVED".into(), "x".into())]),
+                changed: BTreeMap::from([("CHANGED".into(), ("old".into(), "new".into()))]),
+            },
+            diff
+        );
+
+        // a copy of a, here and in another database, becomes b
+        let b = db.live_vars("b").await.unwrap();
+        assert_eq!(diff, db.apply_patch("copy", &patch).await.unwrap());
+        assert_eq!(b, db.live_vars("copy").await.unwrap());
+        other.apply_patch("copy", &patch).await.unwrap();
+        assert_eq!(b, other.live_vars("copy").await.unwrap());
+
+        // it no longer applies once applied
+        let err = db.apply_patch("copy", &patch).await.unwrap_err();
+        assert_eq!(
+            "patch does not apply to copy, CHANGED, REMOVED, ADDED changed since it was made",
+            err.to_string()
+        );
+        let err = db
+            .apply_patch("copy", "{\n\"added\": 1}")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, EnvelopeError::Parse { line: 2, .. }));
+        let err = db.export_diff_patch("a", "missing").await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::EnvNotFound(_)));
+    }
+
+    /// the latest_vars view picks the rows the former `GROUP BY env, key
+    /// HAVING MAX(created_at)` subqueries did, and the methods reading it
+    /// return what they did on top of them
+    #[tokio::test]
+    async fn test_latest_vars_view() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at, expires_at)
+            VALUES
+            ('dev', 'HOST', 'old', 1, NULL), ('dev', 'HOST', 'localhost', 3, NULL),
+            ('dev', 'PORT', '80', 2, NULL), ('dev', 'PORT', NULL, 4, NULL),
+            ('dev', 'TOKEN', 'old', 1, NULL), ('dev', 'TOKEN', 'expired', 2, 10),
+            ('dev', 'Mixed', 'exact', 2, NULL), ('dev', 'MIXED', 'upper', 1, NULL),
+            ('dev', 'UNICODE', 'été', 5, NULL),
+            ('gone', 'A', '1', 1, NULL), ('gone', 'A', NULL, 2, NULL),
+            ('prod', 'HOST', 'example.com', 7, NULL), ('prod', 'PORT', '443', 6, 99999999999);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        type Raw = (String, String, Option<String>, i64, Option<i64>);
+        let old: Vec<Raw> = sqlx::query_as(
+            r"SELECT env, key, value, created_at, expires_at FROM environments
+            GROUP BY env, key HAVING MAX(created_at) ORDER BY env, key",
+        )
+        .fetch_all(pool)
+        .await
+        .unwrap();
+        let view: Vec<Raw> = sqlx::query_as("SELECT * FROM latest_vars ORDER BY env, key")
+            .fetch_all(pool)
+            .await
+            .unwrap();
+        assert_eq!(old, view);
+
+        let live = |env: &str| -> Vec<(String, String)> {
+            old.iter()
+                .filter(|row| row.0 == env && row.4.is_none_or(|at| at > unix_now()))
+                .filter_map(|row| Some((row.1.clone(), row.2.clone()?)))
+                .collect()
+        };
+        let rows = |rows: Vec<EnvironmentRow>| -> Vec<(String, String)> {
+            rows.into_iter().map(|row| (row.key, row.value)).collect()
+        };
+        for env in ["dev", "gone", "prod"] {
+            let listed = db.list_var_in_env(env, SortOrder::Asc).await.unwrap();
+            assert_eq!(live(env), rows(listed));
+            let matching = db.list_var_matching(env, &["*"]).await.unwrap();
+            assert_eq!(live(env), rows(matching));
+
+            let sizes: Vec<(String, i64)> = live(env)
+                .into_iter()
+                .map(|(key, value)| (key, value.len() as i64))
+                .collect();
+            assert_eq!(sizes, db.value_sizes(env).await.unwrap());
+
+            let deleted: Vec<String> = old
+                .iter()
+                .filter(|row| row.0 == env)
+                .filter(|row| row.2.is_none() || row.4.is_some_and(|at| at <= unix_now()))
+                .map(|row| row.1.clone())
+                .collect();
+            assert_eq!(deleted, db.list_deleted_var_in_env(env).await.unwrap());
+        }
+
+        let vars = db
+            .get_vars("dev", &["host".into(), "port".into(), "token".into()])
+            .await
+            .unwrap();
+        assert_eq!(
+            BTreeMap::from([
+                ("HOST".into(), Some("localhost".into())),
+                ("PORT".into(), None),
+                ("TOKEN".into(), None),
+            ]),
+            vars
+        );
+        assert_eq!(vec!["gone"], db.empty_environments().await.unwrap());
+        let duplicates = db.case_duplicates(Some("dev")).await.unwrap();
+        assert_eq!(vec!["Mixed", "MIXED"], duplicates[0].0.variants);
+
+        db.duplicate("dev", "copy", false).await.unwrap();
+        let copied = db.list_var_in_env("copy", SortOrder::Asc).await.unwrap();
+        assert_eq!(live("dev"), rows(copied));
+
+        // the view follows the table prefix
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        migrate_with_prefix(&pool, "tenant").await.unwrap();
+        let tenant = EnvelopeDb::from_pool_with_prefix(pool.clone(), "tenant").unwrap();
+        tenant.insert("dev", "A", "1").await.unwrap();
+        let latest: Vec<Raw> = sqlx::query_as("SELECT * FROM tenant_latest_vars")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(1, latest.len());
+    }
+
+    #[tokio::test]
+    async fn test_insert_many() {
+        let mut db = test_db().await;
+        let pool = db.get_pool().clone();
+        sqlx::query(
+            "INSERT INTO environments (env, key, value, created_at) VALUES ('dev', 'A', 'old', 1)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let vars: Vec<(String, String)> = (0..1200)
+            .map(|i| (format!("k{}", i), i.to_string()))
+            .chain([("a".into(), "1".into()), ("A".into(), "2".into())])
+            .collect();
+        let outcome = db.insert_many("dev", &vars).await.unwrap();
+        assert_eq!(1201, outcome.written);
+        assert_eq!(0, outcome.unchanged);
+        assert_eq!(
+            InsertManyOutcome::default(),
+            db.insert_many("dev", &[]).await.unwrap()
+        );
+
+        let vars = db
+            .get_vars("dev", &["a".into(), "k0".into(), "K1199".into()])
+            .await
+            .unwrap();
+        assert_eq!(Some("2"), vars["A"].as_deref());
+        assert_eq!(Some("0"), vars["K0"].as_deref());
+        assert_eq!(Some("1199"), vars["K1199"].as_deref());
+        assert_eq!(
+            1202,
+            db.history_between("dev", None, None, None)
+                .await
+                .unwrap()
+                .len()
+        );
+
+        // the pragma is only changed for the duration of the insert
+        db.set_fast_import(true);
+        let synchronous: i64 = sqlx::query_scalar("PRAGMA synchronous")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        db.insert_many("prod", &[("B".into(), "1".into())])
+            .await
+            .unwrap();
+        let after: i64 = sqlx::query_scalar("PRAGMA synchronous")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(synchronous, after);
+
+        db.lock_env("prod").await.unwrap();
+        let err = db
+            .insert_many("prod", &[("B".into(), "2".into())])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, EnvelopeError::Locked(_)));
+        let err = db
+            .insert_many("dev", &[("B".into(), "a\0b".into())])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, EnvelopeError::Constraint(_)));
+    }
+
+    #[tokio::test]
+    async fn test_insert_unchanged() {
+        let mut db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at, expires_at)
+            VALUES
+            ('dev', 'SAME', 'x', 1, NULL),
+            ('dev', 'EMPTY', '', 1, NULL),
+            ('dev', 'GONE', '', 1, NULL),
+            ('dev', 'GONE', NULL, 2, NULL),
+            ('dev', 'OLD', 'o', 1, 2),
+            ('dev', 'CHANGED', 'a', 1, NULL),
+            ('prod', 'SAME', 'x', 1, NULL);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        let versions = |db: &EnvelopeDb, env: &str| {
+            let (db, env) = (db.get_pool().clone(), env.to_string());
+            async move {
+                let count: i64 =
+                    sqlx::query_scalar("SELECT COUNT(*) FROM environments WHERE env = ?")
+                        .bind(env)
+                        .fetch_one(&db)
+                        .await
+                        .unwrap();
+                count
+            }
+        };
+
+        // a deleted key is written even with an empty value, and so is an
+        // expired one
+        let vars: Vec<(String, String)> = [
+            ("same", "x"),
+            ("EMPTY", ""),
+            ("GONE", ""),
+            ("OLD", "o"),
+            ("CHANGED", "b"),
+            ("NEW", "n"),
+        ]
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+        let outcome = db.insert_many("dev", &vars).await.unwrap();
+        assert_eq!(
+            InsertManyOutcome {
+                written: 4,
+                unchanged: 2
+            },
+            outcome
+        );
+        assert_eq!(10, versions(&db, "dev").await);
+        let current = db.live_vars("dev").await.unwrap();
+        assert_eq!(Some(""), current.get("GONE").map(String::as_str));
+        assert_eq!(Some("b"), current.get("CHANGED").map(String::as_str));
+
+        // single inserts only skip them when asked to
+        let outcome = db.insert("prod", "same", "x").await.unwrap();
+        assert!(!outcome.changed);
+        assert_eq!(2, versions(&db, "prod").await);
+        db.set_dedupe(true);
+        let outcome = db.insert("prod", "same", "x").await.unwrap();
+        assert!(!outcome.changed);
+        let outcomes = db.set_in_envs(&["prod".into()], "same", "x").await.unwrap();
+        assert_eq!(vec![("prod".to_string(), SetOutcome::Unchanged)], outcomes);
+        assert_eq!(2, versions(&db, "prod").await);
+
+        // setting the same value twice writes it once
+        let outcomes = db
+            .set_in_envs(&["prod".into()], "fresh", "y")
+            .await
+            .unwrap();
+        assert_eq!(vec![("prod".to_string(), SetOutcome::Created)], outcomes);
+        let outcomes = db
+            .set_in_envs(&["prod".into()], "fresh", "y")
+            .await
+            .unwrap();
+        assert_eq!(vec![("prod".to_string(), SetOutcome::Unchanged)], outcomes);
+        assert_eq!(3, versions(&db, "prod").await);
+    }
+
+    #[tokio::test]
+    async fn test_templates() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('api-dev', 'DATABASE_URL', 'x', 1),
+            ('api-dev', 'PORT', '80', 1),
+            ('api-prod', 'DATABASE_URL', 'y', 1),
+            ('api-prod', 'PORT', '80', 1),
+            ('api-prod', 'PORT', NULL, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let keys = vec!["port".to_string(), "DATABASE_URL".to_string()];
+        db.add_template_keys("service", &keys).await.unwrap();
+        db.add_template_keys("service", &keys[..1]).await.unwrap();
+        db.add_template_keys("worker", &["QUEUE".into()])
+            .await
+            .unwrap();
+        assert!(db.add_template_keys("", &keys).await.is_err());
+
+        assert_eq!(
+            vec![
+                Template {
+                    name: "service".into(),
+                    keys: vec!["DATABASE_URL".into(), "PORT".into()]
+                },
+                Template {
+                    name: "worker".into(),
+                    keys: vec!["QUEUE".into()]
+                },
+            ],
+            db.list_templates().await.unwrap()
+        );
+
+        // fully satisfied
+        assert!(db
+            .validate_against_template("api-dev", "service")
+            .await
+            .unwrap()
+            .is_empty());
+        // deleted keys are missing
+        assert_eq!(
+            vec!["PORT"],
+            db.validate_against_template("api-prod", "service")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            vec!["DATABASE_URL", "PORT"],
+            db.validate_against_template("other", "service")
+                .await
+                .unwrap()
+        );
+        assert!(db
+            .validate_against_template("api-dev", "missing")
+            .await
+            .is_err());
+
+        assert_eq!(
+            1,
+            db.remove_template_keys("service", &["Port".into()])
+                .await
+                .unwrap()
+        );
+        assert_eq!(1, db.remove_template_keys("worker", &[]).await.unwrap());
+        assert_eq!(1, db.list_templates().await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_rename_var_everywhere() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'DB', 'd1', 1),
+            ('dev', 'DB', 'd2', 2),
+            ('prod', 'DB', 'p1', 1),
+            ('test', 'DB', 't1', 1),
+            ('test', 'DB', NULL, 2),
+            ('ci', 'OTHER', 'o1', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let renamed = db
+            .rename_var_everywhere("db", "database_url")
+            .await
+            .unwrap();
+        assert_eq!(vec!["dev", "prod"], renamed);
+
+        let current = |env: &'static str| {
+            let db = &db;
+            async move {
+                db.list_var_in_env(env, SortOrder::Asc)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|row| (row.key, row.value))
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(
+            vec![("DATABASE_URL".into(), "d2".into())],
+            current("dev").await
+        );
+        assert_eq!(
+            vec![("DATABASE_URL".into(), "p1".into())],
+            current("prod").await
+        );
+        assert!(current("test").await.is_empty());
+        assert_eq!(vec![("OTHER".into(), "o1".into())], current("ci").await);
+        // the history of the old key is kept
+        assert_eq!(vec!["DB"], db.list_deleted_var_in_env("dev").await.unwrap());
+
+        assert!(db.rename_var_everywhere("DB", "X").await.is_err());
+        assert!(db.rename_var_everywhere("OTHER", "other").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rename_var_conflict() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'OLD', 'd1', 1),
+            ('prod', 'OLD', 'p1', 1),
+            ('prod', 'NEW', 'p2', 1),
+            ('test', 'OLD', 't1', 1),
+            ('test', 'NEW', 't2', 1),
+            ('test', 'NEW', NULL, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let err = db.rename_var_everywhere("OLD", "NEW").await.unwrap_err();
+        assert_eq!("key NEW already exists in prod", err.to_string());
+        assert!(matches!(err, EnvelopeError::Conflict(_)));
+        // nothing has been renamed
+        assert!(db.list_deleted_var_in_env("dev").await.unwrap().is_empty());
+
+        // a deleted key can be reused
+        db.rename_var("test", "OLD", "NEW").await.unwrap();
+        assert_eq!(
+            "t1",
+            db.list_var_in_env("test", SortOrder::Asc).await.unwrap()[0].value
+        );
+        assert!(db.rename_var("test", "OLD", "NEW").await.is_err());
+        assert!(db.rename_var("prod", "OLD", "NEW").await.is_err());
+
+        db.lock_env("dev").await.unwrap();
+        assert!(db.rename_var("dev", "OLD", "NEW").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dedupe_case() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'HOST', 'h2', 2),
+            ('dev', 'Host', 'h3', 3),
+            ('dev', 'API_KEY', 'k', 5),
+            ('dev', 'Api_Key', 'k2', 5),
+            ('dev', 'token', 'b', 1),
+            ('dev', 'Token', 'a', 1),
+            ('dev', 'Port', '80', 1),
+            ('dev', 'PORT', '81', 1),
+            ('dev', 'PORT', NULL, 2),
+            ('prod', 'ApiKey', 'p', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let duplicates = db.diagnose().await.unwrap().case_duplicates;
+        let variants: Vec<(&str, Vec<&str>)> = duplicates
+            .iter()
+            .map(|d| {
+                (
+                    d.key.as_str(),
+                    d.variants.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                // the uppercased key wins within the same second
+                ("API_KEY", vec!["API_KEY", "Api_Key"]),
+                ("HOST", vec!["Host", "HOST"]),
+                ("TOKEN", vec!["Token", "token"]),
+            ],
+            variants
+        );
+
+        assert_eq!(duplicates, db.dedupe_case("dev").await.unwrap());
+        let vars: Vec<(String, String)> = db
+            .list_var_in_env("dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.key, row.value))
+            .collect();
+        assert_eq!(
+            vec![
+                ("API_KEY".to_string(), "k".to_string()),
+                ("HOST".to_string(), "h3".to_string()),
+                ("Port".to_string(), "80".to_string()),
+                ("TOKEN".to_string(), "a".to_string()),
+            ],
+            vars
+        );
+        assert_eq!(
+            vec!["Api_Key", "Host", "PORT", "Token", "token"],
+            db.list_deleted_var_in_env("dev").await.unwrap()
+        );
+
+        assert!(db.dedupe_case("dev").await.unwrap().is_empty());
+        assert!(db.diagnose().await.unwrap().case_duplicates.is_empty());
+    }
+
+    /// the queries of the variables tables `captured` logged
+    fn variable_queries(captured: &Captured) -> Vec<String> {
+        captured
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|line| line.contains("message=query"))
+            .filter(|line| line.contains("\"environments\"") || line.contains("\"latest_vars\""))
+            .cloned()
+            .collect()
+    }
+
+    /// seeds `base` and `target`, whose variables only differ by A, B, C
+    /// and D
+    async fn seed_pair(db: &EnvelopeDb) {
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at, expires_at)
+            VALUES
+            ('base', 'A', 'a', 1, NULL),
+            ('base', 'B', 'old', 1, NULL),
+            ('base', 'C', 'c', 1, NULL),
+            ('base', 'D', 'd', 1, 2),
+            ('base', 'SAME', 's', 1, NULL),
+            ('target', 'B', 'new', 1, NULL),
+            ('target', 'C', 'c', 1, NULL),
+            ('target', 'C', NULL, 2, NULL),
+            ('target', 'E', 'e', 1, NULL),
+            ('target', 'SAME', 'x', 1, NULL),
+            ('target', 'SAME', 's', 2, NULL);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_diff_envs() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let db = test_db().await;
+        seed_pair(&db).await;
+        let captured = Captured::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+
+        let diff = db.diff_envs("base", "target").await.unwrap();
+        assert_eq!(1, variable_queries(&captured).len());
+        assert_eq!(
+            EnvDiff {
+                added: BTreeMap::from([("E".into(), "e".into())]),
+                removed: BTreeMap::from([("A".into(), "a".into()), ("C".into(), "c".into())]),
+                changed: BTreeMap::from([("B".into(), ("old".into(), "new".into()))]),
+            },
+            diff
+        );
+        assert_eq!(
+            EnvDiff::between(
+                db.live_vars("base").await.unwrap(),
+                db.live_vars("target").await.unwrap()
+            ),
+            diff
+        );
+        assert_eq!(
+            EnvDiff {
+                added: db.live_vars("target").await.unwrap(),
+                ..Default::default()
+            },
+            db.diff_envs("none", "target").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge_env() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let db = test_db().await;
+        seed_pair(&db).await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            SELECT 'copy', key, value, created_at FROM environments WHERE env = 'target';",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        let captured = Captured::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+
+        // only the missing A and C are added, the expired D is not
+        let written = db
+            .merge_env("base", "target", MergeStrategy::Keep)
+            .await
+            .unwrap();
+        assert_eq!(2, written);
+        assert_eq!(1, variable_queries(&captured).len());
+        let target = db.live_vars("target").await.unwrap();
+        assert_eq!(Some("a"), target.get("A").map(String::as_str));
+        assert_eq!(Some("new"), target.get("B").map(String::as_str));
+        assert_eq!(Some("c"), target.get("C").map(String::as_str));
+        assert_eq!(None, target.get("D"));
+
+        // B is replaced too, the unchanged SAME is not written again
+        let written = db
+            .merge_env("base", "copy", MergeStrategy::Overwrite)
+            .await
+            .unwrap();
+        assert_eq!(3, written);
+        let copy = db.live_vars("copy").await.unwrap();
+        assert_eq!(Some("old"), copy.get("B").map(String::as_str));
+        assert_eq!(Some("e"), copy.get("E").map(String::as_str));
+        let (versions,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM environments WHERE env = 'copy' AND key = 'SAME'")
+                .fetch_one(db.get_pool())
+                .await
+                .unwrap();
+        assert_eq!(2, versions);
+
+        // the typed keys of the target are checked before anything is written
+        db.set_var_type("typed", "a", Some(ValueType::Int))
+            .await
+            .unwrap();
+        let err = db
+            .merge_env("base", "typed", MergeStrategy::Keep)
+            .await
+            .unwrap_err();
+        assert_eq!("A in typed must be of type int, got \"a\"", err.to_string());
+        assert!(db.live_vars("typed").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_copy_var() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let db = test_db().await;
+        seed_pair(&db).await;
+        let captured = Captured::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+
+        db.copy_var("base", "other", "b").await.unwrap();
+        assert_eq!(1, variable_queries(&captured).len());
+        assert_eq!(
+            BTreeMap::from([("B".to_string(), "old".to_string())]),
+            db.live_vars("other").await.unwrap()
+        );
+
+        for key in ["D", "NONE"] {
+            let err = db.copy_var("base", "other", key).await.unwrap_err();
+            assert!(
+                matches!(err, EnvelopeError::KeyNotFound { .. }),
+                "{:?}",
+                err
+            );
+        }
+
+        db.lock_env("other").await.unwrap();
+        let err = db.copy_var("base", "other", "A").await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::Locked(_)));
+    }
+
+    #[tokio::test]
+    async fn test_list_envs_with_keys() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let db = test_db().await;
+        seed_pair(&db).await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES ('gone', 'A', 'a', 1), ('gone', 'A', NULL, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        let captured = Captured::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+
+        let envs = db.list_envs_with_keys().await.unwrap();
+        assert_eq!(1, variable_queries(&captured).len());
+        assert_eq!(
+            BTreeMap::from([
+                ("base".to_string(), vec!["A", "B", "C", "SAME"]),
+                ("target".to_string(), vec!["B", "E", "SAME"]),
+            ]),
+            envs.iter()
+                .map(|(env, keys)| (env.clone(), keys.iter().map(String::as_str).collect()))
+                .collect::<BTreeMap<_, Vec<_>>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_existing() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'HOST', 'localhost', 1),
+            ('dev', 'PORT', '80', 1),
+            ('prod', 'HOST', 'example.com', 1),
+            ('old', 'HOST', 'x', 1),
+            ('old', 'HOST', NULL, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let err = db.duplicate("dev", "prod", false).await.unwrap_err();
+        assert_eq!(
+            "env prod already exists, use --append to copy into it",
+            err.to_string()
+        );
+        // an environment whose variables are all deleted still has a history
+        assert!(db.duplicate("dev", "old", false).await.is_err());
+        assert_eq!(
+            "example.com",
+            db.list_var_in_env("prod", SortOrder::Asc).await.unwrap()[0].value
+        );
+
+        db.duplicate_filtered("dev", "prod", &["PORT"], true)
+            .await
+            .unwrap();
+        let vars: Vec<(String, String)> = db
+            .list_var_in_env("prod", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.key, row.value))
+            .collect();
+        assert_eq!(
+            vec![
+                ("HOST".to_string(), "example.com".to_string()),
+                ("PORT".to_string(), "80".to_string()),
+            ],
+            vars
+        );
+
+        db.duplicate("dev", "new", false).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_vars() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'HOST', 'old', 1),
+            ('dev', 'HOST', 'localhost', 2),
+            ('dev', 'PORT', '80', 1),
+            ('dev', 'PORT', NULL, 2),
+            ('dev', 'DEBUG', '1', 1),
+            ('prod', 'TOKEN', 'x', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let keys = ["host".into(), "PORT".into(), "TOKEN".into(), "HOST".into()];
+        assert_eq!(
+            BTreeMap::from([
+                ("HOST".to_string(), Some("localhost".to_string())),
+                ("PORT".to_string(), None),
+                ("TOKEN".to_string(), None),
+            ]),
+            db.get_vars("dev", &keys).await.unwrap()
+        );
+        assert!(db.get_vars("dev", &[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_insert_exact() {
+        let db = test_db().await;
+        db.insert_exact("dev", "myKey", "exact").await.unwrap();
+        db.insert("dev", "mykey", "upper").await.unwrap();
+
+        assert_eq!(
+            Some("exact".into()),
+            db.get_var_exact("dev", "myKey").await.unwrap()
+        );
+        assert_eq!(
+            Some("upper".into()),
+            db.get_var_exact("dev", "MYKEY").await.unwrap()
+        );
+        assert_eq!(None, db.get_var_exact("dev", "mykey").await.unwrap());
+
+        let keys: Vec<String> = db
+            .list_var_in_env("dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.key)
+            .collect();
+        assert_eq!(vec!["MYKEY", "myKey"], keys);
+
+        // the type of the uppercased key applies
+        db.set_var_type("dev", "MYKEY", Some(ValueType::Int))
+            .await
+            .unwrap();
+        assert!(db.insert_exact("dev", "myKey", "x").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_var_types() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'PORT', '80', 1),
+            ('dev', 'DEBUG', 'Yes', 1),
+            ('prod', 'PORT', 'http', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        db.set_var_type("dev", "port", Some(ValueType::Port))
+            .await
+            .unwrap();
+        db.set_var_type("dev", "DEBUG", Some(ValueType::Bool))
+            .await
+            .unwrap();
+        assert_eq!(
+            BTreeMap::from([
+                ("DEBUG".to_string(), ValueType::Bool),
+                ("PORT".to_string(), ValueType::Port)
+            ]),
+            db.list_types("dev").await.unwrap()
+        );
+
+        let err = db.insert("dev", "port", "99999").await.unwrap_err();
+        assert_eq!(
+            "PORT in dev must be of type port, got \"99999\"",
+            err.to_string()
+        );
+        assert!(matches!(err, EnvelopeError::Constraint(_)));
+        assert!(db
+            .set_in_envs(&["prod".into(), "dev".into()], "DEBUG", "maybe")
+            .await
+            .is_err());
+        let diff = EnvDiff {
+            changed: BTreeMap::from([("PORT".into(), ("80".into(), "x".into()))]),
+            ..Default::default()
+        };
+        assert!(db.apply_diff("dev", &diff).await.is_err());
+        assert!(db.duplicate("prod", "dev", true).await.is_err());
+        // the types are per environment
+        db.insert("prod", "DEBUG", "maybe").await.unwrap();
+
+        db.insert("dev", "PORT", "8080").await.unwrap();
+        db.set_var_type("dev", "PORT", None).await.unwrap();
+        assert_eq!(1, db.list_types("dev").await.unwrap().len());
+        db.insert("dev", "DEBUG", "no").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_locked_env() {
+        let mut db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('prod', 'A', 'X', 1),
+            ('dev', 'A', 'Y', 1);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        db.lock_env("prod").await.unwrap();
+        db.lock_env("prod").await.unwrap();
+
+        assert!(db.insert("prod", "B", "Z").await.is_err());
+        assert!(db.delete_var_for_env("prod", "A").await.is_err());
+        assert!(db.delete_var_all("A").await.is_err());
+        assert!(db.delete_env("prod").await.is_err());
+        assert!(db.drop_env("prod").await.is_err());
+        assert!(db.duplicate("dev", "prod", true).await.is_err());
+        assert!(db
+            .set_in_envs(&["dev".into(), "prod".into()], "A", "Z")
+            .await
+            .is_err());
+        assert_eq!(
+            1,
+            db.list_var_in_env("prod", SortOrder::Asc)
+                .await
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            "Y",
+            db.list_var_in_env("dev", SortOrder::Asc).await.unwrap()[0].value
+        );
+
+        assert!(db.insert("dev", "B", "Z").await.is_ok());
+
+        db.set_force(true);
+        assert!(db.insert("prod", "B", "Z").await.is_ok());
+        assert_eq!(
+            2,
+            db.list_var_in_env("prod", SortOrder::Asc)
+                .await
+                .unwrap()
+                .len()
+        );
+        assert!(db.drop_env("prod").await.is_ok());
+
+        db.set_force(false);
+        assert!(db.insert("prod", "B", "Z").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_delete_var_all_locked() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('prod', 'A', 'X', 1),
+            ('prod', 'A', NULL, 2),
+            ('dev', 'A', 'Y', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        db.lock_env("prod").await.unwrap();
+
+        // prod deleted the key before it was locked, it is left alone
+        db.delete_var_all("A").await.unwrap();
+        assert_eq!(
+            BTreeMap::from([("A".to_string(), None)]),
+            db.get_vars("dev", &["A".into()]).await.unwrap()
+        );
+        db.delete_var_all("A").await.unwrap();
+
+        let deletions: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT env, COUNT(*) FROM environments WHERE value IS NULL GROUP BY env ORDER BY env",
+        )
+        .fetch_all(db.get_pool())
+        .await
+        .unwrap();
+        assert_eq!(vec![("dev".to_string(), 1), ("prod".into(), 1)], deletions);
+    }
+
+    #[tokio::test]
+    async fn test_unlock_env() {
+        let db = test_db().await;
+
+        db.lock_env("prod").await.unwrap();
+        assert!(db.insert("prod", "A", "X").await.is_err());
+
+        db.unlock_env("prod").await.unwrap();
+        assert!(db.insert("prod", "A", "X").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_diff_with_dotenv() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'SAME', 'x', 1),
+            ('dev', 'CHANGED', 'old', 1),
+            ('dev', 'REMOVED', 'gone', 1),
+            ('dev', 'DELETED', 'y', 1),
+            ('dev', 'DELETED', NULL, 2);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let diff = db
+            .diff_with_dotenv("dev", "SAME=x\nchanged=new\n# comment\nADDED=1\nDELETED=y")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            BTreeMap::from([
+                ("ADDED".to_string(), "1".to_string()),
+                ("DELETED".to_string(), "y".to_string())
+            ]),
+            diff.added
+        );
+        assert_eq!(
+            BTreeMap::from([("REMOVED".to_string(), "gone".to_string())]),
+            diff.removed
+        );
+        assert_eq!(
+            BTreeMap::from([(
+                "CHANGED".to_string(),
+                ("old".to_string(), "new".to_string())
+            )]),
+            diff.changed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_diff_with_dotenv_identical() {
+        let db = test_db().await;
+        db.insert("dev", "A", "1").await.unwrap();
+
+        let diff = db.diff_with_dotenv("dev", "A=1").await.unwrap();
+        assert!(diff.is_empty());
+    }
+
+    /// writes `contents` to a dotenv file of the temporary directory
+    fn dotenv_file(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("envelope-{}-{}.env", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn test_apply_dotenv_file() {
+        let db = test_db().await;
+        db.insert("dev", "HOST", "localhost").await.unwrap();
+        db.insert("dev", "PORT", "80").await.unwrap();
+        db.insert("dev", "KEPT", "k").await.unwrap();
+
+        let path = dotenv_file(
+            "apply",
+            "# database host\nhost=db\nPORT=80\nexport user='admin'\n",
+        );
+        let mode = ImportMode::SuffixOnConflict("_NEW".into());
+        let diff = db.apply_dotenv_file("dev", &path, mode).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            vec!["HOST_NEW", "USER"],
+            diff.added.keys().collect::<Vec<_>>()
+        );
+        assert!(diff.changed.is_empty() && diff.removed.is_empty());
+        let vars: Vec<(String, String)> = db
+            .list_var_in_env("dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.key, row.value))
+            .collect();
+        assert_eq!(
+            vec![
+                ("HOST".to_string(), "localhost".to_string()),
+                ("HOST_NEW".to_string(), "db".to_string()),
+                ("KEPT".to_string(), "k".to_string()),
+                ("PORT".to_string(), "80".to_string()),
+                ("USER".to_string(), "admin".to_string()),
+            ],
+            vars
+        );
+        assert_eq!(
+            Some("database host"),
+            db.list_descriptions("dev")
+                .await
+                .unwrap()
+                .get("HOST_NEW")
+                .map(String::as_str)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_dotenv_file_errors() {
+        let db = test_db().await;
+
+        let path = std::env::temp_dir().join("envelope-missing.env");
+        let err = db
+            .apply_dotenv_file("dev", &path, ImportMode::Overwrite)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, EnvelopeError::File { source, .. } if source.kind() == io::ErrorKind::NotFound)
+        );
+        assert!(err
+            .to_string()
+            .starts_with(&format!("cannot read {}: ", path.display())));
+
+        let path = dotenv_file("malformed", "A=1\n\nnot a variable\nB=2\n");
+        let err = db
+            .apply_dotenv_file("dev", &path, ImportMode::Overwrite)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            format!("{}:3: invalid line \"not a variable\"", path.display()),
+            err.to_string()
+        );
+        std::fs::remove_file(&path).unwrap();
+        // nothing is imported
+        assert!(db.list_environments().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient() {
+        use std::cell::Cell;
+
+        let path = std::env::temp_dir().join(format!("envelope-retry-{}.db", std::process::id()));
+        let options = ConnectOptions {
+            busy_timeout: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let mut db = EnvelopeDb::open_with(&path, &options).await.unwrap();
+        db.set_retry(RetryPolicy {
+            attempts: 4,
+            base_delay: Duration::from_millis(10),
+        });
+
+        // another connection holds the write lock until it commits
+        let blocker = SqlitePool::connect(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        let mut conn = blocker.acquire().await.unwrap();
+        sqlx::query("BEGIN IMMEDIATE")
+            .execute(&mut *conn)
+            .await
+            .unwrap();
+
+        // the first two attempts hit the lock
+        let calls = Cell::new(0);
+        let (pool, count) = (db.get_pool(), &calls);
+        let attempt = |blocked_until| {
+            count.set(count.get() + 1);
+            let blocked = count.get() <= blocked_until;
+            async move {
+                if blocked {
+                    sqlx::query(
+                        "INSERT INTO environments (env, key, value) VALUES ('dev', 'A', '1')",
+                    )
+                    .execute(pool)
+                    .await
+                    .map_err(db_error)?;
+                }
+                Ok(count.get())
+            }
+        };
+        assert_eq!(3, db.retry("test", || attempt(2)).await.unwrap());
+
+        // the lock outlasts every attempt, the waits are bounded by the busy
+        // timeout: 10ms then 20ms twice
+        calls.set(0);
+        let err = db.retry("test", || attempt(4)).await.unwrap_err();
+        let EnvelopeError::Blocked { operation, waited } = err else {
+            panic!("{:?}", err);
+        };
+        assert_eq!("test", operation);
+        assert!(waited >= Duration::from_millis(50), "{:?}", waited);
+        assert_eq!(4, calls.get());
+        assert_eq!(
+            format!(
+                "database is locked: test was blocked by another process for {}ms",
+                waited.as_millis()
+            ),
+            EnvelopeError::Blocked { operation, waited }.to_string()
+        );
+
+        // the other errors are not retried
+        calls.set(0);
+        let err = db
+            .retry("test", || {
+                calls.set(calls.get() + 1);
+                db.insert("dev", "A", "a\0b")
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, EnvelopeError::Constraint(_)), "{:?}", err);
+        assert_eq!(1, calls.get());
+
+        // a write waits for the lock to be released
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(25)).await;
+            sqlx::query("COMMIT").execute(&mut *conn).await.unwrap();
+        });
+        db.insert("dev", "A", "1").await.unwrap();
+        release.await.unwrap();
+        assert_eq!(
+            Some("1".to_string()),
+            db.get_var_exact("dev", "A").await.unwrap()
+        );
+
+        drop(db);
+        blocker.close().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_busy() {
+        let mut db = test_db().await;
+        db.set_write_timeout(Some(Duration::from_millis(50)));
+
+        let guard = db.write_guard().await.unwrap();
+        let err = db.insert("dev", "A", "X").await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::Busy(t) if t == Duration::from_millis(50)));
+        assert_eq!(
+            "database is busy: another write did not complete within 50ms",
+            err.to_string()
+        );
+        drop(guard);
+
+        assert!(db.insert("dev", "A", "X").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_read_only() {
+        let path = std::env::temp_dir().join(format!("envelope-ro-{}.db", std::process::id()));
+        let db = EnvelopeDb::open(&path).await.unwrap();
+        db.insert("dev", "A", "1").await.unwrap();
+        drop(db);
+
+        let db = EnvelopeDb::open_read_only(&path).await.unwrap();
+        let vars = db.list_var_in_env("dev", SortOrder::Asc).await.unwrap();
+        assert_eq!(1, vars.len());
+        assert_eq!(1, db.list_environments().await.unwrap().len());
+
+        let err = db.insert("dev", "B", "2").await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::ReadOnly));
+        assert_eq!("database is opened read-only", err.to_string());
+
+        // rejected before any query, the missing env is never looked up
+        let diff = EnvDiff::between(BTreeMap::new(), BTreeMap::from([("B".into(), "2".into())]));
+        let err = db.duplicate("missing", "copy", false).await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::ReadOnly));
+        for err in [
+            db.delete_env("dev").await.unwrap_err(),
+            db.lock_env("dev").await.unwrap_err(),
+            db.set_active_env(Some("dev")).await.unwrap_err(),
+            db.apply_diff("dev", &diff).await.unwrap_err(),
+        ] {
+            assert!(matches!(err, EnvelopeError::ReadOnly));
+        }
+
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_if_absent() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at, expires_at)
+            VALUES
+            ('ci', 'PORT', '80', 1, NULL),
+            ('ci', 'DEBUG', 'true', 1, NULL),
+            ('ci', 'DEBUG', NULL, 2, NULL),
+            ('ci', 'TOKEN', 'old', 1, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        assert!(db.set_if_absent("ci", "host", "localhost").await.unwrap());
+        assert!(!db.set_if_absent("ci", "port", "8080").await.unwrap());
+        // deleted and expired keys are absent
+        assert!(db.set_if_absent("ci", "debug", "false").await.unwrap());
+        assert!(db.set_if_absent("ci", "token", "new").await.unwrap());
+        // the other environments do not count
+        assert!(db.set_if_absent("dev", "port", "3000").await.unwrap());
+
+        let vars: Vec<(String, String)> = db
+            .list_var_in_env("ci", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.key, v.value))
+            .collect();
+        assert_eq!(
+            vec![
+                ("DEBUG".into(), "false".into()),
+                ("HOST".into(), "localhost".into()),
+                ("PORT".into(), "80".into()),
+                ("TOKEN".into(), "new".into()),
+            ],
+            vars
+        );
+
+        // running it again writes nothing
+        for (key, value) in [("host", "localhost"), ("debug", "false"), ("token", "new")] {
+            assert!(!db.set_if_absent("ci", key, value).await.unwrap());
+        }
+        assert_eq!(
+            7,
+            db.history_between("ci", None, None, None)
+                .await
+                .unwrap()
+                .len()
+        );
+
+        db.lock_env("ci").await.unwrap();
+        assert!(matches!(
+            db.set_if_absent("ci", "other", "1").await.unwrap_err(),
+            EnvelopeError::Locked(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_append_var() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at, expires_at)
+            VALUES
+            ('dev', 'PATH', '/usr/bin', 1, NULL),
+            ('dev', 'FLAGS', '-O2', 1, NULL),
+            ('dev', 'FLAGS', NULL, 2, NULL),
+            ('dev', 'HOSTS', 'a', 1, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        assert_eq!(
+            "/usr/bin:/opt/bin",
+            db.append_var("dev", "path", "/opt/bin", ":").await.unwrap()
+        );
+        // absent, deleted and expired keys get the suffix alone
+        assert_eq!("x", db.append_var("dev", "new", "x", ",").await.unwrap());
+        assert_eq!(
+            "-g",
+            db.append_var("dev", "flags", "-g", " ").await.unwrap()
+        );
+        assert_eq!("b", db.append_var("dev", "hosts", "b", ",").await.unwrap());
+
+        let vars: Vec<(String, String)> = db
+            .list_var_in_env("dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.key, v.value))
+            .collect();
+        assert_eq!(
+            vec![
+                ("FLAGS".into(), "-g".into()),
+                ("HOSTS".into(), "b".into()),
+                ("NEW".into(), "x".into()),
+                ("PATH".into(), "/usr/bin:/opt/bin".into()),
+            ],
+            vars
+        );
+        // the previous value is kept in the history
+        assert_eq!(
+            2,
+            db.history_between("dev", Some("PATH"), None, None)
+                .await
+                .unwrap()
+                .len()
+        );
+
+        // a second append in the same second is refused, it would collide
+        // with the version just written
+        let err = db.append_var("dev", "path", "/a", ":").await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::Conflict(_)), "{:?}", err);
+        sqlx::query("UPDATE environments SET created_at = created_at - 10 WHERE created_at > 2")
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+        assert_eq!(
+            "/usr/bin:/opt/bin:/a",
+            db.append_var("dev", "path", "/a", ":").await.unwrap()
+        );
+        let history = db
+            .history_between("dev", Some("PATH"), None, None)
+            .await
+            .unwrap();
+        assert_eq!(3, history.len());
+        // no version is created ahead of time
+        let newest: i64 = sqlx::query_scalar("SELECT MAX(created_at) FROM environments")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap();
+        assert!(newest <= unix_now());
+
+        db.lock_env("dev").await.unwrap();
+        let err = db.append_var("dev", "PATH", "/x", ":").await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::Locked(_)));
+    }
+
+    #[tokio::test]
+    async fn test_append_var_ttl() {
+        let db = test_db().await;
+        let expires_at = unix_now() + 3600;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at, expires_at)
+            VALUES ('dev', 'HOSTS', 'a', 1, ?);",
+        )
+        .bind(expires_at)
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        assert_eq!(
+            "a,b",
+            db.append_var("dev", "hosts", "b", ",").await.unwrap()
+        );
+        let ttl: Option<i64> = sqlx::query_scalar(
+            "SELECT expires_at FROM environments WHERE key = 'HOSTS' AND value = 'a,b'",
+        )
+        .fetch_one(db.get_pool())
+        .await
+        .unwrap();
+        assert_eq!(Some(expires_at), ttl);
+    }
+
+    #[tokio::test]
+    async fn test_list_var_in_env_resolved() {
+        let db = test_db().await;
+        db.insert("prod", "DB_PASSWORD", "vault://secret/data/app#password")
+            .await
+            .unwrap();
+        db.insert("prod", "API_KEY", "ssm:///app/api_key")
+            .await
+            .unwrap();
+        db.insert("prod", "PORT", "80").await.unwrap();
+        let resolver = MockResolver::new([
+            ("vault://secret/data/app#password", "hunter2"),
+            ("ssm:///app/api_key", "s3cr3t"),
+        ]);
+
+        let vars = db
+            .list_var_in_env_resolved("prod", SortOrder::Asc, &resolver)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![
+                ("API_KEY", "s3cr3t"),
+                ("DB_PASSWORD", "hunter2"),
+                ("PORT", "80")
+            ],
+            vars.iter()
+                .map(|v| (v.key.as_str(), v.value.as_str()))
+                .collect::<Vec<_>>()
+        );
+        // the references are stored, not the secrets
+        let vars = db.list_var_in_env("prod", SortOrder::Asc).await.unwrap();
+        assert_eq!("ssm:///app/api_key", vars[0].value);
+
+        db.insert("staging", "TOKEN", "ssm:///app/token")
+            .await
+            .unwrap();
+        let err = db
+            .list_var_in_env_resolved("staging", SortOrder::Asc, &resolver)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, EnvelopeError::SecretNotFound(r) if r == "ssm:///app/token"));
+
+        db.insert("dev", "TOKEN", "vault://secret/data/app")
+            .await
+            .unwrap();
+        let err = db
+            .list_var_in_env_resolved("dev", SortOrder::Asc, &resolver)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, EnvelopeError::InvalidReference { .. }));
+
+        // op:// values are kept as they are unless marked as references
+        let resolver = MockResolver::new([("op://Dev/db/password", "hunter2")]);
+        db.insert("qa", "DOC", "op://Dev/db/password")
+            .await
+            .unwrap();
+        db.insert("qa", "DB_PASS", "op://Dev/db/password")
+            .await
+            .unwrap();
+        db.set_var_type("qa", "db_pass", Some(ValueType::Reference))
+            .await
+            .unwrap();
+        let vars = db
+            .list_var_in_env_resolved("qa", SortOrder::Asc, &resolver)
+            .await
+            .unwrap();
+        assert_eq!("hunter2", vars[0].value);
+        assert_eq!("op://Dev/db/password", vars[1].value);
+        assert_eq!(
+            1,
+            resolver.lookups.load(std::sync::atomic::Ordering::Relaxed)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_mode() {
+        let path = std::env::temp_dir().join(format!("envelope-audit-{}.db", std::process::id()));
+        let db = EnvelopeDb::open(&path).await.unwrap();
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', '1', 1),
+            ('dev', 'A', '2', 2),
+            ('dev', 'B', '1', 1),
+            ('prod', 'A', '1', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        assert!(!db.audit_mode().await.unwrap());
+        db.enable_audit_mode().await.unwrap();
+        drop(db);
+
+        // the mode is stored in the database, forcing does not lift it
+        let mut db = EnvelopeDb::open(&path).await.unwrap();
+        db.set_force(true);
+        assert!(db.audit_mode().await.unwrap());
+        for (err, operation) in [
+            (db.drop_env("dev").await.unwrap_err(), "drop_env"),
+            (db.flatten_env("dev").await.unwrap_err(), "flatten_env"),
+            (
+                db.purge_older_than("dev", i64::MAX).await.unwrap_err(),
+                "purge_older_than",
+            ),
+            (db.purge_expired().await.unwrap_err(), "purge_expired"),
+        ] {
+            assert!(matches!(err, EnvelopeError::AuditModeEnabled(op) if op == operation));
+            assert_eq!(6, err.exit_code());
+        }
+        assert_eq!(
+            "drop_env is not allowed in audit mode, history can only be appended to",
+            db.drop_env("dev").await.unwrap_err().to_string()
+        );
+        assert_eq!(
+            3,
+            db.history_between("dev", None, None, None)
+                .await
+                .unwrap()
+                .len()
+        );
+
+        // soft deletes record a deletion and keep the history
+        db.delete_var_for_env("dev", "B").await.unwrap();
+        db.delete_env("prod").await.unwrap();
+        let vars = db.list_var_in_env("dev", SortOrder::Asc).await.unwrap();
+        assert_eq!(
+            vec!["A"],
+            vars.iter().map(|v| v.key.as_str()).collect::<Vec<_>>()
+        );
+        assert!(db
+            .list_var_in_env("prod", SortOrder::Asc)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            4,
+            db.history_between("dev", None, None, None)
+                .await
+                .unwrap()
+                .len()
+        );
+        assert_eq!(
+            2,
+            db.history_between("prod", None, None, None)
+                .await
+                .unwrap()
+                .len()
+        );
+
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_open_with() {
+        let path = std::env::temp_dir().join(format!("envelope-opts-{}.db", std::process::id()));
+        let options = ConnectOptions {
+            journal_mode: Some(SqliteJournalMode::Wal),
+            busy_timeout: Duration::from_millis(1500),
+            foreign_keys: false,
+            max_connections: 3,
+            ..Default::default()
+        };
+        let db = EnvelopeDb::open_with(&path, &options).await.unwrap();
+        db.insert("dev", "A", "1").await.unwrap();
+
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap();
+        assert_eq!("wal", journal_mode);
+        let busy_timeout: i64 = sqlx::query_scalar("PRAGMA busy_timeout")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap();
+        assert_eq!(1500, busy_timeout);
+        let foreign_keys: bool = sqlx::query_scalar("PRAGMA foreign_keys")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap();
+        assert!(!foreign_keys);
+        assert_eq!(3, db.get_pool().options().get_max_connections());
+        drop(db);
+
+        // the defaults keep the journal mode of the database
+        let db = EnvelopeDb::open(&path).await.unwrap();
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap();
+        assert_eq!("wal", journal_mode);
+        let foreign_keys: bool = sqlx::query_scalar("PRAGMA foreign_keys")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap();
+        assert!(foreign_keys);
+        drop(db);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+
+        let missing =
+            std::env::temp_dir().join(format!("envelope-missing-{}.db", std::process::id()));
+        let options = ConnectOptions {
+            create_if_missing: false,
+            ..Default::default()
+        };
+        assert!(EnvelopeDb::open_with(&missing, &options).await.is_err());
+        assert!(!missing.exists());
+
+        // without the migrations the tables are left to the caller
+        let options = ConnectOptions {
+            run_migrations: false,
+            ..Default::default()
+        };
+        let db = EnvelopeDb::open_with(&missing, &options).await.unwrap();
+        assert!(matches!(
+            db.list_environments().await.unwrap_err(),
+            EnvelopeError::Sqlx(_)
+        ));
+        migrate(db.get_pool()).await.unwrap();
+        db.insert("dev", "A", "1").await.unwrap();
+        drop(db);
+        std::fs::remove_file(&missing).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_empty_names_rejected() {
+        let db = test_db().await;
+
+        let err = db.insert("", "A", "X").await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::Constraint(m) if m == "env name cannot be empty"));
+        let err = db.insert("dev", "", "X").await.unwrap_err();
+        assert_eq!("key name cannot be empty", err.to_string());
+        let err = db.set_description("dev", "", "X").await.unwrap_err();
+        assert_eq!("key name cannot be empty", err.to_string());
+
+        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM environments")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap();
+        assert_eq!(0, count.0);
+    }
+
+    #[tokio::test]
+    async fn test_insert_outcome() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'SAME', 'x', 1),
+            ('dev', 'OLD', 'x', 1),
+            ('dev', 'GONE', 'x', 1),
+            ('dev', 'GONE', NULL, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let outcome = |previous: Option<&str>, changed| InsertOutcome {
+            previous: previous.map(str::to_string),
+            changed,
+        };
+        assert_eq!(
+            outcome(None, true),
+            db.insert("dev", "new", "x").await.unwrap()
+        );
+        assert_eq!(
+            outcome(Some("x"), false),
+            db.insert("dev", "same", "x").await.unwrap()
+        );
+        assert_eq!(
+            outcome(Some("x"), true),
+            db.insert("dev", "old", "y").await.unwrap()
+        );
+        assert_eq!(
+            outcome(None, true),
+            db.insert("dev", "gone", "x").await.unwrap()
+        );
+        assert_eq!(
+            outcome(None, true),
+            db.insert_exact("dev", "same", "x").await.unwrap()
+        );
+        assert_eq!(
+            Some("y".to_string()),
+            db.get_var_exact("dev", "OLD").await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_nul_rejected() {
+        let db = test_db().await;
+
+        let err = db.insert("dev", "a", "x\0y").await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::Constraint(m) if m == "A in dev contains a NUL byte"));
+        assert!(db.insert("dev", "a\0b", "x").await.is_err());
+        assert!(db.set_in_envs(&["dev".into()], "A", "\0").await.is_err());
+        let diff = EnvDiff {
+            added: BTreeMap::from([("A".into(), "x\0".into())]),
+            ..Default::default()
+        };
+        assert!(db.apply_diff("dev", &diff).await.is_err());
+
+        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM environments")
+            .fetch_one(db.get_pool())
+            .await
+            .unwrap();
+        assert_eq!(0, count.0);
+    }
+
+    #[tokio::test]
+    async fn test_utf8_round_trip() {
+        let db = test_db().await;
+        let values = ["héllo wörld", "日本語", "🦀 crab", "a\u{200b}b", "\r\n\t"];
+
+        for (i, value) in values.iter().enumerate() {
+            db.insert("dev", &format!("k{}", i), value).await.unwrap();
+        }
+        let keys: Vec<String> = (0..values.len()).map(|i| format!("K{}", i)).collect();
+        let vars = db.get_vars("dev", &keys).await.unwrap();
+        for (key, value) in keys.iter().zip(values) {
+            assert_eq!(Some(value), vars[key].as_deref());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_queued() {
+        let db = test_db().await;
+
+        let guard = db.write_guard().await.unwrap();
+        let (res, _) = tokio::join!(db.insert("dev", "A", "X"), async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            drop(guard);
+        });
+        assert!(res.is_ok());
+        assert_eq!(
+            1,
+            db.list_var_in_env("dev", SortOrder::Asc)
+                .await
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flatten_env() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'A', 'a2', 2),
+            ('dev', 'A', 'a3', 3),
+            ('dev', 'B', 'b1', 1),
+            ('dev', 'B', NULL, 2),
+            ('dev', 'C', 'c1', 1),
+            ('prod', 'A', 'p1', 1),
+            ('prod', 'A', 'p2', 2);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        db.flatten_env("dev").await.unwrap();
+
+        assert_eq!(
+            vec![
+                HistoryRow {
+                    env: "dev".into(),
+                    key: "A".into(),
+                    value: Some("a3".into()),
+                    created_at: 3
+                },
+                HistoryRow {
+                    env: "dev".into(),
+                    key: "C".into(),
+                    value: Some("c1".into()),
+                    created_at: 1
+                },
+            ],
+            db.history_between("dev", None, None, None).await.unwrap()
+        );
+        assert_eq!(
+            2,
+            db.history_between("prod", Some("a"), None, None)
+                .await
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_purge_older_than() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'A', 'a2', 2),
+            ('dev', 'A', 'a3', 5),
+            ('dev', 'B', 'b1', 1),
+            ('dev', 'B', 'b2', 2),
+            ('dev', 'C', 'c1', 1),
+            ('dev', 'C', NULL, 2),
+            ('dev', 'D', 'd1', 1),
+            ('dev', 'D', NULL, 6),
+            ('prod', 'A', 'p1', 1),
+            ('prod', 'A', 'p2', 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        assert_eq!(6, db.purge_older_than("dev", 4).await.unwrap());
+
+        let versions: Vec<(String, Option<String>)> = db
+            .history_between("dev", None, None, None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.key, row.value))
+            .collect();
+        assert_eq!(
+            vec![
+                ("A".to_string(), Some("a3".to_string())),
+                ("B".to_string(), Some("b2".to_string())),
+                ("D".to_string(), None),
+            ],
+            versions
+        );
+        let current = db.list_var_in_env("dev", SortOrder::Asc).await.unwrap();
+        assert_eq!(2, current.len());
+        assert_eq!(
+            2,
+            db.history_between("prod", None, None, None)
+                .await
+                .unwrap()
+                .len()
+        );
+
+        assert_eq!(0, db.purge_older_than("dev", 4).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_diagnose() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'A', NULL, 2),
+            ('dev', 'B', NULL, 3),
+            ('dev', 'B', NULL, 4);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let diagnostics = db.diagnose().await.unwrap();
+        assert_eq!(vec!["ok".to_string()], diagnostics.integrity);
+        assert!(diagnostics.pending_migrations.is_empty());
+        assert!(diagnostics.unknown_migrations.is_empty());
+        assert!(!diagnostics.indexes.is_empty());
+        assert_eq!(2, diagnostics.orphaned_tombstones);
+        assert_eq!(Some(4), diagnostics.latest_created_at);
+
+        sqlx::query("INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time) VALUES (99990101000000, 'future', 1, x'00', 0)")
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+        // left by another application until a newer envelope records it
+        assert!(db.diagnose().await.unwrap().unknown_migrations.is_empty());
+        sqlx::query("UPDATE settings SET value = '99990101000000' WHERE name = 'schema_version'")
+            .execute(db.get_pool())
+            .await
+            .unwrap();
+        let diagnostics = db.diagnose().await.unwrap();
+        assert_eq!(vec![99990101000000], diagnostics.unknown_migrations);
+    }
+
+    #[tokio::test]
+    async fn test_history_between() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'a1', 100),
+            ('dev', 'A', 'a2', 200),
+            ('dev', 'A', NULL, 300),
+            ('dev', 'B', 'b1', 150),
+            ('dev', 'B', 'b2', 400),
+            ('prod', 'A', 'p1', 200);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let created_at = |rows: Vec<HistoryRow>| -> Vec<(String, i64)> {
+            rows.into_iter().map(|r| (r.key, r.created_at)).collect()
+        };
+
+        // since is inclusive, until is exclusive
+        let rows = db
+            .history_between("dev", None, Some(150), Some(300))
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![("A".to_string(), 200), ("B".to_string(), 150)],
+            created_at(rows)
+        );
+
+        let rows = db
+            .history_between("dev", Some("a"), Some(200), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![("A".to_string(), 200), ("A".to_string(), 300)],
+            created_at(rows)
+        );
+
+        let rows = db
+            .history_between("dev", None, None, Some(150))
+            .await
+            .unwrap();
+        assert_eq!(vec![("A".to_string(), 100)], created_at(rows));
+
+        let rows = db
+            .history_between("dev", None, Some(300), Some(300))
+            .await
+            .unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_changes_between() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'A', 'a2', 10),
+            ('dev', 'A', 'a2', 11),
+            ('dev', 'A', NULL, 12),
+            ('dev', 'B', 'b1', 5),
+            ('dev', 'B', NULL, 6),
+            ('dev', 'B', 'b2', 11),
+            ('dev', 'C', 'c1', 10),
+            ('dev', 'C', 'c2', 20),
+            ('dev', 'D', NULL, 10),
+            ('prod', 'A', 'p1', 10);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let change =
+            |key: &str, kind, old: Option<&str>, new: Option<&str>, created_at| ChangeEvent {
+                key: key.to_string(),
+                kind,
+                old: old.map(str::to_string),
+                new: new.map(str::to_string),
+                created_at,
+            };
+        assert_eq!(
+            vec![
+                change("A", ChangeKind::Changed, Some("a1"), Some("a2"), 10),
+                change("C", ChangeKind::Added, None, Some("c1"), 10),
+                change("B", ChangeKind::Added, None, Some("b2"), 11),
+                change("A", ChangeKind::Deleted, Some("a2"), None, 12),
+            ],
+            db.changes_between("dev", 10, 20).await.unwrap()
+        );
+        assert_eq!(
+            vec![
+                change("B", ChangeKind::Added, None, Some("b1"), 5),
+                change("B", ChangeKind::Deleted, Some("b1"), None, 6),
+            ],
+            db.changes_between("dev", 2, 10).await.unwrap()
+        );
+        assert!(db.changes_between("dev", 21, 30).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_key_order() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'Z', 'z1', 1),
+            ('dev', 'A', 'a1', 2),
+            ('dev', 'Z', 'z2', 3),
+            ('dev', 'M', 'm1', 2),
+            ('dev', 'B', NULL, 4),
+            ('prod', 'C', 'c1', 0);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        assert_eq!(vec!["Z", "A", "M", "B"], db.key_order("dev").await.unwrap());
+        assert!(db.key_order("missing").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_key_times() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'HOST', 'a', 10),
+            ('dev', 'HOST', 'b', 20),
+            ('dev', 'HOST', NULL, 30),
+            ('dev', 'HOST', 'c', 40),
+            ('dev', 'HOST', NULL, 50),
+            ('dev', 'PORT', '80', 15),
+            ('dev', 'GONE', NULL, 5),
+            ('prod', 'HOST', 'p', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let time = |secs| DateTime::from_timestamp(secs, 0);
+        assert_eq!(time(10), db.key_first_seen("dev", "host").await.unwrap());
+        assert_eq!(time(40), db.key_last_modified("dev", "HOST").await.unwrap());
+        assert_eq!(time(15), db.key_first_seen("dev", "PORT").await.unwrap());
+        assert_eq!(time(15), db.key_last_modified("dev", "PORT").await.unwrap());
+        assert_eq!(time(1), db.key_first_seen("prod", "HOST").await.unwrap());
+
+        // a key that only has deletions was seen but never modified
+        assert_eq!(time(5), db.key_first_seen("dev", "GONE").await.unwrap());
+        assert_eq!(None, db.key_last_modified("dev", "GONE").await.unwrap());
+        assert_eq!(None, db.key_first_seen("dev", "MISSING").await.unwrap());
+        assert_eq!(None, db.key_last_modified("missing", "HOST").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_dump_raw() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('prod', 'A', 'p1', 1),
+            ('dev', 'B', 'b1', 1),
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'A', 'a2', 2),
+            ('dev', 'A', NULL, 3),
+            ('dev', 'B', NULL, 4);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let rows = db.dump_raw().await.unwrap();
+        assert_eq!(6, rows.len());
+        let order: Vec<(&str, &str, i64)> = rows
+            .iter()
+            .map(|r| (r.env.as_str(), r.key.as_str(), r.created_at))
+            .collect();
+        assert_eq!(
+            vec![
+                ("dev", "A", 1),
+                ("dev", "A", 2),
+                ("dev", "A", 3),
+                ("dev", "B", 1),
+                ("dev", "B", 4),
+                ("prod", "A", 1)
+            ],
+            order
+        );
+        assert_eq!(2, rows.iter().filter(|r| r.value.is_none()).count());
+    }
+
+    #[tokio::test]
+    async fn test_value_sizes() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'B', 'a much longer value', 1),
+            ('dev', 'B', 'short', 2),
+            ('dev', 'A', 'café ☕', 1),
+            ('dev', 'C', '', 1),
+            ('dev', 'D', 'deleted', 1),
+            ('dev', 'D', NULL, 2),
+            ('prod', 'A', 'other', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let sizes = db.value_sizes("dev").await.unwrap();
+        assert_eq!(
+            vec![
+                ("A".to_string(), "café ☕".len() as i64),
+                ("B".to_string(), 5),
+                ("C".to_string(), 0)
+            ],
+            sizes
+        );
+
+        for (row, (key, size)) in db
+            .list_var_in_env("dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .iter()
+            .zip(&sizes)
+        {
+            assert_eq!(&row.key, key);
+            assert_eq!(row.value.len() as i64, *size);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_var_detailed() {
+        let db = test_db().await;
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'B', 'b1', 1),
+            ('dev', 'B', 'b2', 2),
+            ('dev', 'B', 'b3', 5),
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'C', 'c1', 1),
+            ('dev', 'C', NULL, 2),
+            ('dev', 'C', 'c2', 3),
+            ('dev', 'D', 'd1', 1),
+            ('dev', 'D', NULL, 4),
+            ('prod', 'A', 'p1', 1),
+            ('prod', 'A', 'p2', 9);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let rows: Vec<(String, String, i64, i64)> = db
+            .list_var_detailed("dev")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.key, r.value, r.version_count, r.last_modified))
+            .collect();
+        assert_eq!(
+            vec![
+                ("A".to_string(), "a1".to_string(), 1, 1),
+                ("B".to_string(), "b3".to_string(), 3, 5),
+                ("C".to_string(), "c2".to_string(), 3, 3),
+            ],
+            rows
+        );
+    }
+
+    #[test]
+    fn test_glob_to_like() {
+        assert_eq!("DB%", glob_to_like("DB*"));
+        assert_eq!("A_B", glob_to_like("A?B"));
+        assert_eq!("\\_\\%\\\\%", glob_to_like("_%\\*"));
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_filtered() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('mono', 'DB_URL', 'postgres://', 1),
+            ('mono', 'DB_PORT', '5432', 1),
+            ('mono', 'DB_NAME', 'app', 1),
+            ('mono', 'DB_NAME', NULL, 2),
+            ('mono', 'DBXHOST', 'x', 1),
+            ('mono', 'API_KEY', 'k', 1),
+            ('mono', 'API_URL', 'u', 1),
+            ('mono', 'PORT', '80', 1);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        db.duplicate_filtered("mono", "db", &["db_*"], false)
+            .await
+            .unwrap();
+        db.duplicate_filtered("mono", "api", &["API_KEY", "?ORT"], false)
+            .await
+            .unwrap();
+        db.duplicate_filtered("mono", "none", &[], false)
+            .await
+            .unwrap();
+
+        let keys = |rows: Vec<EnvironmentRow>| -> Vec<String> {
+            rows.into_iter().map(|r| r.key).collect()
+        };
+        assert_eq!(
+            vec!["DB_PORT", "DB_URL"],
+            keys(db.list_var_in_env("db", SortOrder::Asc).await.unwrap())
+        );
+        assert_eq!(
+            vec!["API_KEY", "PORT"],
+            keys(db.list_var_in_env("api", SortOrder::Asc).await.unwrap())
+        );
+        assert!(db
+            .list_var_in_env("none", SortOrder::Asc)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // lists what is copied
+        assert_eq!(
+            vec!["DB_PORT", "DB_URL"],
+            keys(db.list_var_matching("mono", &["db_*"]).await.unwrap())
+        );
+        assert!(db.list_var_matching("mono", &[]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_group_identical_envs() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('prod', 'A', 'a1', 1),
+            ('prod', 'B', 'b1', 1),
+            ('dev', 'B', 'b1', 1),
+            ('dev', 'A', 'a0', 1),
+            ('dev', 'A', 'a1', 2),
+            ('dev', 'C', 'c1', 2),
+            ('dev', 'C', NULL, 3),
+            ('test', 'A', 'a1', 1),
+            ('test', 'B', 'b2', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        assert_eq!(
+            vec![vec!["dev", "prod"], vec!["test"]],
+            db.group_identical_envs().await.unwrap()
+        );
+        assert!(test_db()
+            .await
+            .group_identical_envs()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expiry() {
+        let mut db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at, expires_at)
+            VALUES
+            ('dev', 'A', 'a1', 1, NULL),
+            ('dev', 'B', 'b1', 1, 2),
+            ('dev', 'C', 'c1', 1, 2),
+            ('dev', 'C', 'c2', 3, NULL),
+            ('prod', 'B', 'p1', 1, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        db.insert_with_ttl("dev", "d", "d1", Duration::from_secs(3600))
+            .await
+            .unwrap();
+
+        let keys = |rows: Vec<EnvironmentRow>| -> Vec<String> {
+            rows.into_iter().map(|row| row.key).collect()
+        };
+        assert_eq!(
+            vec!["A", "C", "D"],
+            keys(db.list_var_in_env("dev", SortOrder::Asc).await.unwrap())
+        );
+        let vars = db.get_vars("dev", &["B".into(), "D".into()]).await.unwrap();
+        assert_eq!(None, vars["B"]);
+        assert_eq!(Some("d1"), vars["D"].as_deref());
+
+        db.set_include_expired(true);
+        assert_eq!(
+            vec!["A", "B", "C", "D"],
+            keys(db.list_var_in_env("dev", SortOrder::Asc).await.unwrap())
+        );
+        db.set_include_expired(false);
+
+        // B in both envs and the first version of C
+        assert_eq!(3, db.purge_expired().await.unwrap());
+        db.set_include_expired(true);
+        assert_eq!(
+            vec!["A", "C", "D"],
+            keys(db.list_var_in_env("dev", SortOrder::Asc).await.unwrap())
+        );
+        let history = db.history_between("dev", Some("C"), None, None).await;
+        assert_eq!(1, history.unwrap().len());
+        assert_eq!(0, db.purge_expired().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_empty_environments() {
+        let db = test_db().await;
+        assert!(db.empty_environments().await.unwrap().is_empty());
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'a', 1),
+            ('dev', 'B', 'b', 1),
+            ('prod', 'A', 'a', 1),
+            ('old', 'A', 'a1', 1),
+            ('old', 'A', NULL, 2),
+            ('old', 'B', 'b1', 1),
+            ('old', 'B', 'b2', 3),
+            ('old', 'B', NULL, 4),
+            ('again', 'A', 'a1', 1),
+            ('again', 'A', NULL, 2),
+            ('again', 'A', 'a2', 3);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        assert_eq!(vec!["old"], db.empty_environments().await.unwrap());
+
+        db.delete_env("dev").await.unwrap();
+
+        assert_eq!(vec!["dev", "old"], db.empty_environments().await.unwrap());
+        assert!(db.check_env_exists("old").await.is_ok());
+        assert!(db.check_env_exists("missing").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'B', 'b1', 2),
+            ('dev', 'C', 'c1', 3),
+            ('dev', 'C', NULL, 4),
+            ('prod', 'B', 'b1', 1),
+            ('prod', 'A', 'a0', 2),
+            ('prod', 'A', 'a1', 3),
+            ('test', 'A', 'a', 1),
+            ('test', 'B', '1b1', 1),
+            ('empty', 'A', NULL, 1);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let dev = db.fingerprint("dev").await.unwrap();
+        assert_eq!(64, dev.len());
+        assert_eq!(dev, db.fingerprint("prod").await.unwrap());
+        assert_ne!(dev, db.fingerprint("test").await.unwrap());
+        assert_eq!(
+            db.fingerprint("missing").await.unwrap(),
+            db.fingerprint("empty").await.unwrap()
+        );
+
+        sqlx::query(
+            "INSERT INTO environments (env, key, value, created_at) VALUES ('dev', 'B', 'b2', 5);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+        assert_ne!(dev, db.fingerprint("dev").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_created_at() {
+        let db = test_db().await;
+        sqlx::query(
+            "INSERT INTO environments (env, key, value, created_at) VALUES ('dev', 'A', 'a', 1697458000);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let row = &db.list_var_in_env("dev", SortOrder::Asc).await.unwrap()[0];
+        assert_eq!("2023-10-16T12:06:40+00:00", row.created_at.to_rfc3339());
+        assert_eq!(1697458000, row.created_at_unix());
+        assert_eq!(
+            serde_json::json!({"env": "dev", "key": "A", "value": "a", "created_at": 1697458000}),
+            serde_json::to_value(row).unwrap()
+        );
+    }
+
+    /// serializes `value`, checks it against `json` and reads it back
+    fn assert_json<T>(json: serde_json::Value, value: T)
+    where
+        T: Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        assert_eq!(json, serde_json::to_value(&value).unwrap());
+        assert_eq!(value, serde_json::from_value::<T>(json).unwrap());
+    }
+
+    #[test]
+    fn test_json_shapes() {
+        assert_json(
+            serde_json::json!({"env": "dev"}),
+            Environment { env: "dev".into() },
+        );
+        assert_json(
+            serde_json::json!({"env": "dev", "key": "A", "value": "a", "created_at": 1697458000}),
+            EnvironmentRow {
+                env: "dev".into(),
+                key: "A".into(),
+                value: "a".into(),
+                created_at: DateTime::from_timestamp(1697458000, 0).unwrap(),
+            },
+        );
+        assert_json(
+            serde_json::json!({
+                "env": "dev",
+                "key": "A",
+                "value": "a",
+                "version_count": 2,
+                "last_modified": 5,
+            }),
+            DetailedRow {
+                env: "dev".into(),
+                key: "A".into(),
+                value: "a".into(),
+                version_count: 2,
+                last_modified: 5,
+            },
+        );
+        assert_json(
+            serde_json::json!({"env": "dev", "key": "A", "value": null, "created_at": 3}),
+            HistoryRow {
+                env: "dev".into(),
+                key: "A".into(),
+                value: None,
+                created_at: 3,
+            },
+        );
+        assert_json(
+            serde_json::json!({
+                "key": "A",
+                "kind": "changed",
+                "old": "a1",
+                "new": "a2",
+                "created_at": 4,
+            }),
+            ChangeEvent {
+                key: "A".into(),
+                kind: ChangeKind::Changed,
+                old: Some("a1".into()),
+                new: Some("a2".into()),
+                created_at: 4,
+            },
+        );
+        assert_json(
+            serde_json::json!({
+                "added": {"A": "1"},
+                "removed": {"B": "2"},
+                "changed": {"C": {"old": "3", "new": "4"}},
+            }),
+            EnvDiff {
+                added: BTreeMap::from([("A".into(), "1".into())]),
+                removed: BTreeMap::from([("B".into(), "2".into())]),
+                changed: BTreeMap::from([("C".into(), ("3".into(), "4".into()))]),
+            },
+        );
+        assert_json(
+            serde_json::json!({"name": "web", "keys": ["HOST", "PORT"]}),
+            Template {
+                name: "web".into(),
+                keys: vec!["HOST".into(), "PORT".into()],
+            },
+        );
+        assert_json(
+            serde_json::json!({
+                "integrity": ["ok"],
+                "pending_migrations": [],
+                "unknown_migrations": [7],
+                "indexes": ["env_key"],
+                "orphaned_tombstones": 1,
+                "latest_created_at": null,
+                "case_duplicates": [{"env": "dev", "key": "A", "variants": ["A", "a"]}],
+            }),
+            Diagnostics {
+                integrity: vec!["ok".into()],
+                unknown_migrations: vec![7],
+                indexes: vec!["env_key".into()],
+                orphaned_tombstones: 1,
+                case_duplicates: vec![CaseDuplicate {
+                    env: "dev".into(),
+                    key: "A".into(),
+                    variants: vec!["A".into(), "a".into()],
+                }],
+                ..Default::default()
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_var_in_env_order() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'B', 'b1', 1),
+            ('dev', 'B', 'b2', 2),
+            ('dev', 'C', 'c1', 1),
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'D', 'd1', 1),
+            ('dev', 'D', NULL, 2),
+            ('prod', 'E', 'e1', 1);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let vars = |rows: Vec<EnvironmentRow>| -> Vec<(String, String)> {
+            rows.into_iter().map(|r| (r.key, r.value)).collect()
+        };
+
+        let rows = db.list_var_in_env("dev", SortOrder::Asc).await.unwrap();
+        assert_eq!(
+            vec![
+                ("A".to_string(), "a1".to_string()),
+                ("B".to_string(), "b2".to_string()),
+                ("C".to_string(), "c1".to_string()),
+            ],
+            vars(rows)
+        );
+
+        let rows = db.list_var_in_env("dev", SortOrder::Desc).await.unwrap();
+        assert_eq!(
+            vec![
+                ("C".to_string(), "c1".to_string()),
+                ("B".to_string(), "b2".to_string()),
+                ("A".to_string(), "a1".to_string()),
+            ],
+            vars(rows)
+        );
+    }
+
+    /// Keeps the fields of every span and event as `name: field=value`
+    #[derive(Clone, Default)]
+    struct Captured(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    struct FieldsOf(String);
+
+    impl tracing::field::Visit for FieldsOf {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for Captured
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _: &tracing::span::Id,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = FieldsOf(format!("{}:", attrs.metadata().name()));
+            attrs.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+
+        fn on_record(
+            &self,
+            id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = FieldsOf(format!("{}:", ctx.span(id).unwrap().name()));
+            values.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = FieldsOf(format!("{}:", event.metadata().name()));
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_insert_spans() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let db = test_db().await;
+        let captured = Captured::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+
+        db.insert("dev", "api_key", "s3cr3t").await.unwrap();
+        db.purge_older_than("dev", 0).await.unwrap();
+
+        let lines = captured.0.lock().unwrap();
+        assert_eq!("insert: env=\"dev\" key=\"api_key\"", lines[0]);
+        assert!(lines.contains(&"insert: rows=1".to_string()), "{:?}", lines);
+        assert!(
+            lines.iter().all(|line| !line.contains("s3cr3t")),
+            "{:?}",
+            lines
+        );
+        assert!(
+            lines.contains(&"purge_older_than: rows=0".to_string()),
+            "{:?}",
+            lines
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_insert_counter() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+
+        use metrics::{
+            Counter, CounterFn, Gauge, Histogram, Key, KeyName, Metadata, Recorder, SharedString,
+            Unit,
+        };
+
+        #[derive(Default)]
+        struct Calls(AtomicU64);
+
+        impl CounterFn for Calls {
+            fn increment(&self, value: u64) {
+                self.0.fetch_add(value, Ordering::Relaxed);
+            }
+
+            fn absolute(&self, value: u64) {
+                self.0.store(value, Ordering::Relaxed);
+            }
+        }
+
+        /// records the calls to `insert` only
+        #[derive(Default)]
+        struct InsertRecorder(Arc<Calls>);
+
+        impl Recorder for InsertRecorder {
+            fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+            fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+            fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+                let insert = key.name() == INSERTS_COUNTER
+                    && key
+                        .labels()
+                        .any(|l| l.key() == "operation" && l.value() == "insert");
+                match insert {
+                    true => Counter::from_arc(self.0.clone()),
+                    false => Counter::noop(),
+                }
+            }
+
+            fn register_gauge(&self, _: &Key, _: &Metadata<'_>) -> Gauge {
+                Gauge::noop()
+            }
+
+            fn register_histogram(&self, _: &Key, _: &Metadata<'_>) -> Histogram {
+                Histogram::noop()
+            }
+        }
+
+        let recorder = InsertRecorder::default();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        metrics::with_local_recorder(&recorder, || {
+            rt.block_on(async {
+                let db = test_db().await;
+                db.insert("dev", "A", "1").await.unwrap();
+                db.insert("dev", "B", "2").await.unwrap();
+                db.list_var_in_env("dev", SortOrder::Asc).await.unwrap();
+                db.insert_many("dev", &[("C".into(), "3".into())])
+                    .await
+                    .unwrap();
+            })
+        });
+
+        assert_eq!(2, recorder.0 .0.load(Ordering::Relaxed));
+    }
 }
@@ -0,0 +1,182 @@
+/// A variable parsed from a dotenv file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DotenvEntry {
+    pub key: String,
+    pub value: String,
+    /// Comment found on the line immediately preceding the variable
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DotenvLine {
+    Entry(DotenvEntry),
+    Comment(String),
+    Blank,
+    Invalid(String),
+}
+
+/// Line based dotenv parser, it keeps track of the comments found right above
+/// a variable so that they can be attached to it as its description
+#[derive(Debug, Default)]
+pub struct DotenvParser {
+    comments: Vec<String>,
+}
+
+impl DotenvParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse_line(&mut self, line: &str) -> DotenvLine {
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() {
+            self.comments.clear();
+            return DotenvLine::Blank;
+        }
+
+        if let Some(comment) = trimmed.strip_prefix('#') {
+            self.comments.push(comment.trim().to_string());
+            return DotenvLine::Comment(line.to_string());
+        }
+
+        let description = match self.comments.is_empty() {
+            true => None,
+            false => Some(self.comments.join("\n")),
+        };
+        self.comments.clear();
+
+        let assignment = trimmed.strip_prefix("export ").unwrap_or(trimmed);
+        match assignment.split_once('=') {
+            Some((k, v)) if !k.trim().is_empty() => DotenvLine::Entry(DotenvEntry {
+                key: k.trim().to_string(),
+                value: unquote(v.trim()).to_string(),
+                description,
+            }),
+            _ => DotenvLine::Invalid(line.to_string()),
+        }
+    }
+}
+
+/// Removes a pair of matching single or double quotes around `value`
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+
+    value
+}
+
+/// Parses the variables found in `contents`, comments, blank and invalid
+/// lines are ignored
+///
+/// ```
+/// let entries = envelope::dotenv::from_dotenv("# local database\nexport DB='sqlite://db'\n");
+/// assert_eq!("DB", entries[0].key);
+/// assert_eq!("sqlite://db", entries[0].value);
+/// assert_eq!(Some("local database"), entries[0].description.as_deref());
+/// ```
+pub fn from_dotenv(contents: &str) -> Vec<DotenvEntry> {
+    let mut parser = DotenvParser::new();
+    contents
+        .lines()
+        .filter_map(|line| match parser.parse_line(line) {
+            DotenvLine::Entry(entry) => Some(entry),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(key: &str, value: &str, description: Option<&str>) -> DotenvEntry {
+        DotenvEntry {
+            key: key.into(),
+            value: value.into(),
+            description: description.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_commented_var() {
+        let entries = from_dotenv("# database connection\nDB_URL=postgres://localhost");
+        assert_eq!(
+            vec![entry(
+                "DB_URL",
+                "postgres://localhost",
+                Some("database connection")
+            )],
+            entries
+        );
+    }
+
+    #[test]
+    fn test_multiline_comment() {
+        let entries = from_dotenv("# first line\n# second line\nKEY=value");
+        assert_eq!(
+            vec![entry("KEY", "value", Some("first line\nsecond line"))],
+            entries
+        );
+    }
+
+    #[test]
+    fn test_uncommented_var() {
+        let entries = from_dotenv("KEY1=value1\nKEY2=value2");
+        assert_eq!(
+            vec![entry("KEY1", "value1", None), entry("KEY2", "value2", None)],
+            entries
+        );
+    }
+
+    #[test]
+    fn test_comment_not_adjacent() {
+        let entries = from_dotenv("# section header\n\nKEY1=value1\nKEY2=value2\n# trailing");
+        assert_eq!(
+            vec![entry("KEY1", "value1", None), entry("KEY2", "value2", None)],
+            entries
+        );
+    }
+
+    #[test]
+    fn test_comment_does_not_carry_over() {
+        let entries = from_dotenv("# about key1\nKEY1=value1\nKEY2=value2");
+        assert_eq!(
+            vec![
+                entry("KEY1", "value1", Some("about key1")),
+                entry("KEY2", "value2", None)
+            ],
+            entries
+        );
+    }
+
+    #[test]
+    fn test_quotes_and_export() {
+        let entries = from_dotenv("export KEY1=\"value 1\"\nKEY2='value=2'\nKEY3=\"unbalanced");
+        assert_eq!(
+            vec![
+                entry("KEY1", "value 1", None),
+                entry("KEY2", "value=2", None),
+                entry("KEY3", "\"unbalanced", None)
+            ],
+            entries
+        );
+    }
+
+    #[test]
+    fn test_invalid_lines() {
+        let mut parser = DotenvParser::new();
+        assert_eq!(
+            DotenvLine::Invalid("key value".into()),
+            parser.parse_line("key value")
+        );
+        assert_eq!(
+            DotenvLine::Invalid("=value".into()),
+            parser.parse_line("=value")
+        );
+        assert_eq!(DotenvLine::Blank, parser.parse_line("   "));
+    }
+}
@@ -1,55 +1,118 @@
 use std::{
     env,
-    fs::OpenOptions,
-    io::{Read, Result, Write},
+    fs::{self, OpenOptions},
+    io::{Result, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use crate::{std_err, subproc::ChildProcess};
+use crate::{err, std_err, subproc::ChildProcess};
+
+/// Variables naming the editor, from the most to the least specific
+const EDITOR_VARS: [&str; 4] = ["ENVELOPE_EDITOR", "VISUAL", "EDITOR", "GIT_EDITOR"];
 
 fn editor_cmd() -> String {
     let editor = "vim";
 
-    if let Some(e) = std::env::var_os("ENVELOPE_EDITOR") {
-        if let Some(e) = e.to_str() {
-            return e.to_string();
+    for var in EDITOR_VARS {
+        if let Some(e) = std::env::var_os(var) {
+            if let Some(e) = e.to_str().filter(|e| !e.trim().is_empty()) {
+                return e.to_string();
+            }
         }
     }
 
-    if let Some(e) = std::env::var_os("GIT_EDITOR") {
-        if let Some(e) = e.to_str() {
-            return e.to_string();
+    editor.to_string()
+}
+
+/// File only readable by its owner, removed when dropped so that the values
+/// it holds do not outlive the edit, even on panic
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn create(data: &[u8]) -> Result<Self> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+        let name = format!("envelope-{}-{}.env", std::process::id(), nanos);
+        let path = env::temp_dir().join(name);
+
+        let mut opts = OpenOptions::new();
+        opts.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            opts.mode(0o600);
         }
+
+        let mut file = opts.open(&path)?;
+        let temp = TempFile { path };
+        file.write_all(data)?;
+
+        Ok(temp)
     }
+}
 
-    editor.to_string()
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
 }
 
+/// Opens `data` in the editor of the user and returns the saved contents.
+///
+/// The editor is read from `ENVELOPE_EDITOR`, `VISUAL`, `EDITOR` then
+/// `GIT_EDITOR` and defaults to vim. The file is created in the temporary
+/// directory, readable by the user only, and removed once the editor exits.
 pub fn spawn_with(data: &[u8]) -> Result<Vec<u8>> {
     let editor = editor_cmd();
-    let pb = env::current_dir()?.join(".ENVELOPE_EDITMSG");
+    let file = TempFile::create(data)?;
 
-    {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .read(true)
-            .create(true)
-            .truncate(true)
-            .open(&pb)?;
+    // editors such as `code --wait` come with their arguments
+    let mut words = editor.split_whitespace();
+    let cmd = words.next().unwrap_or_default();
+    let mut args: Vec<&str> = words.collect();
+    let path = file
+        .path
+        .to_str()
+        .ok_or(std_err!("invalid temporary path"))?;
+    args.push(path);
 
-        file.write_all(data)?;
-        file.write(b"\n\n# Comment variables to remove them")?;
+    let status = ChildProcess::new(cmd, &args, &[])
+        .run_shell_command()
+        .map_err(|e| std_err!("error running {}: {}", editor, e))?;
+    if !status.success() {
+        return err!(
+            "{} exited with {}, nothing has been changed",
+            editor,
+            status
+        );
     }
 
-    let args = &[pb.to_str().unwrap()];
-    let cmd = ChildProcess::new(&editor, args, &[]);
-    cmd.run_shell_command()
-        .map_err(|e| std_err!("error running child process: {}", e))?;
+    fs::read(&file.path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    let mut file = OpenOptions::new().read(true).open(&pb)?;
+    #[test]
+    fn test_temp_file() {
+        let file = TempFile::create(b"A=1").unwrap();
+        let path = file.path.clone();
+        assert_eq!(b"A=1".to_vec(), fs::read(&path).unwrap());
 
-    let mut buf = Vec::new();
-    file.read_to_end(&mut buf).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
 
-    std::fs::remove_file(pb)?;
-    Ok(buf)
+        drop(file);
+        assert!(!path.exists());
+    }
 }
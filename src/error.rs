@@ -1,9 +1,229 @@
-#[macro_export]
+use std::{io, path::PathBuf, time::Duration};
+
+use thiserror::Error;
+
+/// `io::Error` with a formatted message, internal to the crate
 macro_rules! std_err {
     ($($tt:tt)*) => { std::io::Error::new(std::io::ErrorKind::Other, format!($($tt)*)) }
 }
 
-#[macro_export]
+/// `Err` holding a [`std_err!`], for the commands
+#[cfg(feature = "cli")]
 macro_rules! err {
     ($($tt:tt)*) => { Err(std::io::Error::new(std::io::ErrorKind::Other, format!($($tt)*))) }
 }
+
+pub(crate) use std_err;
+#[cfg(feature = "cli")]
+pub(crate) use err;
+
+/// Errors of the database, and of the config files. The commands carry them
+/// inside an `io::Error`, they can be recovered with [`EnvelopeError::from_io`]
+/// and [`EnvelopeError::exit_code`] is the code the CLI exits with.
+#[derive(Debug, Error)]
+pub enum EnvelopeError {
+    #[error("envelope is not initialized in current directory")]
+    NotInitialized,
+    #[error("env {0} does not exist")]
+    EnvNotFound(String),
+    /// `env` is None when the key is looked up in every environment
+    #[error("key {key} is not set in {}", env.as_deref().unwrap_or("any environment"))]
+    KeyNotFound { env: Option<String>, key: String },
+    #[error("template {0} does not exist")]
+    TemplateNotFound(String),
+    /// a [`crate::reference::SecretResolver`] does not have the secret a
+    /// value references
+    #[error("secret {0} does not exist")]
+    SecretNotFound(String),
+    /// a value starts like a secret reference but is not a valid one
+    #[error("invalid secret reference {reference}: {reason}")]
+    InvalidReference {
+        reference: String,
+        reason: &'static str,
+    },
+    /// a write would replace something that exists, such as renaming a key
+    /// to one that is already set
+    #[error("{0}")]
+    Conflict(String),
+    /// a write was rejected by a constraint of the database, such as an empty
+    /// env or key
+    #[error("{0}")]
+    Constraint(String),
+    /// the environments are locked and writes are not forced
+    #[error("env {} is locked, use --force to modify it", .0.join(", "))]
+    Locked(Vec<String>),
+    /// another writer held the database for longer than the write timeout
+    #[error(
+        "database is busy: another write did not complete within {}ms",
+        .0.as_millis()
+    )]
+    Busy(Duration),
+    /// sqlite kept reporting the database busy or locked by another process
+    /// until the retries of a write gave up
+    #[error(
+        "database is locked: {operation} was blocked by another process for {}ms",
+        .waited.as_millis()
+    )]
+    Blocked {
+        operation: &'static str,
+        waited: Duration,
+    },
+    /// the database is in audit mode, where history cannot be removed
+    #[error("{0} is not allowed in audit mode, history can only be appended to")]
+    AuditModeEnabled(&'static str),
+    /// a method of [`crate::blocking::EnvelopeDb`] was called from within an
+    /// async runtime, where blocking on it would stall or panic
+    #[error(
+        "the blocking API cannot be used from an async runtime, use envelope::EnvelopeDb instead"
+    )]
+    InsideRuntime,
+    /// the database was opened with [`crate::db::EnvelopeDb::open_read_only`]
+    #[error("database is opened read-only")]
+    ReadOnly,
+    #[error("{source}\nfile: {}", path.display())]
+    Open {
+        path: PathBuf,
+        #[source]
+        source: sqlx::Error,
+    },
+    #[error("db error: {0}")]
+    Sqlx(#[from] sqlx::Error),
+    #[error("migration failed: {0}")]
+    Migration(#[from] sqlx::migrate::MigrateError),
+    /// the database was migrated by a newer envelope, whose schema this one
+    /// does not know
+    #[error(
+        "database schema version {database} is newer than version {known} of this envelope, upgrade envelope to use it"
+    )]
+    MigrationMismatch { database: i64, known: i64 },
+    #[error("{file}:{line}: {message}")]
+    Parse {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    #[error("cannot read {}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<EnvelopeError> for io::Error {
+    fn from(err: EnvelopeError) -> Self {
+        let kind = match &err {
+            EnvelopeError::Io(err) | EnvelopeError::File { source: err, .. } => err.kind(),
+            EnvelopeError::EnvNotFound(_)
+            | EnvelopeError::KeyNotFound { .. }
+            | EnvelopeError::TemplateNotFound(_)
+            | EnvelopeError::SecretNotFound(_) => io::ErrorKind::NotFound,
+            EnvelopeError::Conflict(_) => io::ErrorKind::AlreadyExists,
+            EnvelopeError::Busy(_) | EnvelopeError::Blocked { .. } => io::ErrorKind::WouldBlock,
+            EnvelopeError::Constraint(_)
+            | EnvelopeError::InvalidReference { .. }
+            | EnvelopeError::Parse { .. } => io::ErrorKind::InvalidInput,
+            EnvelopeError::Locked(_)
+            | EnvelopeError::ReadOnly
+            | EnvelopeError::AuditModeEnabled(_) => io::ErrorKind::PermissionDenied,
+            _ => io::ErrorKind::Other,
+        };
+        match err {
+            EnvelopeError::Io(err) => err,
+            err => io::Error::new(kind, err),
+        }
+    }
+}
+
+impl EnvelopeError {
+    /// returns the `EnvelopeError` wrapped in `err`, if any
+    pub fn from_io(err: &io::Error) -> Option<&EnvelopeError> {
+        err.get_ref()?.downcast_ref()
+    }
+
+    /// whether sqlite reported the database busy or locked by another
+    /// connection, an error which retrying the operation can resolve
+    pub fn is_transient(&self) -> bool {
+        const SQLITE_BUSY: i32 = 5;
+        const SQLITE_LOCKED: i32 = 6;
+
+        let Self::Sqlx(sqlx::Error::Database(err)) = self else {
+            return false;
+        };
+        // the extended result codes keep the primary one in their low byte
+        err.code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED))
+    }
+
+    /// Code the CLI exits with, 1 is left to the errors that are not an
+    /// `EnvelopeError` and 2 to the usage errors
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Io(_) | Self::File { .. } | Self::InsideRuntime => 1,
+            Self::NotInitialized => 3,
+            Self::EnvNotFound(_)
+            | Self::KeyNotFound { .. }
+            | Self::TemplateNotFound(_)
+            | Self::SecretNotFound(_) => 4,
+            Self::Conflict(_) | Self::Constraint(_) | Self::InvalidReference { .. } => 5,
+            Self::Locked(_)
+            | Self::Busy(_)
+            | Self::Blocked { .. }
+            | Self::ReadOnly
+            | Self::AuditModeEnabled(_) => 6,
+            Self::Open { .. }
+            | Self::Sqlx(_)
+            | Self::Migration(_)
+            | Self::MigrationMismatch { .. } => 7,
+            Self::Parse { .. } => 8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_io_round_trip() {
+        let err: io::Error = EnvelopeError::EnvNotFound("dev".into()).into();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        assert_eq!("env dev does not exist", err.to_string());
+        assert_eq!(4, EnvelopeError::from_io(&err).unwrap().exit_code());
+
+        // an io error is not wrapped twice
+        let err: io::Error = EnvelopeError::Io(io::ErrorKind::BrokenPipe.into()).into();
+        assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+        assert!(EnvelopeError::from_io(&err).is_none());
+    }
+
+    #[test]
+    fn test_source_chain() {
+        use std::error::Error;
+
+        let err: io::Error = EnvelopeError::Sqlx(sqlx::Error::RowNotFound).into();
+        assert_eq!(7, EnvelopeError::from_io(&err).unwrap().exit_code());
+        let source = err.source().unwrap();
+        assert!(matches!(
+            source.downcast_ref::<sqlx::Error>(),
+            Some(sqlx::Error::RowNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_messages() {
+        let err = EnvelopeError::KeyNotFound {
+            env: None,
+            key: "HOST".into(),
+        };
+        assert_eq!("key HOST is not set in any environment", err.to_string());
+        let err = EnvelopeError::Locked(vec!["dev".into(), "prod".into()]);
+        assert_eq!(
+            "env dev, prod is locked, use --force to modify it",
+            err.to_string()
+        );
+    }
+}
@@ -0,0 +1,555 @@
+//! Formats variables are imported from and exported to, apart from dotenv
+//! which lives in [`crate::dotenv`]
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Serialize;
+
+use crate::db::EnvelopeResult;
+use crate::error::EnvelopeError;
+use crate::std_err;
+
+/// Suffix of the keys imported with [`ImportMode::SuffixOnConflict`] unless
+/// another one is given
+pub const DEFAULT_IMPORT_SUFFIX: &str = "_IMPORTED";
+
+/// How an import treats the keys already set in the environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportMode {
+    /// the imported value replaces the current one
+    Overwrite,
+    /// a key already set to another value is imported as the key followed by
+    /// the suffix, leaving both values side by side for a manual reconciliation
+    SuffixOnConflict(String),
+}
+
+impl ImportMode {
+    /// Key under which `key` set to `value` is imported, given the `current`
+    /// variables of the environment. When the suffixed key is set to another
+    /// value as well, a number is added: `_OLD_2`, `_OLD_3` and so on.
+    ///
+    /// ```
+    /// use std::collections::BTreeMap;
+    /// use envelope::format::ImportMode;
+    ///
+    /// let current = BTreeMap::from([
+    ///     ("HOST".to_string(), "localhost".to_string()),
+    ///     ("HOST_OLD".to_string(), "remote".to_string()),
+    /// ]);
+    /// let mode = ImportMode::SuffixOnConflict("_OLD".into());
+    /// assert_eq!("host_OLD", mode.key_for(&current, "host", "remote"));
+    /// assert_eq!("host_OLD_2", mode.key_for(&current, "host", "other"));
+    /// assert_eq!("host", mode.key_for(&current, "host", "localhost"));
+    /// ```
+    pub fn key_for(&self, current: &BTreeMap<String, String>, key: &str, value: &str) -> String {
+        let ImportMode::SuffixOnConflict(suffix) = self else {
+            return key.to_string();
+        };
+
+        let free = |key: &str| {
+            current
+                .get(&key.to_ascii_uppercase())
+                .is_none_or(|existing| existing == value)
+        };
+        if free(key) {
+            return key.to_string();
+        }
+        std::iter::once(format!("{}{}", key, suffix))
+            .chain((2..).map(|n| format!("{}{}_{}", key, suffix, n)))
+            .find(|key| free(key))
+            .unwrap()
+    }
+}
+
+/// Sorts `vars` in the order of the keys of `reference`, the keys it does not
+/// list come last in alphabetical order
+///
+/// ```
+/// let mut vars = vec![("A", 1), ("PORT", 2), ("HOST", 3), ("B", 4)];
+/// envelope::format::order_like(&mut vars, &["HOST".into(), "PORT".into()]);
+/// assert_eq!(vec![("HOST", 3), ("PORT", 2), ("A", 1), ("B", 4)], vars);
+/// ```
+pub fn order_like<K: AsRef<str>, V>(vars: &mut [(K, V)], reference: &[String]) {
+    let positions: HashMap<&str, usize> = reference
+        .iter()
+        .enumerate()
+        .map(|(position, key)| (key.as_str(), position))
+        .collect();
+
+    vars.sort_by(|(a, _), (b, _)| {
+        let (a, b) = (a.as_ref(), b.as_ref());
+        let position = |key| positions.get(key).copied().unwrap_or(usize::MAX);
+        position(a).cmp(&position(b)).then_with(|| a.cmp(b))
+    });
+}
+
+/// Builds a CSV document with an `env,key,value` header followed by one
+/// record per row, fields are quoted as described by RFC 4180
+///
+/// ```
+/// let csv = envelope::format::to_csv([("dev", "LIST", "a,b")]);
+/// assert_eq!("env,key,value\r\ndev,LIST,\"a,b\"\r\n", csv);
+/// ```
+pub fn to_csv<'a, I>(rows: I) -> String
+where
+    I: IntoIterator<Item = (&'a str, &'a str, &'a str)>,
+{
+    let mut csv = CSV_HEADER.as_bytes().to_vec();
+    for (env, key, value) in rows {
+        // writing to a Vec cannot fail
+        write_csv_record(&mut csv, env, key, value).unwrap();
+    }
+
+    // the fields are valid UTF-8, and so are the separators
+    String::from_utf8(csv).unwrap()
+}
+
+/// First line of the CSV documents of [`to_csv`]
+pub const CSV_HEADER: &str = "env,key,value\r\n";
+
+/// Writes the record of a single row of [`to_csv`] to `w`, which lets large
+/// documents be written as their rows are read
+pub fn write_csv_record<W: Write>(w: &mut W, env: &str, key: &str, value: &str) -> io::Result<()> {
+    let record = [env, key, value].map(csv_field).join(",");
+    write!(w, "{}\r\n", record)
+}
+
+/// Quotes `value` if it contains a separator, a quote or a line break
+fn csv_field(value: &str) -> Cow<'_, str> {
+    match value.contains([',', '"', '\r', '\n']) {
+        true => Cow::Owned(format!("\"{}\"", value.replace('"', "\"\""))),
+        false => Cow::Borrowed(value),
+    }
+}
+
+/// Parses a CSV document made of `env,key,value` records as written by
+/// [`to_csv`]. The header is optional, quoted fields may contain separators,
+/// escaped quotes and line breaks. Fails with [`EnvelopeError::Parse`]
+/// pointing at the line the faulty record starts on.
+///
+/// ```
+/// let rows = envelope::format::from_csv("env,key,value\ndev,HOST,localhost\n")?;
+/// assert_eq!(vec![("dev".into(), "HOST".into(), "localhost".into())], rows);
+/// # Ok::<(), envelope::EnvelopeError>(())
+/// ```
+pub fn from_csv(contents: &str) -> EnvelopeResult<Vec<(String, String, String)>> {
+    let invalid = |line: usize, message: &str| EnvelopeError::Parse {
+        file: "csv".to_string(),
+        line,
+        message: message.to_string(),
+    };
+
+    // records along with the line they start on
+    let mut records: Vec<(usize, Vec<String>)> = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push((start, std::mem::take(&mut record)));
+            }
+            (false, c) => field.push(c),
+        }
+        if c == '\n' {
+            line += 1;
+            if !quoted {
+                start = line;
+            }
+        }
+    }
+
+    if quoted {
+        return Err(invalid(start, "unterminated quoted field"));
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push((start, record));
+    }
+
+    let mut rows = Vec::new();
+    for (n, (line, record)) in records.into_iter().enumerate() {
+        match <[String; 3]>::try_from(record) {
+            Ok([env, key, value]) if n == 0 && env == "env" && key == "key" && value == "value" => {
+            }
+            Ok([env, key, value]) => rows.push((env, key, value)),
+            Err(record) if record == [""] => {}
+            Err(_) => return Err(invalid(line, "record is not made of env,key,value")),
+        }
+    }
+
+    Ok(rows)
+}
+
+/// Builds the canonical text of the variables of each environment: an
+/// `[env]` line per environment followed by a `KEY="value"` line per
+/// variable, both sorted, the values quoted and escaped as JSON strings. The
+/// same variables always give the same text with `\n` line endings, which
+/// suits a file committed to git.
+///
+/// ```
+/// use std::collections::BTreeMap;
+///
+/// let vars = BTreeMap::from([(
+///     "dev".to_string(),
+///     BTreeMap::from([("MOTD".to_string(), "hello\r\nworld".to_string())]),
+/// )]);
+/// assert_eq!(
+///     "[dev]\nMOTD=\"hello\\r\\nworld\"\n",
+///     envelope::format::to_canonical(&vars)
+/// );
+/// ```
+pub fn to_canonical(vars: &BTreeMap<String, BTreeMap<String, String>>) -> String {
+    let mut text = String::new();
+    for (env, vars) in vars.iter().filter(|(_, vars)| !vars.is_empty()) {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&format!("[{}]\n", env));
+        for (key, value) in vars {
+            // serializing a string cannot fail
+            let value = serde_json::to_string(value).unwrap();
+            text.push_str(&format!("{}={}\n", key, value));
+        }
+    }
+
+    text
+}
+
+/// Parses the text written by [`to_canonical`]. Blank lines and `#`
+/// comments are skipped and `\r\n` line endings are accepted. Fails with
+/// [`EnvelopeError::Parse`] on a variable outside of an environment, a value
+/// that is not a JSON string or a key set twice in an environment.
+pub fn from_canonical(
+    contents: &str,
+) -> EnvelopeResult<BTreeMap<String, BTreeMap<String, String>>> {
+    let invalid = |line: usize, message: String| EnvelopeError::Parse {
+        file: "canonical".to_string(),
+        line: line + 1,
+        message,
+    };
+
+    let mut envs: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    let mut current: Option<&str> = None;
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(env) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            envs.entry(env.to_string()).or_default();
+            current = Some(env);
+            continue;
+        }
+
+        let vars = current
+            .and_then(|env| envs.get_mut(env))
+            .ok_or_else(|| invalid(n, "variable outside of an [env] section".to_string()))?;
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid(n, "expected KEY=\"value\"".to_string()))?;
+        let value: String = serde_json::from_str(value)
+            .map_err(|e| invalid(n, format!("invalid value of {}: {}", key, e)))?;
+        if vars.insert(key.to_string(), value).is_some() {
+            return Err(invalid(n, format!("{} is set twice", key)));
+        }
+    }
+
+    Ok(envs)
+}
+
+/// Kind of the Kubernetes manifest generated by [`to_k8s`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum K8sKind {
+    /// values are base64 encoded under `data`
+    Secret,
+    /// values are stored as is under `data`
+    ConfigMap,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct K8sManifest<'a> {
+    api_version: &'static str,
+    kind: &'static str,
+    metadata: K8sMetadata<'a>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    secret_type: Option<&'static str>,
+    data: BTreeMap<&'a str, String>,
+}
+
+#[derive(Serialize)]
+struct K8sMetadata<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<&'a str>,
+}
+
+/// Builds a Kubernetes `Secret` or `ConfigMap` manifest named `name` holding
+/// `vars`
+pub fn to_k8s<'a, I>(
+    vars: I,
+    kind: K8sKind,
+    name: &str,
+    namespace: Option<&str>,
+) -> EnvelopeResult<String>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut manifest = Vec::new();
+    write_k8s(&mut manifest, vars, kind, name, namespace)?;
+
+    // serde_yaml only writes UTF-8
+    Ok(String::from_utf8(manifest).unwrap())
+}
+
+/// Writes the manifest of [`to_k8s`] to `w` without building it in memory
+/// first
+pub fn write_k8s<'a, W, I>(
+    w: &mut W,
+    vars: I,
+    kind: K8sKind,
+    name: &str,
+    namespace: Option<&str>,
+) -> EnvelopeResult<()>
+where
+    W: Write,
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let data = vars
+        .into_iter()
+        .map(|(key, value)| match kind {
+            K8sKind::Secret => (key, STANDARD.encode(value)),
+            K8sKind::ConfigMap => (key, value.to_string()),
+        })
+        .collect();
+
+    let manifest = K8sManifest {
+        api_version: "v1",
+        kind: match kind {
+            K8sKind::Secret => "Secret",
+            K8sKind::ConfigMap => "ConfigMap",
+        },
+        metadata: K8sMetadata { name, namespace },
+        secret_type: match kind {
+            K8sKind::Secret => Some("Opaque"),
+            K8sKind::ConfigMap => None,
+        },
+        data,
+    };
+
+    serde_yaml::to_writer(w, &manifest).map_err(|e| std_err!("cannot build manifest: {}", e).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_for() {
+        let mode = ImportMode::SuffixOnConflict("_OLD".into());
+        let current = BTreeMap::from([("HOST".to_string(), "localhost".to_string())]);
+        assert_eq!("host_OLD", mode.key_for(&current, "host", "remote"));
+        assert_eq!("host", mode.key_for(&current, "host", "localhost"));
+        let current = BTreeMap::from([
+            ("HOST".to_string(), "localhost".to_string()),
+            ("HOST_OLD".to_string(), "remote".to_string()),
+            ("HOST_OLD_2".to_string(), "other".to_string()),
+        ]);
+        assert_eq!("host_OLD_2", mode.key_for(&current, "host", "other"));
+        assert_eq!("host_OLD_3", mode.key_for(&current, "host", "new"));
+        assert_eq!(
+            "host",
+            ImportMode::Overwrite.key_for(&current, "host", "remote")
+        );
+    }
+
+    #[test]
+    fn test_order_like() {
+        let mut vars = vec![("D", ()), ("C", ()), ("B", ()), ("A", ())];
+        order_like(&mut vars, &["C".into(), "X".into(), "A".into()]);
+        assert_eq!(
+            vec!["C", "A", "B", "D"],
+            vars.iter().map(|(key, _)| *key).collect::<Vec<_>>()
+        );
+
+        let mut vars = vec![("B", ()), ("A", ())];
+        order_like(&mut vars, &[]);
+        assert_eq!(vec![("A", ()), ("B", ())], vars);
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let rows = [
+            ("dev", "URL", "postgres://db:5432"),
+            ("dev", "LIST", "a,b"),
+            ("prod", "QUOTE", "say \"hi\""),
+            ("prod", "MULTI", "one\ntwo"),
+        ];
+
+        assert_eq!(
+            "env,key,value\r\n\
+             dev,URL,postgres://db:5432\r\n\
+             dev,LIST,\"a,b\"\r\n\
+             prod,QUOTE,\"say \"\"hi\"\"\"\r\n\
+             prod,MULTI,\"one\ntwo\"\r\n",
+            to_csv(rows)
+        );
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let rows = [
+            ("dev", "PLAIN", "value"),
+            ("dev", "COMMA", "a,b,c"),
+            ("dev", "QUOTES", "\"quoted\" and \"\""),
+            ("prod", "NEWLINES", "first\nsecond\r\nthird\n"),
+            ("prod", "EMPTY", ""),
+            ("prod", "ALL", "\",\n\""),
+        ];
+
+        let expected: Vec<(String, String, String)> = rows
+            .iter()
+            .map(|(e, k, v)| (e.to_string(), k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(expected, from_csv(&to_csv(rows)).unwrap());
+    }
+
+    #[test]
+    fn test_canonical_round_trip() {
+        let vars = BTreeMap::from([
+            (
+                "prod".to_string(),
+                BTreeMap::from([
+                    ("URL".to_string(), "https://example.com/?a=b".to_string()),
+                    ("EMPTY".to_string(), String::new()),
+                    ("QUOTES".to_string(), "\"x\" # not a comment".to_string()),
+                ]),
+            ),
+            (
+                "dev".to_string(),
+                BTreeMap::from([("MULTI".to_string(), "a\r\nb\tc é".to_string())]),
+            ),
+            ("empty".to_string(), BTreeMap::new()),
+        ]);
+
+        let text = to_canonical(&vars);
+        assert_eq!(
+            "[dev]\nMULTI=\"a\\r\\nb\\tc é\"\n\n[prod]\nEMPTY=\"\"\nQUOTES=\"\\\"x\\\" # not a comment\"\nURL=\"https://example.com/?a=b\"\n",
+            text
+        );
+
+        let mut parsed = from_canonical(&text).unwrap();
+        parsed.insert("empty".to_string(), BTreeMap::new());
+        assert_eq!(vars, parsed);
+        // checked out with CRLF line endings
+        assert_eq!(
+            from_canonical(&text).unwrap(),
+            from_canonical(&text.replace('\n', "\r\n")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_from_canonical_errors() {
+        for (text, message) in [
+            (
+                "A=\"1\"",
+                "canonical:1: variable outside of an [env] section",
+            ),
+            ("[dev]\n\nA", "canonical:3: expected KEY=\"value\""),
+            (
+                "# vars\n[dev]\nA=\"1\"\nA=\"2\"",
+                "canonical:4: A is set twice",
+            ),
+        ] {
+            assert_eq!(message, from_canonical(text).unwrap_err().to_string());
+        }
+        let err = from_canonical("[dev]\nA=1").unwrap_err().to_string();
+        assert!(
+            err.starts_with("canonical:2: invalid value of A: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_from_csv() {
+        let rows = from_csv("dev,A,1\n\n\"prod\",\"B\",\"x\"\"y\"").unwrap();
+        assert_eq!(
+            vec![
+                ("dev".to_string(), "A".to_string(), "1".to_string()),
+                ("prod".to_string(), "B".to_string(), "x\"y".to_string()),
+            ],
+            rows
+        );
+
+        let err = from_csv("env,key,value\ndev,A").unwrap_err();
+        assert_eq!(
+            "csv:2: record is not made of env,key,value",
+            err.to_string()
+        );
+        let err = from_csv("dev,A,\"multi\nline\"\ndev,A,1,2").unwrap_err();
+        assert_eq!(
+            "csv:3: record is not made of env,key,value",
+            err.to_string()
+        );
+        let err = from_csv("dev,A,1\ndev,A,\"unterminated").unwrap_err();
+        assert_eq!("csv:2: unterminated quoted field", err.to_string());
+    }
+
+    #[test]
+    fn test_to_k8s_secret() {
+        let vars = [("API_KEY", "s3cr3t"), ("URL", "postgres://db:5432")];
+        let manifest = to_k8s(vars, K8sKind::Secret, "app", Some("prod")).unwrap();
+
+        let yaml: serde_yaml::Value = serde_yaml::from_str(&manifest).unwrap();
+        assert_eq!(yaml["apiVersion"], "v1");
+        assert_eq!(yaml["kind"], "Secret");
+        assert_eq!(yaml["type"], "Opaque");
+        assert_eq!(yaml["metadata"]["name"], "app");
+        assert_eq!(yaml["metadata"]["namespace"], "prod");
+        assert_eq!(yaml["data"]["API_KEY"], "czNjcjN0");
+
+        let url = yaml["data"]["URL"].as_str().unwrap();
+        assert_eq!(
+            b"postgres://db:5432".to_vec(),
+            STANDARD.decode(url).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_to_k8s_config_map() {
+        let vars = [("PORT", "8080"), ("QUOTED", "a: 'b'")];
+        let manifest = to_k8s(vars, K8sKind::ConfigMap, "app", None).unwrap();
+
+        let yaml: serde_yaml::Value = serde_yaml::from_str(&manifest).unwrap();
+        assert_eq!(yaml["kind"], "ConfigMap");
+        assert!(yaml.get("type").is_none());
+        assert!(yaml["metadata"].get("namespace").is_none());
+        // numbers must stay strings, kubernetes rejects other types
+        assert_eq!(
+            &serde_yaml::Value::String("8080".into()),
+            &yaml["data"]["PORT"]
+        );
+        assert_eq!(yaml["data"]["QUOTED"], "a: 'b'");
+    }
+}
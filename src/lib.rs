@@ -0,0 +1,69 @@
+//! envelope stores the environment variables of a project, along with their
+//! history, in an sqlite database kept next to it in `.envelope`.
+//!
+//! The [`EnvelopeDb`] is the entry point of the library. It is async and
+//! expects a tokio runtime, every method fails with an [`EnvelopeError`].
+//!
+//! ```
+//! use envelope::{EnvelopeDb, SortOrder};
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> envelope::EnvelopeResult<()> {
+//! # let dir = std::env::temp_dir().join(format!("envelope-doc-{}", std::process::id()));
+//! # std::fs::create_dir_all(&dir)?;
+//! let db = EnvelopeDb::open(&dir.join(".envelope")).await?;
+//! db.insert("dev", "database_url", "postgres://localhost/dev").await?;
+//!
+//! let vars = db.list_var_in_env("dev", SortOrder::Asc).await?;
+//! assert_eq!("DATABASE_URL", vars[0].key);
+//! # std::fs::remove_dir_all(&dir)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Programs without an async runtime can use [`blocking::EnvelopeDb`]
+//! instead.
+//!
+//! The command line interface is built with the `cli` feature, enabled by
+//! default. Disable the default features to only depend on the library.
+
+pub mod blocking;
+pub mod config;
+pub mod db;
+pub mod dotenv;
+pub mod error;
+pub mod format;
+pub mod reference;
+pub mod secret;
+pub mod store;
+pub mod validate;
+
+#[cfg(feature = "cli")]
+mod command;
+#[cfg(feature = "cli")]
+mod editor;
+#[cfg(feature = "cli")]
+mod logging;
+#[cfg(feature = "cli")]
+mod ops;
+#[cfg(feature = "cli")]
+mod style;
+#[cfg(feature = "cli")]
+mod subproc;
+#[cfg(feature = "cli")]
+mod table;
+#[cfg(feature = "cli")]
+mod tui;
+
+/// The `envelope` binary, not part of the library API
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub mod cli;
+
+pub use db::{
+    ConnectOptions, EnvDiff, EnvelopeDb, EnvelopeResult, EnvironmentRow, RetryPolicy, SortOrder,
+};
+pub use error::EnvelopeError;
+pub(crate) use error::std_err;
+#[cfg(feature = "cli")]
+pub(crate) use error::err;
@@ -0,0 +1,35 @@
+use tracing_subscriber::EnvFilter;
+
+/// Filter for each number of `-v`, only the logs of envelope go past warnings
+const LEVELS: [&str; 3] = ["warn", "warn,envelope=info", "warn,envelope=debug"];
+
+/// Sends the logs to stderr so that they never mix with exports or JSON on
+/// stdout. `verbose` is the number of `-v` given, `RUST_LOG` overrides it.
+pub fn init(verbose: u8) {
+    let rust_log = std::env::var(EnvFilter::DEFAULT_ENV).ok();
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(directives(verbose, rust_log)))
+        .with_writer(anstream::stderr)
+        .init();
+}
+
+fn directives(verbose: u8, rust_log: Option<String>) -> String {
+    match rust_log {
+        Some(directives) if !directives.is_empty() => directives,
+        _ => LEVELS[usize::from(verbose).min(LEVELS.len() - 1)].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_directives() {
+        assert_eq!("warn", directives(0, None));
+        assert_eq!("warn,envelope=info", directives(1, None));
+        assert_eq!("warn,envelope=debug", directives(2, None));
+        assert_eq!("warn,envelope=debug", directives(5, Some(String::new())));
+        assert_eq!("sqlx=debug", directives(1, Some("sqlx=debug".into())));
+    }
+}
@@ -1,53 +1,208 @@
+use std::collections::BTreeMap;
 use std::io::Result;
 use std::io::{BufRead, Write};
 
-use crate::db::EnvelopeDb;
+use crate::db::{EnvDiff, EnvelopeDb, InsertOutcome, SetOutcome};
+use crate::dotenv::{DotenvLine, DotenvParser};
 use crate::err;
+use crate::format::{from_csv, ImportMode};
+use crate::secret::MaskSecrets;
+use crate::validate::ValueType;
 
-/// Adds a single key-value element to the database
-///
-/// If the value of v is None, an empty string is inserted
-pub async fn add_var(db: &EnvelopeDb, env: &str, k: &str, v: &str) -> Result<()> {
+use super::{current_vars, print_changes, Changes};
+
+/// Fails if `k` cannot be used as a key
+pub fn check_key(k: &str) -> Result<()> {
     if k.starts_with('#') {
         return err!("key name cannot start with #");
     }
 
-    db.insert(env, k, v).await?;
+    Ok(())
+}
+
+/// Fails if `value` is not of `value_type`, the error quotes the value
+pub fn check_type(key: &str, value: &str, value_type: Option<ValueType>) -> Result<()> {
+    match value_type {
+        Some(value_type) if !value_type.accepts(value) => err!(
+            "{} must be of type {}, got {:?}",
+            key.to_ascii_uppercase(),
+            value_type,
+            value
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Annotates `key` with `value_type` in every env of `envs`, or removes its
+/// annotation if None
+pub async fn set_var_type(
+    db: &EnvelopeDb,
+    envs: &[String],
+    key: &str,
+    value_type: Option<ValueType>,
+) -> Result<()> {
+    for env in envs {
+        db.set_var_type(env, key, value_type).await?;
+    }
 
     Ok(())
 }
 
+/// Adds a single key-value element to the database, returns the value it
+/// replaced
+///
+/// If the value of v is None, an empty string is inserted
+pub async fn add_var(db: &EnvelopeDb, env: &str, k: &str, v: &str) -> Result<InsertOutcome> {
+    check_key(k)?;
+
+    Ok(db.insert(env, k, v).await?)
+}
+
+/// Adds a single key-value element to the database without uppercasing the
+/// key, see [`EnvelopeDb::insert_exact`]
+pub async fn add_var_exact(db: &EnvelopeDb, env: &str, k: &str, v: &str) -> Result<InsertOutcome> {
+    check_key(k)?;
+
+    Ok(db.insert_exact(env, k, v).await?)
+}
+
+/// Prints what setting `key` to `value` in `env` changed according to its
+/// `outcome` in the format of [`print_changes`], the values of secrets are
+/// masked
+pub fn print_outcome<W: Write>(
+    w: &mut W,
+    env: &str,
+    key: &str,
+    value: &str,
+    outcome: &InsertOutcome,
+) -> Result<()> {
+    let mut diff = EnvDiff::default();
+    match &outcome.previous {
+        _ if !outcome.changed => {}
+        Some(previous) => {
+            diff.changed
+                .insert(key.to_string(), (previous.clone(), value.to_string()));
+        }
+        None => {
+            diff.added.insert(key.to_string(), value.to_string());
+        }
+    }
+    diff.mask_secrets();
+
+    print_changes(w, &Changes::from([(env.to_string(), diff)]))
+}
+
+/// Adds the same key-value element to every environment in `envs` at once
+/// and prints the changes made to each one, see [`print_changes`]
+pub async fn add_var_in_envs<W: Write>(
+    writer: &mut W,
+    db: &EnvelopeDb,
+    envs: &[String],
+    k: &str,
+    v: &str,
+) -> Result<()> {
+    check_key(k)?;
+
+    let mut changes = Changes::new();
+    for (env, outcome) in db.set_in_envs(envs, k, v).await? {
+        let diff = changes.entry(env).or_default();
+        match outcome {
+            SetOutcome::Created => {
+                diff.added.insert(k.to_ascii_uppercase(), v.to_string());
+            }
+            SetOutcome::Unchanged => {}
+            SetOutcome::Updated { previous } if previous == v => {}
+            SetOutcome::Updated { previous } => {
+                diff.changed
+                    .insert(k.to_ascii_uppercase(), (previous, v.to_string()));
+            }
+        }
+    }
+
+    print_changes(writer, &changes)
+}
+
+/// Imports variables in dotenv format from `reader` into `env`, the keys
+/// already set in `env` before the import are handled according to `mode`
+///
+/// A comment placed on the line right above a variable is stored as the
+/// description of that variable. The variables already set to their value
+/// are not written again, only counted.
 pub async fn import<W: Write, R: BufRead>(
     reader: R,
     writer: &mut W,
     db: &EnvelopeDb,
     env: &str,
+    mode: &ImportMode,
 ) -> Result<()> {
+    let current = match mode {
+        ImportMode::Overwrite => BTreeMap::new(),
+        ImportMode::SuffixOnConflict(_) => current_vars(db, env).await?,
+    };
+
+    let mut parser = DotenvParser::new();
+    let mut vars = Vec::new();
+    let mut descriptions = Vec::new();
     for line in reader.lines() {
         if line.is_err() {
             continue;
         }
 
-        let line = line.unwrap();
-        if line.starts_with('#') {
-            writeln!(writer, "skipping {}", line)?;
-            continue;
+        match parser.parse_line(&line.unwrap()) {
+            DotenvLine::Entry(entry) => {
+                let key = mode.key_for(&current, &entry.key, &entry.value);
+                if let Some(description) = entry.description {
+                    descriptions.push((key.clone(), description));
+                }
+                vars.push((key, entry.value));
+            }
+            DotenvLine::Comment(line) => writeln!(writer, "skipping {}", line)?,
+            DotenvLine::Invalid(line) => writeln!(writer, "invalid {}, skipping", line)?,
+            DotenvLine::Blank => {}
         }
+    }
 
-        if let Some((k, v)) = line.split_once('=') {
-            db.insert(env, k, v).await?;
-        } else {
-            writeln!(writer, "invalid {}, skipping", line)?;
-        }
+    // a single transaction, inserting the variables one by one takes
+    // seconds for large files
+    let outcome = db.insert_many(env, &vars).await?;
+    for (key, description) in descriptions {
+        db.set_description(env, &key, &description).await?;
     }
 
-    Ok(())
+    print_unchanged(writer, outcome.unchanged)
+}
+
+/// Imports the `env,key,value` records of the CSV document `contents`, see
+/// [`from_csv`]. The variables already set to their value are not written
+/// again, only counted.
+pub async fn import_csv<W: Write>(writer: &mut W, db: &EnvelopeDb, contents: &str) -> Result<()> {
+    let mut vars_by_env: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    for (env, key, value) in from_csv(contents)? {
+        check_key(&key)?;
+        vars_by_env.entry(env).or_default().push((key, value));
+    }
+
+    let mut unchanged = 0;
+    for (env, vars) in vars_by_env {
+        unchanged += db.insert_many(&env, &vars).await?.unchanged;
+    }
+
+    print_unchanged(writer, unchanged)
+}
+
+/// Tells how many imported variables already had their value
+fn print_unchanged<W: Write>(writer: &mut W, unchanged: u64) -> Result<()> {
+    match unchanged {
+        0 => Ok(()),
+        unchanged => writeln!(writer, "{} unchanged", unchanged),
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::db::{test_db, EnvironmentRow};
+    use crate::db::{test_db, EnvironmentRow, HistoryRow, SortOrder};
+    use crate::format::DEFAULT_IMPORT_SUFFIX;
     use std::io::BufReader;
 
     pub fn stdin_input(s: &str) -> BufReader<&[u8]> {
@@ -65,6 +220,7 @@ mod test {
             &mut output,
             &db,
             "prod",
+            &ImportMode::Overwrite,
         )
         .await;
         assert!(res.is_ok());
@@ -93,7 +249,14 @@ mod test {
 
         let mut output: Vec<u8> = Vec::new();
 
-        let res = import(stdin_input("# key1=value1"), &mut output, &db, "prod").await;
+        let res = import(
+            stdin_input("# key1=value1"),
+            &mut output,
+            &db,
+            "prod",
+            &ImportMode::Overwrite,
+        )
+        .await;
         assert!(res.is_ok());
         assert!(!output.is_empty());
 
@@ -122,6 +285,7 @@ mod test {
             &mut output,
             &db,
             "prod",
+            &ImportMode::Overwrite,
         )
         .await;
 
@@ -142,4 +306,210 @@ mod test {
             output
         );
     }
+
+    #[tokio::test]
+    async fn test_import_descriptions() {
+        let db = test_db().await;
+        let mut output: Vec<u8> = Vec::new();
+
+        let res = import(
+            stdin_input("# connection string\ndb_url=postgres://\n\n# unrelated\n\nport=5432"),
+            &mut output,
+            &db,
+            "prod",
+            &ImportMode::Overwrite,
+        )
+        .await;
+        assert!(res.is_ok());
+
+        let descriptions = db.list_descriptions("prod").await.unwrap();
+        assert_eq!(1, descriptions.len());
+        assert_eq!("connection string", descriptions["DB_URL"]);
+    }
+
+    #[tokio::test]
+    async fn test_import_suffix_on_conflict() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES ('dev', 'HOST', 'localhost', 1), ('dev', 'PORT', '5432', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let mode = ImportMode::SuffixOnConflict(DEFAULT_IMPORT_SUFFIX.into());
+        let mut output: Vec<u8> = Vec::new();
+        import(
+            stdin_input(
+                "host=db.internal
+port=5432
+user=admin",
+            ),
+            &mut output,
+            &db,
+            "dev",
+            &mode,
+        )
+        .await
+        .unwrap();
+
+        let vars: Vec<(String, String)> = db
+            .list_var_in_env("dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.key, r.value))
+            .collect();
+        let expected = [
+            ("HOST", "localhost"),
+            ("HOST_IMPORTED", "db.internal"),
+            ("PORT", "5432"),
+            ("USER", "admin"),
+        ];
+        assert_eq!(
+            expected
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>(),
+            vars
+        );
+
+        // a second import keeps the first imported value
+        for input in ["host=db.internal", "host=db2.internal"] {
+            import(stdin_input(input), &mut output, &db, "dev", &mode)
+                .await
+                .unwrap();
+        }
+        let vars = db.list_var_in_env("dev", SortOrder::Asc).await.unwrap();
+        let hosts: Vec<(&str, &str)> = vars
+            .iter()
+            .filter(|r| r.key.starts_with("HOST"))
+            .map(|r| (r.key.as_str(), r.value.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("HOST", "localhost"),
+                ("HOST_IMPORTED", "db.internal"),
+                ("HOST_IMPORTED_2", "db2.internal")
+            ],
+            hosts
+        );
+    }
+
+    #[tokio::test]
+    async fn test_add_var_in_envs() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES ('dev', 'TOKEN', 'old', 1), ('test', 'TOKEN', 'new', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let envs = ["dev".to_string(), "prod".to_string(), "test".to_string()];
+        let mut output = anstream::StripStream::new(Vec::new());
+        add_var_in_envs(&mut output, &db, &envs, "token", "new")
+            .await
+            .unwrap();
+        assert_eq!(
+            "dev\n~ TOKEN=old -> new\nprod\n+ TOKEN=new\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_print_outcome() {
+        let print = |key: &str, previous: Option<&str>, changed| {
+            let outcome = InsertOutcome {
+                previous: previous.map(str::to_string),
+                changed,
+            };
+            let mut output = anstream::StripStream::new(Vec::new());
+            print_outcome(&mut output, "dev", key, "new", &outcome).unwrap();
+            String::from_utf8(output.into_inner()).unwrap()
+        };
+
+        assert_eq!("dev\n+ HOST=new\n", print("HOST", None, true));
+        assert_eq!("dev\n~ HOST=old -> new\n", print("HOST", Some("old"), true));
+        assert_eq!("no changes\n", print("HOST", Some("new"), false));
+        assert_eq!(
+            "dev\n~ API_KEY=******** -> ********\n",
+            print("API_KEY", Some("old"), true)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_csv() {
+        let db = test_db().await;
+
+        let csv = "env,key,value\r\ndev,url,\"a,b\"\r\nprod,URL,c\r\n";
+        let mut output = Vec::new();
+        import_csv(&mut output, &db, csv).await.unwrap();
+        assert!(output.is_empty());
+
+        let vars = |rows: Vec<EnvironmentRow>| -> Vec<(String, String)> {
+            rows.into_iter().map(|r| (r.key, r.value)).collect()
+        };
+        let dev = db.list_var_in_env("dev", SortOrder::Asc).await.unwrap();
+        assert_eq!(vec![("URL".to_string(), "a,b".to_string())], vars(dev));
+        let prod = db.list_var_in_env("prod", SortOrder::Asc).await.unwrap();
+        assert_eq!(vec![("URL".to_string(), "c".to_string())], vars(prod));
+
+        // importing the same records again writes nothing
+        let mut output = Vec::new();
+        import_csv(&mut output, &db, csv).await.unwrap();
+        assert_eq!("2 unchanged\n", String::from_utf8(output).unwrap());
+        assert_eq!(2, db.dump_raw().await.unwrap().len());
+    }
+
+    /// a 5k lines file imports in one transaction, leaving the same rows as
+    /// inserting the variables one by one
+    #[tokio::test]
+    async fn test_import_5k() {
+        let path = std::env::temp_dir().join(format!("envelope-5k-{}.db", std::process::id()));
+        let mut db = EnvelopeDb::open(&path).await.unwrap();
+        db.set_fast_import(true);
+        let contents: String = (0..5000)
+            .map(|i| format!("key_{}=value {}\n", i, i))
+            .collect();
+
+        let start = std::time::Instant::now();
+        let mut output: Vec<u8> = Vec::new();
+        import(
+            stdin_input(&contents),
+            &mut output,
+            &db,
+            "fast",
+            &ImportMode::Overwrite,
+        )
+        .await
+        .unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(30));
+
+        // the slow path runs in memory, one commit per variable would take
+        // seconds on disk
+        let slow = test_db().await;
+        for entry in crate::dotenv::from_dotenv(&contents) {
+            slow.insert("fast", &entry.key, &entry.value).await.unwrap();
+        }
+
+        let rows = |rows: Vec<HistoryRow>| -> Vec<(String, Option<String>)> {
+            rows.into_iter().map(|row| (row.key, row.value)).collect()
+        };
+        let fast = rows(db.history_between("fast", None, None, None).await.unwrap());
+        assert_eq!(5000, fast.len());
+        assert_eq!(
+            rows(
+                slow.history_between("fast", None, None, None)
+                    .await
+                    .unwrap()
+            ),
+            fast
+        );
+
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
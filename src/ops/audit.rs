@@ -0,0 +1,42 @@
+use std::io::{Result, Write};
+
+use crate::db::EnvelopeDb;
+
+/// Prints whether the database is in audit mode
+pub async fn audit_status<W: Write>(w: &mut W, db: &EnvelopeDb) -> Result<()> {
+    match db.audit_mode().await? {
+        true => writeln!(w, "audit mode is enabled, history can only be appended to"),
+        false => writeln!(w, "audit mode is disabled"),
+    }
+}
+
+/// Puts the database in audit mode, for good
+pub async fn enable_audit(db: &EnvelopeDb) -> Result<()> {
+    Ok(db.enable_audit_mode().await?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    #[tokio::test]
+    async fn test_audit_status() {
+        let db = test_db().await;
+
+        let mut w = Vec::new();
+        audit_status(&mut w, &db).await.unwrap();
+        assert_eq!("audit mode is disabled\n", String::from_utf8(w).unwrap());
+
+        enable_audit(&db).await.unwrap();
+        // enabling it again is a no-op
+        enable_audit(&db).await.unwrap();
+
+        let mut w = Vec::new();
+        audit_status(&mut w, &db).await.unwrap();
+        assert_eq!(
+            "audit mode is enabled, history can only be appended to\n",
+            String::from_utf8(w).unwrap()
+        );
+    }
+}
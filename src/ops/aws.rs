@@ -0,0 +1,713 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::{Result, Write};
+
+use aws_config::retry::RetryConfig;
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_sdk_ssm::error::DisplayErrorContext;
+use aws_sdk_ssm::types::ParameterType;
+use futures_util::{stream, TryStreamExt};
+
+use crate::db::EnvelopeDb;
+use crate::std_err;
+
+use super::{check_key, ExportPlan};
+
+/// Attempts of a request before giving up, the SDK retries the throttled
+/// ones with an exponential backoff
+const AWS_MAX_ATTEMPTS: u32 = 8;
+
+/// Parameters written at once by an export, SSM throttles PutParameter at a
+/// few requests per second
+const EXPORT_CONCURRENCY: usize = 4;
+
+/// How the name of a parameter becomes a key, keys are uppercased either way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum KeyTransform {
+    /// replace the characters other than letters, digits and `_` with `_`,
+    /// `db-password` becomes `DB_PASSWORD`
+    #[default]
+    Underscore,
+    /// keep the last segment of the name as it is
+    None,
+}
+
+/// The parameters to read with GetParametersByPath
+#[derive(Debug, Clone)]
+pub struct SsmQuery {
+    pub path: String,
+    pub recursive: bool,
+    pub with_decryption: bool,
+}
+
+/// `(name, value)` parameters of a page of GetParametersByPath and the token
+/// of the next page
+pub type ParametersPage = (Vec<(String, String)>, Option<String>);
+
+/// The parameters of SSM, the SSM client or a fake one in tests
+pub trait ParameterStore {
+    /// returns the page of the parameters matched by `query` starting at
+    /// `next_token`
+    fn parameters_page(
+        &self,
+        query: &SsmQuery,
+        next_token: Option<String>,
+    ) -> impl Future<Output = Result<ParametersPage>>;
+
+    /// creates or replaces the SecureString parameter `name`, encrypted with
+    /// the KMS key `kms_key_id` or the default one of the account
+    fn put_parameter(
+        &self,
+        name: &str,
+        value: &str,
+        kms_key_id: Option<&str>,
+    ) -> impl Future<Output = Result<()>>;
+}
+
+impl ParameterStore for aws_sdk_ssm::Client {
+    async fn parameters_page(
+        &self,
+        query: &SsmQuery,
+        next_token: Option<String>,
+    ) -> Result<ParametersPage> {
+        let output = self
+            .get_parameters_by_path()
+            .path(&query.path)
+            .recursive(query.recursive)
+            .with_decryption(query.with_decryption)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .map_err(|e| {
+                std_err!(
+                    "cannot read the parameters under {}: {}",
+                    query.path,
+                    DisplayErrorContext(e)
+                )
+            })?;
+
+        let parameters = output
+            .parameters()
+            .iter()
+            .filter_map(|p| Some((p.name()?.to_string(), p.value()?.to_string())))
+            .collect();
+
+        Ok((parameters, output.next_token().map(str::to_string)))
+    }
+
+    async fn put_parameter(&self, name: &str, value: &str, kms_key_id: Option<&str>) -> Result<()> {
+        self.put_parameter()
+            .name(name)
+            .value(value)
+            .r#type(ParameterType::SecureString)
+            .set_key_id(kms_key_id.map(str::to_string))
+            .overwrite(true)
+            .send()
+            .await
+            .map_err(|e| {
+                std_err!(
+                    "cannot write parameter {}: {}",
+                    name,
+                    DisplayErrorContext(e)
+                )
+            })?;
+
+        Ok(())
+    }
+}
+
+/// The secrets of Secrets Manager, the Secrets Manager client or a fake one
+/// in tests
+pub trait SecretStore {
+    /// returns the string value of the secret `name`, `None` if there is no
+    /// such secret
+    fn get_secret(&self, name: &str) -> impl Future<Output = Result<Option<String>>>;
+
+    /// creates the secret `name`, encrypted with the KMS key `kms_key_id` or
+    /// the default one of the account
+    fn create_secret(
+        &self,
+        name: &str,
+        value: &str,
+        kms_key_id: Option<&str>,
+    ) -> impl Future<Output = Result<()>>;
+
+    /// stores `value` as the new version of the secret `name`
+    fn put_secret(&self, name: &str, value: &str) -> impl Future<Output = Result<()>>;
+}
+
+impl SecretStore for aws_sdk_secretsmanager::Client {
+    async fn get_secret(&self, name: &str) -> Result<Option<String>> {
+        let result = self.get_secret_value().secret_id(name).send().await;
+        match result {
+            Ok(output) => Ok(output.secret_string().map(str::to_string)),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_resource_not_found_exception()) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(std_err!(
+                "cannot read secret {}: {}",
+                name,
+                DisplayErrorContext(e)
+            )),
+        }
+    }
+
+    async fn create_secret(&self, name: &str, value: &str, kms_key_id: Option<&str>) -> Result<()> {
+        self.create_secret()
+            .name(name)
+            .secret_string(value)
+            .set_kms_key_id(kms_key_id.map(str::to_string))
+            .send()
+            .await
+            .map_err(|e| std_err!("cannot create secret {}: {}", name, DisplayErrorContext(e)))?;
+
+        Ok(())
+    }
+
+    async fn put_secret(&self, name: &str, value: &str) -> Result<()> {
+        self.put_secret_value()
+            .secret_id(name)
+            .secret_string(value)
+            .send()
+            .await
+            .map_err(|e| std_err!("cannot write secret {}: {}", name, DisplayErrorContext(e)))?;
+
+        Ok(())
+    }
+}
+
+/// Returns the AWS config of the standard chain: the credentials and region
+/// of the environment, the shared config and credentials files, then the
+/// instance or container role. Throttled requests are retried.
+pub async fn aws_config() -> SdkConfig {
+    aws_config::defaults(BehaviorVersion::latest())
+        .retry_config(RetryConfig::standard().with_max_attempts(AWS_MAX_ATTEMPTS))
+        .load()
+        .await
+}
+
+/// Reads every page of the parameters matched by `query` and returns them as
+/// `(key, value)` variables, the key being the last segment of the name of
+/// the parameter transformed by `transform`
+pub async fn ssm_vars<S: ParameterStore>(
+    store: &S,
+    query: &SsmQuery,
+    transform: KeyTransform,
+) -> Result<Vec<(String, String)>> {
+    let mut vars = Vec::new();
+    for (name, value) in all_parameters(store, query).await? {
+        let key = key_for(&name, transform);
+        check_key(&key).map_err(|e| std_err!("parameter {}: {}", name, e))?;
+        vars.push((key, value));
+    }
+
+    Ok(vars)
+}
+
+/// Sets the `vars` read from the parameters under `path` in `env` in a
+/// single transaction, then prints how many were imported
+pub async fn import_ssm<W: Write>(
+    writer: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    path: &str,
+    vars: &[(String, String)],
+) -> Result<()> {
+    let outcome = db.insert_many(env, vars).await?;
+    writeln!(
+        writer,
+        "{} parameters imported from {}, {} unchanged",
+        outcome.written, path, outcome.unchanged
+    )
+}
+
+/// Compares `vars` with the parameters right under `path`, which are named
+/// after their key. The parameters envelope does not have are left alone.
+pub async fn plan_ssm_export<S: ParameterStore>(
+    store: &S,
+    path: &str,
+    vars: &BTreeMap<String, String>,
+) -> Result<ExportPlan> {
+    let query = SsmQuery {
+        path: path.to_string(),
+        recursive: false,
+        with_decryption: true,
+    };
+    let remote = all_parameters(store, &query)
+        .await?
+        .into_iter()
+        .filter_map(|(name, value)| {
+            Some((name.strip_prefix(&parameter_prefix(path))?.into(), value))
+        })
+        .collect();
+
+    let mut plan = ExportPlan::new(remote, vars);
+    plan.diff.removed.clear();
+    Ok(plan)
+}
+
+/// Writes the parameters `plan` creates or updates under `path`, a few at a
+/// time, then prints the counts of `plan`
+pub async fn export_ssm<W: Write, S: ParameterStore>(
+    writer: &mut W,
+    store: &S,
+    path: &str,
+    plan: &ExportPlan,
+    kms_key_id: Option<&str>,
+) -> Result<()> {
+    let prefix = parameter_prefix(path);
+    let writes = plan
+        .diff
+        .added
+        .iter()
+        .chain(plan.diff.changed.iter().map(|(key, (_, new))| (key, new)));
+    stream::iter(writes.map(Ok))
+        .try_for_each_concurrent(EXPORT_CONCURRENCY, |(key, value)| {
+            let name = format!("{}{}", prefix, key);
+            async move { store.put_parameter(&name, value, kms_key_id).await }
+        })
+        .await?;
+
+    writeln!(writer, "{}", plan)
+}
+
+/// Compares `vars` with the JSON object stored in the secret `name`, a
+/// missing secret is empty. Returns whether the secret exists along with the
+/// plan.
+pub async fn plan_secret_export<S: SecretStore>(
+    store: &S,
+    name: &str,
+    vars: &BTreeMap<String, String>,
+) -> Result<(ExportPlan, bool)> {
+    let Some(secret) = store.get_secret(name).await? else {
+        return Ok((ExportPlan::new(BTreeMap::new(), vars), false));
+    };
+
+    let remote = serde_json::from_str(&secret)
+        .map_err(|e| std_err!("secret {} is not a JSON object of strings: {}", name, e))?;
+    Ok((ExportPlan::new(remote, vars), true))
+}
+
+/// Writes `vars` as a JSON object to the secret `name`, creating it unless
+/// it `exists`, then prints the counts of `plan`. Nothing is written when
+/// the secret already holds `vars`.
+pub async fn export_secret<W: Write, S: SecretStore>(
+    writer: &mut W,
+    store: &S,
+    name: &str,
+    plan: &ExportPlan,
+    exists: bool,
+    vars: &BTreeMap<String, String>,
+    kms_key_id: Option<&str>,
+) -> Result<()> {
+    let value = serde_json::to_string(vars).map_err(|e| std_err!("{}", e))?;
+    match (exists, plan.diff.is_empty()) {
+        (true, true) => {}
+        (true, false) => store.put_secret(name, &value).await?,
+        (false, _) => store.create_secret(name, &value, kms_key_id).await?,
+    }
+
+    writeln!(writer, "{}", plan)
+}
+
+/// Reads every page of the parameters matched by `query`
+async fn all_parameters<S: ParameterStore>(
+    store: &S,
+    query: &SsmQuery,
+) -> Result<Vec<(String, String)>> {
+    let mut parameters = Vec::new();
+    let mut next_token = None;
+    loop {
+        let (page, next) = store.parameters_page(query, next_token).await?;
+        parameters.extend(page);
+
+        match next {
+            Some(token) => next_token = Some(token),
+            None => return Ok(parameters),
+        }
+    }
+}
+
+/// `path` ending with a `/`, the names of the parameters right under it are
+/// the prefix followed by their key
+fn parameter_prefix(path: &str) -> String {
+    match path.ends_with('/') {
+        true => path.to_string(),
+        false => format!("{}/", path),
+    }
+}
+
+/// Key of the parameter `name`, its last segment
+fn key_for(name: &str, transform: KeyTransform) -> String {
+    let segment = name.rsplit('/').next().unwrap_or(name);
+    match transform {
+        KeyTransform::Underscore => segment
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c,
+                false => '_',
+            })
+            .collect(),
+        KeyTransform::None => segment.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+    use crate::db::{test_db, SortOrder};
+
+    /// SSM holding `parameters`, served by pages of 2
+    #[derive(Default)]
+    struct FakeSsm {
+        parameters: RefCell<BTreeMap<String, String>>,
+        /// page failing to be read
+        failing_page: Option<usize>,
+        tokens: RefCell<Vec<Option<String>>>,
+        puts: RefCell<Vec<(String, Option<String>)>>,
+        /// PutParameter running at once, and the most there were
+        running: Cell<usize>,
+        max_running: Cell<usize>,
+    }
+
+    impl FakeSsm {
+        fn new<const N: usize>(parameters: [(&str, &str); N]) -> Self {
+            FakeSsm {
+                parameters: RefCell::new(
+                    parameters
+                        .into_iter()
+                        .map(|(n, v)| (n.to_string(), v.to_string()))
+                        .collect(),
+                ),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl ParameterStore for FakeSsm {
+        async fn parameters_page(
+            &self,
+            query: &SsmQuery,
+            next_token: Option<String>,
+        ) -> Result<ParametersPage> {
+            let page = next_token.as_ref().map_or(0, |t| t.parse().unwrap());
+            self.tokens.borrow_mut().push(next_token);
+            if self.failing_page == Some(page) {
+                return Err(std_err!(
+                    "cannot read the parameters under {}: AccessDenied",
+                    query.path
+                ));
+            }
+
+            let prefix = parameter_prefix(&query.path);
+            let parameters: Vec<_> = self
+                .parameters
+                .borrow()
+                .iter()
+                .filter(|(name, _)| name.starts_with(&prefix))
+                .filter(|(name, _)| query.recursive || !name[prefix.len()..].contains('/'))
+                .map(|(n, v)| (n.clone(), v.clone()))
+                .collect();
+            let next = (parameters.len() > (page + 1) * 2).then(|| (page + 1).to_string());
+            let page = parameters.into_iter().skip(page * 2).take(2).collect();
+
+            Ok((page, next))
+        }
+
+        async fn put_parameter(
+            &self,
+            name: &str,
+            value: &str,
+            kms_key_id: Option<&str>,
+        ) -> Result<()> {
+            self.running.set(self.running.get() + 1);
+            self.max_running
+                .set(self.max_running.get().max(self.running.get()));
+            tokio::task::yield_now().await;
+            self.running.set(self.running.get() - 1);
+
+            self.puts
+                .borrow_mut()
+                .push((name.to_string(), kms_key_id.map(str::to_string)));
+            self.parameters
+                .borrow_mut()
+                .insert(name.to_string(), value.to_string());
+            Ok(())
+        }
+    }
+
+    /// Secrets Manager holding at most one secret
+    #[derive(Default)]
+    struct FakeSecrets {
+        secret: RefCell<Option<String>>,
+        writes: RefCell<Vec<&'static str>>,
+    }
+
+    impl SecretStore for FakeSecrets {
+        async fn get_secret(&self, _name: &str) -> Result<Option<String>> {
+            Ok(self.secret.borrow().clone())
+        }
+
+        async fn create_secret(
+            &self,
+            _name: &str,
+            value: &str,
+            _kms_key_id: Option<&str>,
+        ) -> Result<()> {
+            self.writes.borrow_mut().push("create");
+            *self.secret.borrow_mut() = Some(value.to_string());
+            Ok(())
+        }
+
+        async fn put_secret(&self, _name: &str, value: &str) -> Result<()> {
+            self.writes.borrow_mut().push("put");
+            *self.secret.borrow_mut() = Some(value.to_string());
+            Ok(())
+        }
+    }
+
+    fn query() -> SsmQuery {
+        SsmQuery {
+            path: "/myapp/prod/".into(),
+            recursive: true,
+            with_decryption: true,
+        }
+    }
+
+    fn vars<const N: usize>(vars: [(&str, &str); N]) -> BTreeMap<String, String> {
+        vars.into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_key_for() {
+        assert_eq!(
+            "db-password",
+            key_for("/myapp/prod/db-password", KeyTransform::None)
+        );
+        assert_eq!(
+            "db_password",
+            key_for("/myapp/prod/db-password", KeyTransform::Underscore)
+        );
+        assert_eq!(
+            "API_KEY_V2",
+            key_for("/a/b/c/API.KEY-V2", KeyTransform::Underscore)
+        );
+        assert_eq!("PORT", key_for("PORT", KeyTransform::Underscore));
+    }
+
+    #[tokio::test]
+    async fn test_import_ssm() {
+        let db = test_db().await;
+        db.insert("prod", "PORT", "80").await.unwrap();
+        let ssm = FakeSsm::new([
+            ("/myapp/prod/db-password", "hunter2"),
+            ("/myapp/prod/port", "80"),
+            ("/myapp/prod/nested/api.key", "s3cr3t"),
+            ("/myapp/dev/port", "8080"),
+        ]);
+
+        let imported = ssm_vars(&ssm, &query(), KeyTransform::Underscore)
+            .await
+            .unwrap();
+        assert_eq!(vec![None, Some("1".to_string())], ssm.tokens.take());
+
+        let mut w = Vec::new();
+        import_ssm(&mut w, &db, "prod", "/myapp/prod/", &imported)
+            .await
+            .unwrap();
+        assert_eq!(
+            "2 parameters imported from /myapp/prod/, 1 unchanged\n",
+            String::from_utf8(w).unwrap()
+        );
+
+        let stored: BTreeMap<String, String> = db
+            .list_var_in_env("prod", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.key, v.value))
+            .collect();
+        assert_eq!(
+            vars([
+                ("API_KEY", "s3cr3t"),
+                ("DB_PASSWORD", "hunter2"),
+                ("PORT", "80"),
+            ]),
+            stored
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_ssm_errors() {
+        // a failing page fails the whole import, nothing is written
+        let ssm = FakeSsm {
+            failing_page: Some(1),
+            ..FakeSsm::new([
+                ("/myapp/prod/a", "1"),
+                ("/myapp/prod/b", "2"),
+                ("/myapp/prod/c", "3"),
+            ])
+        };
+        let err = ssm_vars(&ssm, &query(), KeyTransform::Underscore)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "cannot read the parameters under /myapp/prod/: AccessDenied",
+            err.to_string()
+        );
+
+        let ssm = FakeSsm::new([("/myapp/prod/#port", "80")]);
+        let err = ssm_vars(&ssm, &query(), KeyTransform::None)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "parameter /myapp/prod/#port: key name cannot start with #",
+            err.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_ssm() {
+        let ssm = FakeSsm::new([
+            ("/myapp/prod/PORT", "80"),
+            ("/myapp/prod/HOST", "old"),
+            ("/myapp/prod/REMOTE_ONLY", "1"),
+            ("/myapp/prod/nested/HOST", "nested"),
+        ]);
+        let local = vars([
+            ("A", "1"),
+            ("B", "2"),
+            ("C", "3"),
+            ("HOST", "new"),
+            ("PORT", "80"),
+        ]);
+
+        // the path is the same with or without its trailing /
+        let plan = plan_ssm_export(&ssm, "/myapp/prod", &local).await.unwrap();
+        assert_eq!(3, plan.diff.added.len());
+        assert_eq!(
+            BTreeMap::from([("HOST".into(), ("old".into(), "new".into()))]),
+            plan.diff.changed
+        );
+        assert!(plan.diff.removed.is_empty());
+        assert_eq!("3 created, 1 updated, 1 unchanged", plan.to_string());
+
+        let mut w = Vec::new();
+        export_ssm(&mut w, &ssm, "/myapp/prod", &plan, Some("alias/app"))
+            .await
+            .unwrap();
+        assert_eq!(
+            "3 created, 1 updated, 1 unchanged\n",
+            String::from_utf8(w).unwrap()
+        );
+        let mut puts = ssm.puts.take();
+        puts.sort();
+        assert_eq!(
+            vec![
+                ("/myapp/prod/A".to_string(), Some("alias/app".to_string())),
+                ("/myapp/prod/B".into(), Some("alias/app".into())),
+                ("/myapp/prod/C".into(), Some("alias/app".into())),
+                ("/myapp/prod/HOST".into(), Some("alias/app".into())),
+            ],
+            puts
+        );
+        assert!((2..=EXPORT_CONCURRENCY).contains(&ssm.max_running.get()));
+        assert_eq!(
+            Some("1"),
+            ssm.parameters
+                .borrow()
+                .get("/myapp/prod/REMOTE_ONLY")
+                .map(String::as_str)
+        );
+
+        // exporting again writes nothing
+        let plan = plan_ssm_export(&ssm, "/myapp/prod/", &local).await.unwrap();
+        assert!(plan.diff.is_empty());
+        export_ssm(&mut Vec::new(), &ssm, "/myapp/prod/", &plan, None)
+            .await
+            .unwrap();
+        assert!(ssm.puts.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_export_secret() {
+        let secrets = FakeSecrets::default();
+        let local = vars([("A", "1"), ("B", "2")]);
+
+        let (plan, exists) = plan_secret_export(&secrets, "myapp/prod", &local)
+            .await
+            .unwrap();
+        assert!(!exists);
+        assert_eq!("2 created, 0 updated, 0 unchanged", plan.to_string());
+        let mut w = Vec::new();
+        export_secret(&mut w, &secrets, "myapp/prod", &plan, exists, &local, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            "2 created, 0 updated, 0 unchanged\n",
+            String::from_utf8(w).unwrap()
+        );
+        assert_eq!(
+            Some(r#"{"A":"1","B":"2"}"#),
+            secrets.secret.borrow().as_deref()
+        );
+
+        // unchanged, nothing is written
+        let (plan, exists) = plan_secret_export(&secrets, "myapp/prod", &local)
+            .await
+            .unwrap();
+        assert!(exists);
+        export_secret(
+            &mut Vec::new(),
+            &secrets,
+            "myapp/prod",
+            &plan,
+            exists,
+            &local,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(vec!["create"], *secrets.writes.borrow());
+
+        let local = vars([("B", "3"), ("C", "4")]);
+        let (plan, exists) = plan_secret_export(&secrets, "myapp/prod", &local)
+            .await
+            .unwrap();
+        assert_eq!(
+            "1 created, 1 updated, 0 unchanged, 1 removed",
+            plan.to_string()
+        );
+        export_secret(
+            &mut Vec::new(),
+            &secrets,
+            "myapp/prod",
+            &plan,
+            exists,
+            &local,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(vec!["create", "put"], *secrets.writes.borrow());
+        assert_eq!(
+            Some(r#"{"B":"3","C":"4"}"#),
+            secrets.secret.borrow().as_deref()
+        );
+
+        *secrets.secret.borrow_mut() = Some("plain".into());
+        let err = plan_secret_export(&secrets, "myapp/prod", &local)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("secret myapp/prod is not a JSON object of strings"));
+    }
+}
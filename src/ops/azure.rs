@@ -0,0 +1,957 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::future::Future;
+use std::io::{self, Result, Write};
+
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use crate::db::EnvelopeDb;
+use crate::std_err;
+
+use super::{check_key, ExportPlan, SkippedKey};
+
+/// Version of the Key Vault REST API
+const API_VERSION: &str = "7.4";
+
+/// Resource the access tokens of Key Vault are issued for
+const KEYVAULT_RESOURCE: &str = "https://vault.azure.net";
+
+/// Authority of the Azure public cloud, `AZURE_AUTHORITY_HOST` overrides it
+const DEFAULT_AUTHORITY_HOST: &str = "https://login.microsoftonline.com";
+
+/// Longest name of a Key Vault secret
+const MAX_NAME_LEN: usize = 127;
+
+/// Secret listed in a Key Vault, without its value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretItem {
+    pub name: String,
+    /// disabled secrets cannot be read
+    pub enabled: bool,
+    /// secrets backing a certificate, managed by Key Vault
+    pub managed: bool,
+}
+
+/// Page of the secrets of a Key Vault
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SecretPage {
+    pub items: Vec<SecretItem>,
+    /// link to the next page, if there is one
+    pub next: Option<String>,
+}
+
+/// The secrets of a Key Vault, the REST client or a fake one in tests
+pub trait KeyVaultStore {
+    /// name of the vault, for the messages
+    fn display(&self) -> String;
+
+    /// returns the page of secrets `next` links to, the first one if `None`
+    fn list_page(&self, next: Option<&str>) -> impl Future<Output = Result<SecretPage>>;
+
+    /// returns the current value of the secret `name`, `None` if there is
+    /// no such secret
+    fn get(&self, name: &str) -> impl Future<Output = Result<Option<String>>>;
+
+    /// writes `value` as the new version of the secret `name`. Returns
+    /// false without writing if a soft-deleted secret has the name, it must
+    /// be recovered or purged first.
+    fn set(&self, name: &str, value: &str) -> impl Future<Output = Result<bool>>;
+}
+
+/// Client of the REST API of a Key Vault
+pub struct KeyVaultClient {
+    http: reqwest::Client,
+    url: String,
+    token: String,
+}
+
+impl KeyVaultClient {
+    /// returns a client of the vault `vault`, a name or the URL of the vault
+    /// in another cloud. Authenticates as the service principal of
+    /// `AZURE_TENANT_ID`, `AZURE_CLIENT_ID` and `AZURE_CLIENT_SECRET` when
+    /// they are set, like the Azure SDKs, as the user of the `az` CLI
+    /// otherwise.
+    pub async fn from_env(vault: &str) -> Result<Self> {
+        let url = match vault.contains("://") {
+            true => vault.to_string(),
+            false => format!("https://{}.vault.azure.net", vault),
+        };
+        let token = match service_principal() {
+            Some((tenant, id, secret)) => client_credentials_token(&tenant, &id, &secret).await?,
+            None => az_cli_token().await?,
+        };
+
+        Ok(KeyVaultClient::new(&url, &token))
+    }
+
+    fn new(url: &str, token: &str) -> Self {
+        KeyVaultClient {
+            http: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+        }
+    }
+
+    /// sends a request for the secret `name`, or the list of secrets, to
+    /// `url`. Returns the status and body of the response.
+    async fn request(
+        &self,
+        method: Method,
+        url: &str,
+        body: Option<Value>,
+    ) -> Result<(StatusCode, Value)> {
+        let mut request = self
+            .http
+            .request(method, url)
+            .bearer_auth(&self.token)
+            .query(&[("api-version", API_VERSION)]);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| std_err!("cannot reach Key Vault at {}: {}", self.url, e))?;
+        let status = response.status();
+        let text = response
+            .text()
+            .await
+            .map_err(|e| std_err!("cannot read the response of Key Vault: {}", e))?;
+        let body = match text.is_empty() {
+            true => Value::Null,
+            false => serde_json::from_str(&text)
+                .map_err(|e| std_err!("invalid response from Key Vault at {}: {}", url, e))?,
+        };
+
+        Ok((status, body))
+    }
+
+    fn secret_url(&self, name: &str) -> String {
+        format!("{}/secrets/{}", self.url, name)
+    }
+}
+
+impl KeyVaultStore for KeyVaultClient {
+    fn display(&self) -> String {
+        let host = self.url.split_once("://").map_or(&*self.url, |(_, h)| h);
+        host.split('.').next().unwrap_or(host).to_string()
+    }
+
+    async fn list_page(&self, next: Option<&str>) -> Result<SecretPage> {
+        let url = match next {
+            // the token is only sent to the vault
+            Some(next) if !next.starts_with(&format!("{}/", self.url)) => {
+                return Err(std_err!(
+                    "Key Vault returned a next page outside of {}: {}",
+                    self.url,
+                    next
+                ))
+            }
+            Some(next) => next.to_string(),
+            None => format!("{}/secrets", self.url),
+        };
+        let (status, body) = self.request(Method::GET, &url, None).await?;
+        if !status.is_success() {
+            return Err(keyvault_error(status, &body, &self.display(), None));
+        }
+
+        let items = body["value"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|item| {
+                // the id of a secret is its URL, the name is its last segment
+                let name = item["id"].as_str()?.rsplit('/').next()?.to_string();
+                Some(SecretItem {
+                    name,
+                    enabled: item["attributes"]["enabled"].as_bool().unwrap_or(true),
+                    managed: item["managed"].as_bool().unwrap_or(false),
+                })
+            })
+            .collect();
+        let next = body["nextLink"].as_str().map(str::to_string);
+
+        Ok(SecretPage { items, next })
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<String>> {
+        let (status, body) = self
+            .request(Method::GET, &self.secret_url(name), None)
+            .await?;
+        match status {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                Ok(Some(body["value"].as_str().unwrap_or_default().to_string()))
+            }
+            status => Err(keyvault_error(status, &body, &self.display(), Some(name))),
+        }
+    }
+
+    async fn set(&self, name: &str, value: &str) -> Result<bool> {
+        let body = json!({ "value": value });
+        let (status, body) = self
+            .request(Method::PUT, &self.secret_url(name), Some(body))
+            .await?;
+        match status {
+            StatusCode::CONFLICT if error_code(&body) == Some("ObjectIsDeletedButRecoverable") => {
+                Ok(false)
+            }
+            status if status.is_success() => Ok(true),
+            status => Err(keyvault_error(status, &body, &self.display(), Some(name))),
+        }
+    }
+}
+
+/// Code of the error of a Key Vault response, the inner one if any
+fn error_code(body: &Value) -> Option<&str> {
+    let error = &body["error"];
+    error["innererror"]["code"]
+        .as_str()
+        .or_else(|| error["code"].as_str())
+}
+
+/// Error of a Key Vault response of status `status` for the secret `name`
+/// of `vault`, or for the list of its secrets
+fn keyvault_error(status: StatusCode, body: &Value, vault: &str, name: Option<&str>) -> io::Error {
+    let message = body["error"]["message"].as_str().unwrap_or_default();
+    let target = match name {
+        Some(name) => format!("secret {} of Key Vault {}", name, vault),
+        None => format!("the secrets of Key Vault {}", vault),
+    };
+
+    match status {
+        StatusCode::UNAUTHORIZED => io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Key Vault {} rejected the access token: {}", vault, message),
+        ),
+        StatusCode::FORBIDDEN if error_code(body) == Some("SecretDisabled") => {
+            std_err!("{} is disabled", target)
+        }
+        StatusCode::FORBIDDEN => io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "permission denied on {}, check its access policies or RBAC roles: {}",
+                target, message
+            ),
+        ),
+        status => std_err!("Key Vault replied {} for {}: {}", status, target, message),
+    }
+}
+
+/// Tenant, id and secret of the service principal of the environment
+fn service_principal() -> Option<(String, String, String)> {
+    let var = |name| env::var(name).ok().filter(|v: &String| !v.is_empty());
+    Some((
+        var("AZURE_TENANT_ID")?,
+        var("AZURE_CLIENT_ID")?,
+        var("AZURE_CLIENT_SECRET")?,
+    ))
+}
+
+/// Access token to Key Vault of a service principal, from the client
+/// credentials flow of Microsoft Entra ID
+async fn client_credentials_token(tenant: &str, id: &str, secret: &str) -> Result<String> {
+    let authority =
+        env::var("AZURE_AUTHORITY_HOST").unwrap_or_else(|_| DEFAULT_AUTHORITY_HOST.to_string());
+    let url = format!(
+        "{}/{}/oauth2/v2.0/token",
+        authority.trim_end_matches('/'),
+        tenant
+    );
+    let scope = format!("{}/.default", KEYVAULT_RESOURCE);
+
+    let response = reqwest::Client::new()
+        .post(&url)
+        .form(&[
+            ("grant_type", "client_credentials"),
+            ("client_id", id),
+            ("client_secret", secret),
+            ("scope", &scope),
+        ])
+        .send()
+        .await
+        .map_err(|e| std_err!("cannot reach {}: {}", authority, e))?;
+    let status = response.status();
+    let body: Value = response
+        .json()
+        .await
+        .map_err(|e| std_err!("invalid token response from {}: {}", authority, e))?;
+
+    match body["access_token"].as_str() {
+        Some(token) if status.is_success() => Ok(token.to_string()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "cannot authenticate AZURE_CLIENT_ID in the tenant {}: {}",
+                tenant,
+                body["error_description"].as_str().unwrap_or_default()
+            ),
+        )),
+    }
+}
+
+/// Access token to Key Vault of the user signed in to the `az` CLI
+async fn az_cli_token() -> Result<String> {
+    let output = tokio::process::Command::new("az")
+        .args(["account", "get-access-token", "--resource"])
+        .arg(KEYVAULT_RESOURCE)
+        .args(["--query", "accessToken", "--output", "tsv"])
+        .output()
+        .await
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => std_err!(
+                "cannot authenticate to Azure, set AZURE_TENANT_ID, AZURE_CLIENT_ID and \
+                AZURE_CLIENT_SECRET or install the az CLI"
+            ),
+            _ => std_err!("cannot run az: {}", e),
+        })?;
+
+    match output.status.success() {
+        true => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        false => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "az cannot get an access token to Key Vault, run `az login` first: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        )),
+    }
+}
+
+/// Name of the secret of `key`, Key Vault names only have letters, digits
+/// and `-`, so `_` becomes `-`. `None` if the key has other characters.
+pub fn secret_name(key: &str) -> Option<String> {
+    let name = key.replace('_', "-");
+    let valid = (1..=MAX_NAME_LEN).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    valid.then_some(name)
+}
+
+/// Key of the secret `name`, `-` becomes `_` and the key is uppercased
+pub fn secret_key(name: &str) -> String {
+    name.replace('-', "_").to_ascii_uppercase()
+}
+
+/// Secrets of all the pages of the vault
+async fn list_secrets<S: KeyVaultStore>(store: &S) -> Result<Vec<SecretItem>> {
+    let mut page = store.list_page(None).await?;
+    let mut items = std::mem::take(&mut page.items);
+    while let Some(next) = page.next {
+        page = store.list_page(Some(&next)).await?;
+        items.append(&mut page.items);
+    }
+
+    Ok(items)
+}
+
+/// Why the secret `item` is not read, if it is not
+fn skip_reason(item: &SecretItem) -> Option<&'static str> {
+    match (item.enabled, item.managed) {
+        (false, _) => Some("disabled"),
+        (_, true) => Some("managed by a certificate"),
+        _ => None,
+    }
+}
+
+/// Secrets read from a Key Vault
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AzureSecrets {
+    /// the variables to import, by key
+    pub vars: Vec<(String, String)>,
+    /// the secrets that are not imported, and why
+    pub skipped: Vec<SkippedKey>,
+    /// the secrets that could not be read, and the error
+    pub failed: Vec<SkippedKey>,
+}
+
+/// Reads the secrets of the vault, named after their key with `-` instead
+/// of `_`. The disabled secrets, the ones managed by a certificate and the
+/// ones deleted since they were listed are skipped. A secret that cannot be
+/// read does not stop the others from being read.
+pub async fn azure_vars<S: KeyVaultStore>(store: &S) -> Result<AzureSecrets> {
+    let mut secrets = AzureSecrets::default();
+    for item in list_secrets(store).await? {
+        let skipped = |reason: &str| SkippedKey {
+            key: item.name.clone(),
+            reason: reason.to_string(),
+        };
+        if let Some(reason) = skip_reason(&item) {
+            secrets.skipped.push(skipped(reason));
+            continue;
+        }
+
+        let key = secret_key(&item.name);
+        if let Err(e) = check_key(&key) {
+            secrets.skipped.push(skipped(&e.to_string()));
+            continue;
+        }
+        match store.get(&item.name).await {
+            Ok(Some(value)) => secrets.vars.push((key, value)),
+            Ok(None) => secrets.skipped.push(skipped("deleted since it was listed")),
+            Err(e) => secrets.failed.push(skipped(&e.to_string())),
+        }
+    }
+
+    Ok(secrets)
+}
+
+/// Fails with the number of secrets of `failed` that could not be synced
+/// with `vault`
+fn check_failed(failed: &[SkippedKey], vault: &str) -> Result<()> {
+    match failed.len() {
+        0 => Ok(()),
+        n => Err(std_err!(
+            "{} secrets could not be synced with Key Vault {}",
+            n,
+            vault
+        )),
+    }
+}
+
+/// Prints the `skipped` and `failed` secrets
+fn print_skipped<W: Write>(
+    writer: &mut W,
+    skipped: &[SkippedKey],
+    failed: &[SkippedKey],
+) -> Result<()> {
+    for SkippedKey { key, reason } in skipped {
+        writeln!(writer, "skipped {}: {}", key, reason)?;
+    }
+    for SkippedKey { key, reason } in failed {
+        writeln!(writer, "failed {}: {}", key, reason)?;
+    }
+
+    Ok(())
+}
+
+/// Sets the variables read from the vault in `env` in a single transaction,
+/// then prints how many were imported and the secrets that were skipped or
+/// failed. Fails if any did.
+pub async fn import_azure<W: Write, S: KeyVaultStore>(
+    writer: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    store: &S,
+    secrets: &AzureSecrets,
+) -> Result<()> {
+    let AzureSecrets {
+        vars,
+        skipped,
+        failed,
+    } = secrets;
+    let outcome = db.insert_many(env, vars).await?;
+    writeln!(
+        writer,
+        "{} secrets imported from Key Vault {}, {} unchanged, {} skipped",
+        outcome.written,
+        store.display(),
+        outcome.unchanged,
+        skipped.len()
+    )?;
+    print_skipped(writer, skipped, failed)?;
+
+    check_failed(failed, &store.display())
+}
+
+/// What an export writes to a Key Vault
+#[derive(Debug, Default)]
+pub struct AzurePlan {
+    pub plan: ExportPlan,
+    /// name of the secret of each key, the one the vault already has if any
+    names: BTreeMap<String, String>,
+    /// the keys that are not exported, and why
+    pub skipped: Vec<SkippedKey>,
+    /// the secrets that could not be read or written, and the error
+    pub failed: Vec<SkippedKey>,
+}
+
+impl fmt::Display for AzurePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.plan)?;
+        if !self.skipped.is_empty() {
+            write!(f, ", {} skipped", self.skipped.len())?;
+        }
+        match self.failed.len() {
+            0 => Ok(()),
+            failed => write!(f, ", {} failed", failed),
+        }
+    }
+}
+
+/// Compares `vars` with the secrets of the vault, the secrets that envelope
+/// does not have are left alone. Fails if two keys have the same secret
+/// name, such as `DB_HOST` and `DB-HOST`. The keys that cannot be a secret
+/// name are skipped, and so are the ones of secrets that are disabled or
+/// managed by a certificate.
+pub async fn plan_azure_export<S: KeyVaultStore>(
+    store: &S,
+    vars: &BTreeMap<String, String>,
+) -> Result<AzurePlan> {
+    let mut plan = AzurePlan::default();
+    // Key Vault names are case insensitive
+    let mut keys_by_name: BTreeMap<String, String> = BTreeMap::new();
+    for key in vars.keys() {
+        let Some(name) = secret_name(key) else {
+            plan.skipped.push(SkippedKey {
+                key: key.clone(),
+                reason: "not a Key Vault secret name, only letters, digits, - and _ are allowed"
+                    .to_string(),
+            });
+            continue;
+        };
+        if let Some(other) = keys_by_name.insert(name.to_lowercase(), key.clone()) {
+            return Err(std_err!(
+                "{} and {} are both the secret {} in Key Vault, rename one of them",
+                other,
+                key,
+                name
+            ));
+        }
+        plan.names.insert(key.clone(), name);
+    }
+
+    let mut remote = BTreeMap::new();
+    for item in list_secrets(store).await? {
+        let Some(key) = keys_by_name.get(&item.name.to_lowercase()) else {
+            continue;
+        };
+        if let Some(reason) = skip_reason(&item) {
+            plan.names.remove(key);
+            plan.skipped.push(SkippedKey {
+                key: key.clone(),
+                reason: format!("the secret {} is {}", item.name, reason),
+            });
+            continue;
+        }
+        // the secret keeps the name the vault has
+        plan.names.insert(key.clone(), item.name.clone());
+        match store.get(&item.name).await {
+            Ok(Some(value)) => {
+                remote.insert(key.clone(), value);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                plan.names.remove(key);
+                plan.failed.push(SkippedKey {
+                    key: key.clone(),
+                    reason: e.to_string(),
+                });
+            }
+        }
+    }
+
+    let exported = vars
+        .iter()
+        .filter(|(key, _)| plan.names.contains_key(*key))
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    plan.plan = ExportPlan::new(remote, &exported);
+    plan.plan.diff.removed.clear();
+
+    Ok(plan)
+}
+
+/// Writes the secrets of the variables `plan` creates or updates, a secret
+/// that cannot be written does not stop the others. Then prints the counts
+/// of `plan` and the keys that were skipped or failed, and fails if any
+/// did.
+pub async fn export_azure<W: Write, S: KeyVaultStore>(
+    writer: &mut W,
+    store: &S,
+    mut plan: AzurePlan,
+) -> Result<()> {
+    let diff = &plan.plan.diff;
+    let writes = diff
+        .added
+        .iter()
+        .chain(diff.changed.iter().map(|(key, (_, new))| (key, new)));
+    let mut unwritten = Vec::new();
+    for (key, value) in writes {
+        let name = &plan.names[key];
+        let key = key.clone();
+        match store.set(name, value).await {
+            Ok(true) => continue,
+            Ok(false) => plan.skipped.push(SkippedKey {
+                key: key.clone(),
+                reason: format!(
+                    "the secret {} is deleted but recoverable, recover or purge it first",
+                    name
+                ),
+            }),
+            Err(e) => plan.failed.push(SkippedKey {
+                key: key.clone(),
+                reason: e.to_string(),
+            }),
+        }
+        unwritten.push(key);
+    }
+    // the variables that were not written are neither created nor updated
+    for key in unwritten {
+        plan.plan.diff.added.remove(&key);
+        plan.plan.diff.changed.remove(&key);
+    }
+
+    writeln!(writer, "{}", plan)?;
+    print_skipped(writer, &plan.skipped, &plan.failed)?;
+
+    check_failed(&plan.failed, &store.display())
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::db::{test_db, SortOrder};
+    use crate::err;
+
+    /// Key Vault listing its secrets two per page
+    #[derive(Default)]
+    struct FakeVault {
+        /// value of each secret, `None` for the disabled ones
+        secrets: RefCell<BTreeMap<String, Option<String>>>,
+        /// soft-deleted secrets
+        deleted: Vec<String>,
+        /// secrets that fail to be read or written
+        broken: Vec<String>,
+        pages: RefCell<Vec<Option<String>>>,
+    }
+
+    impl FakeVault {
+        fn new<const N: usize>(secrets: [(&str, Option<&str>); N]) -> Self {
+            FakeVault {
+                secrets: RefCell::new(
+                    secrets
+                        .into_iter()
+                        .map(|(k, v)| (k.to_string(), v.map(str::to_string)))
+                        .collect(),
+                ),
+                ..Default::default()
+            }
+        }
+
+        fn value(&self, name: &str) -> Option<String> {
+            self.secrets.borrow().get(name).cloned().flatten()
+        }
+    }
+
+    impl KeyVaultStore for FakeVault {
+        fn display(&self) -> String {
+            "myapp-kv".to_string()
+        }
+
+        async fn list_page(&self, next: Option<&str>) -> Result<SecretPage> {
+            self.pages.borrow_mut().push(next.map(str::to_string));
+            let start: usize = next.map_or(0, |n| n.parse().unwrap());
+            let secrets = self.secrets.borrow();
+            let items = secrets
+                .iter()
+                .skip(start)
+                .take(2)
+                .map(|(name, value)| SecretItem {
+                    name: name.clone(),
+                    enabled: value.is_some(),
+                    managed: name.starts_with("cert"),
+                })
+                .collect();
+            let next = (start + 2 < secrets.len()).then(|| (start + 2).to_string());
+            Ok(SecretPage { items, next })
+        }
+
+        async fn get(&self, name: &str) -> Result<Option<String>> {
+            if self.broken.iter().any(|b| b == name) {
+                return err!("Key Vault replied 500 Internal Server Error for {}", name);
+            }
+            Ok(self
+                .value(name)
+                .filter(|_| !self.deleted.iter().any(|d| d == name)))
+        }
+
+        async fn set(&self, name: &str, value: &str) -> Result<bool> {
+            if self.broken.iter().any(|b| b == name) {
+                return err!("Key Vault replied 500 Internal Server Error for {}", name);
+            }
+            if self.deleted.iter().any(|d| d == name) {
+                return Ok(false);
+            }
+            let mut secrets = self.secrets.borrow_mut();
+            let existing = secrets
+                .keys()
+                .find(|k| k.eq_ignore_ascii_case(name))
+                .cloned();
+            secrets.insert(existing.unwrap_or(name.to_string()), Some(value.into()));
+            Ok(true)
+        }
+    }
+
+    fn vars<const N: usize>(vars: [(&str, &str); N]) -> BTreeMap<String, String> {
+        vars.into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_secret_names() {
+        assert_eq!(Some("DB-PASSWORD".to_string()), secret_name("DB_PASSWORD"));
+        assert_eq!(Some("api-key".to_string()), secret_name("api-key"));
+        assert_eq!(None, secret_name("A.B"));
+        assert_eq!(None, secret_name(""));
+        assert_eq!(None, secret_name(&"A".repeat(MAX_NAME_LEN + 1)));
+
+        assert_eq!("DB_PASSWORD", secret_key("db-password"));
+        assert_eq!(
+            "DB_PASSWORD",
+            secret_key(&secret_name("DB_PASSWORD").unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_azure() {
+        let db = test_db().await;
+        db.insert("prod", "PORT", "80").await.unwrap();
+        let mut vault = FakeVault::new([
+            ("api-key", Some("s3cr3t")),
+            ("cert-tls", Some("pem")),
+            ("db-password", Some("hunter2")),
+            ("gone", Some("x")),
+            ("off", None),
+            ("port", Some("80")),
+            ("slow", Some("x")),
+        ]);
+        vault.deleted = vec!["gone".to_string()];
+        vault.broken = vec!["slow".to_string()];
+
+        let secrets = azure_vars(&vault).await.unwrap();
+        // 7 secrets, 2 per page
+        assert_eq!(
+            vec![
+                None,
+                Some("2".to_string()),
+                Some("4".into()),
+                Some("6".into())
+            ],
+            vault.pages.take()
+        );
+        assert_eq!(
+            vec![
+                ("API_KEY".to_string(), "s3cr3t".to_string()),
+                ("DB_PASSWORD".into(), "hunter2".into()),
+                ("PORT".into(), "80".into()),
+            ],
+            secrets.vars
+        );
+
+        let mut w = Vec::new();
+        let err = import_azure(&mut w, &db, "prod", &vault, &secrets)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "1 secrets could not be synced with Key Vault myapp-kv",
+            err.to_string()
+        );
+        assert_eq!(
+            "2 secrets imported from Key Vault myapp-kv, 1 unchanged, 3 skipped\n\
+            skipped cert-tls: managed by a certificate\n\
+            skipped gone: deleted since it was listed\n\
+            skipped off: disabled\n\
+            failed slow: Key Vault replied 500 Internal Server Error for slow\n",
+            String::from_utf8(w).unwrap()
+        );
+
+        // the secrets that could be read are imported
+        let stored: BTreeMap<String, String> = db
+            .list_var_in_env("prod", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.key, v.value))
+            .collect();
+        assert_eq!(
+            vars([
+                ("API_KEY", "s3cr3t"),
+                ("DB_PASSWORD", "hunter2"),
+                ("PORT", "80")
+            ]),
+            stored
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_azure() {
+        let mut vault = FakeVault::new([
+            ("Db-Host", Some("old")),
+            ("off", None),
+            ("port", Some("80")),
+            ("remote-only", Some("1")),
+        ]);
+        vault.deleted = vec!["TOKEN".to_string()];
+        vault.broken = vec!["BROKEN".to_string()];
+        let local = vars([
+            ("A.B", "1"),
+            ("BROKEN", "x"),
+            ("DB_HOST", "new"),
+            ("NEW_KEY", "n"),
+            ("OFF", "1"),
+            ("PORT", "80"),
+            ("TOKEN", "t"),
+        ]);
+
+        let plan = plan_azure_export(&vault, &local).await.unwrap();
+        assert_eq!(
+            "3 created, 1 updated, 1 unchanged, 2 skipped",
+            plan.to_string()
+        );
+        let mut w = Vec::new();
+        let err = export_azure(&mut w, &vault, plan).await.unwrap_err();
+        assert_eq!(
+            "1 secrets could not be synced with Key Vault myapp-kv",
+            err.to_string()
+        );
+        assert_eq!(
+            "1 created, 1 updated, 1 unchanged, 3 skipped, 1 failed\n\
+            skipped A.B: not a Key Vault secret name, only letters, digits, - and _ are allowed\n\
+            skipped OFF: the secret off is disabled\n\
+            skipped TOKEN: the secret TOKEN is deleted but recoverable, recover or purge it first\n\
+            failed BROKEN: Key Vault replied 500 Internal Server Error for BROKEN\n",
+            String::from_utf8(w).unwrap()
+        );
+
+        // the secrets keep their name, the ones envelope lacks are left alone
+        assert_eq!(Some("new".to_string()), vault.value("Db-Host"));
+        assert_eq!(Some("n".to_string()), vault.value("NEW-KEY"));
+        assert_eq!(Some("1".to_string()), vault.value("remote-only"));
+        assert_eq!(None, vault.value("off"));
+
+        let err = plan_azure_export(&vault, &vars([("DB_HOST", "a"), ("DB-HOST", "b")]))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "DB-HOST and DB_HOST are both the secret DB-HOST in Key Vault, rename one of them",
+            err.to_string()
+        );
+    }
+
+    /// Replies `status` and `body` to a single request, returns the address
+    /// to send it to and the request it received
+    async fn serve_once(
+        status: u16,
+        body: &'static str,
+    ) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let response = format!(
+                "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).to_lowercase()
+        });
+
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn test_client() {
+        let (addr, request) = serve_once(
+            200,
+            r#"{"value": [
+                {"id": "https://myapp-kv.vault.azure.net/secrets/db-password", "attributes": {"enabled": true}},
+                {"id": "https://myapp-kv.vault.azure.net/secrets/tls", "attributes": {"enabled": true}, "managed": true}
+            ], "nextLink": "https://elsewhere.example.com/secrets?$skiptoken=x"}"#,
+        )
+        .await;
+        let client = KeyVaultClient::new(&addr, "t0ken");
+        let page = client.list_page(None).await.unwrap();
+        assert_eq!(
+            vec![
+                SecretItem {
+                    name: "db-password".to_string(),
+                    enabled: true,
+                    managed: false
+                },
+                SecretItem {
+                    name: "tls".to_string(),
+                    enabled: true,
+                    managed: true
+                },
+            ],
+            page.items
+        );
+        let request = request.await.unwrap();
+        assert!(
+            request.starts_with("get /secrets?api-version=7.4 http/1.1\r\n"),
+            "{}",
+            request
+        );
+        assert!(request.contains("authorization: bearer t0ken\r\n"));
+
+        // the token is not sent to another host
+        let err = client.list_page(page.next.as_deref()).await.unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Key Vault returned a next page outside of"),
+            "{}",
+            err
+        );
+
+        let (addr, _) = serve_once(
+            409,
+            r#"{"error": {"code": "Conflict", "message": "Secret TOKEN is currently in a deleted but recoverable state", "innererror": {"code": "ObjectIsDeletedButRecoverable"}}}"#,
+        )
+        .await;
+        let client = KeyVaultClient::new(&addr, "t");
+        assert!(!client.set("TOKEN", "t").await.unwrap());
+
+        let (addr, _) = serve_once(404, r#"{"error": {"code": "SecretNotFound"}}"#).await;
+        let client = KeyVaultClient::new(&addr, "t");
+        assert_eq!(None, client.get("nope").await.unwrap());
+
+        let (addr, _) = serve_once(
+            403,
+            r#"{"error": {"code": "Forbidden", "message": "The user does not have secrets get permission"}}"#,
+        )
+        .await;
+        let client = KeyVaultClient::new(&addr, "t");
+        let err = client.get("db-password").await.unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+    }
+
+    #[test]
+    fn test_keyvault_error() {
+        let body =
+            json!({ "error": { "code": "Unauthorized", "message": "AKV10022: Invalid audience" } });
+        assert_eq!(
+            "Key Vault myapp-kv rejected the access token: AKV10022: Invalid audience",
+            keyvault_error(StatusCode::UNAUTHORIZED, &body, "myapp-kv", None).to_string()
+        );
+
+        let body =
+            json!({ "error": { "code": "Forbidden", "innererror": { "code": "SecretDisabled" } } });
+        assert_eq!(
+            "secret off of Key Vault myapp-kv is disabled",
+            keyvault_error(StatusCode::FORBIDDEN, &body, "myapp-kv", Some("off")).to_string()
+        );
+
+        let body = json!({ "error": { "code": "Throttled", "message": "slow down" } });
+        assert_eq!(
+            "Key Vault replied 429 Too Many Requests for the secrets of Key Vault myapp-kv: slow down",
+            keyvault_error(StatusCode::TOO_MANY_REQUESTS, &body, "myapp-kv", None).to_string()
+        );
+    }
+}
@@ -1,17 +1,291 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::io::{Result, Write};
+use std::path::Path;
 
-use crate::db::EnvelopeDb;
+use serde::Serialize;
+
+use crate::config::{Unused, CONFIG_FILE};
+use crate::db::{EnvelopeDb, SortOrder};
+use crate::dotenv::from_dotenv;
+use crate::validate::{Schema, Violation};
+use crate::{err, style};
+
+use super::{current_vars, referenced_keys, Changes, Output};
 
 pub async fn check<W: Write>(w: &mut W, db: &EnvelopeDb) -> Result<()> {
     let res = check_active_envs(db).await?;
     for env in res {
-        writeln!(w, "{}", env)?;
+        writeln!(w, "{}", style::paint(style::ENV, env))?;
     }
 
     Ok(())
 }
 
+/// Problems found in an environment compared to an example file
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ExampleReport {
+    pub env: String,
+    /// keys of the example that the environment does not set
+    pub missing: Vec<String>,
+    /// keys of the example set to an empty value
+    pub empty: Vec<String>,
+    /// keys of the environment that are not in the example, only filled when
+    /// checking strictly
+    pub extra: Vec<String>,
+}
+
+impl ExampleReport {
+    fn problems(&self) -> usize {
+        self.missing.len() + self.empty.len() + self.extra.len()
+    }
+}
+
+/// Compares every env of `envs` with the keys of the dotenv file `example`,
+/// whose values are ignored. Keys set in an env but not in the example are
+/// only reported when `strict` is set.
+pub async fn check_against_example(
+    db: &EnvelopeDb,
+    envs: &[String],
+    example: &str,
+    strict: bool,
+) -> Result<Vec<ExampleReport>> {
+    let required: BTreeSet<String> = from_dotenv(example)
+        .into_iter()
+        .map(|entry| entry.key.to_ascii_uppercase())
+        .collect();
+
+    let mut reports = Vec::new();
+    for env in envs {
+        db.check_env_exists(env).await?;
+
+        let mut report = ExampleReport {
+            env: env.clone(),
+            ..Default::default()
+        };
+        let mut seen = BTreeSet::new();
+        for row in db.list_var_in_env(env, SortOrder::Asc).await? {
+            match required.contains(&row.key) {
+                true if row.value.is_empty() => report.empty.push(row.key.clone()),
+                true => {}
+                false if strict => report.extra.push(row.key.clone()),
+                false => {}
+            }
+            seen.insert(row.key);
+        }
+        report.missing = required.difference(&seen).cloned().collect();
+
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
+
+/// Writes the problems found by [`check_against_example`], one per line,
+/// then fails if there are any
+pub async fn check_example<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    envs: &[String],
+    example: &str,
+    strict: bool,
+    output: Output,
+) -> Result<()> {
+    let reports = check_against_example(db, envs, example, strict).await?;
+    output.write(w, &reports, |w, reports| {
+        for report in reports {
+            let env = style::paint(style::ENV, &report.env);
+            if report.problems() == 0 {
+                writeln!(w, "{}: ok", env)?;
+            }
+            for key in &report.missing {
+                writeln!(w, "{}: {} is missing", env, key)?;
+            }
+            for key in &report.empty {
+                writeln!(w, "{}: {} is empty", env, key)?;
+            }
+            for key in &report.extra {
+                writeln!(w, "{}: {} is not in the example", env, key)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    let problems: usize = reports.iter().map(ExampleReport::problems).sum();
+    let failed = reports.iter().filter(|r| r.problems() > 0).count();
+    match problems {
+        0 => Ok(()),
+        1 => err!(
+            "1 problem found in {} of {} environments",
+            failed,
+            reports.len()
+        ),
+        _ => err!(
+            "{} problems found in {} of {} environments",
+            problems,
+            failed,
+            reports.len()
+        ),
+    }
+}
+
+/// Variables of an environment breaking the schema of the project config
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct SchemaReport {
+    pub env: String,
+    pub violations: Vec<Violation>,
+}
+
+/// Validates every env of `envs` against `schema`, writes the violations one
+/// per line then fails if there are any
+pub async fn check_schema<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    envs: &[String],
+    schema: &Schema,
+    output: Output,
+) -> Result<()> {
+    if schema.is_empty() {
+        return err!("no [schema] in {}", CONFIG_FILE);
+    }
+
+    let mut reports = Vec::new();
+    for env in envs {
+        db.check_env_exists(env).await?;
+
+        let vars = current_vars(db, env).await?;
+        reports.push(SchemaReport {
+            env: env.clone(),
+            violations: schema.check_env(env, &vars),
+        });
+    }
+
+    output.write(w, &reports, |w, reports| {
+        for report in reports {
+            let env = style::paint(style::ENV, &report.env);
+            if report.violations.is_empty() {
+                writeln!(w, "{}: ok", env)?;
+            }
+            for violation in &report.violations {
+                writeln!(w, "{}: {}", env, violation)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    let problems: usize = reports.iter().map(|r| r.violations.len()).sum();
+    let failed = reports.iter().filter(|r| !r.violations.is_empty()).count();
+    match problems {
+        0 => Ok(()),
+        1 => err!(
+            "1 problem found in {} of {} environments",
+            failed,
+            reports.len()
+        ),
+        _ => err!(
+            "{} problems found in {} of {} environments",
+            problems,
+            failed,
+            reports.len()
+        ),
+    }
+}
+
+/// Variables of an environment that nothing in the project reads
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct UnusedReport {
+    pub env: String,
+    pub unused: Vec<String>,
+}
+
+/// Finds the keys of every env of `envs` that are neither referenced by the
+/// files of `root`, see [`referenced_keys`], nor listed in the dotenv
+/// `example`. The keys `allowed` by the config are left out.
+pub async fn find_unused(
+    db: &EnvelopeDb,
+    envs: &[String],
+    root: &Path,
+    example: Option<&str>,
+    allowed: &Unused,
+) -> Result<Vec<UnusedReport>> {
+    let mut used = referenced_keys(root)?;
+    used.extend(
+        from_dotenv(example.unwrap_or_default())
+            .into_iter()
+            .map(|entry| entry.key.to_ascii_uppercase()),
+    );
+
+    let mut reports = Vec::new();
+    for env in envs {
+        db.check_env_exists(env).await?;
+
+        let unused = current_vars(db, env)
+            .await?
+            .into_keys()
+            .filter(|key| !used.contains(key) && !allowed.allows(key))
+            .collect();
+        reports.push(UnusedReport {
+            env: env.clone(),
+            unused,
+        });
+    }
+
+    Ok(reports)
+}
+
+/// Writes the unused keys found by [`find_unused`], one per line, and
+/// returns them. Never fails because of them, the report is advisory.
+pub async fn check_unused<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    envs: &[String],
+    root: &Path,
+    example: Option<&str>,
+    allowed: &Unused,
+    output: Output,
+) -> Result<Vec<UnusedReport>> {
+    let reports = find_unused(db, envs, root, example, allowed).await?;
+    output.write(w, &reports, |w, reports| {
+        for report in reports {
+            let env = style::paint(style::ENV, &report.env);
+            if report.unused.is_empty() {
+                writeln!(w, "{}: ok", env)?;
+            }
+            for key in &report.unused {
+                writeln!(w, "{}: {} is unused", env, key)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    Ok(reports)
+}
+
+/// Fails if the values that `changes` sets break `schema`, listing every
+/// violation
+pub fn verify_changes(schema: &Schema, changes: &Changes) -> Result<()> {
+    let violations: Vec<String> = changes
+        .iter()
+        .flat_map(|(env, diff)| {
+            schema
+                .check_diff(env, diff)
+                .into_iter()
+                .map(move |violation| format!("  {}: {}", env, violation))
+        })
+        .collect();
+
+    let count = match violations.len() {
+        0 => return Ok(()),
+        1 => "1 variable breaks".to_string(),
+        n => format!("{} variables break", n),
+    };
+    err!(
+        "{} the schema of {}, use --no-verify to write anyway\n{}",
+        count,
+        CONFIG_FILE,
+        violations.join("\n")
+    )
+}
+
 async fn check_active_envs(db: &EnvelopeDb) -> Result<HashSet<String>> {
     let rows = db.get_all_env_vars().await?;
     // dumb implementation
@@ -45,7 +319,233 @@ async fn check_active_envs(db: &EnvelopeDb) -> Result<HashSet<String>> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::db::test_db;
+    use crate::db::{test_db, EnvDiff};
+
+    #[tokio::test]
+    async fn test_check_against_example() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value)
+            VALUES
+            ('dev', 'DATABASE_URL', 'postgres://'),
+            ('dev', 'DEBUG', ''),
+            ('dev', 'LEGACY', 'x'),
+            ('prod', 'DATABASE_URL', 'postgres://'),
+            ('prod', 'DEBUG', 'false'),
+            ('prod', 'API_KEY', 'secret');",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let example = "# required\nDATABASE_URL=\ndebug=true\nAPI_KEY=changeme\n";
+        let envs = ["dev".to_string(), "prod".to_string()];
+        let reports = check_against_example(&db, &envs, example, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![
+                ExampleReport {
+                    env: "dev".into(),
+                    missing: vec!["API_KEY".into()],
+                    empty: vec!["DEBUG".into()],
+                    extra: vec![],
+                },
+                ExampleReport {
+                    env: "prod".into(),
+                    ..Default::default()
+                },
+            ],
+            reports
+        );
+
+        let reports = check_against_example(&db, &envs[..1], example, true)
+            .await
+            .unwrap();
+        assert_eq!(vec!["LEGACY".to_string()], reports[0].extra);
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        let err = check_example(&mut output, &db, &envs, example, true, Output::Text)
+            .await
+            .unwrap_err();
+        assert_eq!("3 problems found in 1 of 2 environments", err.to_string());
+        assert_eq!(
+            "dev: API_KEY is missing\ndev: DEBUG is empty\ndev: LEGACY is not in the example\nprod: ok\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let mut output = Vec::new();
+        check_example(&mut output, &db, &envs[1..], example, true, Output::Json)
+            .await
+            .unwrap();
+        let reports: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!([{"env": "prod", "missing": [], "empty": [], "extra": []}]),
+            reports
+        );
+
+        assert!(check_against_example(&db, &["test".into()], example, false)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_unused() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value)
+            VALUES
+            ('dev', 'DATABASE_URL', 'postgres://'),
+            ('dev', 'DEBUG', 'true'),
+            ('dev', 'LEGACY', 'x'),
+            ('dev', 'PORT', '80'),
+            ('prod', 'DATABASE_URL', 'postgres://'),
+            ('prod', 'AWS_REGION', 'eu-west-1');",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let root = std::env::temp_dir().join(format!("envelope-unused-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("main.py"), "os.getenv('DATABASE_URL')\n").unwrap();
+
+        let envs = ["dev".to_string(), "prod".to_string()];
+        let allowed = Unused {
+            allow: vec!["aws_*".into()],
+        };
+        let mut output = anstream::StripStream::new(Vec::new());
+        let reports = check_unused(
+            &mut output,
+            &db,
+            &envs,
+            &root,
+            Some("debug=\n"),
+            &allowed,
+            Output::Text,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            vec![
+                UnusedReport {
+                    env: "dev".into(),
+                    unused: vec!["LEGACY".into(), "PORT".into()],
+                },
+                UnusedReport {
+                    env: "prod".into(),
+                    unused: vec![],
+                },
+            ],
+            reports
+        );
+        assert_eq!(
+            "dev: LEGACY is unused\ndev: PORT is unused\nprod: ok\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let reports = find_unused(&db, &envs[1..], &root, None, &Unused::default())
+            .await
+            .unwrap();
+        assert_eq!(vec!["AWS_REGION".to_string()], reports[0].unused);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_check_schema() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value)
+            VALUES
+            ('dev', 'PORT', 'eighty'),
+            ('prod', 'PORT', '80'),
+            ('prod', 'DATABASE_URL', 'postgres://db');",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let schema = crate::config::Config::parse(
+            "[schema.required]\nprod = [\"DATABASE_URL\"]\n[schema.rules]\nport = \"integer\"",
+        )
+        .unwrap()
+        .schema;
+        let envs = ["dev".to_string(), "prod".to_string()];
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        let err = check_schema(&mut output, &db, &envs, &schema, Output::Text)
+            .await
+            .unwrap_err();
+        assert_eq!("1 problem found in 1 of 2 environments", err.to_string());
+        assert_eq!(
+            "dev: PORT is not an integer (rule: integer)\nprod: ok\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let mut output = Vec::new();
+        check_schema(&mut output, &db, &envs[1..], &schema, Output::Json)
+            .await
+            .unwrap();
+        let reports: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!([{"env": "prod", "violations": []}]),
+            reports
+        );
+
+        let err = check_schema(
+            &mut Vec::new(),
+            &db,
+            &envs,
+            &Schema::default(),
+            Output::Text,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!("no [schema] in envelope.toml", err.to_string());
+    }
+
+    #[test]
+    fn test_verify_changes() {
+        let schema = crate::config::Config::parse(
+            "[schema.rules]\nPORT = \"integer\"\nLEVEL = { one-of = [\"info\"] }\n\
+            [schema.key_pattern]\nprod = \"[A-Z]+\"",
+        )
+        .unwrap()
+        .schema;
+        let diff = |key: &str, value: &str| {
+            EnvDiff::between(
+                Default::default(),
+                [(key.to_string(), value.to_string())].into(),
+            )
+        };
+
+        let changes = Changes::from([("dev".into(), diff("PORT", "80"))]);
+        assert!(verify_changes(&schema, &changes).is_ok());
+
+        let changes = Changes::from([
+            ("dev".into(), diff("PORT", "x")),
+            (
+                "prod".into(),
+                EnvDiff::between(
+                    Default::default(),
+                    [
+                        ("LEVEL".to_string(), "debug".to_string()),
+                        ("LOG_LEVEL".to_string(), "info".to_string()),
+                    ]
+                    .into(),
+                ),
+            ),
+        ]);
+        let err = verify_changes(&schema, &changes).unwrap_err();
+        assert_eq!(
+            "3 variables break the schema of envelope.toml, use --no-verify to write anyway\n  \
+            dev: PORT is not an integer (rule: integer)\n  \
+            prod: LOG_LEVEL does not match [A-Z]+ (rule: key-pattern)\n  \
+            prod: LEVEL is not one of info (rule: one-of)",
+            err.to_string()
+        );
+    }
 
     #[tokio::test]
     async fn test_check_multiple_active_subset() {
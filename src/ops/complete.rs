@@ -0,0 +1,338 @@
+use std::collections::BTreeSet;
+use std::io::{Result, Write};
+
+use clap::{Command, ValueEnum};
+use clap_complete::Shell;
+
+use crate::db::{EnvelopeDb, SortOrder};
+
+/// Arguments whose values are environment names
+const ENV_ARGS: &[&str] = &["env", "envs", "source", "also"];
+
+/// Arguments whose values are keys
+const KEY_ARGS: &[&str] = &["key"];
+
+/// Values listed by `envelope __complete`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Completion {
+    /// names of the environments
+    Envs,
+    /// keys of an environment
+    Keys,
+}
+
+/// Writes the candidates of `kind`, one per line. Keys are only listed when
+/// `env` is given.
+pub async fn complete<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    kind: Completion,
+    env: Option<&str>,
+) -> Result<()> {
+    let candidates: Vec<String> = match (kind, env) {
+        (Completion::Envs, _) => db
+            .list_environments()
+            .await?
+            .into_iter()
+            .map(|e| e.env)
+            .collect(),
+        (Completion::Keys, Some(env)) => db
+            .list_var_in_env(env, SortOrder::Asc)
+            .await?
+            .into_iter()
+            .map(|r| r.key)
+            .collect(),
+        (Completion::Keys, None) => Vec::new(),
+    };
+
+    for candidate in candidates {
+        writeln!(w, "{}", candidate)?;
+    }
+
+    Ok(())
+}
+
+/// Where environment names and keys are expected on the command line of
+/// `cmd`, options are written `subcommand:--option` so that the same flag
+/// can mean different things in different subcommands
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Completable {
+    /// subcommands whose first positional argument is an environment
+    env_commands: BTreeSet<String>,
+    /// subcommands whose second positional argument is a key
+    key_commands: BTreeSet<String>,
+    env_options: BTreeSet<String>,
+    key_options: BTreeSet<String>,
+    /// every option followed by a value
+    value_options: BTreeSet<String>,
+}
+
+impl Completable {
+    fn new(cmd: &Command) -> Self {
+        let mut cmd = cmd.clone();
+        // propagates the global options to the subcommands
+        cmd.build();
+
+        let mut completable = Completable::default();
+        completable.add_options("", &cmd);
+        for sub in cmd.get_subcommands() {
+            let name = sub.get_name();
+            completable.add_options(name, sub);
+
+            let positionals: Vec<&str> =
+                sub.get_positionals().map(|a| a.get_id().as_str()).collect();
+            if positionals.first().is_some_and(|id| ENV_ARGS.contains(id)) {
+                completable.env_commands.insert(name.to_string());
+            }
+            if positionals.get(1).is_some_and(|id| KEY_ARGS.contains(id)) {
+                completable.key_commands.insert(name.to_string());
+            }
+        }
+
+        completable
+    }
+
+    fn add_options(&mut self, name: &str, cmd: &Command) {
+        for arg in cmd.get_opts() {
+            if !arg.get_action().takes_values() {
+                continue;
+            }
+
+            let id = arg.get_id().as_str();
+            let flags = arg
+                .get_short()
+                .map(|s| format!("{}:-{}", name, s))
+                .into_iter()
+                .chain(arg.get_long().map(|l| format!("{}:--{}", name, l)));
+            for flag in flags {
+                if ENV_ARGS.contains(&id) {
+                    self.env_options.insert(flag.clone());
+                }
+                if KEY_ARGS.contains(&id) {
+                    self.key_options.insert(flag.clone());
+                }
+                self.value_options.insert(flag);
+            }
+        }
+    }
+
+    /// Fills the placeholders of `script` with the lists
+    fn fill(&self, script: &str) -> String {
+        let join = |set: &BTreeSet<String>| set.iter().cloned().collect::<Vec<_>>().join(" ");
+        script
+            .replace("@ENV_COMMANDS@", &join(&self.env_commands))
+            .replace("@KEY_COMMANDS@", &join(&self.key_commands))
+            .replace("@ENV_OPTIONS@", &join(&self.env_options))
+            .replace("@KEY_OPTIONS@", &join(&self.key_options))
+            .replace("@VALUE_OPTIONS@", &join(&self.value_options))
+    }
+}
+
+/// Walks the words before the cursor, shared by bash and zsh. Leaves the
+/// subcommand in `cmd`, the first environment in `env`, the positional
+/// arguments in `positionals` and what the previous option expects in
+/// `expect`.
+const POSIX_WALK: &str = r#"    local env_commands=" @ENV_COMMANDS@ " key_commands=" @KEY_COMMANDS@ "
+    local env_options=" @ENV_OPTIONS@ " key_options=" @KEY_OPTIONS@ "
+    local value_options=" @VALUE_OPTIONS@ "
+    local cmd="" env="" expect="" kind="" word
+    local -a positionals=()
+    for word in "${_envelope_words[@]}"; do
+        if [[ -n $expect ]]; then
+            [[ $expect == env && -z $env ]] && env=$word
+            expect=""
+        elif [[ $env_options == *" $cmd:$word "* ]]; then
+            expect=env
+        elif [[ $key_options == *" $cmd:$word "* ]]; then
+            expect=key
+        elif [[ $value_options == *" $cmd:$word "* ]]; then
+            expect=value
+        elif [[ $word == -* ]]; then
+            :
+        elif [[ -z $cmd ]]; then
+            cmd=$word
+        else
+            positionals+=("$word")
+            [[ -z $env && $env_commands == *" $cmd "* ]] && env=$word
+        fi
+    done
+
+    if [[ $expect == env ]]; then
+        kind=envs
+    elif [[ $expect == key ]]; then
+        kind=keys
+    elif [[ -z $expect && ${#positionals[@]} -eq 0 && $env_commands == *" $cmd "* ]]; then
+        kind=envs
+    elif [[ -z $expect && ${#positionals[@]} -eq 1 && $key_commands == *" $cmd "* ]]; then
+        kind=keys
+    fi
+"#;
+
+const BASH: &str = r#"
+_envelope_dynamic() {
+    local -a _envelope_words=("${COMP_WORDS[@]:1:COMP_CWORD-1}")
+@WALK@
+    if [[ -n $kind ]]; then
+        local candidates
+        candidates=$(envelope __complete "$kind" ${env:+"$env"} 2>/dev/null)
+        if [[ -n $candidates ]]; then
+            COMPREPLY=($(compgen -W "$candidates" -- "${COMP_WORDS[COMP_CWORD]}"))
+            return 0
+        fi
+    fi
+
+    _envelope "$@"
+}
+
+complete -F _envelope_dynamic -o bashdefault -o default envelope
+"#;
+
+const ZSH: &str = r#"
+_envelope_dynamic() {
+    local -a _envelope_words=("${(@)words[2,CURRENT-1]}")
+@WALK@
+    if [[ -n $kind ]]; then
+        local -a candidates
+        candidates=(${(f)"$(envelope __complete "$kind" ${env:+"$env"} 2>/dev/null)"})
+        if (( ${#candidates} )); then
+            compadd -a candidates
+            return 0
+        fi
+    fi
+
+    _envelope "$@"
+}
+
+compdef _envelope_dynamic envelope
+"#;
+
+const FISH: &str = r#"
+function __envelope_dynamic
+    set -l env_commands @ENV_COMMANDS@
+    set -l key_commands @KEY_COMMANDS@
+    set -l env_options @ENV_OPTIONS@
+    set -l key_options @KEY_OPTIONS@
+    set -l value_options @VALUE_OPTIONS@
+    set -l cmd ''
+    set -l env
+    set -l expect ''
+    set -l positionals
+    set -l words (commandline -opc)
+    set -e words[1]
+    for word in $words
+        if test -n "$expect"
+            if test "$expect" = env; and test -z "$env"
+                set env $word
+            end
+            set expect ''
+        else if contains -- "$cmd:$word" $env_options
+            set expect env
+        else if contains -- "$cmd:$word" $key_options
+            set expect key
+        else if contains -- "$cmd:$word" $value_options
+            set expect value
+        else if string match -q -- '-*' $word
+            continue
+        else if test -z "$cmd"
+            set cmd $word
+        else
+            set -a positionals $word
+            if test -z "$env"; and contains -- $cmd $env_commands
+                set env $word
+            end
+        end
+    end
+
+    if test "$expect" = env
+        envelope __complete envs 2>/dev/null
+    else if test "$expect" = key
+        envelope __complete keys $env 2>/dev/null
+    else if test -z "$expect"; and test (count $positionals) -eq 0; and contains -- "$cmd" $env_commands
+        envelope __complete envs 2>/dev/null
+    else if test -z "$expect"; and test (count $positionals) -eq 1; and contains -- "$cmd" $key_commands
+        envelope __complete keys $env 2>/dev/null
+    end
+end
+
+complete -c envelope -a '(__envelope_dynamic)'
+"#;
+
+/// Returns the script that completes environment names and keys of `cmd` in
+/// `shell` by calling `envelope __complete`, it must be loaded after the
+/// static completions generated by clap. Only bash, zsh and fish are
+/// supported.
+pub fn dynamic_completion(shell: Shell, cmd: &Command) -> Option<String> {
+    let script = match shell {
+        Shell::Bash => BASH.replace("@WALK@", POSIX_WALK),
+        Shell::Zsh => ZSH.replace("@WALK@", POSIX_WALK),
+        Shell::Fish => FISH.to_string(),
+        _ => return None,
+    };
+
+    Some(Completable::new(cmd).fill(&script))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+    use clap::{Arg, ArgAction};
+
+    async fn output(db: &EnvelopeDb, kind: Completion, env: Option<&str>) -> String {
+        let mut buf: Vec<u8> = Vec::new();
+        complete(&mut buf, db, kind, env).await.unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_complete() {
+        let db = test_db().await;
+        db.insert("dev", "B", "1").await.unwrap();
+        db.insert("dev", "A", "1").await.unwrap();
+        db.insert("prod", "C", "1").await.unwrap();
+
+        assert_eq!("dev\nprod\n", output(&db, Completion::Envs, None).await);
+        assert_eq!("A\nB\n", output(&db, Completion::Keys, Some("dev")).await);
+        assert_eq!("", output(&db, Completion::Keys, None).await);
+        assert_eq!("", output(&db, Completion::Keys, Some("none")).await);
+    }
+
+    #[test]
+    fn test_completable() {
+        let cmd = Command::new("envelope")
+            .arg(Arg::new("color").long("color").global(true))
+            .subcommand(
+                Command::new("add")
+                    .arg(Arg::new("env"))
+                    .arg(Arg::new("key"))
+                    .arg(Arg::new("stdin").short('s').action(ArgAction::SetTrue)),
+            )
+            .subcommand(
+                Command::new("delete")
+                    .arg(Arg::new("env").short('e').long("env"))
+                    .arg(Arg::new("key").short('k').long("key")),
+            )
+            .subcommand(Command::new("init"));
+
+        let set = |items: &[&str]| items.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            Completable {
+                env_commands: set(&["add"]),
+                key_commands: set(&["add"]),
+                env_options: set(&["delete:-e", "delete:--env"]),
+                key_options: set(&["delete:-k", "delete:--key"]),
+                value_options: set(&[
+                    ":--color",
+                    "add:--color",
+                    "delete:--color",
+                    "delete:-e",
+                    "delete:--env",
+                    "delete:-k",
+                    "delete:--key",
+                    "init:--color",
+                ]),
+            },
+            Completable::new(&cmd)
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::io::{self, BufRead, IsTerminal, Result, Write};
+
+use crate::err;
+
+/// Whether the user can answer a prompt, stdin and stdout must both be
+/// terminals
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Asks the user to type `answer` before doing what `message` describes,
+/// `yes` skips the question. Fails if the answer does not match, or if the
+/// user cannot be asked and `yes` is not set.
+pub fn confirm<R: BufRead, W: Write>(
+    reader: &mut R,
+    w: &mut W,
+    interactive: bool,
+    yes: bool,
+    message: &str,
+    answer: &str,
+) -> Result<()> {
+    if yes {
+        return Ok(());
+    }
+
+    if !interactive {
+        return err!("refusing to run destructively without --yes in non-interactive mode");
+    }
+
+    write!(w, "{}, type {} to confirm: ", message, answer)?;
+    w.flush()?;
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    match line.trim_end_matches(['\r', '\n']) == answer {
+        true => Ok(()),
+        false => err!("aborted, the answer did not match {}", answer),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ask(input: &str, interactive: bool, yes: bool) -> (Result<()>, String) {
+        let mut output: Vec<u8> = Vec::new();
+        let res = confirm(
+            &mut input.as_bytes(),
+            &mut output,
+            interactive,
+            yes,
+            "this will remove 2 variables from 'prod'",
+            "prod",
+        );
+        (res, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_confirm() {
+        let (res, output) = ask("prod\n", true, false);
+        assert!(res.is_ok());
+        assert_eq!(
+            "this will remove 2 variables from 'prod', type prod to confirm: ",
+            output
+        );
+
+        let (res, _) = ask("yes\n", true, false);
+        assert!(res.is_err());
+        let (res, _) = ask("", true, false);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_confirm_yes() {
+        let (res, output) = ask("", true, true);
+        assert!(res.is_ok());
+        assert!(output.is_empty());
+
+        let (res, _) = ask("", false, true);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_confirm_non_interactive() {
+        let (res, output) = ask("prod\n", false, false);
+        assert_eq!(
+            "refusing to run destructively without --yes in non-interactive mode",
+            res.unwrap_err().to_string()
+        );
+        assert!(output.is_empty());
+    }
+}
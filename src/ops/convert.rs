@@ -0,0 +1,136 @@
+use std::io::{Result, Write};
+use std::path::Path;
+
+use crate::db::EnvelopeDb;
+use crate::error::EnvelopeError;
+use crate::store::JsonStore;
+
+/// Copies every version of the sqlite database `db` to the JSON file at
+/// `path`, which must not exist. Fails if `db` holds something the JSON file
+/// cannot, rather than leaving it behind.
+pub async fn convert_to_json<W: Write>(w: &mut W, db: &EnvelopeDb, path: &Path) -> Result<()> {
+    ensure_missing(path)?;
+    let sqlite_only = db.sqlite_only_data().await?;
+    if !sqlite_only.is_empty() {
+        return Err(EnvelopeError::Constraint(format!(
+            "the {} of the database cannot be stored in JSON, remove them to convert it",
+            sqlite_only.join(", ")
+        ))
+        .into());
+    }
+
+    let versions = db.versions().await?;
+    let count = versions.len();
+    JsonStore::open(path).write_versions(versions).await?;
+
+    writeln!(w, "converted {} versions to {}", count, path.display())
+}
+
+/// Copies every version of the JSON file `store` to a new sqlite database at
+/// `path`, which must not exist
+pub async fn convert_to_sqlite<W: Write>(w: &mut W, store: &JsonStore, path: &Path) -> Result<()> {
+    ensure_missing(path)?;
+    let versions = store.versions()?;
+    let db = EnvelopeDb::open(path).await?;
+    db.import_versions(&versions).await?;
+
+    writeln!(
+        w,
+        "converted {} versions to {}",
+        versions.len(),
+        path.display()
+    )
+}
+
+/// The converted database is written to a new file, an existing one is
+/// never merged into nor replaced
+fn ensure_missing(path: &Path) -> Result<()> {
+    match path.exists() {
+        true => Err(EnvelopeError::Conflict(format!("{} already exists", path.display())).into()),
+        false => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+    use crate::store::Store;
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at, expires_at)
+            VALUES
+            ('dev', 'HOST', 'old', 1, NULL),
+            ('dev', 'HOST', 'localhost', 2, NULL),
+            ('dev', 'HOST', NULL, 3, NULL),
+            ('dev', 'TOKEN', 't', 1, 4102444800),
+            ('prod', 'PORT', '443', 1, NULL);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let dir = std::env::temp_dir().join(format!("envelope-convert-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = dir.join(".envelope.json");
+        let sqlite = dir.join(".envelope");
+
+        let mut output = Vec::new();
+        convert_to_json(&mut output, &db, &json).await.unwrap();
+        assert_eq!(
+            format!("converted 5 versions to {}\n", json.display()),
+            String::from_utf8(output).unwrap()
+        );
+        let store = JsonStore::open(&json);
+        assert_eq!(db.versions().await.unwrap(), store.versions().unwrap());
+        assert_eq!(
+            db.fingerprint("dev").await.unwrap(),
+            store.fingerprint("dev").await.unwrap()
+        );
+
+        convert_to_sqlite(&mut Vec::new(), &store, &sqlite)
+            .await
+            .unwrap();
+        let converted = EnvelopeDb::open(&sqlite).await.unwrap();
+        assert_eq!(
+            db.versions().await.unwrap(),
+            converted.versions().await.unwrap()
+        );
+
+        // neither file is replaced
+        let err = convert_to_sqlite(&mut Vec::new(), &store, &sqlite)
+            .await
+            .unwrap_err();
+        assert_eq!(std::io::ErrorKind::AlreadyExists, err.kind());
+        let err = convert_to_json(&mut Vec::new(), &db, &json)
+            .await
+            .unwrap_err();
+        assert_eq!(std::io::ErrorKind::AlreadyExists, err.kind());
+        drop(converted);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_only_data() {
+        let db = test_db().await;
+        db.insert("dev", "HOST", "localhost").await.unwrap();
+        db.set_description("dev", "HOST", "where to connect")
+            .await
+            .unwrap();
+        db.lock_env("dev").await.unwrap();
+
+        let path =
+            std::env::temp_dir().join(format!("envelope-convert-meta-{}.json", std::process::id()));
+        let err = convert_to_json(&mut Vec::new(), &db, &path)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "the descriptions, locks of the database cannot be stored in JSON, remove them to convert it",
+            err.to_string()
+        );
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,64 @@
+use std::io::{Result, Write};
+
+use crate::db::EnvelopeDb;
+use crate::style;
+
+/// Merges the keys of `env` that differ only by case, see
+/// [`EnvelopeDb::dedupe_case`], then writes the merged keys
+pub async fn dedupe_case<W: Write>(w: &mut W, db: &EnvelopeDb, env: &str) -> Result<()> {
+    db.check_env_exists(env).await?;
+
+    let merged = db.dedupe_case(env).await?;
+    let env = style::paint(style::ENV, env);
+    if merged.is_empty() {
+        return writeln!(w, "no keys of {} differ only by case", env);
+    }
+
+    for duplicate in merged {
+        writeln!(
+            w,
+            "{}: merged {} into {}",
+            env,
+            duplicate.variants.join(", "),
+            duplicate.key
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    #[tokio::test]
+    async fn test_dedupe_case() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'ApiKey', 'old', 1),
+            ('dev', 'APIKEY', 'new', 2),
+            ('dev', 'HOST', 'localhost', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        dedupe_case(&mut output, &db, "dev").await.unwrap();
+        assert_eq!(
+            "dev: merged APIKEY, ApiKey into APIKEY\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        dedupe_case(&mut output, &db, "dev").await.unwrap();
+        assert_eq!(
+            "no keys of dev differ only by case\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        assert!(dedupe_case(&mut Vec::new(), &db, "prod").await.is_err());
+    }
+}
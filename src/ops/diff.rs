@@ -0,0 +1,100 @@
+use std::io::{Result, Write};
+
+use crate::db::{EnvDiff, EnvelopeDb};
+use crate::style::{self, ADDED, REMOVED};
+
+use super::Output;
+
+/// Writes `diff` in a patch-like format, `+` for added variables, `-` for
+/// removed ones and `~` for changed ones
+pub fn print_diff<W: Write>(w: &mut W, diff: &EnvDiff) -> Result<()> {
+    for (key, value) in &diff.added {
+        writeln!(w, "{}", style::paint(ADDED, format!("+ {}={}", key, value)))?;
+    }
+
+    for (key, value) in &diff.removed {
+        writeln!(
+            w,
+            "{}",
+            style::paint(REMOVED, format!("- {}={}", key, value))
+        )?;
+    }
+
+    for (key, (old, new)) in &diff.changed {
+        writeln!(
+            w,
+            "~ {}={} -> {}",
+            key,
+            style::paint(REMOVED, old),
+            style::paint(ADDED, new)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Shows what importing the dotenv `contents` into `env` would change
+pub async fn diff_dotenv<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    contents: &str,
+    output: Output,
+) -> Result<()> {
+    let diff = db.diff_with_dotenv(env, contents).await?;
+    output.write(w, &diff, |w, diff| match diff.is_empty() {
+        true => writeln!(w, "no changes"),
+        false => print_diff(w, diff),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_print_diff() {
+        let diff = EnvDiff {
+            added: BTreeMap::from([("A".into(), "1".into())]),
+            removed: BTreeMap::from([("B".into(), "2".into())]),
+            changed: BTreeMap::from([("C".into(), ("3".into(), "4".into()))]),
+        };
+
+        let mut output: Vec<u8> = Vec::new();
+        print_diff(&mut output, &diff).unwrap();
+        assert_eq!(
+            "\x1b[32m+ A=1\x1b[0m\n\x1b[31m- B=2\x1b[0m\n~ C=\x1b[31m3\x1b[0m -> \x1b[32m4\x1b[0m\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        print_diff(&mut output, &diff).unwrap();
+        assert_eq!(
+            "+ A=1\n- B=2\n~ C=3 -> 4\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_diff_dotenv_json() {
+        let db = crate::db::test_db().await;
+        db.insert("dev", "A", "1").await.unwrap();
+        db.insert("dev", "B", "2").await.unwrap();
+
+        let mut output: Vec<u8> = Vec::new();
+        diff_dotenv(&mut output, &db, "dev", "B=3\nC=4", Output::Json)
+            .await
+            .unwrap();
+
+        let diff: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "added": {"C": "4"},
+                "removed": {"A": "1"},
+                "changed": {"B": {"old": "2", "new": "3"}},
+            }),
+            diff
+        );
+    }
+}
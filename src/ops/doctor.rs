@@ -0,0 +1,628 @@
+use std::fs;
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anstyle::AnsiColor;
+
+use crate::db::{find_db, Diagnostics, EnvelopeDb, PATH_VAR};
+use crate::{err, style};
+
+/// Seconds a version may be ahead of the clock before it is reported, to
+/// allow for small drifts between machines sharing a database
+const CLOCK_TOLERANCE: i64 = 60;
+
+/// Result of a [`Check`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+    /// the check could not run because an earlier one failed
+    Skip,
+}
+
+/// What a [`Check`] found, along with how to fix it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub status: Status,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Outcome {
+    fn pass(message: impl Into<String>) -> Self {
+        Outcome {
+            status: Status::Pass,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Outcome {
+            status: Status::Warn,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn fail(message: impl Into<String>, hint: impl Into<String>) -> Self {
+        Outcome {
+            status: Status::Fail,
+            message: message.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn skip() -> Self {
+        Outcome {
+            status: Status::Skip,
+            message: "the database could not be opened".into(),
+            hint: None,
+        }
+    }
+}
+
+/// Everything the checks look at, gathered once so that each check is a
+/// plain function of it
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    /// where envelope looks for the database, see [`crate::db::db_path`]
+    pub location: PathBuf,
+    /// the database file at `location`, if there is one
+    pub path: Option<PathBuf>,
+    /// the closest `.envelope` file of a parent directory
+    pub parent: Option<PathBuf>,
+    /// unix permissions of the database file
+    pub mode: Option<u32>,
+    /// why the database could not be opened
+    pub open_error: Option<String>,
+    pub diagnostics: Option<Diagnostics>,
+    /// whether git ignores the database, None outside of a git repository
+    pub git_ignored: Option<bool>,
+    /// current time in unix seconds
+    pub now: i64,
+}
+
+impl Context {
+    /// Inspects the database at `path`, which is opened read-only so that
+    /// nothing is migrated nor written
+    pub async fn gather(path: &Path) -> Context {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let path = path.to_path_buf();
+        if !path.is_file() {
+            return Context {
+                parent: find_db(dir),
+                location: path,
+                now,
+                ..Default::default()
+            };
+        }
+
+        let mut context = Context {
+            mode: file_mode(&path),
+            git_ignored: git_ignored(dir, &path),
+            location: path.clone(),
+            now,
+            ..Default::default()
+        };
+        let opened = match fs::File::open(&path) {
+            Ok(_) => match EnvelopeDb::open_read_only(&path).await {
+                Ok(db) => db.diagnose().await.map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            },
+            Err(e) => Err(e.to_string()),
+        };
+        match opened {
+            Ok(diagnostics) => context.diagnostics = Some(diagnostics),
+            Err(e) => context.open_error = Some(e),
+        }
+        context.path = Some(path);
+
+        context
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .ok()
+        .map(|m| m.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_: &Path) -> Option<u32> {
+    None
+}
+
+fn git_ignored(dir: &Path, path: &Path) -> Option<bool> {
+    let status = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["check-ignore", "-q"])
+        .arg(path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok()?;
+
+    // 1 means not ignored, anything else is not being in a repository
+    match status.code() {
+        Some(0) => Some(true),
+        Some(1) => Some(false),
+        _ => None,
+    }
+}
+
+/// A diagnostic run by `envelope doctor`
+pub trait Check {
+    /// short name printed before the outcome
+    fn name(&self) -> &'static str;
+
+    fn run(&self, context: &Context) -> Outcome;
+}
+
+/// Every check, in the order they are run
+pub fn checks() -> Vec<Box<dyn Check>> {
+    vec![
+        Box::new(DatabaseFound),
+        Box::new(Readable),
+        Box::new(Permissions),
+        Box::new(Integrity),
+        Box::new(Migrations),
+        Box::new(Index),
+        Box::new(OrphanedTombstones),
+        Box::new(CaseDuplicates),
+        Box::new(Clock),
+        Box::new(GitIgnored),
+    ]
+}
+
+struct DatabaseFound;
+
+impl Check for DatabaseFound {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    fn run(&self, context: &Context) -> Outcome {
+        match (&context.path, &context.parent) {
+            (Some(path), _) => Outcome::pass(format!("found {}", path.display())),
+            (None, Some(parent)) => Outcome::fail(
+                format!("no database at {}", context.location.display()),
+                format!(
+                    "envelope reads the .envelope of the current directory, cd to {} or set {} to {}",
+                    parent.parent().unwrap_or(parent).display(),
+                    PATH_VAR,
+                    parent.display()
+                ),
+            ),
+            (None, None) => Outcome::fail(
+                format!("no database at {}", context.location.display()),
+                "run `envelope init` to create one",
+            ),
+        }
+    }
+}
+
+struct Readable;
+
+impl Check for Readable {
+    fn name(&self) -> &'static str {
+        "readable"
+    }
+
+    fn run(&self, context: &Context) -> Outcome {
+        match (&context.diagnostics, &context.open_error) {
+            (Some(_), _) => Outcome::pass("the database can be opened"),
+            (None, Some(e)) => Outcome::fail(
+                format!("the database cannot be opened: {}", e),
+                "check that the file belongs to you and is an envelope database",
+            ),
+            (None, None) => Outcome::skip(),
+        }
+    }
+}
+
+struct Permissions;
+
+impl Check for Permissions {
+    fn name(&self) -> &'static str {
+        "permissions"
+    }
+
+    fn run(&self, context: &Context) -> Outcome {
+        let (Some(path), Some(mode)) = (&context.path, context.mode) else {
+            return Outcome::skip();
+        };
+
+        match mode & 0o077 {
+            0 => Outcome::pass(format!("only readable by its owner ({:o})", mode)),
+            _ => Outcome::warn(
+                format!("readable by other users ({:o})", mode),
+                format!("chmod 600 {}", path.display()),
+            ),
+        }
+    }
+}
+
+struct Integrity;
+
+impl Check for Integrity {
+    fn name(&self) -> &'static str {
+        "integrity"
+    }
+
+    fn run(&self, context: &Context) -> Outcome {
+        let Some(diagnostics) = &context.diagnostics else {
+            return Outcome::skip();
+        };
+
+        match diagnostics.integrity.as_slice() {
+            [ok] if ok == "ok" => Outcome::pass("sqlite integrity check passed"),
+            problems => Outcome::fail(
+                format!(
+                    "sqlite found {} problems, the first one is: {}",
+                    problems.len(),
+                    problems.first().map(String::as_str).unwrap_or_default()
+                ),
+                "restore a backup, or try `sqlite3 .envelope .recover`",
+            ),
+        }
+    }
+}
+
+struct Migrations;
+
+impl Check for Migrations {
+    fn name(&self) -> &'static str {
+        "migrations"
+    }
+
+    fn run(&self, context: &Context) -> Outcome {
+        let Some(diagnostics) = &context.diagnostics else {
+            return Outcome::skip();
+        };
+
+        if !diagnostics.unknown_migrations.is_empty() {
+            return Outcome::fail(
+                format!(
+                    "{} migrations come from a newer envelope",
+                    diagnostics.unknown_migrations.len()
+                ),
+                "upgrade envelope",
+            );
+        }
+        match diagnostics.pending_migrations.len() {
+            0 => Outcome::pass("the schema is up to date"),
+            pending => Outcome::warn(
+                format!("{} migrations are not applied yet", pending),
+                "any other command, such as `envelope list`, applies them",
+            ),
+        }
+    }
+}
+
+struct Index;
+
+impl Check for Index {
+    fn name(&self) -> &'static str {
+        "index"
+    }
+
+    fn run(&self, context: &Context) -> Outcome {
+        let Some(diagnostics) = &context.diagnostics else {
+            return Outcome::skip();
+        };
+
+        match diagnostics.indexes.is_empty() {
+            false => Outcome::pass("the variables are indexed"),
+            true => Outcome::fail(
+                "the environments table has no index",
+                "export the environments, then init a new database and import them",
+            ),
+        }
+    }
+}
+
+struct OrphanedTombstones;
+
+impl Check for OrphanedTombstones {
+    fn name(&self) -> &'static str {
+        "tombstones"
+    }
+
+    fn run(&self, context: &Context) -> Outcome {
+        let Some(diagnostics) = &context.diagnostics else {
+            return Outcome::skip();
+        };
+
+        match diagnostics.orphaned_tombstones {
+            0 => Outcome::pass("every deletion follows a value"),
+            count => Outcome::warn(
+                format!("{} deletions of variables that never had a value", count),
+                "`envelope flatten ENV` drops them",
+            ),
+        }
+    }
+}
+
+struct CaseDuplicates;
+
+impl Check for CaseDuplicates {
+    fn name(&self) -> &'static str {
+        "case"
+    }
+
+    fn run(&self, context: &Context) -> Outcome {
+        let Some(diagnostics) = &context.diagnostics else {
+            return Outcome::skip();
+        };
+
+        let mut envs: Vec<&str> = diagnostics
+            .case_duplicates
+            .iter()
+            .map(|d| d.env.as_str())
+            .collect();
+        envs.dedup();
+        match diagnostics.case_duplicates.as_slice() {
+            [] => Outcome::pass("no keys differ only by case"),
+            [duplicate] => Outcome::warn(
+                format!(
+                    "{} differ only by case in {}",
+                    duplicate.variants.join(", "),
+                    duplicate.env
+                ),
+                format!("`envelope dedupe-case {}` merges them", duplicate.env),
+            ),
+            duplicates => Outcome::warn(
+                format!(
+                    "{} keys differ only by case in {}",
+                    duplicates.len(),
+                    envs.join(", ")
+                ),
+                "`envelope dedupe-case ENV` merges them, keeping the newest value",
+            ),
+        }
+    }
+}
+
+struct Clock;
+
+impl Check for Clock {
+    fn name(&self) -> &'static str {
+        "clock"
+    }
+
+    fn run(&self, context: &Context) -> Outcome {
+        let Some(diagnostics) = &context.diagnostics else {
+            return Outcome::skip();
+        };
+
+        match diagnostics.latest_created_at {
+            Some(latest) if latest > context.now + CLOCK_TOLERANCE => Outcome::warn(
+                format!(
+                    "a version was created {}s in the future",
+                    latest - context.now
+                ),
+                "check the system clock, newer writes are hidden behind that version",
+            ),
+            _ => Outcome::pass("no version was created in the future"),
+        }
+    }
+}
+
+struct GitIgnored;
+
+impl Check for GitIgnored {
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    fn run(&self, context: &Context) -> Outcome {
+        if context.path.is_none() {
+            return Outcome::skip();
+        }
+
+        match context.git_ignored {
+            Some(true) => Outcome::pass(".envelope is ignored by git"),
+            Some(false) => Outcome::warn(
+                ".envelope is not ignored by git, its secrets could be committed",
+                "echo .envelope >> .gitignore",
+            ),
+            None => Outcome::pass("not in a git repository"),
+        }
+    }
+}
+
+/// Runs every check of [`checks`] on the `.envelope` file of `dir` and writes
+/// their outcome, fails if any of them failed
+pub async fn doctor<W: Write>(w: &mut W, path: &Path) -> Result<()> {
+    let context = Context::gather(path).await;
+    write_outcomes(w, &context, &checks())
+}
+
+fn write_outcomes<W: Write>(w: &mut W, context: &Context, checks: &[Box<dyn Check>]) -> Result<()> {
+    let mut failed = 0;
+    for check in checks {
+        let outcome = check.run(context);
+        let status = match outcome.status {
+            Status::Pass => style::paint(AnsiColor::Green.on_default(), "pass"),
+            Status::Warn => style::paint(style::WARNING, "warn"),
+            Status::Fail => {
+                failed += 1;
+                style::paint(style::REMOVED, "fail")
+            }
+            Status::Skip => style::paint(style::DELETED, "skip"),
+        };
+        writeln!(w, "{}  {}: {}", status, check.name(), outcome.message)?;
+        if let Some(hint) = outcome.hint {
+            writeln!(w, "      {}", hint)?;
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        1 => err!("1 check failed"),
+        _ => err!("{} checks failed", failed),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::CaseDuplicate;
+
+    fn healthy() -> Context {
+        Context {
+            path: Some(PathBuf::from("/project/.envelope")),
+            mode: Some(0o600),
+            diagnostics: Some(Diagnostics {
+                integrity: vec!["ok".into()],
+                indexes: vec!["sqlite_autoindex_environments_1".into()],
+                latest_created_at: Some(1000),
+                ..Default::default()
+            }),
+            git_ignored: Some(true),
+            now: 1000,
+            ..Default::default()
+        }
+    }
+
+    fn statuses(context: &Context) -> Vec<(&'static str, Status)> {
+        checks()
+            .iter()
+            .map(|check| (check.name(), check.run(context).status))
+            .collect()
+    }
+
+    #[test]
+    fn test_healthy() {
+        let context = healthy();
+        assert!(statuses(&context)
+            .iter()
+            .all(|(_, status)| *status == Status::Pass));
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        write_outcomes(&mut output, &context, &checks()).unwrap();
+        let output = String::from_utf8(output.into_inner()).unwrap();
+        assert!(output.starts_with("pass  database: found /project/.envelope\n"));
+    }
+
+    #[test]
+    fn test_database_found() {
+        let context = Context::default();
+        assert_eq!(Status::Fail, DatabaseFound.run(&context).status);
+        assert_eq!(Status::Skip, Readable.run(&context).status);
+        assert_eq!(Status::Skip, Integrity.run(&context).status);
+
+        let context = Context {
+            parent: Some(PathBuf::from("/project/.envelope")),
+            ..Default::default()
+        };
+        let outcome = DatabaseFound.run(&context);
+        assert_eq!(
+            Some(
+                "envelope reads the .envelope of the current directory, cd to /project \
+                or set ENVELOPE_PATH to /project/.envelope"
+                    .into()
+            ),
+            outcome.hint
+        );
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        let err = write_outcomes(&mut output, &context, &checks()).unwrap_err();
+        assert_eq!("1 check failed", err.to_string());
+    }
+
+    #[test]
+    fn test_permissions() {
+        let context = Context {
+            mode: Some(0o644),
+            ..healthy()
+        };
+        let outcome = Permissions.run(&context);
+        assert_eq!(Status::Warn, outcome.status);
+        assert_eq!(Some("chmod 600 /project/.envelope".into()), outcome.hint);
+    }
+
+    #[test]
+    fn test_database_checks() {
+        let mut context = healthy();
+        let diagnostics = context.diagnostics.as_mut().unwrap();
+        diagnostics.integrity = vec!["row 1 missing from index".into()];
+        diagnostics.pending_migrations = vec![1];
+        diagnostics.indexes.clear();
+        diagnostics.orphaned_tombstones = 3;
+        diagnostics.case_duplicates = vec![CaseDuplicate {
+            env: "dev".into(),
+            key: "API_KEY".into(),
+            variants: vec!["ApiKey".into(), "API_KEY".into()],
+        }];
+        diagnostics.latest_created_at = Some(5000);
+        context.git_ignored = Some(false);
+
+        assert_eq!(
+            vec![
+                ("database", Status::Pass),
+                ("readable", Status::Pass),
+                ("permissions", Status::Pass),
+                ("integrity", Status::Fail),
+                ("migrations", Status::Warn),
+                ("index", Status::Fail),
+                ("tombstones", Status::Warn),
+                ("case", Status::Warn),
+                ("clock", Status::Warn),
+                ("git", Status::Warn),
+            ],
+            statuses(&context)
+        );
+
+        assert_eq!(
+            "ApiKey, API_KEY differ only by case in dev",
+            CaseDuplicates.run(&context).message
+        );
+
+        context.diagnostics.as_mut().unwrap().unknown_migrations = vec![2];
+        assert_eq!(Status::Fail, Migrations.run(&context).status);
+    }
+
+    #[tokio::test]
+    async fn test_gather() {
+        let dir = std::env::temp_dir().join(format!("envelope-doctor-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join(".envelope");
+        let context = Context::gather(&path).await;
+        assert!(context.path.is_none());
+        assert!(context.diagnostics.is_none());
+
+        drop(EnvelopeDb::open(&path).await.unwrap());
+
+        let context = Context::gather(&path).await;
+        assert_eq!(Some(path.clone()), context.path);
+        let diagnostics = context.diagnostics.unwrap();
+        assert_eq!(vec!["ok".to_string()], diagnostics.integrity);
+        assert!(diagnostics.pending_migrations.is_empty());
+
+        // the database can be anywhere, see ENVELOPE_PATH
+        let elsewhere = dir.join("shared.db");
+        fs::rename(&path, &elsewhere).unwrap();
+        let context = Context::gather(&elsewhere).await;
+        assert_eq!(Some(elsewhere.clone()), context.path);
+        assert!(context.diagnostics.is_some());
+
+        fs::write(&elsewhere, "not a database").unwrap();
+        let context = Context::gather(&elsewhere).await;
+        assert!(context.open_error.is_some());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
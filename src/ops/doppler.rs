@@ -0,0 +1,263 @@
+use std::io::{Result, Write};
+
+use serde_json::Value;
+
+use crate::db::EnvelopeDb;
+use crate::std_err;
+
+use super::check_key;
+
+/// Keys Doppler adds to every download to tell where the secrets come from
+const DOPPLER_METADATA: [&str; 3] = ["DOPPLER_CONFIG", "DOPPLER_ENVIRONMENT", "DOPPLER_PROJECT"];
+
+/// A key of a Doppler download that is not imported, and why
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedKey {
+    pub key: String,
+    pub reason: String,
+}
+
+/// Secrets of a Doppler download
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DopplerSecrets {
+    /// the variables to import, keys uppercased
+    pub vars: Vec<(String, String)>,
+    /// the keys envelope cannot store
+    pub skipped: Vec<SkippedKey>,
+}
+
+/// Reads the secrets of a Doppler download in JSON, the object mapping each
+/// name to its value written by `doppler secrets download --format json`.
+/// The Doppler metadata keys are left out, the keys that envelope cannot
+/// store are skipped along with the reason. Fails if `json` is not an
+/// object.
+pub fn doppler_vars(json: &str) -> Result<DopplerSecrets> {
+    let json_secrets: serde_json::Map<String, Value> = serde_json::from_str(json)
+        .map_err(|e| std_err!("invalid Doppler download, expected a JSON object: {}", e))?;
+
+    let mut secrets = DopplerSecrets::default();
+    for (key, value) in json_secrets {
+        if DOPPLER_METADATA.contains(&key.as_str()) {
+            continue;
+        }
+
+        let Value::String(value) = value else {
+            let reason = "value is not a string".to_string();
+            secrets.skipped.push(SkippedKey { key, reason });
+            continue;
+        };
+        match skip_reason(&key, &value) {
+            Some(reason) => secrets.skipped.push(SkippedKey { key, reason }),
+            None => secrets.vars.push((key.to_ascii_uppercase(), value)),
+        }
+    }
+
+    Ok(secrets)
+}
+
+/// Why envelope cannot store `key` set to `value`, if it cannot
+fn skip_reason(key: &str, value: &str) -> Option<String> {
+    if key.is_empty() {
+        return Some("empty key".to_string());
+    }
+    if key.contains(['=', '\0']) || key.contains(char::is_whitespace) {
+        return Some("not a variable name".to_string());
+    }
+    if value.contains('\0') {
+        return Some("value has a NUL byte".to_string());
+    }
+
+    check_key(key).err().map(|e| e.to_string())
+}
+
+/// Sets the variables of a Doppler download in `env` in a single
+/// transaction, then prints how many were imported and the keys that were
+/// skipped
+pub async fn import_doppler<W: Write>(
+    writer: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    secrets: &DopplerSecrets,
+) -> Result<()> {
+    let DopplerSecrets { vars, skipped } = secrets;
+    let outcome = db.insert_many(env, vars).await?;
+    writeln!(
+        writer,
+        "{} values imported from Doppler, {} unchanged, {} skipped",
+        outcome.written,
+        outcome.unchanged,
+        skipped.len()
+    )?;
+    for SkippedKey { key, reason } in skipped {
+        writeln!(writer, "skipped {}: {}", key, reason)?;
+    }
+
+    Ok(())
+}
+
+/// Downloads the secrets of the `config` of the Doppler `project` as JSON,
+/// authenticated by `DOPPLER_TOKEN`. `DOPPLER_API_HOST` points to another
+/// API host, like it does for the Doppler CLI.
+#[cfg(feature = "doppler")]
+pub async fn download_doppler(project: &str, config: &str) -> Result<String> {
+    let token = std::env::var("DOPPLER_TOKEN").map_err(|_| std_err!("DOPPLER_TOKEN is not set"))?;
+    let host =
+        std::env::var("DOPPLER_API_HOST").unwrap_or_else(|_| "https://api.doppler.com".to_string());
+    let host = host.trim_end_matches('/');
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/v3/configs/config/secrets/download", host))
+        .query(&[("project", project), ("config", config), ("format", "json")])
+        .bearer_auth(token)
+        .send()
+        .await
+        .map_err(|e| std_err!("cannot reach Doppler at {}: {}", host, e))?;
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| std_err!("cannot read the response of Doppler: {}", e))?;
+
+    match status.is_success() {
+        true => Ok(body),
+        false => Err(doppler_error(status.as_u16(), &body, project, config)),
+    }
+}
+
+/// Error of a Doppler response of status `status`, with the messages of its
+/// `body` when it has any
+#[cfg(feature = "doppler")]
+fn doppler_error(status: u16, body: &str, project: &str, config: &str) -> std::io::Error {
+    let messages = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|body| {
+            let messages: Vec<String> = body["messages"]
+                .as_array()?
+                .iter()
+                .filter_map(|m| m.as_str().map(str::to_string))
+                .collect();
+            Some(messages.join(", "))
+        })
+        .filter(|messages| !messages.is_empty())
+        .unwrap_or_else(|| body.trim().to_string());
+
+    match status {
+        401 => std_err!("Doppler rejected DOPPLER_TOKEN: {}", messages),
+        403 => std_err!(
+            "DOPPLER_TOKEN cannot read the {} config of {}: {}",
+            config,
+            project,
+            messages
+        ),
+        404 => std_err!(
+            "there is no {} config in the Doppler project {}: {}",
+            config,
+            project,
+            messages
+        ),
+        status => std_err!("Doppler replied {}: {}", status, messages),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{test_db, SortOrder};
+
+    #[test]
+    fn test_doppler_vars() {
+        let json = r##"{
+            "DATABASE_URL": "postgres://localhost/app",
+            "api_key": "s3cr3t",
+            "DOPPLER_CONFIG": "prd",
+            "DOPPLER_ENVIRONMENT": "prd",
+            "DOPPLER_PROJECT": "myapp",
+            "PORT": 8080,
+            "#COMMENTED": "x",
+            "WITH SPACE": "x",
+            "NUL": "a\u0000b"
+        }"##;
+
+        let DopplerSecrets { vars, skipped } = doppler_vars(json).unwrap();
+        assert_eq!(
+            vec![
+                (
+                    "DATABASE_URL".to_string(),
+                    "postgres://localhost/app".to_string()
+                ),
+                ("API_KEY".into(), "s3cr3t".into()),
+            ],
+            vars
+        );
+        let skipped: Vec<(&str, &str)> = skipped
+            .iter()
+            .map(|s| (s.key.as_str(), s.reason.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                ("#COMMENTED", "key name cannot start with #"),
+                ("NUL", "value has a NUL byte"),
+                ("PORT", "value is not a string"),
+                ("WITH SPACE", "not a variable name"),
+            ],
+            skipped
+        );
+
+        for json in ["[]", "\"a\"", "{", ""] {
+            let err = doppler_vars(json).unwrap_err();
+            assert!(
+                err.to_string()
+                    .starts_with("invalid Doppler download, expected a JSON object"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_import_doppler() {
+        let db = test_db().await;
+        db.insert("prod", "PORT", "80").await.unwrap();
+        let secrets = doppler_vars(r#"{"PORT": "80", "HOST": "example.com", "": "x"}"#).unwrap();
+
+        let mut w = Vec::new();
+        import_doppler(&mut w, &db, "prod", &secrets).await.unwrap();
+        assert_eq!(
+            "1 values imported from Doppler, 1 unchanged, 1 skipped\nskipped : empty key\n",
+            String::from_utf8(w).unwrap()
+        );
+
+        let stored: Vec<(String, String)> = db
+            .list_var_in_env("prod", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.key, v.value))
+            .collect();
+        assert_eq!(
+            vec![
+                ("HOST".to_string(), "example.com".to_string()),
+                ("PORT".into(), "80".into()),
+            ],
+            stored
+        );
+    }
+
+    #[cfg(feature = "doppler")]
+    #[test]
+    fn test_doppler_error() {
+        let body = r#"{"messages": ["Could not find requested config 'prd'"], "success": false}"#;
+        assert_eq!(
+            "there is no prd config in the Doppler project myapp: Could not find requested config 'prd'",
+            doppler_error(404, body, "myapp", "prd").to_string()
+        );
+        assert_eq!(
+            "Doppler rejected DOPPLER_TOKEN: Invalid Auth token",
+            doppler_error(401, r#"{"messages": ["Invalid Auth token"]}"#, "a", "b").to_string()
+        );
+        assert_eq!(
+            "Doppler replied 502: bad gateway",
+            doppler_error(502, "bad gateway\n", "a", "b").to_string()
+        );
+    }
+}
@@ -2,6 +2,23 @@ use std::io::Result;
 
 use crate::db::EnvelopeDb;
 
-pub async fn duplicate(db: &EnvelopeDb, source: &str, target: &str) -> Result<()> {
-    db.duplicate(source, target).await
+/// Copies the variables of `source` to `target`, which must not exist unless
+/// `append` is set
+pub async fn duplicate(db: &EnvelopeDb, source: &str, target: &str, append: bool) -> Result<()> {
+    Ok(db.duplicate(source, target, append).await?)
+}
+
+/// Copies the variables of `source` whose key matches one of the globs of
+/// `include` to `target`, which must not exist unless `append` is set
+pub async fn duplicate_filtered(
+    db: &EnvelopeDb,
+    source: &str,
+    target: &str,
+    include: &[String],
+    append: bool,
+) -> Result<()> {
+    let include: Vec<&str> = include.iter().map(String::as_str).collect();
+    Ok(db
+        .duplicate_filtered(source, target, &include, append)
+        .await?)
 }
@@ -1,102 +1,254 @@
-use std::io::{BufRead, BufReader, Result, Write};
+use std::collections::BTreeMap;
+use std::io::{Result, Write};
 
-use crate::{
-    db::{EnvelopeDb, EnvironmentRow, Truncate},
-    editor,
-};
+use crate::db::{EnvDiff, EnvelopeDb, SortOrder};
+use crate::dotenv::{DotenvLine, DotenvParser};
+use crate::{editor, std_err};
 
-pub struct EditorData {
-    delete: Vec<String>,
-    upsert: Vec<(String, String)>,
-}
+use super::{print_changes, Changes};
+
+/// Prefix of the comments reporting why the previous edit was rejected, they
+/// are removed before the file is parsed again
+const ERROR_PREFIX: &str = "# error: ";
 
-fn parse(bufr: BufReader<&[u8]>) -> EditorData {
-    let (mut delete, mut upsert) = (Vec::new(), Vec::new());
+const HELP: &str = "# Remove or comment a line to delete its variable";
+
+/// Opens the variables of `env` in the editor of the user then applies the
+/// changes at once and prints them, see [`print_changes`]
+pub async fn edit<W: Write>(w: &mut W, db: &EnvelopeDb, env: &str) -> Result<()> {
+    edit_with(w, db, env, |contents| {
+        let edited = editor::spawn_with(contents.as_bytes())?;
+        String::from_utf8(edited).map_err(|_| std_err!("the edited file is not valid utf-8"))
+    })
+    .await
+}
 
-    for kv in bufr.lines() {
-        if kv.is_err() {
-            continue;
+/// Same as [`edit`], `open` is given the contents of the file and returns
+/// them once edited. It is called again, with the errors added on top, as
+/// long as the result cannot be parsed.
+async fn edit_with<W, F>(w: &mut W, db: &EnvelopeDb, env: &str, mut open: F) -> Result<()>
+where
+    W: Write,
+    F: FnMut(&str) -> Result<String>,
+{
+    let (editable, multiline): (BTreeMap<String, String>, BTreeMap<String, String>) = db
+        .list_var_in_env(env, SortOrder::Asc)
+        .await?
+        .into_iter()
+        .map(|row| (row.key, row.value))
+        .partition(|(_, value)| !value.contains('\n'));
+
+    let mut contents = render(&editable, multiline.keys());
+    let edited = loop {
+        let edited = open(&contents)?;
+        match parse(&edited) {
+            Ok(vars) => break vars,
+            Err(errors) => contents = with_errors(&edited, &errors),
         }
+    };
 
-        let kv = kv.unwrap();
+    let diff = EnvDiff::between(editable, edited);
+    db.apply_diff(env, &diff).await?;
+
+    print_changes(w, &Changes::from([(env.to_string(), diff)]))
+}
+
+/// Renders `vars` in dotenv format, the keys of `skipped`, whose values span
+/// several lines, are only mentioned
+fn render<'a>(
+    vars: &BTreeMap<String, String>,
+    skipped: impl IntoIterator<Item = &'a String>,
+) -> String {
+    let mut contents = String::new();
+    for (key, value) in vars {
+        contents.push_str(&render_var(key, value));
+        contents.push('\n');
+    }
+
+    contents.push('\n');
+    contents.push_str(HELP);
+    contents.push('\n');
+    for key in skipped {
+        contents.push_str(&format!(
+            "# {} is left untouched, its value spans several lines\n",
+            key
+        ));
+    }
+
+    contents
+}
+
+/// Writes `key=value` so that it is parsed back to `value`, it is quoted if
+/// it has surrounding spaces or quotes
+fn render_var(key: &str, value: &str) -> String {
+    let line = format!("{}={}", key, value);
+    match DotenvParser::new().parse_line(&line) {
+        DotenvLine::Entry(entry) if entry.value == value => line,
+        _ => format!("{}=\"{}\"", key, value),
+    }
+}
 
-        match kv.starts_with("#") {
-            true => {
-                if let Some((k, _)) = kv.split_once("=") {
-                    delete.push(k[1..k.len()].trim().into());
+/// Parses the edited file, keys are uppercased. Returns the errors found if
+/// a line is not a variable or a key is set twice, lines are numbered
+/// without the errors of the previous attempt.
+fn parse(contents: &str) -> std::result::Result<BTreeMap<String, String>, Vec<String>> {
+    let mut parser = DotenvParser::new();
+    let mut vars = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    let lines = contents
+        .lines()
+        .filter(|line| !line.starts_with(ERROR_PREFIX))
+        .enumerate();
+    for (n, line) in lines {
+        match parser.parse_line(line) {
+            DotenvLine::Entry(entry) => {
+                let key = entry.key.to_ascii_uppercase();
+                if vars.insert(key.clone(), entry.value).is_some() {
+                    errors.push(format!("line {}: {} is set more than once", n + 1, key));
                 }
             }
-            false => {
-                if let Some((k, v)) = kv.split_once("=") {
-                    upsert.push((k.trim().into(), v.trim().into()));
-                }
+            DotenvLine::Invalid(line) => {
+                errors.push(format!("line {}: `{}` is not KEY=VALUE", n + 1, line));
             }
+            DotenvLine::Comment(_) | DotenvLine::Blank => {}
         }
     }
 
-    EditorData { delete, upsert }
-}
-
-pub async fn edit(db: &EnvelopeDb, env: &str) -> Result<()> {
-    let mut kv_list = Vec::new();
-    let envs: Vec<EnvironmentRow> = db.list_all_var_in_env(env, Truncate::None).await?;
-    for env in envs {
-        writeln!(&mut kv_list, "{}={}", &env.key, &env.value)?;
+    match errors.is_empty() {
+        true => Ok(vars),
+        false => Err(errors),
     }
+}
 
-    let bytes = editor::spawn_with(&kv_list)?;
-    let EditorData { delete, upsert } = parse(BufReader::new(&bytes));
-
-    for k in delete {
-        db.delete_var_for_env(env, &k).await?;
+/// Puts `errors` on top of `contents` in place of the previous ones, the
+/// line numbers refer to `contents` without the errors
+fn with_errors(contents: &str, errors: &[String]) -> String {
+    let mut annotated = String::new();
+    for error in errors {
+        annotated.push_str(ERROR_PREFIX);
+        annotated.push_str(error);
+        annotated.push('\n');
     }
 
-    for (k, v) in upsert {
-        db.insert(env, &k, &v).await?;
+    for line in contents.lines() {
+        if !line.starts_with(ERROR_PREFIX) {
+            annotated.push_str(line);
+            annotated.push('\n');
+        }
     }
 
-    Ok(())
+    annotated
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::io::BufReader;
+    use crate::db::test_db;
+
+    fn vars(items: &[(&str, &str)]) -> BTreeMap<String, String> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
 
     #[test]
-    fn test_upsert() {
-        let bytes = b"key1=value1\nkey2=value2\n\nkey3=value3\n\n\nk4=v4";
-        let EditorData { delete, upsert } = parse(BufReader::new(bytes));
-        assert_eq!(4, upsert.len());
-        for (i, kv) in vec![("key1", "value1"), ("key2", "value2"), ("key3", "value3")]
-            .into_iter()
-            .enumerate()
-        {
-            assert_eq!((upsert[i].0.as_str(), upsert[i].1.as_str()), kv);
-        }
-        assert!(delete.is_empty());
+    fn test_render_parse_round_trip() {
+        let original = vars(&[
+            ("PLAIN", "value"),
+            ("EMPTY", ""),
+            ("SPACES", "  padded  "),
+            ("QUOTED", "\"quoted\""),
+            ("EQUALS", "a=b"),
+        ]);
+
+        let contents = render(&original, []);
+        assert!(contents.contains("SPACES=\"  padded  \"\n"));
+        assert_eq!(Ok(original), parse(&contents));
     }
 
     #[test]
-    fn test_delete() {
-        let bytes = b"#key1=value1\n#  key2=value2\n\n\n#key3=value3";
-        let EditorData { delete, upsert } = parse(BufReader::new(bytes));
-        assert_eq!(3, delete.len());
-        for (i, k) in vec!["key1", "key2", "key3"].iter().enumerate() {
-            assert_eq!(&delete[i], k);
-        }
-        assert!(upsert.is_empty());
+    fn test_parse() {
+        assert_eq!(
+            Ok(vars(&[("KEY1", "value1"), ("KEY2", "value2")])),
+            parse("key1=value1\n#key3=value3\n\n  key2 = value2\n# comment")
+        );
+        assert_eq!(
+            Err(vec![
+                "line 2: `oops` is not KEY=VALUE".to_string(),
+                "line 3: A is set more than once".to_string(),
+            ]),
+            parse("A=1\noops\na=2")
+        );
     }
 
     #[test]
-    fn test_upsert_delete() {
-        let bytes = b"#key1=value1\n  key2=value2\n\n\n#key3=value3";
-        let EditorData { delete, upsert } = parse(BufReader::new(bytes));
-        assert_eq!(2, delete.len());
-        assert_eq!(1, upsert.len());
-        assert_eq!("key1", delete[0]);
-        assert_eq!("key3", delete[1]);
-        assert_eq!("key2", upsert[0].0);
-        assert_eq!("value2", upsert[0].1);
+    fn test_with_errors() {
+        let contents = "# error: line 1: old\nA=1\noops\n";
+        assert_eq!(
+            "# error: line 2: `oops` is not KEY=VALUE\nA=1\noops\n",
+            with_errors(contents, &["line 2: `oops` is not KEY=VALUE".into()])
+        );
+
+        // the errors are not part of the variables nor of the line numbers
+        assert_eq!(
+            Err(vec!["line 2: `oops` is not KEY=VALUE".into()]),
+            parse(contents)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_edit() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', '1', 1),
+            ('dev', 'B', '2', 1),
+            ('dev', 'C', '3', 1),
+            ('dev', 'CERT', 'line1
+line2', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let mut opened = Vec::new();
+        let mut output = anstream::StripStream::new(Vec::new());
+        edit_with(&mut output, &db, "dev", |contents| {
+            opened.push(contents.to_string());
+            match opened.len() {
+                1 => Ok("A=1\nB=changed\nnot a variable\nd=4\n".into()),
+                _ => Ok(contents.replace("not a variable\n", "")),
+            }
+        })
+        .await
+        .unwrap();
+
+        assert!(opened[0].starts_with("A=1\nB=2\nC=3\n"));
+        assert!(opened[0].contains("# CERT is left untouched"));
+        assert!(opened[1].starts_with("# error: line 3: `not a variable` is not KEY=VALUE\nA=1\n"));
+        assert_eq!(
+            "dev\n+ D=4\n- C=3\n~ B=2 -> changed\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let current: BTreeMap<String, String> = db
+            .list_var_in_env("dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.key, r.value))
+            .collect();
+        assert_eq!(
+            vars(&[
+                ("A", "1"),
+                ("B", "changed"),
+                ("CERT", "line1\nline2"),
+                ("D", "4")
+            ]),
+            current
+        );
     }
 }
@@ -0,0 +1,276 @@
+use std::collections::BTreeSet;
+use std::io::{Result, Write};
+
+use clap::ValueEnum;
+
+use crate::db::EnvelopeDb;
+
+use super::get_env;
+
+/// Variable holding the keys exported by the last evaluation of `envelope env`,
+/// it is used to unset the variables that are not part of the environment
+/// anymore
+pub const ENVELOPE_LOADED_VARS: &str = "ENVELOPE_LOADED_VARS";
+
+/// Shells supported by `envelope env`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+impl Shell {
+    /// Guesses the shell of the caller from `$SHELL`, falls back to bash, or
+    /// to powershell on windows
+    pub fn detect() -> Self {
+        let shell = std::env::var("SHELL").unwrap_or_default();
+        match Self::from_path(&shell) {
+            Some(shell) => shell,
+            None if cfg!(windows) => Shell::Powershell,
+            None => Shell::Bash,
+        }
+    }
+
+    fn from_path(path: &str) -> Option<Self> {
+        let name = path.rsplit(['/', '\\']).next()?;
+        let name = name.strip_suffix(".exe").unwrap_or(name);
+        match name {
+            "bash" | "sh" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            "pwsh" | "powershell" => Some(Shell::Powershell),
+            _ => None,
+        }
+    }
+
+    /// Quotes `value` so that the shell reads it literally
+    pub fn quote(&self, value: &str) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh => format!("'{}'", value.replace('\'', r"'\''")),
+            Shell::Fish => format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'")),
+            Shell::Powershell => format!("'{}'", value.replace('\'', "''")),
+        }
+    }
+
+    /// Whether `key` can be the name of a shell variable, the keys are
+    /// written unquoted in the statements so nothing else may be
+    pub fn is_var_name(key: &str) -> bool {
+        let mut chars = key.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    /// Statement exporting `key`, which must be a [`Shell::is_var_name`]
+    pub fn export(&self, key: &str, value: &str) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh => format!("export {}={}", key, self.quote(value)),
+            Shell::Fish => format!("set -gx {} {}", key, self.quote(value)),
+            Shell::Powershell => format!("$env:{} = {}", key, self.quote(value)),
+        }
+    }
+
+    /// Statement unsetting `key`, which must be a [`Shell::is_var_name`]
+    pub fn unset(&self, key: &str) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh => format!("unset {}", key),
+            Shell::Fish => format!("set -e {}", key),
+            Shell::Powershell => {
+                format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", key)
+            }
+        }
+    }
+}
+
+/// Writes the statements that load `env` in `shell` when evaluated.
+///
+/// `loaded` is the value of [`ENVELOPE_LOADED_VARS`] left by a previous
+/// evaluation, the variables listed there that are not part of `env` anymore
+/// are unset.
+///
+/// The keys that are not shell variable names, which the shell would run as
+/// code, are left out and returned.
+pub async fn print_env<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    shell: Shell,
+    loaded: Option<&str>,
+) -> Result<Vec<String>> {
+    let mut layers = get_env(db, &[env.to_string()]).await?;
+    let skipped: Vec<String> = layers
+        .vars
+        .keys()
+        .filter(|key| !Shell::is_var_name(key))
+        .cloned()
+        .collect();
+    for key in &skipped {
+        layers.vars.remove(key);
+    }
+
+    for key in loaded_keys(loaded) {
+        if !layers.vars.contains_key(key) {
+            writeln!(w, "{}", shell.unset(key))?;
+        }
+    }
+
+    for (key, var) in &layers.vars {
+        writeln!(w, "{}", shell.export(key, &var.value))?;
+    }
+
+    let keys: Vec<&str> = layers.vars.keys().map(String::as_str).collect();
+    writeln!(w, "{}", shell.export(ENVELOPE_LOADED_VARS, &keys.join(",")))?;
+
+    Ok(skipped)
+}
+
+/// Keys listed in the value `loaded` of [`ENVELOPE_LOADED_VARS`], the ones
+/// that are not variable names are ignored
+pub fn loaded_keys(loaded: Option<&str>) -> BTreeSet<&str> {
+    loaded
+        .unwrap_or_default()
+        .split(',')
+        .filter(|k| Shell::is_var_name(k))
+        .collect()
+}
+
+/// Warns on `w` that the `skipped` keys were not loaded in the shell
+pub fn warn_skipped_keys<W: Write>(w: &mut W, skipped: &[String]) -> Result<()> {
+    for key in skipped {
+        writeln!(
+            w,
+            "envelope: skipped {:?}, it is not a valid shell variable name",
+            key
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    async fn seed(db: &EnvelopeDb) {
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'it''s', 1),
+            ('dev', 'B', 'b', 1),
+            ('dev', 'B', NULL, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+    }
+
+    async fn output(db: &EnvelopeDb, shell: Shell, loaded: Option<&str>) -> String {
+        let mut buf: Vec<u8> = Vec::new();
+        assert!(print_env(&mut buf, db, "dev", shell, loaded)
+            .await
+            .unwrap()
+            .is_empty());
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_print_env_bash() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        assert_eq!(
+            "export A='it'\\''s'\nexport ENVELOPE_LOADED_VARS='A'\n",
+            output(&db, Shell::Bash, None).await
+        );
+        assert_eq!(
+            "unset B\nexport A='it'\\''s'\nexport ENVELOPE_LOADED_VARS='A'\n",
+            output(&db, Shell::Zsh, Some("A,B")).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_print_env_fish() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        assert_eq!(
+            "set -e B\nset -gx A 'it\\'s'\nset -gx ENVELOPE_LOADED_VARS 'A'\n",
+            output(&db, Shell::Fish, Some("B")).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_print_env_powershell() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        assert_eq!(
+            "Remove-Item Env:B -ErrorAction SilentlyContinue\n$env:A = 'it''s'\n$env:ENVELOPE_LOADED_VARS = 'A'\n",
+            output(&db, Shell::Powershell, Some("B")).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_print_env_invalid_keys() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A;RM -RF ~', 'x', 1),
+            ('dev', '$(TOUCH PWNED)', 'x', 1),
+            ('dev', 'WITH SPACE', 'x', 1),
+            ('dev', '1ST', 'x', 1),
+            ('dev', '_OK', 'ok', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        for shell in [Shell::Bash, Shell::Fish, Shell::Powershell] {
+            let mut buf: Vec<u8> = Vec::new();
+            let loaded = Some("B;echo pwned,_OK,C");
+            let skipped = print_env(&mut buf, &db, "dev", shell, loaded)
+                .await
+                .unwrap();
+            assert_eq!(
+                vec!["$(TOUCH PWNED)", "1ST", "A;RM -RF ~", "WITH SPACE"],
+                skipped
+            );
+
+            let out = String::from_utf8(buf).unwrap();
+            assert_eq!(
+                vec![
+                    shell.unset("C"),
+                    shell.export("_OK", "ok"),
+                    shell.export(ENVELOPE_LOADED_VARS, "_OK")
+                ],
+                out.lines().collect::<Vec<_>>()
+            );
+        }
+
+        let mut warnings = Vec::new();
+        warn_skipped_keys(&mut warnings, &["A;B".to_string()]).unwrap();
+        assert_eq!(
+            "envelope: skipped \"A;B\", it is not a valid shell variable name\n",
+            String::from_utf8(warnings).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_shell_from_path() {
+        assert_eq!(Some(Shell::Zsh), Shell::from_path("/usr/bin/zsh"));
+        assert_eq!(
+            Some(Shell::Fish),
+            Shell::from_path("/opt/homebrew/bin/fish")
+        );
+        assert_eq!(
+            Some(Shell::Powershell),
+            Shell::from_path(r"C:\Program Files\PowerShell\7\pwsh.exe")
+        );
+        assert_eq!(None, Shell::from_path("/bin/tcsh"));
+    }
+}
@@ -1,17 +1,434 @@
-use crate::db::EnvelopeDb;
+use crate::db::{EnvelopeDb, SortOrder};
+use crate::format::{self, write_csv_record, write_k8s, K8sKind, CSV_HEADER};
+use crate::validate::ValueType;
 
-use std::fs;
-use std::io;
-use std::io::{Result, Write};
+use futures_util::stream::{self, BoxStream};
+use futures_util::{StreamExt, TryStreamExt};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
 
-pub async fn export_dotenv(
+use std::collections::HashMap;
+use std::io::{Error, Result, Write};
+
+use super::{get_env, LayeredVar};
+
+/// Layers `envs` on top of each other and lists their variables sorted by
+/// key, or in the order of the keys of the `order_like` environment, see
+/// [`EnvelopeDb::key_order`]
+async fn ordered_vars(
     db: &EnvelopeDb,
-    env: &str,
-    buf: &mut io::BufWriter<fs::File>,
+    envs: &[String],
+    order_like: Option<&str>,
+) -> Result<Vec<(String, LayeredVar)>> {
+    let mut vars: Vec<(String, LayeredVar)> = get_env(db, envs).await?.vars.into_iter().collect();
+    if let Some(reference) = order_like {
+        db.check_env_exists(reference).await?;
+        format::order_like(&mut vars, &db.key_order(reference).await?);
+    }
+
+    Ok(vars)
+}
+
+/// Same as [`ordered_vars`], but a single environment has nothing to layer:
+/// its variables are yielded as they are read, so that the exports write
+/// them out without holding the whole environment in memory
+async fn exported_vars<'a>(
+    db: &'a EnvelopeDb,
+    envs: &'a [String],
+    order_like: Option<&str>,
+) -> Result<BoxStream<'a, Result<(String, LayeredVar)>>> {
+    if let ([env], None) = (envs, order_like) {
+        db.check_env_exists(env).await?;
+
+        let rows = db
+            .stream_var_in_env(env, SortOrder::Asc)
+            .map_ok(|row| {
+                let var = LayeredVar {
+                    value: row.value,
+                    env: env.clone(),
+                };
+                (row.key, var)
+            })
+            .map_err(Error::from);
+        return Ok(rows.boxed());
+    }
+
+    let vars = ordered_vars(db, envs, order_like).await?;
+    Ok(stream::iter(vars.into_iter().map(Ok)).boxed())
+}
+
+/// Writes the variables of `envs` layered on top of each other in dotenv
+/// format, later environments take precedence. Descriptions are written as
+/// comments above their variable. Variables are sorted by key unless
+/// `order_like` names the environment whose key order they follow.
+pub async fn export_dotenv<W: Write>(
+    db: &EnvelopeDb,
+    envs: &[String],
+    order_like: Option<&str>,
+    buf: &mut W,
 ) -> Result<()> {
-    for env in db.list_var_in_env(env).await? {
-        writeln!(buf, "{}={}", &env.key, &env.value)?;
+    let mut descriptions = HashMap::new();
+    for env in envs {
+        descriptions.insert(env.as_str(), db.list_descriptions(env).await?);
+    }
+
+    let mut vars = exported_vars(db, envs, order_like).await?;
+    while let Some((key, var)) = vars.try_next().await? {
+        let description = descriptions[var.env.as_str()].get(&key);
+        write_dotenv_var(buf, &key, &var.value, description)?;
     }
 
     Ok(())
 }
+
+/// Writes `key` set to `value`, preceded by its description as comments
+fn write_dotenv_var<W: Write>(
+    buf: &mut W,
+    key: &str,
+    value: &str,
+    description: Option<&String>,
+) -> Result<()> {
+    for line in description.into_iter().flat_map(|d| d.lines()) {
+        writeln!(buf, "# {}", line)?;
+    }
+    writeln!(buf, "{}={}", key, value)
+}
+
+/// A variable exported as JSON along with its metadata
+#[derive(Debug, Serialize)]
+struct JsonVar<'a> {
+    value: &'a str,
+    /// environment providing the value
+    env: &'a str,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    value_type: Option<ValueType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<&'a str>,
+}
+
+/// Writes the variables of `envs` layered on top of each other as a JSON
+/// object mapping each key to its value, the environment providing it and
+/// its type and description if any
+pub async fn export_json<W: Write>(db: &EnvelopeDb, envs: &[String], buf: &mut W) -> Result<()> {
+    let mut descriptions = HashMap::new();
+    let mut types = HashMap::new();
+    for env in envs {
+        descriptions.insert(env.as_str(), db.list_descriptions(env).await?);
+        types.insert(env.as_str(), db.list_types(env).await?);
+    }
+
+    // the object is written one entry at a time, the keys come sorted
+    let mut vars = exported_vars(db, envs, None).await?;
+    let mut json = serde_json::Serializer::pretty(&mut *buf);
+    let mut object = json.serialize_map(None).map_err(Error::from)?;
+    while let Some((key, var)) = vars.try_next().await? {
+        let env = var.env.as_str();
+        let value = JsonVar {
+            value: &var.value,
+            env,
+            value_type: types[env].get(&key).copied(),
+            description: descriptions[env].get(&key).map(String::as_str),
+        };
+        object.serialize_entry(&key, &value).map_err(Error::from)?;
+    }
+    object.end().map_err(Error::from)?;
+
+    writeln!(buf)
+}
+
+/// Writes the variables of `envs` layered on top of each other as CSV, see
+/// [`format::to_csv`]. The env of each record is the environment that
+/// provides it, records are ordered like [`export_dotenv`] orders variables.
+pub async fn export_csv<W: Write>(
+    db: &EnvelopeDb,
+    envs: &[String],
+    order_like: Option<&str>,
+    buf: &mut W,
+) -> Result<()> {
+    let mut vars = exported_vars(db, envs, order_like).await?;
+    buf.write_all(CSV_HEADER.as_bytes())?;
+    while let Some((key, var)) = vars.try_next().await? {
+        write_csv_record(buf, &var.env, &key, &var.value)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the variables of `envs` layered on top of each other as a
+/// Kubernetes manifest, see [`format::to_k8s`]. The manifest is a single
+/// YAML document, its variables are collected before it is written.
+pub async fn export_k8s<W: Write>(
+    db: &EnvelopeDb,
+    envs: &[String],
+    kind: K8sKind,
+    name: &str,
+    namespace: Option<&str>,
+    buf: &mut W,
+) -> Result<()> {
+    let vars: Vec<(String, LayeredVar)> =
+        exported_vars(db, envs, None).await?.try_collect().await?;
+    let vars = vars
+        .iter()
+        .map(|(key, var)| (key.as_str(), var.value.as_str()));
+
+    Ok(write_k8s(buf, vars, kind, name, namespace)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    #[tokio::test]
+    async fn test_export_descriptions() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('base', 'A', 'base-a', 1),
+            ('base', 'B', 'base-b', 1),
+            ('dev', 'B', 'dev-b', 1);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        db.set_description("base", "A", "first\nsecond")
+            .await
+            .unwrap();
+        db.set_description("base", "B", "shadowed").await.unwrap();
+
+        let mut output: Vec<u8> = Vec::new();
+        export_dotenv(&db, &["base".into(), "dev".into()], None, &mut output)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            "# first\n# second\nA=base-a\nB=dev-b\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_json() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('base', 'PORT', '80', 1),
+            ('base', 'HOST', 'localhost', 1),
+            ('dev', 'PORT', '8080', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        db.set_var_type("dev", "port", Some(ValueType::Port))
+            .await
+            .unwrap();
+        db.set_description("base", "HOST", "bound address")
+            .await
+            .unwrap();
+
+        let mut output: Vec<u8> = Vec::new();
+        export_json(&db, &["base".into(), "dev".into()], &mut output)
+            .await
+            .unwrap();
+
+        let vars: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "HOST": {"value": "localhost", "env": "base", "description": "bound address"},
+                "PORT": {"value": "8080", "env": "dev", "type": "port"},
+            }),
+            vars
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_csv() {
+        let db = test_db().await;
+        db.insert("base", "A", "base-a").await.unwrap();
+        db.insert("base", "B", "base-b").await.unwrap();
+        db.insert("dev", "B", "dev, b").await.unwrap();
+
+        let mut output: Vec<u8> = Vec::new();
+        export_csv(&db, &["base".into(), "dev".into()], None, &mut output)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            "env,key,value\r\nbase,A,base-a\r\ndev,B,\"dev, b\"\r\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+    #[tokio::test]
+    async fn test_export_order_like() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('prod', 'PORT', '443', 1),
+            ('prod', 'HOST', 'example.com', 2),
+            ('prod', 'DEBUG', 'false', 3),
+            ('dev', 'DEBUG', 'true', 1),
+            ('dev', 'HOST', 'localhost', 1),
+            ('dev', 'PORT', '8080', 1),
+            ('dev', 'LOG', 'trace', 1),
+            ('dev', 'CACHE', 'none', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let mut output: Vec<u8> = Vec::new();
+        export_dotenv(&db, &["dev".into()], Some("prod"), &mut output)
+            .await
+            .unwrap();
+        assert_eq!(
+            "PORT=8080\nHOST=localhost\nDEBUG=true\nCACHE=none\nLOG=trace\n",
+            String::from_utf8(output).unwrap()
+        );
+
+        let mut output: Vec<u8> = Vec::new();
+        export_csv(&db, &["dev".into()], Some("prod"), &mut output)
+            .await
+            .unwrap();
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .starts_with("env,key,value\r\ndev,PORT,8080\r\ndev,HOST,localhost\r\n"));
+
+        let mut output: Vec<u8> = Vec::new();
+        let err = export_dotenv(&db, &["dev".into()], Some("missing"), &mut output)
+            .await
+            .unwrap_err();
+        assert_eq!("env missing does not exist", err.to_string());
+    }
+
+    /// the streamed exports write the same bytes as rendering the layered
+    /// variables at once
+    #[tokio::test]
+    async fn test_export_streamed() {
+        let db = test_db().await;
+        sqlx::query(
+            r#"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('base', 'A', 'base-a', 1),
+            ('base', 'QUOTE', 'say "hi", twice', 1),
+            ('dev', 'A', 'dev-a', 1),
+            ('dev', 'MULTI', 'one' || char(10) || 'two', 1),
+            ('dev', 'PORT', '8080', 1),
+            ('dev', 'UNICODE', 'été ✓', 1);"#,
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        db.set_var_type("dev", "port", Some(ValueType::Port))
+            .await
+            .unwrap();
+        db.set_description("dev", "A", "first\nsecond")
+            .await
+            .unwrap();
+
+        for envs in [vec!["dev".to_string()], vec!["base".into(), "dev".into()]] {
+            let layers = get_env(&db, &envs).await.unwrap();
+            let descriptions = db.list_descriptions("dev").await.unwrap();
+            let types = db.list_types("dev").await.unwrap();
+            let described = |env: &str, key: &str| match env {
+                "dev" => descriptions.get(key),
+                _ => None,
+            };
+
+            let mut expected = Vec::new();
+            for (key, var) in &layers.vars {
+                write_dotenv_var(&mut expected, key, &var.value, described(&var.env, key)).unwrap();
+            }
+            let mut output = Vec::new();
+            export_dotenv(&db, &envs, None, &mut output).await.unwrap();
+            assert_eq!(expected, output);
+
+            let json: std::collections::BTreeMap<&str, JsonVar> = layers
+                .vars
+                .iter()
+                .map(|(key, var)| {
+                    let value = JsonVar {
+                        value: &var.value,
+                        env: &var.env,
+                        value_type: types.get(key).copied().filter(|_| var.env == "dev"),
+                        description: described(&var.env, key).map(String::as_str),
+                    };
+                    (key.as_str(), value)
+                })
+                .collect();
+            let expected = serde_json::to_string_pretty(&json).unwrap() + "\n";
+            let mut output = Vec::new();
+            export_json(&db, &envs, &mut output).await.unwrap();
+            assert_eq!(expected, String::from_utf8(output).unwrap());
+
+            let rows = layers
+                .vars
+                .iter()
+                .map(|(key, var)| (var.env.as_str(), key.as_str(), var.value.as_str()));
+            let expected = format::to_csv(rows);
+            let mut output = Vec::new();
+            export_csv(&db, &envs, None, &mut output).await.unwrap();
+            assert_eq!(expected, String::from_utf8(output).unwrap());
+
+            let vars = layers
+                .vars
+                .iter()
+                .map(|(key, var)| (key.as_str(), var.value.as_str()));
+            let expected = format::to_k8s(vars, K8sKind::ConfigMap, "app", None).unwrap();
+            let mut output = Vec::new();
+            export_k8s(&db, &envs, K8sKind::ConfigMap, "app", None, &mut output)
+                .await
+                .unwrap();
+            assert_eq!(expected, String::from_utf8(output).unwrap());
+        }
+    }
+
+    /// Counts what is written to it without keeping it
+    #[derive(Default)]
+    struct Counter {
+        bytes: usize,
+        lines: usize,
+    }
+
+    impl Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.bytes += buf.len();
+            self.lines += buf.iter().filter(|b| **b == b'\n').count();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_large() {
+        let db = test_db().await;
+        // 20k variables of 5 versions each
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            WITH RECURSIVE n(i) AS (SELECT 0 UNION ALL SELECT i + 1 FROM n WHERE i < 99999)
+            SELECT 'dev', printf('KEY_%05d', i / 5), printf('value-%d', i), i % 5 + 1 FROM n;",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let mut output = Counter::default();
+        export_dotenv(&db, &["dev".into()], None, &mut output)
+            .await
+            .unwrap();
+
+        let expected: usize = (0..20_000)
+            .map(|i| format!("KEY_{:05}=value-{}\n", i, i * 5 + 4).len())
+            .sum();
+        assert_eq!(20_000, output.lines);
+        assert_eq!(expected, output.bytes);
+    }
+}
@@ -0,0 +1,248 @@
+use std::io::{Result, Write};
+
+use crate::db::{ChangeKind, EnvelopeDb, HistoryRow};
+use crate::store::Store;
+use crate::style::{self, ADDED, REMOVED};
+
+use super::Output;
+
+/// Writes every version of the variables of `env`, or of `key` only, one per
+/// line prefixed by the time it was created at. Only the versions created
+/// from `since` up to `until` excluded are written.
+pub async fn history<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    key: Option<&str>,
+    since: Option<i64>,
+    until: Option<i64>,
+    output: Output,
+) -> Result<()> {
+    db.check_env_exists(env).await?;
+
+    let rows = db.stream_history_between(env, key, since, until);
+    output.write_stream(w, rows, write_version).await
+}
+
+/// Writes the history of `env` like [`history`] with any backend, the
+/// versions are read at once instead of being streamed
+pub async fn store_history<W: Write, S: Store>(
+    w: &mut W,
+    store: &S,
+    env: &str,
+    key: Option<&str>,
+    since: Option<i64>,
+    until: Option<i64>,
+    output: Output,
+) -> Result<()> {
+    store.check_env_exists(env).await?;
+
+    let rows = store.history_between(env, key, since, until).await?;
+    output.write(w, &rows, |w, rows| {
+        rows.iter().try_for_each(|row| write_version(w, row))
+    })
+}
+
+fn write_version<W: Write>(w: &mut W, row: &HistoryRow) -> Result<()> {
+    match &row.value {
+        Some(value) => writeln!(w, "{} {}={}", row.created_at, row.key, value),
+        None => {
+            let deleted = format!("{} {} (deleted)", row.created_at, row.key);
+            writeln!(w, "{}", style::paint(style::DELETED, deleted))
+        }
+    }
+}
+
+/// Writes the changes made to the variables of `env` from `since` up to
+/// `until` excluded, one per line prefixed by the time it was made at, `+`
+/// for the variables set, `~` for the changed ones and `-` for the deleted
+/// ones
+pub async fn changes<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    since: i64,
+    until: i64,
+    output: Output,
+) -> Result<()> {
+    db.check_env_exists(env).await?;
+
+    let changes = db.changes_between(env, since, until).await?;
+    output.write(w, &changes, |w, changes| {
+        for change in changes {
+            let old = change.old.as_deref().unwrap_or_default();
+            let new = change.new.as_deref().unwrap_or_default();
+            let line = match change.kind {
+                ChangeKind::Added => style::paint(ADDED, format!("+ {}={}", change.key, new)),
+                ChangeKind::Deleted => style::paint(REMOVED, format!("- {}={}", change.key, old)),
+                ChangeKind::Changed => format!(
+                    "~ {}={} -> {}",
+                    change.key,
+                    style::paint(REMOVED, old),
+                    style::paint(ADDED, new)
+                ),
+            };
+            writeln!(w, "{} {}", change.created_at, line)?;
+        }
+        Ok(())
+    })
+}
+
+/// Writes every row of the database, all the versions of every variable of
+/// every env, one per line prefixed by the time it was created at
+pub async fn dump<W: Write>(w: &mut W, db: &EnvelopeDb, output: Output) -> Result<()> {
+    output
+        .write_stream(w, db.stream_raw(), |w, row| match &row.value {
+            Some(value) => writeln!(w, "{} {} {}={}", row.created_at, row.env, row.key, value),
+            None => {
+                let deleted = format!("{} {} {} (deleted)", row.created_at, row.env, row.key);
+                writeln!(w, "{}", style::paint(style::DELETED, deleted))
+            }
+        })
+        .await
+}
+
+/// Drops the history of `env`, keeping the current value of its variables
+pub async fn flatten(db: &EnvelopeDb, env: &str) -> Result<()> {
+    db.check_env_exists(env).await?;
+
+    Ok(db.flatten_env(env).await?)
+}
+
+/// Drops the versions of the variables of `env` created before the unix
+/// timestamp `ts`, keeping the current value of every variable, and writes
+/// how many were removed
+pub async fn purge<W: Write>(w: &mut W, db: &EnvelopeDb, env: &str, ts: i64) -> Result<()> {
+    db.check_env_exists(env).await?;
+
+    let removed = db.purge_older_than(env, ts).await?;
+    writeln!(
+        w,
+        "{} versions removed from {}",
+        removed,
+        style::paint(style::ENV, env)
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    #[tokio::test]
+    async fn test_history() {
+        let db = test_db().await;
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'B', 'b1', 1),
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'A', 'a2', 2),
+            ('dev', 'A', NULL, 3);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        history(&mut output, &db, "dev", None, None, None, Output::Text)
+            .await
+            .unwrap();
+        assert_eq!(
+            "1 A=a1\n2 A=a2\n3 A (deleted)\n1 B=b1\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let mut output: Vec<u8> = Vec::new();
+        history(&mut output, &db, "dev", Some("b"), None, None, Output::Text)
+            .await
+            .unwrap();
+        assert_eq!("1 B=b1\n", String::from_utf8(output).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_history_json() {
+        let db = test_db().await;
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'A', NULL, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let mut output: Vec<u8> = Vec::new();
+        history(&mut output, &db, "dev", None, None, None, Output::Json)
+            .await
+            .unwrap();
+
+        let rows: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!([
+                {"env": "dev", "key": "A", "value": "a1", "created_at": 1},
+                {"env": "dev", "key": "A", "value": null, "created_at": 2},
+            ]),
+            rows
+        );
+    }
+
+    #[tokio::test]
+    async fn test_changes() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'a1', 1),
+            ('dev', 'A', 'a2', 2),
+            ('dev', 'B', 'b1', 2),
+            ('dev', 'A', NULL, 3);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        changes(&mut output, &db, "dev", 2, 10, Output::Text)
+            .await
+            .unwrap();
+        assert_eq!(
+            "2 ~ A=a1 -> a2\n2 + B=b1\n3 - A=a2\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let mut output: Vec<u8> = Vec::new();
+        changes(&mut output, &db, "dev", 3, 10, Output::Json)
+            .await
+            .unwrap();
+        let rows: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!([
+                {"key": "A", "kind": "deleted", "old": "a2", "new": null, "created_at": 3},
+            ]),
+            rows
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dump() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES ('prod', 'A', 'p1', 1), ('dev', 'A', 'a1', 1), ('dev', 'A', NULL, 2);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        dump(&mut output, &db, Output::Text).await.unwrap();
+        assert_eq!(
+            "1 dev A=a1\n2 dev A (deleted)\n1 prod A=p1\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+    }
+}
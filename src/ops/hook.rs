@@ -0,0 +1,179 @@
+use std::io::{Result, Write};
+use std::path::Path;
+
+use crate::db::EnvelopeDb;
+use crate::std_err;
+
+use super::{loaded_keys, print_env, Shell, ENVELOPE_LOADED_VARS};
+
+/// Variable identifying the environment loaded by the shell hook and its
+/// state, the hook does nothing as long as it does not change
+pub const ENVELOPE_HOOK_STATE: &str = "ENVELOPE_HOOK_STATE";
+
+/// Sets the environment loaded by the shell hook
+pub async fn activate(db: &EnvelopeDb, env: &str) -> Result<()> {
+    db.check_env_exists(env).await?;
+
+    Ok(db.set_active_env(Some(env)).await?)
+}
+
+/// Disables the shell hook for the database
+pub async fn deactivate(db: &EnvelopeDb) -> Result<()> {
+    Ok(db.set_active_env(None).await?)
+}
+
+/// Returns the snippet that installs the hook in `shell`, the hook runs `exe`
+/// before every prompt
+pub fn hook_snippet(shell: Shell, exe: &str) -> Result<String> {
+    let exe = shell.quote(exe);
+    let snippet = match shell {
+        Shell::Bash => format!(
+            r#"_envelope_hook() {{
+  local previous_exit_status=$?
+  eval "$({exe} env --for-hook --shell bash)"
+  return $previous_exit_status
+}}
+if [[ ";${{PROMPT_COMMAND[*]:-}};" != *";_envelope_hook;"* ]]; then
+  PROMPT_COMMAND="_envelope_hook${{PROMPT_COMMAND:+;$PROMPT_COMMAND}}"
+fi
+"#
+        ),
+        Shell::Zsh => format!(
+            r#"_envelope_hook() {{
+  eval "$({exe} env --for-hook --shell zsh)"
+}}
+typeset -ag precmd_functions chpwd_functions
+if (( ! ${{precmd_functions[(I)_envelope_hook]}} )); then
+  precmd_functions=(_envelope_hook $precmd_functions)
+fi
+if (( ! ${{chpwd_functions[(I)_envelope_hook]}} )); then
+  chpwd_functions=(_envelope_hook $chpwd_functions)
+fi
+"#
+        ),
+        Shell::Fish => format!(
+            r#"function __envelope_hook --on-event fish_prompt --on-variable PWD
+    {exe} env --for-hook --shell fish | source
+end
+"#
+        ),
+        Shell::Powershell => return Err(std_err!("the hook is not available for powershell")),
+    };
+
+    Ok(snippet)
+}
+
+/// Writes the statements that bring the shell in sync with the active
+/// environment of the database found at `path`, nothing is written if the
+/// shell is already in sync.
+///
+/// Without a database or an active environment, the variables previously
+/// loaded by the hook are unset. `loaded` and `state` are the values of
+/// [`ENVELOPE_LOADED_VARS`] and [`ENVELOPE_HOOK_STATE`] in the shell.
+/// Returns the keys left out as they are not variable names, see
+/// [`print_env`].
+pub async fn print_hook_env<W: Write>(
+    w: &mut W,
+    db: Option<(&Path, &EnvelopeDb)>,
+    shell: Shell,
+    loaded: Option<&str>,
+    state: Option<&str>,
+) -> Result<Vec<String>> {
+    let active = match db {
+        Some((path, db)) => match db.active_env().await? {
+            Some(env) => {
+                let hash = db.fingerprint(&env).await?;
+                let fingerprint = format!("{}:{}:{}", path.display(), env, hash);
+                Some((db, env, fingerprint))
+            }
+            None => None,
+        },
+        None => None,
+    };
+
+    match active {
+        Some((_, _, fingerprint)) if state == Some(fingerprint.as_str()) => {}
+        Some((db, env, fingerprint)) => {
+            let skipped = print_env(w, db, &env, shell, loaded).await?;
+            writeln!(w, "{}", shell.export(ENVELOPE_HOOK_STATE, &fingerprint))?;
+            return Ok(skipped);
+        }
+        // only unload what has been loaded by the hook
+        None if state.is_none() => {}
+        None => {
+            for key in loaded_keys(loaded) {
+                writeln!(w, "{}", shell.unset(key))?;
+            }
+            writeln!(w, "{}", shell.unset(ENVELOPE_LOADED_VARS))?;
+            writeln!(w, "{}", shell.unset(ENVELOPE_HOOK_STATE))?;
+        }
+    }
+
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    async fn output(
+        db: Option<(&Path, &EnvelopeDb)>,
+        loaded: Option<&str>,
+        state: Option<&str>,
+    ) -> String {
+        let mut buf: Vec<u8> = Vec::new();
+        print_hook_env(&mut buf, db, Shell::Bash, loaded, state)
+            .await
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_hook_load() {
+        let db = test_db().await;
+        db.insert("dev", "A", "1").await.unwrap();
+        let path = Path::new("/project/.envelope");
+
+        assert_eq!("", output(Some((path, &db)), None, None).await);
+
+        db.set_active_env(Some("dev")).await.unwrap();
+        let loaded = output(Some((path, &db)), None, None).await;
+        assert!(loaded.starts_with("export A='1'\nexport ENVELOPE_LOADED_VARS='A'\n"));
+
+        let state = loaded
+            .lines()
+            .last()
+            .and_then(|l| l.strip_prefix("export ENVELOPE_HOOK_STATE='"))
+            .and_then(|l| l.strip_suffix('\''))
+            .unwrap();
+        assert!(state.starts_with("/project/.envelope:dev:"));
+
+        // nothing changed, nothing to do
+        assert_eq!("", output(Some((path, &db)), Some("A"), Some(state)).await);
+
+        db.insert("dev", "B", "2").await.unwrap();
+        let reloaded = output(Some((path, &db)), Some("A"), Some(state)).await;
+        assert!(reloaded.starts_with("export A='1'\nexport B='2'\n"));
+    }
+
+    #[tokio::test]
+    async fn test_hook_unload() {
+        assert_eq!("", output(None, Some("A,B"), None).await);
+        assert_eq!(
+            "unset A\nunset B\nunset ENVELOPE_LOADED_VARS\nunset ENVELOPE_HOOK_STATE\n",
+            output(None, Some("A,B"), Some("/project/.envelope:dev:0123abcd")).await
+        );
+
+        let db = test_db().await;
+        assert_eq!(
+            "unset A\nunset ENVELOPE_LOADED_VARS\nunset ENVELOPE_HOOK_STATE\n",
+            output(
+                Some((Path::new("/project/.envelope"), &db)),
+                Some("A"),
+                Some("/project/.envelope:dev:0123abcd")
+            )
+            .await
+        );
+    }
+}
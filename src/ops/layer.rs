@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Result, Write};
+
+use crate::db::{EnvelopeDb, SortOrder};
+use crate::dotenv;
+use crate::reference::{resolve_value, SecretResolver, OP_PREFIX};
+use crate::std_err;
+use crate::validate::ValueType;
+
+/// Value of a variable together with the environment that provided it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayeredVar {
+    pub value: String,
+    pub env: String,
+}
+
+/// Result of layering multiple environments on top of each other
+#[derive(Debug, Clone, Default)]
+pub struct Layers {
+    pub vars: BTreeMap<String, LayeredVar>,
+    /// keys that have been deleted in the highest environment that mentions
+    /// them and that must therefore be masked
+    pub removed: Vec<String>,
+}
+
+/// Ad-hoc changes applied on top of the stored environments, they are never
+/// written to the database
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub set: Vec<(String, String)>,
+    pub unset: Vec<String>,
+}
+
+/// Name used as provenance of the variables coming from `--set`
+pub const OVERRIDE_SOURCE: &str = "--set";
+
+impl Layers {
+    /// Applies `overrides` on top of the layered environments, `--set`
+    /// values win over every stored value while `--unset` removes the
+    /// variable entirely
+    pub fn apply(&mut self, overrides: &Overrides) {
+        for (key, value) in &overrides.set {
+            self.removed.retain(|k| k != key);
+            self.vars.insert(
+                key.clone(),
+                LayeredVar {
+                    value: value.clone(),
+                    env: OVERRIDE_SOURCE.into(),
+                },
+            );
+        }
+
+        for key in &overrides.unset {
+            self.vars.remove(key);
+            if !self.removed.contains(key) {
+                self.removed.push(key.clone());
+            }
+        }
+    }
+}
+
+/// Merges the variables of `envs`, later environments take precedence over
+/// earlier ones. A variable that has been deleted in an environment masks the
+/// values provided by the environments that come before it.
+pub async fn get_env(db: &EnvelopeDb, envs: &[String]) -> Result<Layers> {
+    if envs.is_empty() {
+        return Err(std_err!("at least one environment is required"));
+    }
+
+    let mut vars: BTreeMap<String, LayeredVar> = BTreeMap::new();
+    let mut removed: BTreeSet<String> = BTreeSet::new();
+
+    for env in envs {
+        db.check_env_exists(env).await?;
+
+        for key in db.list_deleted_var_in_env(env).await? {
+            vars.remove(&key);
+            removed.insert(key);
+        }
+
+        for row in db.list_var_in_env(env, SortOrder::Asc).await? {
+            removed.remove(&row.key);
+            vars.insert(
+                row.key,
+                LayeredVar {
+                    value: row.value,
+                    env: env.clone(),
+                },
+            );
+        }
+    }
+
+    Ok(Layers {
+        vars,
+        removed: removed.into_iter().collect(),
+    })
+}
+
+/// Replaces the 1Password references stored with `add --dynamic`, the `op://`
+/// values of the keys typed [`ValueType::Reference`] in the environment that
+/// provided them, by what `resolver` returns for them. Every other value is
+/// kept as it is, whatever it looks like.
+pub async fn resolve_dynamic<R: SecretResolver>(
+    db: &EnvelopeDb,
+    layers: &mut Layers,
+    resolver: &R,
+) -> Result<()> {
+    let mut types: BTreeMap<String, BTreeMap<String, ValueType>> = BTreeMap::new();
+    for (key, var) in layers.vars.iter_mut() {
+        if var.env == OVERRIDE_SOURCE || !var.value.starts_with(OP_PREFIX) {
+            continue;
+        }
+        if !types.contains_key(&var.env) {
+            types.insert(var.env.clone(), db.list_types(&var.env).await?);
+        }
+        if types[&var.env].get(key) == Some(&ValueType::Reference) {
+            var.value = resolve_value(resolver, &var.value).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the variables of the dotenv `contents` as a single layer, `source`
+/// is used as their provenance
+pub fn dotenv_layer(contents: &str, source: &str) -> Layers {
+    let vars = dotenv::from_dotenv(contents)
+        .into_iter()
+        .map(|entry| {
+            let var = LayeredVar {
+                value: entry.value,
+                env: source.into(),
+            };
+            (entry.key, var)
+        })
+        .collect();
+
+    Layers {
+        vars,
+        removed: Vec::new(),
+    }
+}
+
+/// Writes which environment provided each variable
+pub fn print_provenance<W: Write>(w: &mut W, layers: &Layers) -> Result<()> {
+    for (key, var) in &layers.vars {
+        writeln!(w, "{} <- {}", key, var.env)?;
+    }
+
+    for key in &layers.removed {
+        writeln!(w, "{} <- (deleted)", key)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+    use crate::reference::test::MockResolver;
+
+    async fn seed(db: &EnvelopeDb) {
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('base', 'A', 'base-a', 1),
+            ('base', 'B', 'base-b', 1),
+            ('base', 'C', 'base-c', 1),
+            ('dev', 'B', 'dev-b', 1),
+            ('dev', 'C', 'dev-c', 1),
+            ('dev', 'C', NULL, 2),
+            ('dev', 'D', 'dev-d', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_env_precedence() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        let layers = get_env(&db, &["base".into(), "dev".into()]).await.unwrap();
+        let vars: Vec<(&str, &str, &str)> = layers
+            .vars
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.value.as_str(), v.env.as_str()))
+            .collect();
+
+        assert_eq!(
+            vec![
+                ("A", "base-a", "base"),
+                ("B", "dev-b", "dev"),
+                ("D", "dev-d", "dev"),
+            ],
+            vars
+        );
+        assert_eq!(vec!["C".to_string()], layers.removed);
+    }
+
+    #[tokio::test]
+    async fn test_get_env_reversed_precedence() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        let layers = get_env(&db, &["dev".into(), "base".into()]).await.unwrap();
+        assert_eq!("base-b", layers.vars["B"].value);
+        assert_eq!("base-c", layers.vars["C"].value);
+        assert!(layers.removed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_env_expired() {
+        let db = test_db().await;
+        seed(&db).await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at, expires_at)
+            VALUES ('dev', 'A', 'dev-a', 2, 3);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        // a key that expired in dev masks the value of base like a deletion
+        let layers = get_env(&db, &["base".into(), "dev".into()]).await.unwrap();
+        assert!(!layers.vars.contains_key("A"));
+        assert_eq!(vec!["A".to_string(), "C".to_string()], layers.removed);
+    }
+
+    #[tokio::test]
+    async fn test_get_env_missing_env() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        assert!(get_env(&db, &["base".into(), "prod".into()]).await.is_err());
+        assert!(get_env(&db, &[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_apply_overrides() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        let mut layers = get_env(&db, &["base".into(), "dev".into()]).await.unwrap();
+        layers.apply(&Overrides {
+            set: vec![("B".into(), "set-b".into()), ("C".into(), "set-c".into())],
+            unset: vec!["A".into()],
+        });
+
+        assert_eq!("set-b", layers.vars["B"].value);
+        assert_eq!(OVERRIDE_SOURCE, layers.vars["B"].env);
+        assert_eq!("set-c", layers.vars["C"].value);
+        assert_eq!("dev-d", layers.vars["D"].value);
+        assert!(!layers.vars.contains_key("A"));
+        assert_eq!(vec!["A".to_string()], layers.removed);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_dynamic() {
+        let db = test_db().await;
+        db.insert("dev", "DYNAMIC", "op://Dev/db/password")
+            .await
+            .unwrap();
+        db.set_var_type("dev", "DYNAMIC", Some(ValueType::Reference))
+            .await
+            .unwrap();
+        db.insert("dev", "LITERAL", "op://Dev/db/password")
+            .await
+            .unwrap();
+        db.insert("dev", "MALFORMED", "vault://host").await.unwrap();
+        let resolver = MockResolver::new([("op://Dev/db/password", "hunter2")]);
+
+        let mut layers = get_env(&db, &["dev".into()]).await.unwrap();
+        resolve_dynamic(&db, &mut layers, &resolver).await.unwrap();
+        assert_eq!("hunter2", layers.vars["DYNAMIC"].value);
+        assert_eq!("op://Dev/db/password", layers.vars["LITERAL"].value);
+        assert_eq!("vault://host", layers.vars["MALFORMED"].value);
+
+        // a --set value is never resolved
+        layers.apply(&Overrides {
+            set: vec![("DYNAMIC".into(), "op://Dev/db/password".into())],
+            unset: vec![],
+        });
+        resolve_dynamic(&db, &mut layers, &resolver).await.unwrap();
+        assert_eq!("op://Dev/db/password", layers.vars["DYNAMIC"].value);
+    }
+
+    #[tokio::test]
+    async fn test_print_provenance() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        let layers = get_env(&db, &["base".into(), "dev".into()]).await.unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        print_provenance(&mut output, &layers).unwrap();
+
+        assert_eq!(
+            "A <- base\nB <- dev\nD <- dev\nC <- (deleted)\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_dotenv_layer() {
+        let mut layers = dotenv_layer("# comment\nA=1\nexport B='two'\n", ".env");
+        layers.apply(&Overrides {
+            set: vec![("C".into(), "3".into())],
+            unset: vec!["A".into()],
+        });
+
+        let vars: Vec<(&str, &str, &str)> = layers
+            .vars
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.value.as_str(), v.env.as_str()))
+            .collect();
+        assert_eq!(
+            vec![("B", "two", ".env"), ("C", "3", OVERRIDE_SOURCE)],
+            vars
+        );
+        assert_eq!(vec!["A".to_string()], layers.removed);
+    }
+}
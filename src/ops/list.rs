@@ -1,81 +1,265 @@
-use crate::db::{EnvelopeDb, Environment, EnvironmentRow, Truncate};
-use crate::std_err;
+use crate::db::{EnvelopeDb, Environment, EnvironmentRow, SortOrder};
+use crate::dotenv;
+use crate::store::Store;
+use crate::style;
+use crate::table::{self, Overflow, Table};
+use crate::validate::ValueType;
 
-use prettytable::{row, Table};
+use super::{resolve_value, Output};
+
+use serde::Serialize;
 
 use std::io;
-use std::io::{BufRead, Result, Write};
+use std::io::{Read, Result, Write};
 
 pub async fn print_from_stdin() -> Result<()> {
-    let mut table = Table::new();
-    table.add_row(row!["VARIABLE", "VALUE"]);
+    let mut table = Table::new(["VARIABLE", "VALUE"]);
 
-    let buf = io::BufReader::new(io::stdin());
-    for line in buf.lines() {
-        if line.is_err() {
-            continue;
-        }
+    let mut contents = String::new();
+    io::stdin().read_to_string(&mut contents)?;
+    for entry in dotenv::from_dotenv(&contents) {
+        table.add_row([entry.key, entry.value]);
+    }
 
-        if line.as_ref().unwrap().starts_with('#') {
-            continue;
-        }
+    table.render(
+        &mut io::stdout(),
+        table::terminal_width(),
+        Overflow::Truncate,
+    )
+}
 
-        if let Some((k, v)) = line.unwrap().split_once('=') {
-            table.add_row(row![FrB->k, Fb->v]);
+/// Writes the variables of `env` as a table sorted by key in `order`, values
+/// that do not fit in `width` columns are truncated or wrapped according to
+/// `overflow`. With `detailed` the type of each variable, its number of
+/// versions and the time it was last modified at are shown as well.
+pub async fn list<W: Write>(
+    writer: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    order: SortOrder,
+    width: Option<usize>,
+    overflow: Overflow,
+    detailed: bool,
+) -> Result<()> {
+    db.check_env_exists(env).await?;
+
+    let mut table = match detailed {
+        true => Table::new([
+            "ENVIRONMENT",
+            "VARIABLE",
+            "TYPE",
+            "VERSIONS",
+            "MODIFIED",
+            "VALUE",
+        ]),
+        false => Table::new(["ENVIRONMENT", "VARIABLE", "VALUE"]),
+    };
+    table.style_column(0, style::ENV);
+
+    if detailed {
+        let mut rows = db.list_var_detailed(env).await?;
+        if order == SortOrder::Desc {
+            rows.reverse();
+        }
+        let types = db.list_types(env).await?;
+        for row in rows {
+            let value_type = types.get(&row.key).map(ValueType::as_str);
+            table.add_row([
+                row.env,
+                row.key.clone(),
+                value_type.unwrap_or("").to_string(),
+                row.version_count.to_string(),
+                row.last_modified.to_string(),
+                row.value,
+            ]);
+        }
+    } else {
+        for row in db.list_var_in_env(env, order).await? {
+            table.add_row([row.env, row.key, row.value]);
         }
     }
 
-    table.printstd();
+    if !table.is_empty() {
+        table.render(writer, width, overflow)?;
+    }
 
     Ok(())
 }
 
-struct EnvRows(Vec<EnvironmentRow>);
+/// Writes the variables of `env` in dotenv format sorted by key in `order`,
+/// if `resolve` is set file references are replaced by the contents of the
+/// file, see [`resolve_value`]
+pub async fn list_raw<W: Write, S: Store>(
+    writer: &mut W,
+    db: &S,
+    env: &str,
+    order: SortOrder,
+    resolve: bool,
+    output: Output,
+) -> Result<()> {
+    db.check_env_exists(env).await?;
 
-impl From<EnvRows> for Table {
-    fn from(value: EnvRows) -> Self {
-        let mut table = Table::new();
-        table.set_titles(row!["ENVIRONMENT", "VARIABLE", "VALUE"]);
+    let mut envs: Vec<EnvironmentRow> = db.list_var_in_env(env, order).await?;
+    if resolve {
+        for env in envs.iter_mut() {
+            env.value = resolve_value(&env.value)?;
+        }
+    }
 
-        for env in value.0 {
-            table.add_row(row![Fy->&env.env, Frb->&env.key, Fb->&env.value]);
+    output.write(writer, &envs, |w, envs| {
+        for env in envs {
+            writeln!(w, "{}={}", &env.key, &env.value)?;
         }
+        Ok(())
+    })
+}
 
-        table
-    }
+/// Size of the value of a variable, see [`list_sizes`]
+#[derive(Debug, Serialize)]
+struct ValueSize {
+    key: String,
+    bytes: i64,
 }
 
-pub async fn list(db: &EnvelopeDb, env: &str, truncate: Truncate) -> Result<()> {
-    db.check_env_exists(&env)
-        .await
-        .map_err(|_| std_err!("env {} does not exist", env))?;
+/// Writes the size in bytes of the value of each variable of `env` sorted by
+/// key in `order`, without reading the values
+pub async fn list_sizes<W: Write>(
+    writer: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    order: SortOrder,
+    output: Output,
+) -> Result<()> {
+    db.check_env_exists(env).await?;
 
-    let envs: Vec<EnvironmentRow> = db.list_all_var_in_env(env, truncate).await?;
-    if !envs.is_empty() {
-        Table::from(EnvRows(envs)).printstd();
+    let mut sizes: Vec<ValueSize> = db
+        .value_sizes(env)
+        .await?
+        .into_iter()
+        .map(|(key, bytes)| ValueSize { key, bytes })
+        .collect();
+    if order == SortOrder::Desc {
+        sizes.reverse();
     }
 
-    Ok(())
+    output.write(writer, &sizes, |w, sizes| {
+        let mut table = Table::new(["VARIABLE", "BYTES"]);
+        for size in sizes {
+            table.add_row([size.key.clone(), size.bytes.to_string()]);
+        }
+        match table.is_empty() {
+            true => Ok(()),
+            false => table.render(w, None, Overflow::Truncate),
+        }
+    })
 }
 
-pub async fn list_raw<W: Write>(writer: &mut W, db: &EnvelopeDb, env: &str) -> Result<()> {
-    db.check_env_exists(&env)
+pub async fn list_envs<W: Write, S: Store>(writer: &mut W, db: &S, output: Output) -> Result<()> {
+    let envs: Vec<Environment> = db.list_environments().await?;
+    output.write(writer, &envs, |w, envs| {
+        for env in envs {
+            writeln!(w, "{}", style::paint(style::ENV, &env.env))?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    async fn seed(db: &EnvelopeDb) {
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'X', 1),
+            ('dev', 'B', 'Y', 2);",
+        )
+        .execute(db.get_pool())
         .await
-        .map_err(|_| std_err!("env {} does not exist", env))?;
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_raw_json() {
+        let db = test_db().await;
+        seed(&db).await;
 
-    let envs: Vec<EnvironmentRow> = db.list_all_var_in_env(env, Truncate::None).await?;
-    for env in envs {
-        writeln!(writer, "{}={}", &env.key, &env.value)?;
+        let mut output: Vec<u8> = Vec::new();
+        list_raw(&mut output, &db, "dev", SortOrder::Asc, false, Output::Json)
+            .await
+            .unwrap();
+
+        let rows: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!([
+                {"env": "dev", "key": "A", "value": "X", "created_at": 1},
+                {"env": "dev", "key": "B", "value": "Y", "created_at": 2},
+            ]),
+            rows
+        );
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_list_raw_order() {
+        let db = test_db().await;
+        seed(&db).await;
 
-pub async fn list_envs<W: Write>(writer: &mut W, db: &EnvelopeDb) -> Result<()> {
-    let envs: Vec<Environment> = db.list_environments().await?;
-    for env in envs {
-        writeln!(writer, "{}", &env.env)?;
+        let mut output: Vec<u8> = Vec::new();
+        list_raw(&mut output, &db, "dev", SortOrder::Asc, false, Output::Text)
+            .await
+            .unwrap();
+        assert_eq!("A=X\nB=Y\n", String::from_utf8(output).unwrap());
+
+        let mut output: Vec<u8> = Vec::new();
+        list_raw(
+            &mut output,
+            &db,
+            "dev",
+            SortOrder::Desc,
+            false,
+            Output::Text,
+        )
+        .await
+        .unwrap();
+        assert_eq!("B=Y\nA=X\n", String::from_utf8(output).unwrap());
     }
 
-    Ok(())
+    #[tokio::test]
+    async fn test_list_sizes() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        list_sizes(&mut output, &db, "dev", SortOrder::Desc, Output::Text)
+            .await
+            .unwrap();
+        assert_eq!(
+            "VARIABLE  BYTES\nB         1\nA         1\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let mut output: Vec<u8> = Vec::new();
+        list_sizes(&mut output, &db, "dev", SortOrder::Asc, Output::Json)
+            .await
+            .unwrap();
+        let sizes: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!([{"key": "A", "bytes": 1}, {"key": "B", "bytes": 1}]),
+            sizes
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_envs_json() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        let mut output: Vec<u8> = Vec::new();
+        list_envs(&mut output, &db, Output::Json).await.unwrap();
+
+        let envs: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(serde_json::json!([{"env": "dev"}]), envs);
+    }
 }
@@ -0,0 +1,15 @@
+use std::io::Result;
+
+use crate::db::EnvelopeDb;
+
+/// Locks an existing environment
+pub async fn lock(db: &EnvelopeDb, env: &str) -> Result<()> {
+    db.check_env_exists(env).await?;
+
+    Ok(db.lock_env(env).await?)
+}
+
+/// Unlocks an environment
+pub async fn unlock(db: &EnvelopeDb, env: &str) -> Result<()> {
+    Ok(db.unlock_env(env).await?)
+}
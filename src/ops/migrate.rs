@@ -0,0 +1,125 @@
+use std::io::{Result, Write};
+
+use crate::db::{EnvelopeDb, MigrationStatus};
+
+use super::Output;
+
+/// Writes the schema version of `db`, the one of this version of envelope
+/// and the migrations that would bring `db` up to date
+pub async fn migration_status<W: Write>(w: &mut W, db: &EnvelopeDb, output: Output) -> Result<()> {
+    let status = db.migration_status().await?;
+    output.write(w, &status, write_status)
+}
+
+fn write_status<W: Write>(w: &mut W, status: &MigrationStatus) -> Result<()> {
+    let current = status
+        .current
+        .map_or_else(|| "none".to_string(), |version| version.to_string());
+    writeln!(w, "database: {}", current)?;
+    writeln!(w, "envelope: {}", status.available)?;
+
+    if status.current > Some(status.available) {
+        return writeln!(
+            w,
+            "the database was migrated by a newer envelope, upgrade envelope to use it"
+        );
+    }
+    if status.pending.is_empty() {
+        return writeln!(w, "up to date");
+    }
+
+    writeln!(w, "pending:")?;
+    for migration in &status.pending {
+        writeln!(w, "  {} {}", migration.version, migration.description)?;
+    }
+    Ok(())
+}
+
+/// Applies the migrations `db` lacks, see [`EnvelopeDb::apply_migrations`],
+/// then writes them
+pub async fn migrate<W: Write>(w: &mut W, db: &EnvelopeDb) -> Result<()> {
+    let applied = db.apply_migrations().await?;
+    if applied.is_empty() {
+        return writeln!(w, "up to date");
+    }
+
+    for migration in applied {
+        writeln!(w, "applied {} {}", migration.version, migration.description)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect(":memory:")
+            .await
+            .unwrap();
+        let db = EnvelopeDb::from_pool(pool);
+        let migrations: Vec<(i64, String)> = sqlx::migrate!("./migrations")
+            .iter()
+            .map(|m| (m.version, m.description.to_string()))
+            .collect();
+        let newest = migrations.last().unwrap().0;
+
+        let mut output = Vec::new();
+        migration_status(&mut output, &db, Output::Text)
+            .await
+            .unwrap();
+        let pending: String = migrations
+            .iter()
+            .map(|(version, description)| format!("  {} {}\n", version, description))
+            .collect();
+        assert_eq!(
+            format!(
+                "database: none\nenvelope: {}\npending:\n{}",
+                newest, pending
+            ),
+            String::from_utf8(output).unwrap()
+        );
+
+        let mut output = Vec::new();
+        migrate(&mut output, &db).await.unwrap();
+        let applied: String = migrations
+            .iter()
+            .map(|(version, description)| format!("applied {} {}\n", version, description))
+            .collect();
+        assert_eq!(applied, String::from_utf8(output).unwrap());
+
+        let mut output = Vec::new();
+        migrate(&mut output, &db).await.unwrap();
+        assert_eq!("up to date\n", String::from_utf8(output).unwrap());
+
+        let mut output = Vec::new();
+        migration_status(&mut output, &db, Output::Json)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(
+            serde_json::json!({"current": newest, "available": newest, "pending": []}),
+            json
+        );
+    }
+
+    #[test]
+    fn test_newer_database() {
+        let status = MigrationStatus {
+            current: Some(99990101000000),
+            available: 20240101000000,
+            pending: Vec::new(),
+        };
+
+        let mut output = Vec::new();
+        write_status(&mut output, &status).unwrap();
+        assert_eq!(
+            "database: 99990101000000\nenvelope: 20240101000000\n\
+            the database was migrated by a newer envelope, upgrade envelope to use it\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+}
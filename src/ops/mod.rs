@@ -1,17 +1,83 @@
 mod add;
+mod audit;
+#[cfg(feature = "aws")]
+mod aws;
+#[cfg(feature = "azure")]
+mod azure;
 mod check;
+mod complete;
+mod confirm;
+mod convert;
+mod dedupe;
 mod delete;
+mod diff;
+mod doctor;
+mod doppler;
 mod drop;
 mod duplicate;
 mod edit;
+mod env;
 mod export;
+mod history;
+mod hook;
+mod layer;
 mod list;
+mod lock;
+mod migrate;
+mod onepassword;
+mod output;
+mod plan;
+#[cfg(any(feature = "aws", feature = "azure", feature = "vault"))]
+mod remote;
+mod rename;
+mod resolve;
+mod run;
+mod runtime;
+mod scan;
+mod shell;
+mod template;
+#[cfg(feature = "vault")]
+mod vault;
+mod watch;
 
 pub use add::*;
+pub use audit::*;
 pub use check::*;
+pub use complete::*;
+pub use confirm::*;
+pub use convert::*;
+pub use dedupe::*;
 pub use delete::*;
+pub use diff::*;
+pub use doctor::*;
+pub use doppler::*;
 pub use drop::*;
 pub use duplicate::*;
 pub use edit::*;
+pub use env::*;
 pub use export::*;
+pub use history::*;
+pub use hook::*;
+pub use layer::*;
 pub use list::*;
+pub use lock::*;
+pub use migrate::*;
+pub use onepassword::*;
+pub use output::*;
+pub use plan::*;
+#[cfg(any(feature = "aws", feature = "azure", feature = "vault"))]
+pub use remote::*;
+pub use rename::*;
+pub use resolve::*;
+pub use run::*;
+pub use runtime::*;
+pub use scan::*;
+pub use shell::*;
+#[cfg(feature = "aws")]
+pub use aws::*;
+#[cfg(feature = "azure")]
+pub use azure::*;
+pub use template::*;
+#[cfg(feature = "vault")]
+pub use vault::*;
+pub use watch::*;
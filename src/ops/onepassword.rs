@@ -0,0 +1,516 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io::{self, Result, Write};
+
+use serde::Deserialize;
+
+use crate::db::{EnvDiff, EnvelopeDb, EnvelopeResult};
+use crate::reference::{SecretRef, SecretResolver};
+use crate::secret::MASK;
+use crate::std_err;
+
+/// Where to get the 1Password CLI
+const OP_INSTALL_URL: &str = "https://developer.1password.com/docs/cli/get-started";
+
+/// Runs the 1Password CLI, the `op` binary or a fake one in tests
+pub trait OpCli: Sync {
+    /// runs `op` with `args` and returns what it wrote to stdout, fails with
+    /// the error it reported otherwise. `what` names what is read, for the
+    /// messages.
+    fn output(&self, args: &[&str], what: &str) -> impl Future<Output = Result<String>> + Send;
+}
+
+/// The `op` binary found in `PATH`, or another program
+pub struct OpBinary {
+    program: String,
+}
+
+impl OpBinary {
+    pub fn new(program: &str) -> Self {
+        OpBinary {
+            program: program.to_string(),
+        }
+    }
+}
+
+impl Default for OpBinary {
+    fn default() -> Self {
+        OpBinary::new("op")
+    }
+}
+
+impl OpCli for OpBinary {
+    async fn output(&self, args: &[&str], what: &str) -> Result<String> {
+        let output = tokio::process::Command::new(&self.program)
+            .args(args)
+            .output()
+            .await
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                        "cannot find the 1Password CLI {}, see {}",
+                        self.program, OP_INSTALL_URL
+                    ),
+                ),
+                _ => std_err!("cannot run {}: {}", self.program, e),
+            })?;
+
+        match output.status.success() {
+            true => String::from_utf8(output.stdout)
+                .map_err(|_| std_err!("{} is not valid UTF-8", what)),
+            false => Err(op_error(&String::from_utf8_lossy(&output.stderr), what)),
+        }
+    }
+}
+
+/// Error of `op` from what it wrote to stderr while reading `what`
+fn op_error(stderr: &str, what: &str) -> io::Error {
+    // op prefixes its messages with [ERROR] and a timestamp
+    let message = stderr
+        .lines()
+        .map(|line| match line.strip_prefix("[ERROR] ") {
+            Some(line) => line.splitn(3, ' ').nth(2).unwrap_or(line),
+            None => line,
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let message = message.trim();
+    let lower = message.to_lowercase();
+
+    if lower.contains("not currently signed in")
+        || lower.contains("not signed in")
+        || lower.contains("no accounts configured")
+    {
+        return io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "not signed in to 1Password, run `eval $(op signin)` first",
+        );
+    }
+    if lower.contains("isn't an item")
+        || lower.contains("isn't a vault")
+        || lower.contains("isn't a field")
+        || lower.contains("not found")
+    {
+        return io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found in 1Password: {}", what, message),
+        );
+    }
+
+    std_err!("op failed to read {}: {}", what, message)
+}
+
+/// Field of a 1Password item, as `op item get --format json` writes it
+#[derive(Debug, Deserialize)]
+struct ItemField {
+    #[serde(default)]
+    label: String,
+    #[serde(rename = "type", default)]
+    field_type: String,
+    value: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Item {
+    #[serde(default)]
+    fields: Vec<ItemField>,
+}
+
+/// Field of a 1Password item to import
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpField {
+    /// the label, uppercased with the characters that cannot be in a
+    /// variable name replaced by `_`
+    pub key: String,
+    pub value: String,
+    /// whether 1Password conceals the value, such as a password
+    pub concealed: bool,
+}
+
+/// Reads the fields of `item` in the 1Password `vault` that have a value.
+/// Fails if two labels give the same key.
+pub async fn op_item_fields<C: OpCli>(cli: &C, vault: &str, item: &str) -> Result<Vec<OpField>> {
+    let what = format!("item {} of vault {}", item, vault);
+    let json = cli
+        .output(
+            &["item", "get", item, "--vault", vault, "--format", "json"],
+            &what,
+        )
+        .await?;
+    let item: Item = serde_json::from_str(&json)
+        .map_err(|e| std_err!("invalid output of op item get for {}: {}", what, e))?;
+
+    let mut fields: BTreeMap<String, OpField> = BTreeMap::new();
+    for field in item.fields {
+        let value = match field.value {
+            Some(serde_json::Value::String(value)) => value,
+            Some(serde_json::Value::Null) | None => continue,
+            Some(value) => value.to_string(),
+        };
+        if field.label.is_empty() || value.is_empty() {
+            continue;
+        }
+
+        let key = field_key(&field.label);
+        if fields.contains_key(&key) {
+            return Err(std_err!(
+                "several fields of {} are named {}, rename them in 1Password",
+                what,
+                key
+            ));
+        }
+        let concealed = field.field_type == "CONCEALED";
+        fields.insert(
+            key.clone(),
+            OpField {
+                key,
+                value,
+                concealed,
+            },
+        );
+    }
+
+    Ok(fields.into_values().collect())
+}
+
+/// Key of the field labelled `label`
+fn field_key(label: &str) -> String {
+    label
+        .trim()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+/// Replaces the values of the `concealed` fields in `diff` with
+/// [`MASK`], before it is printed
+pub fn mask_concealed(diff: &mut EnvDiff, fields: &[OpField]) {
+    for field in fields.iter().filter(|f| f.concealed) {
+        if let Some(value) = diff.added.get_mut(&field.key) {
+            *value = MASK.to_string();
+        }
+        if let Some((old, new)) = diff.changed.get_mut(&field.key) {
+            *old = MASK.to_string();
+            *new = MASK.to_string();
+        }
+    }
+}
+
+/// Sets the `fields` of the 1Password `item` in `env` in a single
+/// transaction, then prints how many were imported
+pub async fn import_op<W: Write>(
+    writer: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    item: &str,
+    fields: &[OpField],
+) -> Result<()> {
+    let vars: Vec<(String, String)> = fields
+        .iter()
+        .map(|f| (f.key.clone(), f.value.clone()))
+        .collect();
+    let outcome = db.insert_many(env, &vars).await?;
+    writeln!(
+        writer,
+        "{} fields imported from {}, {} unchanged, {} concealed",
+        outcome.written,
+        item,
+        outcome.unchanged,
+        fields.iter().filter(|f| f.concealed).count()
+    )
+}
+
+/// Fails if `reference` is not an `op://vault/item/field` reference, the
+/// field can be in a section: `op://vault/item/section/field`
+pub fn check_op_reference(reference: &str) -> Result<()> {
+    match SecretRef::parse(reference)? {
+        Some(SecretRef::Op { .. }) => Ok(()),
+        _ => Err(std_err!(
+            "{} is not a 1Password reference, such as op://vault/item/field",
+            reference
+        )),
+    }
+}
+
+/// Reads the secret `reference` points to with `op read`
+pub async fn read_op<C: OpCli>(cli: &C, reference: &str) -> Result<String> {
+    check_op_reference(reference)?;
+    cli.output(&["read", "--no-newline", reference], reference)
+        .await
+}
+
+/// Resolves the 1Password references stored with `add --dynamic`, see
+/// [`super::resolve_dynamic`]. The other references are left as they are.
+pub struct OpResolver<C: OpCli>(pub C);
+
+impl<C: OpCli> SecretResolver for OpResolver<C> {
+    async fn resolve(&self, reference: &SecretRef<'_>) -> EnvelopeResult<Option<String>> {
+        let value = reference.to_string();
+        match reference {
+            SecretRef::Op { .. } => Ok(Some(read_op(&self.0, &value).await?)),
+            _ => Ok(Some(value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::db::{test_db, SortOrder};
+    use crate::validate::ValueType;
+
+    /// Replies to `op` with the output of its arguments, fails with the
+    /// stderr of the other ones
+    struct FakeOp {
+        outputs: BTreeMap<String, String>,
+        stderr: &'static str,
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl FakeOp {
+        fn new<const N: usize>(outputs: [(&str, &str); N]) -> Self {
+            FakeOp {
+                outputs: outputs
+                    .into_iter()
+                    .map(|(a, o)| (a.to_string(), o.to_string()))
+                    .collect(),
+                stderr: "[ERROR] 2024/01/01 00:00:00 \"nope\" isn't an item in the \"Dev\" vault.",
+                calls: Mutex::default(),
+            }
+        }
+    }
+
+    impl OpCli for FakeOp {
+        async fn output(&self, args: &[&str], what: &str) -> Result<String> {
+            let args = args.join(" ");
+            self.calls.lock().unwrap().push(args.clone());
+            match self.outputs.get(&args) {
+                Some(output) => Ok(output.clone()),
+                None => Err(op_error(self.stderr, what)),
+            }
+        }
+    }
+
+    const ITEM: &str = r#"{
+        "id": "abc",
+        "title": "myapp",
+        "fields": [
+            {"id": "username", "type": "STRING", "purpose": "USERNAME", "label": "username", "value": "admin"},
+            {"id": "password", "type": "CONCEALED", "purpose": "PASSWORD", "label": "password", "value": "hunter2"},
+            {"id": "notesPlain", "type": "STRING", "purpose": "NOTES", "label": "notesPlain"},
+            {"id": "x1", "type": "STRING", "label": "db host", "value": "db.internal", "section": {"id": "s", "label": "Database"}},
+            {"id": "x2", "type": "CONCEALED", "label": "api-key", "value": "k3y"}
+        ]
+    }"#;
+
+    fn op_field(key: &str, value: &str, concealed: bool) -> OpField {
+        OpField {
+            key: key.to_string(),
+            value: value.to_string(),
+            concealed,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_op_item_fields() {
+        let op = FakeOp::new([("item get myapp --vault Dev --format json", ITEM)]);
+        let fields = op_item_fields(&op, "Dev", "myapp").await.unwrap();
+        assert_eq!(
+            vec![
+                op_field("API_KEY", "k3y", true),
+                op_field("DB_HOST", "db.internal", false),
+                op_field("PASSWORD", "hunter2", true),
+                op_field("USERNAME", "admin", false),
+            ],
+            fields
+        );
+
+        let err = op_item_fields(&op, "Dev", "nope").await.unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        assert_eq!(
+            "item nope of vault Dev not found in 1Password: \"nope\" isn't an item in the \"Dev\" vault.",
+            err.to_string()
+        );
+
+        let op = FakeOp::new([(
+            "item get dup --vault Dev --format json",
+            r#"{"fields": [{"label": "db-host", "value": "a"}, {"label": "db host", "value": "b"}]}"#,
+        )]);
+        let err = op_item_fields(&op, "Dev", "dup").await.unwrap_err();
+        assert_eq!(
+            "several fields of item dup of vault Dev are named DB_HOST, rename them in 1Password",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_op_error() {
+        let err = op_error(
+            "[ERROR] 2024/01/01 00:00:00 You are not currently signed in. Please run `op signin --help` for instructions\n",
+            "op://Dev/db/password",
+        );
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        assert_eq!(
+            "not signed in to 1Password, run `eval $(op signin)` first",
+            err.to_string()
+        );
+
+        let err = op_error(
+            "[ERROR] 2024/01/01 00:00:00 \"Prod\" isn't a vault in this account. Specify the vault with its ID or name.",
+            "item myapp of vault Prod",
+        );
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+
+        let err = op_error("something else broke", "op://Dev/db/password");
+        assert_eq!(
+            "op failed to read op://Dev/db/password: something else broke",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_check_op_reference() {
+        for reference in ["op://Dev/db/password", "op://Dev/db/section/password"] {
+            assert!(check_op_reference(reference).is_ok(), "{}", reference);
+        }
+        for reference in [
+            "op://Dev/db",
+            "op://Dev//password",
+            "op://a/b/c/d/e",
+            "op:/Dev/db/x",
+        ] {
+            assert!(check_op_reference(reference).is_err(), "{}", reference);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_op_resolver() {
+        let db = test_db().await;
+        let op = OpResolver(FakeOp::new([(
+            "read --no-newline op://Dev/db/password",
+            "hunter2",
+        )]));
+        db.insert("dev", "DB_PASS", "op://Dev/db/password")
+            .await
+            .unwrap();
+        db.set_var_type("dev", "DB_PASS", Some(ValueType::Reference))
+            .await
+            .unwrap();
+        // stored without --dynamic, documents where the secret is
+        db.insert("dev", "DOC", "op://Dev/db/password")
+            .await
+            .unwrap();
+        db.insert("dev", "TOKEN", "ssm:///app/token").await.unwrap();
+
+        let vars = db
+            .list_var_in_env_resolved("dev", SortOrder::Asc, &op)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![
+                ("DB_PASS", "hunter2"),
+                ("DOC", "op://Dev/db/password"),
+                ("TOKEN", "ssm:///app/token")
+            ],
+            vars.iter()
+                .map(|v| (v.key.as_str(), v.value.as_str()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(1, op.0.calls.lock().unwrap().len());
+
+        db.insert("dev", "GONE", "op://Dev/nope/password")
+            .await
+            .unwrap();
+        db.set_var_type("dev", "GONE", Some(ValueType::Reference))
+            .await
+            .unwrap();
+        let err = db
+            .list_var_in_env_resolved("dev", SortOrder::Asc, &op)
+            .await
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, io::Error::from(err).kind());
+    }
+
+    #[tokio::test]
+    async fn test_import_op() {
+        let db = test_db().await;
+        db.insert("dev", "USERNAME", "admin").await.unwrap();
+        let fields = vec![
+            op_field("PASSWORD", "hunter2", true),
+            op_field("USERNAME", "admin", false),
+        ];
+
+        let mut diff = EnvDiff {
+            added: BTreeMap::from([("PASSWORD".to_string(), "hunter2".to_string())]),
+            ..Default::default()
+        };
+        mask_concealed(&mut diff, &fields);
+        assert_eq!(MASK, diff.added["PASSWORD"]);
+
+        let mut w = Vec::new();
+        import_op(&mut w, &db, "dev", "myapp", &fields)
+            .await
+            .unwrap();
+        assert_eq!(
+            "1 fields imported from myapp, 1 unchanged, 1 concealed\n",
+            String::from_utf8(w).unwrap()
+        );
+        let stored: Vec<(String, String)> = db
+            .list_var_in_env("dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.key, v.value))
+            .collect();
+        assert_eq!(
+            vec![
+                ("PASSWORD".to_string(), "hunter2".to_string()),
+                ("USERNAME".into(), "admin".into()),
+            ],
+            stored
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_op_binary() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("envelope-op-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            "#!/bin/sh\n\
+            if [ \"$3\" = op://Dev/db/password ]; then printf hunter2; exit 0; fi\n\
+            echo '[ERROR] 2024/01/01 00:00:00 You are not currently signed in.' >&2\n\
+            exit 1\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let op = OpBinary::new(path.to_str().unwrap());
+
+        let secret = read_op(&op, "op://Dev/db/password").await;
+        let signed_out = read_op(&op, "op://Dev/db/other").await;
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("hunter2", secret.unwrap());
+        assert_eq!(
+            io::ErrorKind::PermissionDenied,
+            signed_out.unwrap_err().kind()
+        );
+
+        let missing = OpBinary::new("/does/not/exist/op");
+        let err = read_op(&missing, "op://Dev/db/password").await.unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        assert!(
+            err.to_string()
+                .starts_with("cannot find the 1Password CLI /does/not/exist/op"),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{Error, Result, Write};
+
+use futures_util::{pin_mut, Stream, TryStreamExt};
+use serde::Serialize;
+
+use crate::db::EnvelopeResult;
+
+/// Format used by the commands that print data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Output {
+    /// human readable output, specific to each command
+    #[default]
+    Text,
+    /// JSON with stable field names, meant for scripts
+    Json,
+}
+
+impl Output {
+    /// Writes `value` as JSON, or with `text` for the human readable output
+    pub fn write<W, T, F>(self, w: &mut W, value: &T, text: F) -> Result<()>
+    where
+        W: Write,
+        T: Serialize + ?Sized,
+        F: FnOnce(&mut W, &T) -> Result<()>,
+    {
+        match self {
+            Output::Text => text(w, value),
+            Output::Json => {
+                serde_json::to_writer_pretty(&mut *w, value).map_err(Error::from)?;
+                writeln!(w)
+            }
+        }
+    }
+
+    /// Writes the items of `items` as they are read, as a JSON array or each
+    /// with `text` for the human readable output. The JSON is the same as the
+    /// one [`Output::write`] writes for a `Vec` of the items.
+    pub async fn write_stream<W, T, S, F>(self, w: &mut W, items: S, mut text: F) -> Result<()>
+    where
+        W: Write,
+        T: Serialize,
+        S: Stream<Item = EnvelopeResult<T>>,
+        F: FnMut(&mut W, &T) -> Result<()>,
+    {
+        pin_mut!(items);
+        let mut empty = true;
+        while let Some(item) = items.try_next().await? {
+            match self {
+                Output::Text => text(w, &item)?,
+                Output::Json => {
+                    // line breaks within values are escaped, the ones left
+                    // are the ones of the pretty printing
+                    let json = serde_json::to_string_pretty(&item).map_err(Error::from)?;
+                    let separator = if empty { "[" } else { "," };
+                    write!(w, "{}\n  {}", separator, json.replace('\n', "\n  "))?;
+                }
+            }
+            empty = false;
+        }
+
+        match (self, empty) {
+            (Output::Text, _) => Ok(()),
+            (Output::Json, true) => writeln!(w, "[]"),
+            (Output::Json, false) => writeln!(w, "\n]"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write() {
+        let value = vec!["a", "b"];
+        let text = |w: &mut Vec<u8>, v: &Vec<&str>| writeln!(w, "{}", v.join(" "));
+
+        let mut output: Vec<u8> = Vec::new();
+        Output::Text.write(&mut output, &value, text).unwrap();
+        assert_eq!("a b\n", String::from_utf8(output).unwrap());
+
+        let mut output: Vec<u8> = Vec::new();
+        Output::Json.write(&mut output, &value, text).unwrap();
+        assert_eq!(
+            "[\n  \"a\",\n  \"b\"\n]\n",
+            String::from_utf8(output).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_stream() {
+        let values = vec![
+            serde_json::json!({"key": "A", "value": "multi\nline"}),
+            serde_json::json!({"key": "B", "nested": {"list": [1, 2]}}),
+        ];
+        for values in [vec![], values] {
+            let mut expected: Vec<u8> = Vec::new();
+            Output::Json
+                .write(&mut expected, &values, |_, _| Ok(()))
+                .unwrap();
+
+            let items = futures_util::stream::iter(values.into_iter().map(Ok));
+            let mut output: Vec<u8> = Vec::new();
+            Output::Json
+                .write_stream(&mut output, items, |_, _| Ok(()))
+                .await
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(expected).unwrap(),
+                String::from_utf8(output).unwrap()
+            );
+        }
+
+        let items = futures_util::stream::iter(["a", "b"].map(Ok));
+        let mut output: Vec<u8> = Vec::new();
+        Output::Text
+            .write_stream(&mut output, items, |w, item| writeln!(w, "{}", item))
+            .await
+            .unwrap();
+        assert_eq!("a\nb\n", String::from_utf8(output).unwrap());
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::io::{Result, Write};
+
+use crate::db::{EnvDiff, EnvelopeDb, SortOrder};
+use crate::style::{self, ENV};
+
+use super::print_diff;
+
+/// What a mutating command changes, by environment
+pub type Changes = BTreeMap<String, EnvDiff>;
+
+/// Computes what setting `vars` in `env` changes, keys are uppercased like
+/// they are on insert
+pub async fn plan_set(
+    db: &EnvelopeDb,
+    env: &str,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<EnvDiff> {
+    let incoming: BTreeMap<String, String> = vars
+        .into_iter()
+        .map(|(k, v)| (k.to_ascii_uppercase(), v))
+        .collect();
+    let keys: Vec<String> = incoming.keys().cloned().collect();
+    let current = db
+        .get_vars(env, &keys)
+        .await?
+        .into_iter()
+        .filter_map(|(key, value)| Some((key, value?)))
+        .collect();
+
+    Ok(EnvDiff::between(current, incoming))
+}
+
+/// Computes what setting `key` to `value` in `env` changes when `key` is
+/// stored as is, see [`EnvelopeDb::insert_exact`]
+pub async fn plan_set_exact(db: &EnvelopeDb, env: &str, key: &str, value: &str) -> Result<EnvDiff> {
+    let current = db
+        .get_var_exact(env, key)
+        .await?
+        .map(|current| (key.to_string(), current));
+    let incoming = BTreeMap::from([(key.to_string(), value.to_string())]);
+
+    Ok(EnvDiff::between(current.into_iter().collect(), incoming))
+}
+
+/// Computes what deleting `keys` from `env` changes, every variable of `env`
+/// is deleted when `keys` is None
+pub async fn plan_delete(db: &EnvelopeDb, env: &str, keys: Option<&[&str]>) -> Result<EnvDiff> {
+    let mut removed = current_vars(db, env).await?;
+    if let Some(keys) = keys {
+        removed.retain(|key, _| keys.contains(&key.as_str()));
+    }
+
+    Ok(EnvDiff {
+        removed,
+        ..Default::default()
+    })
+}
+
+/// Computes what renaming `old_key` to `new_key` changes in `env`, or in
+/// every environment where `old_key` is set. Environments where it is not
+/// set are left out.
+pub async fn plan_rename(
+    db: &EnvelopeDb,
+    env: Option<&str>,
+    old_key: &str,
+    new_key: &str,
+) -> Result<Changes> {
+    let envs = match env {
+        Some(env) => vec![env.to_string()],
+        None => db
+            .list_environments()
+            .await?
+            .into_iter()
+            .map(|e| e.env)
+            .collect(),
+    };
+    let (old_key, new_key) = (old_key.to_ascii_uppercase(), new_key.to_ascii_uppercase());
+
+    let mut changes = Changes::new();
+    for env in envs {
+        let current = db.get_vars(&env, std::slice::from_ref(&old_key)).await?;
+        if let Some(Some(value)) = current.into_values().next() {
+            let diff = EnvDiff {
+                added: BTreeMap::from([(new_key.clone(), value.clone())]),
+                removed: BTreeMap::from([(old_key.clone(), value)]),
+                ..Default::default()
+            };
+            changes.insert(env, diff);
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Computes what copying the variables of `source` matching `include` to
+/// `target` changes, every variable is copied when `include` is empty
+pub async fn plan_duplicate(
+    db: &EnvelopeDb,
+    source: &str,
+    target: &str,
+    include: &[String],
+) -> Result<EnvDiff> {
+    let include: Vec<&str> = match include.is_empty() {
+        true => vec!["*"],
+        false => include.iter().map(String::as_str).collect(),
+    };
+    let copied = db.list_var_matching(source, &include).await?;
+
+    plan_set(
+        db,
+        target,
+        copied.into_iter().map(|row| (row.key, row.value)),
+    )
+    .await
+}
+
+/// Latest value of every variable of `env`
+pub async fn current_vars(db: &EnvelopeDb, env: &str) -> Result<BTreeMap<String, String>> {
+    Ok(db
+        .list_var_in_env(env, SortOrder::Asc)
+        .await?
+        .into_iter()
+        .map(|row| (row.key, row.value))
+        .collect())
+}
+
+/// Writes the name of every changed environment followed by its changes in
+/// the format of [`print_diff`]
+pub fn print_changes<W: Write>(w: &mut W, changes: &Changes) -> Result<()> {
+    let mut changed = changes
+        .iter()
+        .filter(|(_, diff)| !diff.is_empty())
+        .peekable();
+    if changed.peek().is_none() {
+        return writeln!(w, "no changes");
+    }
+
+    for (env, diff) in changed {
+        writeln!(w, "{}", style::paint(ENV, env))?;
+        print_diff(w, diff)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    fn vars(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_plan_set() {
+        let db = test_db().await;
+        db.insert("dev", "A", "1").await.unwrap();
+        db.insert("dev", "B", "2").await.unwrap();
+        db.insert("dev", "C", "3").await.unwrap();
+
+        let diff = plan_set(&db, "dev", vars(&[("a", "1"), ("b", "x"), ("d", "4")]))
+            .await
+            .unwrap();
+        assert_eq!(
+            EnvDiff {
+                added: BTreeMap::from([("D".into(), "4".into())]),
+                changed: BTreeMap::from([("B".into(), ("2".into(), "x".into()))]),
+                ..Default::default()
+            },
+            diff
+        );
+
+        // nothing has been written
+        assert_eq!(
+            3,
+            db.list_var_in_env("dev", SortOrder::Asc)
+                .await
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plan_set_non_ascii() {
+        let db = test_db().await;
+        db.insert("dev", "straße", "1").await.unwrap();
+
+        // sqlite upper() leaves the ß alone, so the planned key must too
+        let diff = plan_set(&db, "dev", vars(&[("straße", "1")]))
+            .await
+            .unwrap();
+        assert_eq!(EnvDiff::default(), diff);
+    }
+
+    #[tokio::test]
+    async fn test_plan_delete() {
+        let db = test_db().await;
+        db.insert("dev", "A", "1").await.unwrap();
+        db.insert("dev", "B", "2").await.unwrap();
+
+        let diff = plan_delete(&db, "dev", Some(&["B", "C"])).await.unwrap();
+        assert_eq!(BTreeMap::from([("B".into(), "2".into())]), diff.removed);
+        assert!(diff.added.is_empty() && diff.changed.is_empty());
+
+        let diff = plan_delete(&db, "dev", None).await.unwrap();
+        assert_eq!(2, diff.removed.len());
+    }
+
+    #[tokio::test]
+    async fn test_plan_duplicate() {
+        let db = test_db().await;
+        db.insert("dev", "DB_URL", "postgres://").await.unwrap();
+        db.insert("dev", "PORT", "80").await.unwrap();
+        db.insert("prod", "PORT", "443").await.unwrap();
+
+        let diff = plan_duplicate(&db, "dev", "prod", &[]).await.unwrap();
+        assert_eq!(
+            EnvDiff {
+                added: BTreeMap::from([("DB_URL".into(), "postgres://".into())]),
+                changed: BTreeMap::from([("PORT".into(), ("443".into(), "80".into()))]),
+                ..Default::default()
+            },
+            diff
+        );
+
+        let diff = plan_duplicate(&db, "dev", "prod", &["db_*".into()])
+            .await
+            .unwrap();
+        assert!(diff.changed.is_empty());
+        assert_eq!(1, diff.added.len());
+    }
+
+    #[test]
+    fn test_print_changes() {
+        let mut changes = Changes::new();
+        changes.insert("empty".into(), EnvDiff::default());
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        print_changes(&mut output, &changes).unwrap();
+        assert_eq!(
+            "no changes\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        changes.insert(
+            "dev".into(),
+            EnvDiff {
+                removed: BTreeMap::from([("A".into(), "1".into())]),
+                ..Default::default()
+            },
+        );
+        changes.insert(
+            "prod".into(),
+            EnvDiff {
+                added: BTreeMap::from([("B".into(), "2".into())]),
+                ..Default::default()
+            },
+        );
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        print_changes(&mut output, &changes).unwrap();
+        assert_eq!(
+            "dev\n- A=1\nprod\n+ B=2\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_plan_rename() {
+        let db = test_db().await;
+        db.insert("dev", "HOST", "localhost").await.unwrap();
+        db.insert("prod", "HOST", "example.com").await.unwrap();
+        db.insert("test", "PORT", "80").await.unwrap();
+
+        let changes = plan_rename(&db, None, "host", "hostname").await.unwrap();
+        assert_eq!(vec!["dev", "prod"], changes.keys().collect::<Vec<_>>());
+        assert_eq!(
+            EnvDiff {
+                added: BTreeMap::from([("HOSTNAME".into(), "localhost".into())]),
+                removed: BTreeMap::from([("HOST".into(), "localhost".into())]),
+                ..Default::default()
+            },
+            changes["dev"]
+        );
+
+        let changes = plan_rename(&db, Some("test"), "HOST", "X").await.unwrap();
+        assert!(changes.is_empty());
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::db::EnvDiff;
+
+/// What an export writes to a remote store: `diff` goes from the remote
+/// variables to the local ones and `unchanged` counts the variables both
+/// have
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ExportPlan {
+    pub diff: EnvDiff,
+    pub unchanged: usize,
+}
+
+impl ExportPlan {
+    pub fn new(remote: BTreeMap<String, String>, vars: &BTreeMap<String, String>) -> Self {
+        let diff = EnvDiff::between(remote, vars.clone());
+        let unchanged = vars.len() - diff.added.len() - diff.changed.len();
+        ExportPlan { diff, unchanged }
+    }
+}
+
+/// Counts of an export, `removed` only for the stores where the environment
+/// is written as a whole
+impl fmt::Display for ExportPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} created, {} updated, {} unchanged",
+            self.diff.added.len(),
+            self.diff.changed.len(),
+            self.unchanged
+        )?;
+        match self.diff.removed.len() {
+            0 => Ok(()),
+            removed => write!(f, ", {} removed", removed),
+        }
+    }
+}
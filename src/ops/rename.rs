@@ -0,0 +1,85 @@
+use std::io::{Result, Write};
+
+use crate::db::EnvelopeDb;
+use crate::style;
+
+use super::check_key;
+
+/// Renames `old_key` to `new_key` in `env`, or in every environment where it
+/// is set, then writes where it has been renamed
+pub async fn rename<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    env: Option<&str>,
+    old_key: &str,
+    new_key: &str,
+) -> Result<()> {
+    check_key(new_key)?;
+
+    let envs = match env {
+        Some(env) => {
+            db.check_env_exists(env).await?;
+            db.rename_var(env, old_key, new_key).await?;
+            vec![env.to_string()]
+        }
+        None => db.rename_var_everywhere(old_key, new_key).await?,
+    };
+
+    let envs: Vec<String> = envs
+        .iter()
+        .map(|env| style::paint(style::ENV, env))
+        .collect();
+    writeln!(
+        w,
+        "renamed {} to {} in {}",
+        old_key.to_ascii_uppercase(),
+        new_key.to_ascii_uppercase(),
+        envs.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    #[tokio::test]
+    async fn test_rename() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'HOST', 'localhost', 1),
+            ('dev', 'PORT', '80', 1),
+            ('prod', 'HOST', 'example.com', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        rename(&mut output, &db, None, "host", "hostname")
+            .await
+            .unwrap();
+        assert_eq!(
+            "renamed HOST to HOSTNAME in dev, prod\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        rename(&mut output, &db, Some("dev"), "port", "http_port")
+            .await
+            .unwrap();
+        assert_eq!(
+            "renamed PORT to HTTP_PORT in dev\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        assert!(rename(&mut Vec::new(), &db, Some("test"), "HOSTNAME", "X")
+            .await
+            .is_err());
+        assert!(rename(&mut Vec::new(), &db, None, "HOSTNAME", "#HOST")
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::io::Result;
+
+use crate::std_err;
+
+/// Prefix of the values that reference the contents of a file
+pub const FILE_REF_PREFIX: &str = "@file:";
+
+/// Resolves `value` if it is a file reference such as `@file:/path/to/secret`,
+/// the reference is replaced by the contents of the file without its trailing
+/// newline. Any other value is returned as is.
+pub fn resolve_value(value: &str) -> Result<String> {
+    let Some(path) = value.strip_prefix(FILE_REF_PREFIX) else {
+        return Ok(value.to_string());
+    };
+
+    let contents =
+        fs::read_to_string(path).map_err(|e| std_err!("cannot resolve {}: {}", value, e))?;
+
+    let contents = contents.strip_suffix('\n').unwrap_or(&contents);
+    let contents = contents.strip_suffix('\r').unwrap_or(contents);
+
+    Ok(contents.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_file() {
+        let path = std::env::temp_dir().join(format!("envelope-resolve-{}", std::process::id()));
+        fs::write(&path, "s3cr3t\n").unwrap();
+
+        let value = format!("{}{}", FILE_REF_PREFIX, path.display());
+        let resolved = resolve_value(&value);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!("s3cr3t", resolved.unwrap());
+    }
+
+    #[test]
+    fn test_resolve_missing_file() {
+        let value = format!("{}/does/not/exist", FILE_REF_PREFIX);
+        assert!(resolve_value(&value).is_err());
+    }
+
+    #[test]
+    fn test_resolve_literal() {
+        assert_eq!(
+            "postgres://localhost",
+            resolve_value("postgres://localhost").unwrap()
+        );
+        assert_eq!("file:/etc/hosts", resolve_value("file:/etc/hosts").unwrap());
+    }
+}
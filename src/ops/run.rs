@@ -0,0 +1,322 @@
+use std::fs;
+use std::io::{Result, Write};
+use std::path::Path;
+
+use crate::db::EnvelopeDb;
+use crate::std_err;
+use crate::subproc::ChildProcess;
+
+use super::{
+    dotenv_layer, get_env, print_provenance, resolve_dynamic, Layers, OpBinary, OpResolver,
+    Overrides,
+};
+
+/// Inherited variables that are kept by default in a pristine environment
+pub const PRISTINE_KEEP: &[&str] = &["PATH", "HOME", "TERM"];
+
+/// Dotenv file loaded by `run` when no envelope database is used
+pub const DOTENV_FILE: &str = ".env";
+
+/// Describes how the environment of a command is built
+#[derive(Debug, Default)]
+pub struct RunOptions {
+    /// environments layered on top of each other, later ones take precedence
+    pub envs: Vec<String>,
+    /// ad-hoc changes applied after the stored variables
+    pub overrides: Overrides,
+    /// start from an empty environment instead of inheriting the current one
+    pub pristine: bool,
+    /// inherited variables kept in a pristine environment, on top of
+    /// [`PRISTINE_KEEP`]
+    pub keep: Vec<String>,
+    /// write which environment provided each variable
+    pub verbose: bool,
+}
+
+/// Prepares `cmd` to be run with the variables of the environments of `opts`
+/// layered on top of each other and its overrides applied last. The
+/// precedence is: inherited environment, then stored variables, then
+/// overrides. In pristine mode nothing is inherited besides the variables
+/// that have been explicitly kept, overrides still apply.
+///
+/// If `verbose` is set, the environment that provided each variable is
+/// written to `w`
+pub async fn prepare_run<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    opts: &RunOptions,
+    cmd: &[String],
+) -> Result<ChildProcess> {
+    let layers = run_env(db, opts).await?;
+    if opts.verbose {
+        print_provenance(w, &layers)?;
+    }
+
+    command_with(&layers, opts, cmd)
+}
+
+/// Prepares `cmd` like [`prepare_run`], the variables are read from the
+/// dotenv file at `path` instead of the stored environments, which are ignored
+pub fn prepare_run_dotenv<W: Write>(
+    w: &mut W,
+    path: &Path,
+    opts: &RunOptions,
+    cmd: &[String],
+) -> Result<ChildProcess> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| std_err!("cannot read {}: {}", path.display(), e))?;
+
+    let mut layers = dotenv_layer(&contents, &path.display().to_string());
+    layers.apply(&opts.overrides);
+    if opts.verbose {
+        print_provenance(w, &layers)?;
+    }
+
+    command_with(&layers, opts, cmd)
+}
+
+/// Layers the environments of `opts` and applies its overrides, then
+/// resolves the 1Password references stored with `add --dynamic`
+pub async fn run_env(db: &EnvelopeDb, opts: &RunOptions) -> Result<Layers> {
+    resolve_env(db, &stored_env(db, opts).await?).await
+}
+
+/// Layers the environments of `opts` and applies its overrides, the values
+/// are kept as they are stored
+pub async fn stored_env(db: &EnvelopeDb, opts: &RunOptions) -> Result<Layers> {
+    let mut layers = get_env(db, &opts.envs).await?;
+    layers.apply(&opts.overrides);
+
+    Ok(layers)
+}
+
+/// Returns `layers` with the 1Password references stored with `add --dynamic`
+/// read with `op`, see [`resolve_dynamic`]
+pub async fn resolve_env(db: &EnvelopeDb, layers: &Layers) -> Result<Layers> {
+    let mut layers = layers.clone();
+    resolve_dynamic(db, &mut layers, &OpResolver(OpBinary::default())).await?;
+
+    Ok(layers)
+}
+
+/// Builds `cmd` with the environment described by `layers` and `opts`
+pub fn command_with(layers: &Layers, opts: &RunOptions, cmd: &[String]) -> Result<ChildProcess> {
+    let (program, args) = cmd
+        .split_first()
+        .ok_or_else(|| std_err!("no command to run"))?;
+
+    let vars: Vec<(&str, &str)> = layers
+        .vars
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.value.as_str()))
+        .collect();
+    let removed: Vec<&str> = layers.removed.iter().map(String::as_str).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let child = ChildProcess::new(program, &args, &vars).env_remove(&removed);
+    if !opts.pristine {
+        return Ok(child);
+    }
+
+    let keep: Vec<&str> = PRISTINE_KEEP
+        .iter()
+        .copied()
+        .chain(opts.keep.iter().map(String::as_str))
+        .collect();
+    Ok(child.pristine(&keep))
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use crate::db::{test_db, SortOrder};
+    use std::process::ExitStatus;
+
+    async fn run_sh(db: &EnvelopeDb, opts: RunOptions, script: &str) -> ExitStatus {
+        let opts = RunOptions {
+            envs: vec!["dev".into()],
+            ..opts
+        };
+        let cmd = ["sh".to_string(), "-c".to_string(), script.to_string()];
+        prepare_run(&mut Vec::new(), db, &opts, &cmd)
+            .await
+            .unwrap()
+            .run_shell_command()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_run_precedence() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'ENVELOPE_TEST_RUN_STORED', 'stored', 1),
+            ('dev', 'ENVELOPE_TEST_RUN_SET', 'stored', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        std::env::set_var("ENVELOPE_TEST_RUN_INHERITED", "inherited");
+        std::env::set_var("ENVELOPE_TEST_RUN_STORED", "inherited");
+        std::env::set_var("ENVELOPE_TEST_RUN_SET", "inherited");
+
+        let overrides = Overrides {
+            set: vec![("ENVELOPE_TEST_RUN_SET".into(), "set".into())],
+            unset: vec![],
+        };
+        let opts = RunOptions {
+            overrides,
+            ..Default::default()
+        };
+        let status = run_sh(
+            &db,
+            opts,
+            r#"[ "$ENVELOPE_TEST_RUN_INHERITED" = inherited ] \
+            && [ "$ENVELOPE_TEST_RUN_STORED" = stored ] \
+            && [ "$ENVELOPE_TEST_RUN_SET" = set ]"#,
+        )
+        .await;
+        assert!(status.success());
+    }
+
+    #[tokio::test]
+    async fn test_run_unset() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES ('dev', 'ENVELOPE_TEST_UNSET_STORED', 'stored', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        std::env::set_var("ENVELOPE_TEST_UNSET_INHERITED", "inherited");
+
+        let overrides = Overrides {
+            set: vec![],
+            unset: vec![
+                "ENVELOPE_TEST_UNSET_STORED".into(),
+                "ENVELOPE_TEST_UNSET_INHERITED".into(),
+            ],
+        };
+        let opts = RunOptions {
+            overrides,
+            ..Default::default()
+        };
+        let status = run_sh(
+            &db,
+            opts,
+            r#"[ -z "${ENVELOPE_TEST_UNSET_STORED+x}" ] \
+            && [ -z "${ENVELOPE_TEST_UNSET_INHERITED+x}" ]"#,
+        )
+        .await;
+        assert!(status.success());
+
+        let rows = db.list_var_in_env("dev", SortOrder::Asc).await.unwrap();
+        assert_eq!(1, rows.len());
+    }
+
+    #[tokio::test]
+    async fn test_run_pristine() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES ('dev', 'ENVELOPE_TEST_PRISTINE_STORED', 'stored', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        std::env::set_var("ENVELOPE_TEST_PRISTINE_INHERITED", "inherited");
+        std::env::set_var("ENVELOPE_TEST_PRISTINE_KEPT", "kept");
+
+        let opts = RunOptions {
+            overrides: Overrides {
+                set: vec![("ENVELOPE_TEST_PRISTINE_SET".into(), "set".into())],
+                unset: vec![],
+            },
+            pristine: true,
+            keep: vec!["ENVELOPE_TEST_PRISTINE_KEPT".into()],
+            ..Default::default()
+        };
+        let status = run_sh(
+            &db,
+            opts,
+            r#"[ -z "${ENVELOPE_TEST_PRISTINE_INHERITED+x}" ] \
+            && [ -n "$PATH" ] \
+            && [ "$ENVELOPE_TEST_PRISTINE_KEPT" = kept ] \
+            && [ "$ENVELOPE_TEST_PRISTINE_STORED" = stored ] \
+            && [ "$ENVELOPE_TEST_PRISTINE_SET" = set ]"#,
+        )
+        .await;
+        assert!(status.success());
+    }
+
+    #[tokio::test]
+    async fn test_run_pristine_layers() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('base', 'ENVELOPE_TEST_LAYERS_BASE', 'base', 1),
+            ('base', 'ENVELOPE_TEST_LAYERS_BOTH', 'base', 1),
+            ('dev', 'ENVELOPE_TEST_LAYERS_BOTH', 'dev', 1),
+            ('dev', 'ENVELOPE_TEST_LAYERS_DEV', 'dev', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        std::env::set_var("ENVELOPE_TEST_LAYERS_INHERITED", "inherited");
+
+        let opts = RunOptions {
+            envs: vec!["base".into(), "dev".into()],
+            pristine: true,
+            ..Default::default()
+        };
+        let cmd = [
+            "sh".to_string(),
+            "-c".to_string(),
+            r#"[ -z "${ENVELOPE_TEST_LAYERS_INHERITED+x}" ] \
+            && [ "$ENVELOPE_TEST_LAYERS_BASE" = base ] \
+            && [ "$ENVELOPE_TEST_LAYERS_DEV" = dev ] \
+            && [ "$ENVELOPE_TEST_LAYERS_BOTH" = dev ]"#
+                .to_string(),
+        ];
+        let status = prepare_run(&mut Vec::new(), &db, &opts, &cmd)
+            .await
+            .unwrap()
+            .run_shell_command()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[tokio::test]
+    async fn test_run_literal_references() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'ENVELOPE_TEST_LITERAL_VAULT', 'vault://host', 1),
+            ('dev', 'ENVELOPE_TEST_LITERAL_SSM', 'ssm://param', 1),
+            ('dev', 'ENVELOPE_TEST_LITERAL_OP', 'op://Dev/db/password', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        // only the references stored with --dynamic are resolved, the other
+        // values are passed as they are, malformed references included
+        let status = run_sh(
+            &db,
+            RunOptions::default(),
+            r#"[ "$ENVELOPE_TEST_LITERAL_VAULT" = vault://host ] \
+            && [ "$ENVELOPE_TEST_LITERAL_SSM" = ssm://param ] \
+            && [ "$ENVELOPE_TEST_LITERAL_OP" = op://Dev/db/password ]"#,
+        )
+        .await;
+        assert!(status.success());
+    }
+}
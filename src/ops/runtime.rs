@@ -0,0 +1,124 @@
+use std::collections::BTreeMap;
+use std::io::{Result, Write};
+
+use crate::db::{EnvDiff, EnvelopeDb};
+use crate::secret::MaskSecrets;
+use crate::{err, style};
+
+use super::{get_env, print_diff, Output};
+
+/// Compares the `stored` variables with the `runtime` ones: variables only
+/// set in the runtime are `added`, stored ones missing from it are `removed`
+/// and the ones with another value are `changed`. Variables only set in the
+/// runtime are ignored unless their name starts with `prefix`.
+pub fn runtime_drift<I>(
+    stored: BTreeMap<String, String>,
+    runtime: I,
+    prefix: Option<&str>,
+) -> EnvDiff
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let runtime = runtime
+        .into_iter()
+        .filter(|(key, _)| {
+            stored.contains_key(key) || prefix.is_some_and(|prefix| key.starts_with(prefix))
+        })
+        .collect();
+
+    EnvDiff::between(stored, runtime)
+}
+
+/// Writes how the `runtime` variables drifted from the layered `envs`, see
+/// [`runtime_drift`], and fails if they did. Values are compared as is but
+/// secret ones are masked.
+pub async fn check_runtime<W, I>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    envs: &[String],
+    runtime: I,
+    prefix: Option<&str>,
+    output: Output,
+) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = (String, String)>,
+{
+    let stored = get_env(db, envs)
+        .await?
+        .vars
+        .into_iter()
+        .map(|(key, var)| (key, var.value))
+        .collect();
+
+    let mut diff = runtime_drift(stored, runtime, prefix);
+    diff.mask_secrets();
+
+    let envs = envs.join(", ");
+    output.write(w, &diff, |w, diff| match diff.is_empty() {
+        true => writeln!(w, "in sync with {}", style::paint(style::ENV, &envs)),
+        false => print_diff(w, diff),
+    })?;
+
+    match diff.added.len() + diff.removed.len() + diff.changed.len() {
+        0 => Ok(()),
+        1 => err!("1 variable drifted from {}", envs),
+        n => err!("{} variables drifted from {}", n, envs),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+    use crate::secret::MASK;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_runtime_drift() {
+        let stored = BTreeMap::from_iter(vars(&[("A", "1"), ("B", "2"), ("C", "3")]));
+        let runtime = vars(&[("A", "1"), ("B", "2 "), ("APP_X", "x"), ("HOME", "/root")]);
+
+        let diff = runtime_drift(stored.clone(), runtime.clone(), None);
+        assert!(diff.added.is_empty());
+        assert_eq!(vec!["C"], diff.removed.keys().collect::<Vec<_>>());
+        assert_eq!(("2".into(), "2 ".into()), diff.changed["B"]);
+
+        let diff = runtime_drift(stored, runtime, Some("APP_"));
+        assert_eq!(vec!["APP_X"], diff.added.keys().collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_check_runtime() {
+        let db = test_db().await;
+        db.insert("dev", "HOST", "localhost").await.unwrap();
+        db.insert("dev", "API_KEY", "abc").await.unwrap();
+
+        let envs = ["dev".to_string()];
+        let runtime = vars(&[("HOST", "localhost"), ("API_KEY", "abc")]);
+        let mut output = anstream::StripStream::new(Vec::new());
+        check_runtime(&mut output, &db, &envs, runtime, None, Output::Text)
+            .await
+            .unwrap();
+        assert_eq!(
+            "in sync with dev\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let runtime = vars(&[("API_KEY", "abd")]);
+        let mut output = anstream::StripStream::new(Vec::new());
+        let err = check_runtime(&mut output, &db, &envs, runtime, None, Output::Text)
+            .await
+            .unwrap_err();
+        assert_eq!("2 variables drifted from dev", err.to_string());
+        assert_eq!(
+            format!("- HOST=localhost\n~ API_KEY={} -> {}\n", MASK, MASK),
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+    }
+}
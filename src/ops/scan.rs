@@ -0,0 +1,338 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::db::{EnvelopeDb, SortOrder};
+use crate::table::{Overflow, Table};
+use crate::{err, std_err};
+
+use super::Output;
+
+/// File at the root of the scanned directory holding extra patterns, one
+/// regex per line whose first group captures the name of the variable
+pub const PATTERNS_FILE: &str = ".envelope-scan";
+
+/// Files bigger than this are not read, they are rarely sources
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Common ways of reading a variable, the first group captures its name
+const DEFAULT_PATTERNS: &[&str] = &[
+    // javascript
+    r"process\.env\.([A-Za-z_]\w*)",
+    r#"process\.env\[["']([A-Za-z_]\w*)["']\]"#,
+    // python
+    r#"os\.environ\[["']([A-Za-z_]\w*)["']\]"#,
+    r#"os\.(?:environ\.get|getenv)\(\s*["']([A-Za-z_]\w*)["']"#,
+    // rust
+    r#"env::var(?:_os)?\(\s*"([A-Za-z_]\w*)""#,
+    r#"env!\(\s*"([A-Za-z_]\w*)""#,
+    // go, ruby, java, c and php
+    r#"os\.(?:Getenv|LookupEnv)\(\s*"([A-Za-z_]\w*)""#,
+    r#"ENV(?:\.fetch\(|\[)\s*["']([A-Za-z_]\w*)["']"#,
+    r#"getenv\(\s*["']([A-Za-z_]\w*)["']"#,
+    // shell, yaml and docker compose, with an optional default
+    r"\$\{([A-Za-z_]\w*)(?::?[-=?+][^}]*)?\}",
+];
+
+/// Where a variable is referenced
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+}
+
+/// Variables referenced by the sources compared to the ones of an env
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ScanReport {
+    /// variables referenced but not set in the env, with where they are
+    pub missing: BTreeMap<String, Vec<Location>>,
+    /// variables set in the env but never referenced
+    pub unused: Vec<String>,
+}
+
+/// Regexes finding the variables referenced in a file
+#[derive(Debug)]
+pub struct Patterns(Vec<Regex>);
+
+impl Patterns {
+    /// The default patterns along with `extra` ones, which must capture the
+    /// name of the variable in their first group
+    pub fn new<S: AsRef<str>>(extra: &[S]) -> Result<Self> {
+        let mut patterns = Vec::new();
+        let all = DEFAULT_PATTERNS
+            .iter()
+            .copied()
+            .chain(extra.iter().map(AsRef::as_ref));
+        for pattern in all {
+            let regex =
+                Regex::new(pattern).map_err(|e| std_err!("invalid pattern {}: {}", pattern, e))?;
+            if regex.captures_len() < 2 {
+                return err!("pattern {} does not capture the variable name", pattern);
+            }
+            patterns.push(regex);
+        }
+
+        Ok(Patterns(patterns))
+    }
+
+    /// Reads the extra patterns of the [`PATTERNS_FILE`] of `root`, if any,
+    /// blank lines and lines starting with `#` are skipped
+    pub fn load(root: &Path) -> Result<Self> {
+        let extra = match fs::read_to_string(root.join(PATTERNS_FILE)) {
+            Ok(contents) => contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+
+        Patterns::new(&extra)
+    }
+
+    /// Names of the variables referenced in `contents` with the line they are
+    /// referenced at, counted from 1. A name matched by several patterns on
+    /// the same line is only listed once.
+    fn references(&self, contents: &str) -> Vec<(String, usize)> {
+        let mut found = Vec::new();
+        for (n, line) in contents.lines().enumerate() {
+            let mut names = BTreeSet::new();
+            for regex in &self.0 {
+                for captures in regex.captures_iter(line) {
+                    if let Some(name) = captures.get(1) {
+                        names.insert(name.as_str());
+                    }
+                }
+            }
+            found.extend(names.into_iter().map(|name| (name.to_string(), n + 1)));
+        }
+
+        found
+    }
+}
+
+/// Finds the variables referenced by the files of `root`, the files ignored
+/// by `.gitignore` and hidden ones are skipped, as well as binaries
+fn find_references(root: &Path, patterns: &Patterns) -> BTreeMap<String, Vec<Location>> {
+    let mut references: BTreeMap<String, Vec<Location>> = BTreeMap::new();
+    let walk = WalkBuilder::new(root).require_git(false).build();
+    for entry in walk.flatten() {
+        let path = entry.path();
+        let is_small_file = entry
+            .metadata()
+            .map(|m| m.is_file() && m.len() <= MAX_FILE_SIZE)
+            .unwrap_or(false);
+        if !is_small_file {
+            continue;
+        }
+        let Ok(contents) = fs::read_to_string(path) else {
+            continue;
+        };
+
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        for (name, line) in patterns.references(&contents) {
+            references.entry(name).or_default().push(Location {
+                path: relative.to_path_buf(),
+                line,
+            });
+        }
+    }
+
+    // the walk order depends on the file system
+    for locations in references.values_mut() {
+        locations.sort_by(|a, b| (&a.path, a.line).cmp(&(&b.path, b.line)));
+    }
+
+    references
+}
+
+/// Names of the variables referenced by the files of `root`, uppercased like
+/// keys are stored
+pub fn referenced_keys(root: &Path) -> Result<BTreeSet<String>> {
+    let references = find_references(root, &Patterns::load(root)?);
+
+    Ok(references.keys().map(|k| k.to_ascii_uppercase()).collect())
+}
+
+/// Compares the variables referenced by the files of `root` with the ones of
+/// `env`, names are compared case insensitively like keys are stored
+pub async fn scan(db: &EnvelopeDb, env: &str, root: &Path) -> Result<ScanReport> {
+    db.check_env_exists(env).await?;
+
+    let references = find_references(root, &Patterns::load(root)?);
+    let keys: BTreeSet<String> = db
+        .list_var_in_env(env, SortOrder::Asc)
+        .await?
+        .into_iter()
+        .map(|row| row.key)
+        .collect();
+
+    let referenced: BTreeSet<String> = references.keys().map(|k| k.to_ascii_uppercase()).collect();
+    let missing = references
+        .into_iter()
+        .filter(|(name, _)| !keys.contains(&name.to_ascii_uppercase()))
+        .collect();
+    let unused = keys.difference(&referenced).cloned().collect();
+
+    Ok(ScanReport { missing, unused })
+}
+
+/// Writes the report of [`scan`] as a table, fails if a referenced variable
+/// is missing and `strict` is set
+pub async fn print_scan<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    root: &Path,
+    strict: bool,
+    output: Output,
+) -> Result<()> {
+    let report = scan(db, env, root).await?;
+    output.write(w, &report, |w, report| {
+        if report.missing.is_empty() && report.unused.is_empty() {
+            return writeln!(w, "every variable of {} is referenced", env);
+        }
+
+        let mut table = Table::new(["KEY", "STATUS", "REFERENCED AT"]);
+        for (key, locations) in &report.missing {
+            let first = &locations[0];
+            let mut at = format!("{}:{}", first.path.display(), first.line);
+            if locations.len() > 1 {
+                at.push_str(&format!(" (+{})", locations.len() - 1));
+            }
+            table.add_row([key.as_str(), "missing", &at]);
+        }
+        for key in &report.unused {
+            table.add_row([key.as_str(), "unused", ""]);
+        }
+        table.render(w, None, Overflow::Truncate)
+    })?;
+
+    match (strict, report.missing.len()) {
+        (true, 1) => err!("1 referenced variable is missing from {}", env),
+        (true, n) if n > 1 => err!("{} referenced variables are missing from {}", n, env),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    fn names(patterns: &Patterns, contents: &str) -> Vec<String> {
+        patterns
+            .references(contents)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn test_references() {
+        let patterns = Patterns::new::<&str>(&[]).unwrap();
+        let sources = [
+            ("const url = process.env.DATABASE_URL;", "DATABASE_URL"),
+            ("process.env['API_KEY']", "API_KEY"),
+            ("os.environ[\"SECRET\"]", "SECRET"),
+            ("os.environ.get('DEBUG', '0')", "DEBUG"),
+            ("os.getenv(\"PORT\")", "PORT"),
+            ("std::env::var(\"RUST_LOG\").ok()", "RUST_LOG"),
+            ("env::var_os(\"HOME\")", "HOME"),
+            ("os.Getenv(\"GO_ENV\")", "GO_ENV"),
+            ("ENV.fetch('RAILS_ENV')", "RAILS_ENV"),
+            ("ENV['REDIS_URL']", "REDIS_URL"),
+            ("System.getenv(\"JAVA_OPTS\")", "JAVA_OPTS"),
+            ("image: app:${TAG:-latest}", "TAG"),
+            ("echo ${USER}", "USER"),
+        ];
+        for (source, name) in sources {
+            assert_eq!(
+                vec![name.to_string()],
+                names(&patterns, source),
+                "{}",
+                source
+            );
+        }
+
+        assert!(names(&patterns, "echo $USER and process.env").is_empty());
+        assert_eq!(
+            vec![("A".to_string(), 1), ("B".to_string(), 3)],
+            patterns.references("${A}\n\nprocess.env.B")
+        );
+    }
+
+    #[test]
+    fn test_extra_patterns() {
+        let patterns = Patterns::new(&[r"config\.get\('(\w+)'\)"]).unwrap();
+        assert_eq!(
+            vec!["FROM_CONFIG"],
+            names(&patterns, "config.get('FROM_CONFIG')")
+        );
+
+        assert!(Patterns::new(&["no_group"]).is_err());
+        assert!(Patterns::new(&["(unclosed"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        let root = std::env::temp_dir().join(format!("envelope-scan-{}", std::process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join(".gitignore"), "ignored.js\n").unwrap();
+        fs::write(root.join("ignored.js"), "process.env.IGNORED").unwrap();
+        fs::write(
+            root.join("src/app.js"),
+            "const db = process.env.DATABASE_URL;\nconst key = process.env.API_KEY;\n",
+        )
+        .unwrap();
+        fs::write(
+            root.join("compose.yml"),
+            "port: ${PORT:-80}\nkey: ${API_KEY}\n",
+        )
+        .unwrap();
+        fs::write(root.join(PATTERNS_FILE), "# custom\nsettings\\.(\\w+)\n").unwrap();
+        fs::write(root.join("src/settings.py"), "settings.feature_flag\n").unwrap();
+
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value)
+            VALUES ('dev', 'DATABASE_URL', 'x'), ('dev', 'LEGACY', 'y'), ('dev', 'FEATURE_FLAG', 'z');",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+
+        let report = scan(&db, "dev", &root).await.unwrap();
+        let missing: Vec<&str> = report.missing.keys().map(String::as_str).collect();
+        assert_eq!(vec!["API_KEY", "PORT"], missing);
+        assert_eq!(2, report.missing["API_KEY"].len());
+        assert_eq!(
+            Location {
+                path: PathBuf::from("compose.yml"),
+                line: 1
+            },
+            report.missing["PORT"][0]
+        );
+        assert_eq!(vec!["LEGACY".to_string()], report.unused);
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        let err = print_scan(&mut output, &db, "dev", &root, true, Output::Text)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "2 referenced variables are missing from dev",
+            err.to_string()
+        );
+        let output = String::from_utf8(output.into_inner()).unwrap();
+        assert!(output.contains("PORT     missing  compose.yml:1\n"));
+        assert!(output.contains("LEGACY   unused"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
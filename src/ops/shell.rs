@@ -0,0 +1,143 @@
+use std::io::{Result, Write};
+use std::process::ExitStatus;
+
+use crate::db::{EnvelopeDb, SortOrder};
+use crate::std_err;
+use crate::style;
+use crate::subproc::ChildProcess;
+
+use super::PRISTINE_KEEP;
+
+/// Variable set in every envelope subshell, holds the name of the loaded environment
+pub const ENVELOPE_ACTIVE: &str = "ENVELOPE_ACTIVE";
+
+/// Variables that the subshell of `env` should receive and the ones that it
+/// should not inherit from the parent process because they have been deleted
+pub struct ShellEnv {
+    pub vars: Vec<(String, String)>,
+    pub removed: Vec<String>,
+}
+
+pub async fn shell_env(db: &EnvelopeDb, env: &str) -> Result<ShellEnv> {
+    db.check_env_exists(env).await?;
+
+    let mut vars: Vec<(String, String)> = db
+        .list_var_in_env(env, SortOrder::Asc)
+        .await?
+        .into_iter()
+        .map(|row| (row.key, row.value))
+        .collect();
+    vars.push((ENVELOPE_ACTIVE.into(), env.into()));
+
+    let removed = db.list_deleted_var_in_env(env).await?;
+
+    Ok(ShellEnv { vars, removed })
+}
+
+/// Returns the shell of the current user, defaults to `/bin/sh`
+#[cfg(not(windows))]
+fn user_shell() -> String {
+    match std::env::var("SHELL") {
+        Ok(shell) if !shell.is_empty() => shell,
+        _ => "/bin/sh".into(),
+    }
+}
+
+/// Returns the shell of the current user, powershell is preferred when
+/// envelope is launched from it, otherwise `cmd` is used
+#[cfg(windows)]
+fn user_shell() -> String {
+    if let Ok(shell) = std::env::var("SHELL") {
+        if !shell.is_empty() {
+            return shell;
+        }
+    }
+
+    if std::env::var_os("PSModulePath").is_some() {
+        return "powershell.exe".into();
+    }
+
+    std::env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".into())
+}
+
+/// Spawns an interactive shell with the variables of `env` loaded and hands
+/// the terminal over to it, see [`ChildProcess::exec`]
+///
+/// If `pristine` is set the shell starts from an empty environment, only
+/// [`PRISTINE_KEEP`] and the variables in `keep` are inherited
+pub async fn shell<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    pristine: bool,
+    keep: &[String],
+) -> Result<ExitStatus> {
+    let ShellEnv { vars, removed } = shell_env(db, env).await?;
+
+    if let Ok(active) = std::env::var(ENVELOPE_ACTIVE) {
+        let warning = format!(
+            "warning: already inside an envelope shell for env {}, nesting {}",
+            active, env
+        );
+        writeln!(w, "{}", style::paint(style::WARNING, warning))?;
+    }
+
+    let vars: Vec<(&str, &str)> = vars.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    let removed: Vec<&str> = removed.iter().map(String::as_str).collect();
+
+    let shell = user_shell();
+    let mut child = ChildProcess::new(&shell, &[], &vars).env_remove(&removed);
+    if pristine {
+        let keep: Vec<&str> = PRISTINE_KEEP
+            .iter()
+            .copied()
+            .chain(keep.iter().map(String::as_str))
+            .collect();
+        child = child.pristine(&keep);
+    }
+
+    child
+        .exec()
+        .map_err(|e| std_err!("error running {}: {}", shell, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    #[tokio::test]
+    async fn test_shell_env() {
+        let db = test_db().await;
+        let pool = db.get_pool();
+
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'A', 'X', 1),
+            ('dev', 'B', 'Y', 1),
+            ('dev', 'B', NULL, 2),
+            ('loc', 'C', 'Z', 1);",
+        )
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let ShellEnv { vars, removed } = shell_env(&db, "dev").await.unwrap();
+        assert_eq!(
+            vec![
+                ("A".to_string(), "X".to_string()),
+                (ENVELOPE_ACTIVE.to_string(), "dev".to_string())
+            ],
+            vars
+        );
+        assert_eq!(vec!["B".to_string()], removed);
+    }
+
+    #[tokio::test]
+    async fn test_shell_env_missing() {
+        let db = test_db().await;
+
+        assert!(shell_env(&db, "dev").await.is_err());
+    }
+}
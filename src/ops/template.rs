@@ -0,0 +1,166 @@
+use std::io::{Result, Write};
+
+use serde::Serialize;
+
+use crate::db::EnvelopeDb;
+use crate::error::EnvelopeError;
+use crate::{err, style};
+
+use super::Output;
+
+/// Writes every template, or only the one named `name`, with its keys
+pub async fn list_templates<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    name: Option<&str>,
+    output: Output,
+) -> Result<()> {
+    let mut templates = db.list_templates().await?;
+    if let Some(name) = name {
+        templates.retain(|t| t.name == name);
+        if templates.is_empty() {
+            return Err(EnvelopeError::TemplateNotFound(name.to_string()).into());
+        }
+    }
+
+    output.write(w, &templates, |w, templates| {
+        for template in templates {
+            writeln!(w, "{}: {}", template.name, template.keys.join(", "))?;
+        }
+        Ok(())
+    })
+}
+
+/// Removes `keys` from the template `name`, or the whole template when `keys`
+/// is empty
+pub async fn remove_template(db: &EnvelopeDb, name: &str, keys: &[String]) -> Result<()> {
+    match db.remove_template_keys(name, keys).await? {
+        0 if keys.is_empty() => Err(EnvelopeError::TemplateNotFound(name.to_string()).into()),
+        0 => err!("template {} has none of these keys", name),
+        _ => Ok(()),
+    }
+}
+
+/// Keys of a template that an environment does not set
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct TemplateReport {
+    pub env: String,
+    pub missing: Vec<String>,
+}
+
+/// Writes the keys of `template` that each env of `envs` does not set, one
+/// per line, then fails if there are any
+pub async fn check_template<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    envs: &[String],
+    template: &str,
+    output: Output,
+) -> Result<()> {
+    let mut reports = Vec::new();
+    for env in envs {
+        db.check_env_exists(env).await?;
+
+        reports.push(TemplateReport {
+            env: env.clone(),
+            missing: db.validate_against_template(env, template).await?,
+        });
+    }
+
+    output.write(w, &reports, |w, reports| {
+        for report in reports {
+            let env = style::paint(style::ENV, &report.env);
+            if report.missing.is_empty() {
+                writeln!(w, "{}: ok", env)?;
+            }
+            for key in &report.missing {
+                writeln!(w, "{}: {} is missing", env, key)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    let missing: usize = reports.iter().map(|r| r.missing.len()).sum();
+    let failed = reports.iter().filter(|r| !r.missing.is_empty()).count();
+    match missing {
+        0 => Ok(()),
+        1 => err!(
+            "1 key of {} missing in {} of {} environments",
+            template,
+            failed,
+            reports.len()
+        ),
+        _ => err!(
+            "{} keys of {} missing in {} of {} environments",
+            missing,
+            template,
+            failed,
+            reports.len()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    async fn seed(db: &EnvelopeDb) {
+        db.insert("dev", "HOST", "localhost").await.unwrap();
+        db.insert("prod", "HOST", "example.com").await.unwrap();
+        db.insert("prod", "PORT", "443").await.unwrap();
+        db.add_template_keys("web", &["HOST".into(), "PORT".into()])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_templates() {
+        let db = test_db().await;
+        seed(&db).await;
+
+        let mut output = Vec::new();
+        list_templates(&mut output, &db, None, Output::Text)
+            .await
+            .unwrap();
+        assert_eq!("web: HOST, PORT\n", String::from_utf8(output).unwrap());
+        assert!(
+            list_templates(&mut Vec::new(), &db, Some("api"), Output::Text)
+                .await
+                .is_err()
+        );
+
+        assert!(remove_template(&db, "web", &["OTHER".into()])
+            .await
+            .is_err());
+        remove_template(&db, "web", &[]).await.unwrap();
+        assert!(remove_template(&db, "web", &[]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_template() {
+        let db = test_db().await;
+        seed(&db).await;
+        let envs = ["dev".to_string(), "prod".to_string()];
+
+        let mut output = anstream::StripStream::new(Vec::new());
+        let err = check_template(&mut output, &db, &envs, "web", Output::Text)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            "1 key of web missing in 1 of 2 environments",
+            err.to_string()
+        );
+        assert_eq!(
+            "dev: PORT is missing\nprod: ok\n",
+            String::from_utf8(output.into_inner()).unwrap()
+        );
+
+        let mut output = Vec::new();
+        check_template(&mut output, &db, &envs[1..], "web", Output::Json)
+            .await
+            .unwrap();
+        let reports: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(serde_json::json!([{"env": "prod", "missing": []}]), reports);
+    }
+}
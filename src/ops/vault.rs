@@ -0,0 +1,826 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::future::Future;
+use std::io::{self, Result, Write};
+
+use reqwest::{Method, StatusCode};
+use serde_json::{json, Value};
+
+use crate::db::EnvelopeDb;
+use crate::std_err;
+
+use super::{check_key, ExportPlan};
+
+/// Mount of the KV v2 engine of the Vault dev servers
+pub const DEFAULT_VAULT_MOUNT: &str = "secret";
+
+/// Address the Vault CLI uses when `VAULT_ADDR` is not set
+const DEFAULT_VAULT_ADDR: &str = "http://127.0.0.1:8200";
+
+/// Field holding the value of a variable exported with `--per-key`
+const VALUE_FIELD: &str = "value";
+
+/// Current version of a KV v2 secret
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KvSecret {
+    /// the fields of the secret, empty if its current version is deleted
+    pub data: BTreeMap<String, String>,
+    /// version of the secret, writes check it has not changed since
+    pub version: u64,
+}
+
+/// The secrets of a KV v2 engine, the Vault client or a fake one in tests.
+/// Paths are relative to the mount of the engine.
+pub trait KvStore {
+    /// `mount/path`, to tell the secrets apart in messages
+    fn display(&self, path: &str) -> String;
+
+    /// returns the secret at `path`, `None` if there never was one
+    fn read(&self, path: &str) -> impl Future<Output = Result<Option<KvSecret>>>;
+
+    /// writes `data` as the new version of the secret at `path`, only if its
+    /// current version is `version`, 0 if there is no secret yet
+    fn write(
+        &self,
+        path: &str,
+        data: &BTreeMap<String, String>,
+        version: u64,
+    ) -> impl Future<Output = Result<()>>;
+
+    /// returns the names of the secrets and folders right under `path`, the
+    /// folders end with `/`
+    fn list(&self, path: &str) -> impl Future<Output = Result<Vec<String>>>;
+}
+
+/// Client of the HTTP API of a Vault KV v2 engine, configured like the Vault
+/// CLI by `VAULT_ADDR`, `VAULT_TOKEN` and `VAULT_NAMESPACE`
+pub struct VaultClient {
+    http: reqwest::Client,
+    addr: String,
+    token: String,
+    namespace: Option<String>,
+    mount: String,
+}
+
+impl VaultClient {
+    /// returns a client of the engine mounted at `mount`
+    pub fn from_env(mount: &str) -> Result<Self> {
+        let token = env::var("VAULT_TOKEN").map_err(|_| std_err!("VAULT_TOKEN is not set"))?;
+        let addr = env::var("VAULT_ADDR").unwrap_or_else(|_| DEFAULT_VAULT_ADDR.into());
+        let namespace = env::var("VAULT_NAMESPACE").ok().filter(|ns| !ns.is_empty());
+
+        Ok(VaultClient::new(&addr, &token, namespace, mount))
+    }
+
+    fn new(addr: &str, token: &str, namespace: Option<String>, mount: &str) -> Self {
+        VaultClient {
+            http: reqwest::Client::new(),
+            addr: addr.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            namespace,
+            mount: mount.trim_matches('/').to_string(),
+        }
+    }
+
+    /// sends a request to the `api` of the engine, `data` or `metadata`,
+    /// for the secret at `path`. Returns the body of the response, `None` if
+    /// the secret does not exist.
+    async fn request(
+        &self,
+        method: Method,
+        api: &str,
+        path: &str,
+        body: Option<Value>,
+    ) -> Result<Option<Value>> {
+        let url = format!(
+            "{}/v1/{}/{}/{}",
+            self.addr,
+            self.mount,
+            api,
+            path.trim_matches('/')
+        );
+        let mut request = self
+            .http
+            .request(method, &url)
+            .header("X-Vault-Token", &self.token);
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+
+        let response = request.send().await.map_err(|e| self.send_error(e))?;
+        let status = response.status();
+        let text = response.text().await.map_err(|e| self.send_error(e))?;
+        let body: Value = match text.is_empty() {
+            true => Value::Null,
+            false => serde_json::from_str(&text)
+                .map_err(|e| std_err!("invalid response from Vault at {}: {}", url, e))?,
+        };
+
+        match check_status(status, &body, &self.mount, &self.display(path))? {
+            true => Ok(Some(body)),
+            false => Ok(None),
+        }
+    }
+
+    /// tells the TLS errors apart from the other connection errors
+    fn send_error(&self, err: reqwest::Error) -> io::Error {
+        let mut source = err.source();
+        while let Some(e) = source {
+            if is_tls_error(e) {
+                return std_err!(
+                    "TLS error connecting to Vault at {}: {}",
+                    self.addr,
+                    root_cause(e)
+                );
+            }
+            source = e.source();
+        }
+
+        match err.is_connect() {
+            true => std_err!(
+                "cannot connect to Vault at {}: {}",
+                self.addr,
+                root_cause(&err)
+            ),
+            false => std_err!(
+                "request to Vault at {} failed: {}",
+                self.addr,
+                root_cause(&err)
+            ),
+        }
+    }
+}
+
+impl KvStore for VaultClient {
+    fn display(&self, path: &str) -> String {
+        format!("{}/{}", self.mount, path.trim_matches('/'))
+    }
+
+    async fn read(&self, path: &str) -> Result<Option<KvSecret>> {
+        let Some(body) = self.request(Method::GET, "data", path, None).await? else {
+            return Ok(None);
+        };
+
+        // KV v2 nests the fields of the secret under `data.data`, next to the
+        // `data.metadata` of its version
+        let version = body["data"]["metadata"]["version"].as_u64().unwrap_or(0);
+        let data = match &body["data"]["data"] {
+            Value::Object(fields) => fields
+                .iter()
+                .map(|(k, v)| (k.clone(), field_value(v)))
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+
+        Ok(Some(KvSecret { data, version }))
+    }
+
+    async fn write(&self, path: &str, data: &BTreeMap<String, String>, version: u64) -> Result<()> {
+        let body = json!({ "options": { "cas": version }, "data": data });
+        self.request(Method::POST, "data", path, Some(body)).await?;
+
+        Ok(())
+    }
+
+    async fn list(&self, path: &str) -> Result<Vec<String>> {
+        let list = Method::from_bytes(b"LIST").unwrap();
+        let Some(body) = self.request(list, "metadata", path, None).await? else {
+            return Ok(Vec::new());
+        };
+
+        Ok(body["data"]["keys"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|key| key.as_str().map(str::to_string))
+            .collect())
+    }
+}
+
+/// Returns whether the response of status `status` has the secret `secret`,
+/// or the error Vault replied with
+fn check_status(status: StatusCode, body: &Value, mount: &str, secret: &str) -> Result<bool> {
+    let errors: Vec<&str> = body["errors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+
+    match status {
+        status if status.is_success() => Ok(true),
+        StatusCode::NOT_FOUND if errors.iter().any(|e| e.contains("no handler for route")) => {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("there is no secrets engine mounted at {} in Vault", mount),
+            ))
+        }
+        // a deleted version still has metadata
+        StatusCode::NOT_FOUND => Ok(body["data"]["metadata"].is_object()),
+        StatusCode::FORBIDDEN => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "permission denied on {} in Vault, check VAULT_TOKEN and its policies",
+                secret
+            ),
+        )),
+        StatusCode::BAD_REQUEST if errors.iter().any(|e| e.contains("check-and-set")) => {
+            Err(std_err!(
+                "{} was changed in Vault since it was read, try again",
+                secret
+            ))
+        }
+        status => Err(std_err!(
+            "Vault replied {} for {}: {}",
+            status,
+            secret,
+            errors.join(", ")
+        )),
+    }
+}
+
+/// Text of the value of a field, the fields that are not strings are kept
+/// as JSON
+fn field_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Whether `err` comes from rustls, which reports its errors as invalid
+/// data. The IO errors can wrap each other, their source skips the wrapped
+/// one.
+fn is_tls_error(err: &(dyn Error + 'static)) -> bool {
+    let mut err = err;
+    while let Some(e) = err.downcast_ref::<io::Error>() {
+        if e.kind() == io::ErrorKind::InvalidData {
+            return true;
+        }
+        match e.get_ref() {
+            Some(inner) => err = inner,
+            None => return false,
+        }
+    }
+    false
+}
+
+/// Message of the innermost error of `err`
+fn root_cause(err: &dyn Error) -> String {
+    let mut cause = err;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    cause.to_string()
+}
+
+/// Joins `path` and the name of a secret under it
+fn child(path: &str, name: &str) -> String {
+    format!("{}/{}", path.trim_end_matches('/'), name)
+}
+
+/// Names of the secrets right under `path`
+async fn children<S: KvStore>(store: &S, path: &str) -> Result<Vec<String>> {
+    let names = store.list(path).await?;
+    Ok(names
+        .into_iter()
+        .filter(|name| !name.ends_with('/'))
+        .collect())
+}
+
+/// Remote names of the keys of `fields` by uppercased key, fails if two of
+/// them are the same key
+fn by_key<'a>(
+    store: &impl KvStore,
+    path: &str,
+    fields: impl IntoIterator<Item = &'a String>,
+) -> Result<BTreeMap<String, String>> {
+    let mut names = BTreeMap::new();
+    for name in fields {
+        if let Some(other) = names.insert(name.to_ascii_uppercase(), name.clone()) {
+            return Err(std_err!(
+                "{} has both {} and {}, keys are case insensitive",
+                store.display(path),
+                other,
+                name
+            ));
+        }
+    }
+
+    Ok(names)
+}
+
+/// Reads the variables of the secret at `path`, or with `per_key` of the
+/// secrets right under it which hold the value of their key in a `value`
+/// field. Keys are case insensitive, they are uppercased.
+pub async fn vault_vars<S: KvStore>(
+    store: &S,
+    path: &str,
+    per_key: bool,
+) -> Result<Vec<(String, String)>> {
+    let mut fields = BTreeMap::new();
+    if per_key {
+        for name in children(store, path).await? {
+            let secret = store.read(&child(path, &name)).await?.unwrap_or_default();
+            let value = secret.data.get(VALUE_FIELD).ok_or_else(|| {
+                std_err!(
+                    "{} has no {} field",
+                    store.display(&child(path, &name)),
+                    VALUE_FIELD
+                )
+            })?;
+            fields.insert(name, value.clone());
+        }
+    } else {
+        let secret = store.read(path).await?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not exist in Vault", store.display(path)),
+            )
+        })?;
+        fields = secret.data;
+    }
+
+    by_key(store, path, fields.keys())?
+        .into_iter()
+        .map(|(key, name)| {
+            check_key(&name).map_err(|e| std_err!("{}: {}", store.display(path), e))?;
+            Ok((key, fields[&name].clone()))
+        })
+        .collect()
+}
+
+/// Sets the `vars` read from Vault in `env` in a single transaction, then
+/// prints how many were imported
+pub async fn import_vault<W: Write, S: KvStore>(
+    writer: &mut W,
+    db: &EnvelopeDb,
+    env: &str,
+    store: &S,
+    path: &str,
+    vars: &[(String, String)],
+) -> Result<()> {
+    let outcome = db.insert_many(env, vars).await?;
+    writeln!(
+        writer,
+        "{} keys imported from {}, {} unchanged",
+        outcome.written,
+        store.display(path),
+        outcome.unchanged
+    )
+}
+
+/// What an export writes to Vault, along with the names and versions of the
+/// secrets it replaces
+#[derive(Debug, Default)]
+pub struct VaultPlan {
+    pub plan: ExportPlan,
+    /// remote name of the keys Vault already has, by uppercased key
+    names: BTreeMap<String, String>,
+    /// version of the secret at the path, or of each secret under it by
+    /// name with `per_key`
+    versions: BTreeMap<String, u64>,
+}
+
+/// Compares `vars` with the secret at `path`, or with `per_key` with the
+/// secrets right under it. The keys match whatever their case, the secrets
+/// under the path that envelope does not have are left alone.
+pub async fn plan_vault_export<S: KvStore>(
+    store: &S,
+    path: &str,
+    vars: &BTreeMap<String, String>,
+    per_key: bool,
+) -> Result<VaultPlan> {
+    let mut fields = BTreeMap::new();
+    let mut versions = BTreeMap::new();
+    if per_key {
+        for name in children(store, path).await? {
+            let secret = store.read(&child(path, &name)).await?.unwrap_or_default();
+            if let Some(value) = secret.data.get(VALUE_FIELD) {
+                fields.insert(name.clone(), value.clone());
+            }
+            versions.insert(name, secret.version);
+        }
+    } else if let Some(secret) = store.read(path).await? {
+        fields = secret.data;
+        versions.insert(String::new(), secret.version);
+    }
+
+    let names = by_key(store, path, fields.keys())?;
+    let remote = fields
+        .into_iter()
+        .map(|(name, value)| (name.to_ascii_uppercase(), value))
+        .collect();
+    let mut plan = ExportPlan::new(remote, vars);
+    if per_key {
+        plan.diff.removed.clear();
+    }
+
+    Ok(VaultPlan {
+        plan,
+        names,
+        versions,
+    })
+}
+
+/// Writes the variables `plan` creates or updates: `vars` as the new version
+/// of the secret at `path`, or with `per_key` each to the secret of its key
+/// under it. Then prints the counts of `plan`.
+pub async fn export_vault<W: Write, S: KvStore>(
+    writer: &mut W,
+    store: &S,
+    path: &str,
+    plan: &VaultPlan,
+    vars: &BTreeMap<String, String>,
+    per_key: bool,
+) -> Result<()> {
+    // the keys Vault has keep their name
+    let name = |key: &String| plan.names.get(key).unwrap_or(key).clone();
+
+    if per_key {
+        let diff = &plan.plan.diff;
+        let writes = diff
+            .added
+            .iter()
+            .chain(diff.changed.iter().map(|(key, (_, new))| (key, new)));
+        for (key, value) in writes {
+            let name = name(key);
+            let version = plan.versions.get(&name).copied().unwrap_or(0);
+            let data = BTreeMap::from([(VALUE_FIELD.to_string(), value.clone())]);
+            store.write(&child(path, &name), &data, version).await?;
+        }
+    } else if !plan.plan.diff.is_empty() {
+        let data = vars.iter().map(|(k, v)| (name(k), v.clone())).collect();
+        let version = plan.versions.get("").copied().unwrap_or(0);
+        store.write(path, &data, version).await?;
+    }
+
+    writeln!(writer, "{}", plan.plan)
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::db::{test_db, SortOrder};
+    use crate::err;
+
+    /// KV v2 engine keeping the secrets by path
+    #[derive(Default)]
+    struct FakeKv {
+        secrets: RefCell<BTreeMap<String, KvSecret>>,
+        writes: RefCell<Vec<String>>,
+    }
+
+    impl FakeKv {
+        fn insert<const N: usize>(&self, path: &str, fields: [(&str, &str); N]) {
+            let mut secrets = self.secrets.borrow_mut();
+            let version = secrets.get(path).map_or(0, |s| s.version) + 1;
+            let data = fields
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            secrets.insert(path.to_string(), KvSecret { data, version });
+        }
+
+        fn data(&self, path: &str) -> BTreeMap<String, String> {
+            self.secrets.borrow()[path].data.clone()
+        }
+    }
+
+    impl KvStore for FakeKv {
+        fn display(&self, path: &str) -> String {
+            format!("secret/{}", path)
+        }
+
+        async fn read(&self, path: &str) -> Result<Option<KvSecret>> {
+            Ok(self.secrets.borrow().get(path).cloned())
+        }
+
+        async fn write(
+            &self,
+            path: &str,
+            data: &BTreeMap<String, String>,
+            version: u64,
+        ) -> Result<()> {
+            let mut secrets = self.secrets.borrow_mut();
+            let current = secrets.get(path).map_or(0, |s| s.version);
+            if current != version {
+                return err!("check-and-set parameter did not match the current version");
+            }
+            self.writes.borrow_mut().push(path.to_string());
+            secrets.insert(
+                path.to_string(),
+                KvSecret {
+                    data: data.clone(),
+                    version: version + 1,
+                },
+            );
+            Ok(())
+        }
+
+        async fn list(&self, path: &str) -> Result<Vec<String>> {
+            let prefix = format!("{}/", path.trim_end_matches('/'));
+            Ok(self
+                .secrets
+                .borrow()
+                .keys()
+                .filter_map(|p| p.strip_prefix(&prefix))
+                .map(|name| match name.split_once('/') {
+                    Some((folder, _)) => format!("{}/", folder),
+                    None => name.to_string(),
+                })
+                .collect())
+        }
+    }
+
+    fn vars<const N: usize>(vars: [(&str, &str); N]) -> BTreeMap<String, String> {
+        vars.into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// Replies `status` and `body` to a single request, returns the address
+    /// to send it to and the request it received
+    async fn serve_once(
+        status: u16,
+        body: &'static str,
+    ) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // a TLS handshake is not HTTP, it gets the response right away
+            while request.first().is_none_or(u8::is_ascii_uppercase)
+                && !request.windows(4).any(|w| w == b"\r\n\r\n")
+            {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let response = format!(
+                "HTTP/1.1 {} X\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).to_lowercase()
+        });
+
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn test_client_read() {
+        let (addr, request) = serve_once(
+            200,
+            r#"{"data": {"data": {"db_password": "hunter2", "port": 80}, "metadata": {"version": 3}}}"#,
+        )
+        .await;
+        let client = VaultClient::new(&addr, "t0ken", Some("team".into()), "/kv/");
+
+        let secret = client.read("myapp/prod").await.unwrap().unwrap();
+        assert_eq!(
+            KvSecret {
+                data: vars([("db_password", "hunter2"), ("port", "80")]),
+                version: 3
+            },
+            secret
+        );
+        let request = request.await.unwrap();
+        assert!(request.starts_with("get /v1/kv/data/myapp/prod http/1.1\r\n"));
+        assert!(request.contains("x-vault-token: t0ken\r\n"));
+        assert!(request.contains("x-vault-namespace: team\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_client_errors() {
+        let (addr, _) = serve_once(404, r#"{"errors": []}"#).await;
+        let client = VaultClient::new(&addr, "t", None, "secret");
+        assert_eq!(None, client.read("missing").await.unwrap());
+
+        let (addr, _) = serve_once(403, r#"{"errors": ["permission denied"]}"#).await;
+        let client = VaultClient::new(&addr, "t", None, "secret");
+        let err = client.read("myapp/prod").await.unwrap_err();
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+        assert_eq!(
+            "permission denied on secret/myapp/prod in Vault, check VAULT_TOKEN and its policies",
+            err.to_string()
+        );
+
+        let (addr, _) = serve_once(
+            404,
+            r#"{"errors": ["no handler for route \"nope/data/a\". route entry not found."]}"#,
+        )
+        .await;
+        let client = VaultClient::new(&addr, "t", None, "nope");
+        let err = client.read("a").await.unwrap_err();
+        assert_eq!(
+            "there is no secrets engine mounted at nope in Vault",
+            err.to_string()
+        );
+
+        // TLS to a server speaking plain HTTP
+        let (addr, _) = serve_once(200, "{}").await;
+        let client = VaultClient::new(&addr.replace("http", "https"), "t", None, "secret");
+        let err = client.read("a").await.unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("TLS error connecting to Vault at https://"),
+            "{}",
+            err
+        );
+
+        // nothing listens on the port of the finished server
+        let client = VaultClient::new(&addr, "t", None, "secret");
+        let err = client.read("a").await.unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("cannot connect to Vault at http://"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_check_status() {
+        let body =
+            json!({ "errors": ["check-and-set parameter did not match the current version"] });
+        assert_eq!(
+            "secret/a was changed in Vault since it was read, try again",
+            check_status(StatusCode::BAD_REQUEST, &body, "secret", "secret/a")
+                .unwrap_err()
+                .to_string()
+        );
+
+        // the current version was deleted
+        let body = json!({ "data": { "data": null, "metadata": { "version": 2 } } });
+        assert!(check_status(StatusCode::NOT_FOUND, &body, "secret", "secret/a").unwrap());
+
+        let body = json!({ "errors": ["internal error"] });
+        assert_eq!(
+            "Vault replied 500 Internal Server Error for secret/a: internal error",
+            check_status(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &body,
+                "secret",
+                "secret/a"
+            )
+            .unwrap_err()
+            .to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_import_vault() {
+        let db = test_db().await;
+        db.insert("prod", "PORT", "80").await.unwrap();
+        let kv = FakeKv::default();
+        kv.insert("myapp/prod", [("db-password", "hunter2"), ("port", "80")]);
+        kv.insert("myapp/keys/API_KEY", [("value", "s3cr3t")]);
+        kv.insert("myapp/keys/nested/TOKEN", [("value", "t")]);
+
+        let imported = vault_vars(&kv, "myapp/prod", false).await.unwrap();
+        let mut w = Vec::new();
+        import_vault(&mut w, &db, "prod", &kv, "myapp/prod", &imported)
+            .await
+            .unwrap();
+        assert_eq!(
+            "1 keys imported from secret/myapp/prod, 1 unchanged\n",
+            String::from_utf8(w).unwrap()
+        );
+
+        let imported = vault_vars(&kv, "myapp/keys", true).await.unwrap();
+        assert_eq!(
+            vec![("API_KEY".to_string(), "s3cr3t".to_string())],
+            imported
+        );
+        db.insert_many("prod", &imported).await.unwrap();
+
+        let stored: BTreeMap<String, String> = db
+            .list_var_in_env("prod", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| (v.key, v.value))
+            .collect();
+        assert_eq!(
+            vars([
+                ("API_KEY", "s3cr3t"),
+                ("DB-PASSWORD", "hunter2"),
+                ("PORT", "80")
+            ]),
+            stored
+        );
+
+        let err = vault_vars(&kv, "myapp/missing", false).await.unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        assert_eq!(
+            "secret/myapp/missing does not exist in Vault",
+            err.to_string()
+        );
+
+        kv.insert("myapp/dup", [("port", "80"), ("PORT", "8080")]);
+        let err = vault_vars(&kv, "myapp/dup", false).await.unwrap_err();
+        assert_eq!(
+            "secret/myapp/dup has both PORT and port, keys are case insensitive",
+            err.to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_vault() {
+        let kv = FakeKv::default();
+        kv.insert(
+            "myapp/prod",
+            [("port", "80"), ("host", "old"), ("gone", "1")],
+        );
+        let local = vars([("HOST", "new"), ("PORT", "80"), ("TOKEN", "t")]);
+
+        let plan = plan_vault_export(&kv, "myapp/prod", &local, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            "1 created, 1 updated, 1 unchanged, 1 removed",
+            plan.plan.to_string()
+        );
+        let mut w = Vec::new();
+        export_vault(&mut w, &kv, "myapp/prod", &plan, &local, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            "1 created, 1 updated, 1 unchanged, 1 removed\n",
+            String::from_utf8(w).unwrap()
+        );
+        // a single write, the keys Vault had keep their case
+        assert_eq!(vec!["myapp/prod"], kv.writes.take());
+        assert_eq!(
+            vars([("TOKEN", "t"), ("host", "new"), ("port", "80")]),
+            kv.data("myapp/prod")
+        );
+        assert_eq!(2, kv.secrets.borrow()["myapp/prod"].version);
+
+        let plan = plan_vault_export(&kv, "myapp/prod", &local, false)
+            .await
+            .unwrap();
+        export_vault(&mut Vec::new(), &kv, "myapp/prod", &plan, &local, false)
+            .await
+            .unwrap();
+        assert!(kv.writes.take().is_empty());
+
+        // written in between, the check-and-set fails
+        let local = vars([("HOST", "newer")]);
+        let plan = plan_vault_export(&kv, "myapp/prod", &local, false)
+            .await
+            .unwrap();
+        kv.insert("myapp/prod", [("host", "other")]);
+        assert!(
+            export_vault(&mut Vec::new(), &kv, "myapp/prod", &plan, &local, false)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_vault_per_key() {
+        let kv = FakeKv::default();
+        kv.insert("myapp/prod/port", [("value", "80")]);
+        kv.insert("myapp/prod/host", [("value", "old")]);
+        kv.insert("myapp/prod/REMOTE_ONLY", [("value", "1")]);
+        let local = vars([("HOST", "new"), ("PORT", "80"), ("TOKEN", "t")]);
+
+        let plan = plan_vault_export(&kv, "myapp/prod", &local, true)
+            .await
+            .unwrap();
+        assert_eq!("1 created, 1 updated, 1 unchanged", plan.plan.to_string());
+        export_vault(&mut Vec::new(), &kv, "myapp/prod/", &plan, &local, true)
+            .await
+            .unwrap();
+
+        let mut writes = kv.writes.take();
+        writes.sort();
+        assert_eq!(vec!["myapp/prod/TOKEN", "myapp/prod/host"], writes);
+        assert_eq!(vars([("value", "new")]), kv.data("myapp/prod/host"));
+        assert_eq!(vars([("value", "1")]), kv.data("myapp/prod/REMOTE_ONLY"));
+        assert_eq!(
+            vec![
+                ("HOST".to_string(), "new".to_string()),
+                ("PORT".into(), "80".into()),
+                ("REMOTE_ONLY".into(), "1".into()),
+                ("TOKEN".into(), "t".into()),
+            ],
+            vault_vars(&kv, "myapp/prod", true).await.unwrap()
+        );
+    }
+}
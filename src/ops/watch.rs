@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+use std::io::{Result, Write};
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use tokio::process::Child;
+
+use crate::db::{EnvDiff, EnvelopeDb};
+use crate::std_err;
+
+use super::{command_with, print_provenance, resolve_env, stored_env, Layers, RunOptions};
+
+/// How long a command has to exit after SIGTERM before it gets killed
+pub const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// Returns the names of the variables that differ between `old` and `new`
+pub fn changed_keys(old: &Layers, new: &Layers) -> Vec<String> {
+    let values = |layers: &Layers| -> BTreeMap<String, String> {
+        layers
+            .vars
+            .iter()
+            .map(|(k, v)| (k.clone(), v.value.clone()))
+            .collect()
+    };
+
+    let diff = EnvDiff::between(values(old), values(new));
+    let mut keys: Vec<String> = diff
+        .added
+        .into_keys()
+        .chain(diff.removed.into_keys())
+        .chain(diff.changed.into_keys())
+        .collect();
+    keys.sort();
+
+    keys
+}
+
+/// Asks the process group led by `child` to exit with SIGTERM and kills it if
+/// `child` is still running after `grace`. On platforms without signals the
+/// child is killed right away.
+pub async fn terminate(child: &mut Child, grace: Duration) -> Result<ExitStatus> {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        let group = -(pid as libc::pid_t);
+        // SAFETY: `pid` belongs to a child that has not been reaped yet and
+        // that leads its own process group, see `ChildProcess::spawn_async`
+        unsafe { libc::kill(group, libc::SIGTERM) };
+        if let Ok(status) = tokio::time::timeout(grace, child.wait()).await {
+            return status;
+        }
+
+        unsafe { libc::kill(group, libc::SIGKILL) };
+        return child.wait().await;
+    }
+
+    #[cfg(not(unix))]
+    let _ = grace;
+
+    child.kill().await?;
+    child.wait().await
+}
+
+/// Waits until the stored variables differ from `current`, returns the new
+/// ones together with the names of the variables that changed. The values
+/// are compared as stored, the 1Password references are not read again.
+async fn next_change(
+    db: &EnvelopeDb,
+    opts: &RunOptions,
+    current: &Layers,
+    interval: Duration,
+) -> Result<(Layers, Vec<String>)> {
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let layers = stored_env(db, opts).await?;
+        let changed = changed_keys(current, &layers);
+        if !changed.is_empty() {
+            return Ok((layers, changed));
+        }
+    }
+}
+
+/// Runs `cmd` and restarts it every time the variables it has been started
+/// with change in the database, polling it every `interval`. The 1Password
+/// references stored with `add --dynamic` are read when the command starts
+/// and restarts only, a restart that cannot read them waits for the next
+/// change. Stops at Ctrl-C, terminating the command.
+pub async fn watch<W: Write>(
+    w: &mut W,
+    db: &EnvelopeDb,
+    opts: &RunOptions,
+    cmd: &[String],
+    interval: Duration,
+) -> Result<ExitStatus> {
+    let mut stored = stored_env(db, opts).await?;
+    let mut layers = resolve_env(db, &stored).await?;
+
+    loop {
+        if opts.verbose {
+            print_provenance(w, &layers)?;
+        }
+
+        let mut child = command_with(&layers, opts, cmd)?
+            .spawn_async()
+            .map_err(|e| std_err!("error running {}: {}", cmd[0], e))?;
+
+        let (mut next, mut changed, status) = tokio::select! {
+            change = next_change(db, opts, &stored, interval) => {
+                let (next, changed) = change?;
+                let status = terminate(&mut child, GRACE_PERIOD).await?;
+                (next, changed, status)
+            }
+            status = child.wait() => {
+                let status = status?;
+                writeln!(w, "envelope: command exited with {}, waiting for changes", status)?;
+                tokio::select! {
+                    change = next_change(db, opts, &stored, interval) => {
+                        let (next, changed) = change?;
+                        (next, changed, status)
+                    }
+                    _ = tokio::signal::ctrl_c() => return Ok(status),
+                }
+            }
+            _ = tokio::signal::ctrl_c() => {
+                return terminate(&mut child, GRACE_PERIOD).await;
+            }
+        };
+
+        layers = loop {
+            writeln!(w, "envelope: {} changed, restarting", changed.join(", "))?;
+            stored = next;
+            match resolve_env(db, &stored).await {
+                Ok(layers) => break layers,
+                Err(e) => writeln!(w, "envelope: {}, waiting for changes", e)?,
+            }
+            (next, changed) = tokio::select! {
+                change = next_change(db, opts, &stored, interval) => change?,
+                _ = tokio::signal::ctrl_c() => return Ok(status),
+            };
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ops::LayeredVar;
+    use crate::subproc::ChildProcess;
+
+    fn layers(vars: &[(&str, &str)]) -> Layers {
+        Layers {
+            vars: vars
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        k.to_string(),
+                        LayeredVar {
+                            value: v.to_string(),
+                            env: "dev".into(),
+                        },
+                    )
+                })
+                .collect(),
+            removed: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_changed_keys() {
+        let old = layers(&[("A", "1"), ("B", "2"), ("C", "3")]);
+        let new = layers(&[("A", "1"), ("B", "changed"), ("D", "4")]);
+
+        assert_eq!(vec!["B", "C", "D"], changed_keys(&old, &new));
+        assert!(changed_keys(&old, &old).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_terminate() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut child = ChildProcess::new("sleep", &["30"], &[])
+            .spawn_async()
+            .unwrap();
+        let status = terminate(&mut child, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(Some(libc::SIGTERM), status.signal());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_terminate_kill() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut child = ChildProcess::new("sh", &["-c", "trap '' TERM; sleep 30"], &[])
+            .spawn_async()
+            .unwrap();
+        // let the shell install its trap
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let status = terminate(&mut child, Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert_eq!(Some(libc::SIGKILL), status.signal());
+    }
+}
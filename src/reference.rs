@@ -0,0 +1,293 @@
+//! References to secrets kept outside of envelope, such as
+//! `vault://secret/data/app#password`, `ssm:///my/param` or
+//! `op://Dev/db/password`
+//!
+//! The database stores the reference as the value of the variable, a
+//! [`SecretResolver`] given by the caller fetches the secret when the
+//! variables are read with [`EnvelopeDb::list_var_in_env_resolved`]. The
+//! library does not talk to Vault, SSM or 1Password itself.
+//!
+//! `op://` values are also stored as they are, for documentation, so they
+//! are only resolved for the variables of type
+//! [`ValueType::Reference`](crate::validate::ValueType::Reference).
+//!
+//! ```
+//! use std::collections::BTreeMap;
+//! use envelope::reference::{SecretRef, SecretResolver};
+//! use envelope::{EnvelopeDb, EnvelopeResult, SortOrder};
+//!
+//! struct Fixed(BTreeMap<String, String>);
+//!
+//! impl SecretResolver for Fixed {
+//!     async fn resolve(&self, reference: &SecretRef<'_>) -> EnvelopeResult<Option<String>> {
+//!         Ok(self.0.get(&reference.to_string()).cloned())
+//!     }
+//! }
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> EnvelopeResult<()> {
+//! # let path = std::env::temp_dir().join(format!("envelope-ref-{}.db", std::process::id()));
+//! let db = EnvelopeDb::open(&path).await?;
+//! db.insert("prod", "db_password", "ssm:///app/db_password").await?;
+//!
+//! let resolver = Fixed(BTreeMap::from([(
+//!     "ssm:///app/db_password".into(),
+//!     "s3cr3t".into(),
+//! )]));
+//! let vars = db
+//!     .list_var_in_env_resolved("prod", SortOrder::Asc, &resolver)
+//!     .await?;
+//! assert_eq!("s3cr3t", vars[0].value);
+//! # drop(db);
+//! # std::fs::remove_file(&path)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`EnvelopeDb::list_var_in_env_resolved`]: crate::EnvelopeDb::list_var_in_env_resolved
+
+use std::fmt;
+use std::future::Future;
+
+use crate::db::EnvelopeResult;
+use crate::error::EnvelopeError;
+
+/// Prefix of the references to a field of a Vault secret
+pub const VAULT_PREFIX: &str = "vault://";
+
+/// Prefix of the references to an SSM parameter, its name starts with `/`
+pub const SSM_PREFIX: &str = "ssm://";
+
+/// Prefix of the references to a field of a 1Password item
+pub const OP_PREFIX: &str = "op://";
+
+/// A reference to a secret held by an external store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretRef<'a> {
+    /// `vault://<path>#<field>`, the `field` of the secret at `path`
+    Vault { path: &'a str, field: &'a str },
+    /// `ssm://<name>`, the parameter `name`, e.g. `/my/param`
+    Ssm { name: &'a str },
+    /// `op://<vault>/<item>/<field>`, the field can be in a section:
+    /// `op://<vault>/<item>/<section>/<field>`
+    Op { path: &'a str },
+}
+
+impl<'a> SecretRef<'a> {
+    /// Parses `value` as a reference. Returns `None` for the values that do
+    /// not start with a reference prefix, and fails with
+    /// [`EnvelopeError::InvalidReference`] for the ones that do but are
+    /// incomplete.
+    pub fn parse(value: &'a str) -> EnvelopeResult<Option<Self>> {
+        let invalid = |reason| EnvelopeError::InvalidReference {
+            reference: value.to_string(),
+            reason,
+        };
+
+        if let Some(rest) = value.strip_prefix(VAULT_PREFIX) {
+            let (path, field) = rest
+                .split_once('#')
+                .ok_or_else(|| invalid("missing #field"))?;
+            if path.is_empty() {
+                return Err(invalid("empty path"));
+            }
+            if field.is_empty() {
+                return Err(invalid("empty field"));
+            }
+            return Ok(Some(SecretRef::Vault { path, field }));
+        }
+
+        if let Some(name) = value.strip_prefix(SSM_PREFIX) {
+            if !name.starts_with('/') {
+                return Err(invalid("the parameter name must start with /"));
+            }
+            if name.len() == 1 {
+                return Err(invalid("empty parameter name"));
+            }
+            return Ok(Some(SecretRef::Ssm { name }));
+        }
+
+        if let Some(path) = value.strip_prefix(OP_PREFIX) {
+            let segments: Vec<&str> = path.split('/').collect();
+            if !(3..=4).contains(&segments.len()) {
+                return Err(invalid("expected op://vault/item/field"));
+            }
+            if segments.iter().any(|segment| segment.is_empty()) {
+                return Err(invalid("empty vault, item or field"));
+            }
+            return Ok(Some(SecretRef::Op { path }));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Formats the reference as it is stored
+impl fmt::Display for SecretRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretRef::Vault { path, field } => write!(f, "{}{}#{}", VAULT_PREFIX, path, field),
+            SecretRef::Ssm { name } => write!(f, "{}{}", SSM_PREFIX, name),
+            SecretRef::Op { path } => write!(f, "{}{}", OP_PREFIX, path),
+        }
+    }
+}
+
+/// Fetches the secrets that references point to, implemented by the caller
+/// for the stores it can reach
+pub trait SecretResolver: Sync {
+    /// returns the secret `reference` points to, `None` if the store does
+    /// not have it
+    fn resolve(
+        &self,
+        reference: &SecretRef<'_>,
+    ) -> impl Future<Output = EnvelopeResult<Option<String>>> + Send;
+}
+
+/// Returns the secret `value` references through `resolver`, or `value`
+/// itself when it is not a reference. Fails with
+/// [`EnvelopeError::SecretNotFound`] when the resolver does not have it.
+pub async fn resolve_value<R: SecretResolver>(resolver: &R, value: &str) -> EnvelopeResult<String> {
+    let Some(reference) = SecretRef::parse(value)? else {
+        return Ok(value.to_string());
+    };
+
+    resolver
+        .resolve(&reference)
+        .await?
+        .ok_or_else(|| EnvelopeError::SecretNotFound(value.to_string()))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// Resolves the references found in a map, counting the lookups
+    #[derive(Default)]
+    pub(crate) struct MockResolver {
+        pub(crate) secrets: BTreeMap<String, String>,
+        pub(crate) lookups: AtomicUsize,
+    }
+
+    impl MockResolver {
+        pub(crate) fn new<const N: usize>(secrets: [(&str, &str); N]) -> Self {
+            MockResolver {
+                secrets: secrets
+                    .into_iter()
+                    .map(|(r, s)| (r.to_string(), s.to_string()))
+                    .collect(),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl SecretResolver for MockResolver {
+        async fn resolve(&self, reference: &SecretRef<'_>) -> EnvelopeResult<Option<String>> {
+            self.lookups.fetch_add(1, Ordering::Relaxed);
+            Ok(self.secrets.get(&reference.to_string()).cloned())
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Some(SecretRef::Vault {
+                path: "secret/data/app",
+                field: "password"
+            }),
+            SecretRef::parse("vault://secret/data/app#password").unwrap()
+        );
+        assert_eq!(
+            Some(SecretRef::Ssm { name: "/my/param" }),
+            SecretRef::parse("ssm:///my/param").unwrap()
+        );
+        assert_eq!(
+            Some(SecretRef::Op {
+                path: "Dev/db/Database/password"
+            }),
+            SecretRef::parse("op://Dev/db/Database/password").unwrap()
+        );
+        for value in [
+            "s3cr3t",
+            "",
+            "https://example.com",
+            "vault:secret#a",
+            "@file:/tmp/a",
+        ] {
+            assert_eq!(None, SecretRef::parse(value).unwrap(), "{}", value);
+        }
+        for value in [
+            "vault://secret/data/app#password",
+            "ssm:///my/param",
+            "op://Dev/db/password",
+        ] {
+            assert_eq!(value, SecretRef::parse(value).unwrap().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        for (value, reason) in [
+            ("vault://secret/data/app", "missing #field"),
+            ("vault://#password", "empty path"),
+            ("vault://secret/data/app#", "empty field"),
+            ("ssm://my/param", "the parameter name must start with /"),
+            ("ssm:///", "empty parameter name"),
+            ("ssm://", "the parameter name must start with /"),
+            ("op://Dev/db", "expected op://vault/item/field"),
+            ("op://a/b/c/d/e", "expected op://vault/item/field"),
+            ("op://Dev//password", "empty vault, item or field"),
+        ] {
+            let err = SecretRef::parse(value).unwrap_err();
+            assert!(
+                matches!(&err, EnvelopeError::InvalidReference { reference, reason: r } if reference == value && *r == reason),
+                "{}: {:?}",
+                value,
+                err
+            );
+            assert_eq!(5, err.exit_code());
+        }
+        assert_eq!(
+            "invalid secret reference ssm:///: empty parameter name",
+            SecretRef::parse("ssm:///").unwrap_err().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_value() {
+        let resolver = MockResolver::new([
+            ("vault://secret/data/app#password", "hunter2"),
+            ("ssm:///my/param", "s3cr3t"),
+        ]);
+
+        assert_eq!(
+            "hunter2",
+            resolve_value(&resolver, "vault://secret/data/app#password")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            "s3cr3t",
+            resolve_value(&resolver, "ssm:///my/param").await.unwrap()
+        );
+        // literal values are not looked up
+        assert_eq!("plain", resolve_value(&resolver, "plain").await.unwrap());
+        assert_eq!(2, resolver.lookups.load(Ordering::Relaxed));
+
+        let err = resolve_value(&resolver, "ssm:///missing")
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, EnvelopeError::SecretNotFound(r) if r == "ssm:///missing"));
+        assert_eq!(4, err.exit_code());
+
+        let err = resolve_value(&resolver, "vault://secret")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, EnvelopeError::InvalidReference { .. }));
+        // malformed references never reach the resolver
+        assert_eq!(3, resolver.lookups.load(Ordering::Relaxed));
+    }
+}
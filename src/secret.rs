@@ -0,0 +1,214 @@
+//! Secret variables, told apart by their key, and the masking of their values
+//!
+//! The data types serialize their values as they are, [`Masked`] serializes
+//! them with the values of the secret variables replaced by [`MASK`].
+//!
+//! ```
+//! use std::collections::BTreeMap;
+//! use envelope::secret::Masked;
+//! use envelope::EnvDiff;
+//!
+//! let diff = EnvDiff {
+//!     added: BTreeMap::from([("API_KEY".into(), "s3cr3t".into()), ("PORT".into(), "80".into())]),
+//!     ..Default::default()
+//! };
+//! assert_eq!(
+//!     r#"{"added":{"API_KEY":"********","PORT":"80"},"removed":{},"changed":{}}"#,
+//!     serde_json::to_string(&Masked(&diff))?
+//! );
+//! # Ok::<(), serde_json::Error>(())
+//! ```
+
+use serde::{Serialize, Serializer};
+
+use crate::db::{ChangeEvent, DetailedRow, EnvDiff, EnvironmentRow, HistoryRow};
+
+/// Shown in place of the values of secret variables
+pub const MASK: &str = "********";
+
+/// Parts of a key naming a secret, e.g. `DB_PASSWORD` or `GITHUB_TOKEN`
+const SECRET_WORDS: &[&str] = &[
+    "SECRET",
+    "TOKEN",
+    "PASSWORD",
+    "PASSWD",
+    "PASS",
+    "PWD",
+    "PRIVATE",
+    "CREDENTIAL",
+    "CREDENTIALS",
+    "KEY",
+    "APIKEY",
+    "AUTH",
+];
+
+/// Whether `key` holds a secret going by its name, one of the words of the
+/// key separated by `_` must be one of [`SECRET_WORDS`]
+pub fn is_secret(key: &str) -> bool {
+    key.to_uppercase()
+        .split('_')
+        .any(|word| SECRET_WORDS.contains(&word))
+}
+
+/// Types holding values of variables, the values of the secret ones can be
+/// replaced with [`MASK`]. Values must be compared beforehand, masked values
+/// are all equal.
+pub trait MaskSecrets {
+    fn mask_secrets(&mut self);
+}
+
+impl MaskSecrets for EnvDiff {
+    fn mask_secrets(&mut self) {
+        let values = self
+            .added
+            .iter_mut()
+            .chain(self.removed.iter_mut())
+            .filter(|(key, _)| is_secret(key))
+            .map(|(_, value)| value);
+        for value in values {
+            *value = MASK.to_string();
+        }
+
+        for (_, (old, new)) in self.changed.iter_mut().filter(|(key, _)| is_secret(key)) {
+            *old = MASK.to_string();
+            *new = MASK.to_string();
+        }
+    }
+}
+
+impl MaskSecrets for EnvironmentRow {
+    fn mask_secrets(&mut self) {
+        if is_secret(&self.key) {
+            self.value = MASK.to_string();
+        }
+    }
+}
+
+impl MaskSecrets for DetailedRow {
+    fn mask_secrets(&mut self) {
+        if is_secret(&self.key) {
+            self.value = MASK.to_string();
+        }
+    }
+}
+
+impl MaskSecrets for HistoryRow {
+    fn mask_secrets(&mut self) {
+        if is_secret(&self.key) {
+            mask_option(&mut self.value);
+        }
+    }
+}
+
+impl MaskSecrets for ChangeEvent {
+    fn mask_secrets(&mut self) {
+        if is_secret(&self.key) {
+            mask_option(&mut self.old);
+            mask_option(&mut self.new);
+        }
+    }
+}
+
+impl<T: MaskSecrets> MaskSecrets for Vec<T> {
+    fn mask_secrets(&mut self) {
+        self.iter_mut().for_each(MaskSecrets::mask_secrets);
+    }
+}
+
+/// Masks `value` unless it is missing, which tells a deletion apart
+fn mask_option(value: &mut Option<String>) {
+    if let Some(value) = value {
+        *value = MASK.to_string();
+    }
+}
+
+/// Serializes the wrapped value with the values of its secret variables
+/// replaced with [`MASK`], the value itself is left untouched
+#[derive(Debug, Clone, Copy)]
+pub struct Masked<'a, T>(pub &'a T);
+
+impl<T: MaskSecrets + Clone + Serialize> Serialize for Masked<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut masked = self.0.clone();
+        masked.mask_secrets();
+        masked.serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_is_secret() {
+        for key in [
+            "API_KEY",
+            "DB_PASSWORD",
+            "github_token",
+            "AWS_SECRET_ACCESS_KEY",
+        ] {
+            assert!(is_secret(key), "{}", key);
+        }
+        for key in ["DATABASE_URL", "KEYBOARD", "PASSPORT_OFFICE", "PORT"] {
+            assert!(!is_secret(key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_mask_secrets() {
+        let mut diff = EnvDiff {
+            added: BTreeMap::from([("API_KEY".into(), "1".into()), ("A".into(), "1".into())]),
+            removed: BTreeMap::from([("DB_PASS".into(), "2".into())]),
+            changed: BTreeMap::from([
+                ("TOKEN".into(), ("3".into(), "4".into())),
+                ("C".into(), ("3".into(), "4".into())),
+            ]),
+        };
+
+        diff.mask_secrets();
+        assert_eq!(MASK, diff.added["API_KEY"]);
+        assert_eq!("1", diff.added["A"]);
+        assert_eq!(MASK, diff.removed["DB_PASS"]);
+        assert_eq!((MASK.into(), MASK.into()), diff.changed["TOKEN"]);
+        assert_eq!(("3".into(), "4".into()), diff.changed["C"]);
+    }
+
+    #[test]
+    fn test_masked() {
+        let rows = vec![
+            HistoryRow {
+                env: "dev".into(),
+                key: "TOKEN".into(),
+                value: Some("s3cr3t".into()),
+                created_at: 1,
+            },
+            HistoryRow {
+                env: "dev".into(),
+                key: "TOKEN".into(),
+                value: None,
+                created_at: 2,
+            },
+            HistoryRow {
+                env: "dev".into(),
+                key: "PORT".into(),
+                value: Some("80".into()),
+                created_at: 1,
+            },
+        ];
+
+        assert_eq!(
+            serde_json::json!([
+                {"env": "dev", "key": "TOKEN", "value": MASK, "created_at": 1},
+                {"env": "dev", "key": "TOKEN", "value": null, "created_at": 2},
+                {"env": "dev", "key": "PORT", "value": "80", "created_at": 1},
+            ]),
+            serde_json::to_value(Masked(&rows)).unwrap()
+        );
+        // the unmasked values are left as they are
+        assert_eq!(
+            serde_json::json!("s3cr3t"),
+            serde_json::to_value(&rows).unwrap()[0]["value"]
+        );
+    }
+}
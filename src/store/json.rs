@@ -0,0 +1,553 @@
+//! The JSON file backend, see [`JsonStore`]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use super::Store;
+use crate::db::{
+    check_nul, decode_created_at, unix_now, EnvDiff, Environment, EnvelopeResult, EnvironmentRow,
+    HistoryRow, InsertOutcome, Version, DEFAULT_WRITE_TIMEOUT,
+};
+use crate::error::EnvelopeError;
+use crate::SortOrder;
+
+/// How often a writer checks whether the lock file is gone
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Contents of the file, an object leaves room for more than the versions
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JsonFile {
+    versions: Vec<Version>,
+}
+
+/// A [`Store`] keeping every version of the variables in a single
+/// pretty-printed JSON file, which can be read and reviewed in git. The
+/// versions are sorted by env, key then creation time so that a write only
+/// adds lines to the file.
+///
+/// Reads go through the file as it is, writes hold the lock file
+/// `{path}.lock` and replace the file at once. Versions are keyed by the
+/// second they were created in like in sqlite: a write to a key that was
+/// already written in the same second replaces that version.
+#[derive(Debug, Clone)]
+pub struct JsonStore {
+    path: PathBuf,
+    write_timeout: Option<Duration>,
+}
+
+impl JsonStore {
+    /// uses the file at `path`, which is created by the first write
+    pub fn open(path: &Path) -> Self {
+        JsonStore {
+            path: path.to_path_buf(),
+            write_timeout: Some(DEFAULT_WRITE_TIMEOUT),
+        }
+    }
+
+    /// sets how long a write waits for the lock file of another writer to
+    /// go away before failing with [`EnvelopeError::Busy`], `None` waits
+    /// forever
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout = timeout;
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// lists every version in the file, see [`crate::EnvelopeDb::versions`]
+    pub fn versions(&self) -> EnvelopeResult<Vec<Version>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => {
+                return Err(EnvelopeError::File {
+                    path: self.path.clone(),
+                    source,
+                })
+            }
+        };
+
+        let file: JsonFile =
+            serde_json::from_str(&contents).map_err(|err| EnvelopeError::Parse {
+                file: self.path.display().to_string(),
+                line: err.line(),
+                message: err.to_string(),
+            })?;
+
+        // sorted again in case the file was edited by hand
+        let mut versions = file.versions;
+        sort_versions(&mut versions);
+
+        Ok(versions)
+    }
+
+    /// replaces the versions in the file with `versions`
+    pub async fn write_versions(&self, versions: Vec<Version>) -> EnvelopeResult<()> {
+        self.update(|current| *current = versions).await
+    }
+
+    /// applies `update` to the versions in the file while holding the lock
+    /// file, then writes them back
+    async fn update<T>(&self, update: impl FnOnce(&mut Vec<Version>) -> T) -> EnvelopeResult<T> {
+        let _lock = LockFile::acquire(&self.path, self.write_timeout).await?;
+        let mut versions = self.versions()?;
+        let result = update(&mut versions);
+
+        sort_versions(&mut versions);
+        // the newest write of a second wins, as it is the last one pushed
+        let mut deduped: Vec<Version> = Vec::with_capacity(versions.len());
+        for version in versions {
+            match deduped.last_mut() {
+                Some(last)
+                    if (&last.env, &last.key, last.created_at)
+                        == (&version.env, &version.key, version.created_at) =>
+                {
+                    *last = version
+                }
+                _ => deduped.push(version),
+            }
+        }
+
+        let mut contents = serde_json::to_string_pretty(&JsonFile { versions: deduped })
+            .map_err(io::Error::from)?;
+        contents.push('\n');
+        // written aside then renamed, so that reads never see half a file
+        let tmp = sibling(&self.path, "tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)?;
+        debug!(path = %self.path.display(), "written");
+
+        Ok(result)
+    }
+
+    /// appends a deletion of `key`, or of every key, of `env` if it still has
+    /// a value
+    async fn delete_keys(&self, env: &str, key: Option<&str>) -> EnvelopeResult<()> {
+        let now = unix_now();
+        self.update(|versions| {
+            let deleted: Vec<Version> = latest_versions(versions, env)
+                .into_values()
+                .filter(|v| v.value.is_some() && key.is_none_or(|key| v.key == key))
+                .map(|v| Version {
+                    value: None,
+                    created_at: now,
+                    expires_at: None,
+                    ..v.clone()
+                })
+                .collect();
+            versions.extend(deleted);
+        })
+        .await
+    }
+}
+
+/// latest version of each variable of `env` among the sorted `versions`,
+/// deletions included
+fn latest_versions<'a>(versions: &'a [Version], env: &str) -> BTreeMap<&'a str, &'a Version> {
+    versions
+        .iter()
+        .filter(|v| v.env == env)
+        // the newest version of a key comes last and replaces the others
+        .map(|v| (v.key.as_str(), v))
+        .collect()
+}
+
+/// Sorts `versions` by env, key then creation time, the order is stable so
+/// that the versions of the same second stay in the order they were written
+fn sort_versions(versions: &mut [Version]) {
+    versions.sort_by(|a, b| (&a.env, &a.key, a.created_at).cmp(&(&b.env, &b.key, b.created_at)));
+}
+
+/// Whether `version` holds a value that has not expired
+fn is_live(version: &Version, now: i64) -> bool {
+    version.value.is_some() && version.expires_at.is_none_or(|at| at > now)
+}
+
+/// Names and keys are not empty, as the sqlite tables require
+fn check_names(env: &str, key: &str) -> EnvelopeResult<()> {
+    match (env.is_empty(), key.is_empty()) {
+        (true, _) => Err(EnvelopeError::Constraint("env name cannot be empty".into())),
+        (_, true) => Err(EnvelopeError::Constraint("key name cannot be empty".into())),
+        _ => Ok(()),
+    }
+}
+
+/// `path` with `extension` appended to its file name
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(extension);
+    path.with_file_name(name)
+}
+
+/// Created next to the file by a writer and removed when it is done, the
+/// other writers wait for it to go away
+#[derive(Debug)]
+struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    async fn acquire(path: &Path, timeout: Option<Duration>) -> EnvelopeResult<Self> {
+        let path = sibling(path, "lock");
+        let start = Instant::now();
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    writeln!(file, "{}", std::process::id())?;
+                    return Ok(LockFile { path });
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            match timeout {
+                Some(timeout) if start.elapsed() >= timeout => {
+                    debug!(path = %path.display(), "lock file still present");
+                    return Err(EnvelopeError::Busy(timeout));
+                }
+                _ => tokio::time::sleep(LOCK_POLL_INTERVAL).await,
+            }
+        }
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Store for JsonStore {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, value))
+    )]
+    async fn insert(&self, env: &str, key: &str, value: &str) -> EnvelopeResult<InsertOutcome> {
+        check_names(env, key)?;
+        check_nul(env, [(key, value)])?;
+
+        let key = key.to_ascii_uppercase();
+        let now = unix_now();
+        self.update(|versions| {
+            let previous = latest_versions(versions, env)
+                .get(key.as_str())
+                .filter(|v| is_live(v, now))
+                .and_then(|v| v.value.clone());
+            versions.push(Version {
+                env: env.to_string(),
+                key,
+                value: Some(value.to_string()),
+                created_at: now,
+                expires_at: None,
+            });
+
+            InsertOutcome {
+                changed: previous.as_deref() != Some(value),
+                previous,
+            }
+        })
+        .await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn get_vars(
+        &self,
+        env: &str,
+        keys: &[String],
+    ) -> EnvelopeResult<BTreeMap<String, Option<String>>> {
+        let versions = self.versions()?;
+        let latest = latest_versions(&versions, env);
+        let now = unix_now();
+
+        Ok(keys
+            .iter()
+            .map(|key| {
+                let key = key.to_ascii_uppercase();
+                let value = latest
+                    .get(key.as_str())
+                    .filter(|v| is_live(v, now))
+                    .and_then(|v| v.value.clone());
+                (key, value)
+            })
+            .collect())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn list_var_in_env(
+        &self,
+        env: &str,
+        order: SortOrder,
+    ) -> EnvelopeResult<Vec<EnvironmentRow>> {
+        let versions = self.versions()?;
+        let now = unix_now();
+        let mut rows = Vec::new();
+        for version in latest_versions(&versions, env).into_values() {
+            if let (Some(value), true) = (&version.value, is_live(version, now)) {
+                rows.push(EnvironmentRow {
+                    env: version.env.clone(),
+                    key: version.key.clone(),
+                    value: value.clone(),
+                    created_at: decode_created_at(version.created_at)?,
+                });
+            }
+        }
+        if order == SortOrder::Desc {
+            rows.reverse();
+        }
+
+        Ok(rows)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn list_environments(&self) -> EnvelopeResult<Vec<Environment>> {
+        let envs: BTreeSet<String> = self.versions()?.into_iter().map(|v| v.env).collect();
+
+        Ok(envs.into_iter().map(|env| Environment { env }).collect())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn delete_var_for_env(&self, env: &str, key: &str) -> EnvelopeResult<()> {
+        self.delete_keys(env, Some(key)).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn delete_env(&self, env: &str) -> EnvelopeResult<()> {
+        self.delete_keys(env, None).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, diff))
+    )]
+    async fn apply_diff(&self, env: &str, diff: &EnvDiff) -> EnvelopeResult<()> {
+        if diff.is_empty() {
+            return Ok(());
+        }
+
+        let set: Vec<(String, Option<String>)> = diff
+            .added
+            .iter()
+            .chain(diff.changed.iter().map(|(key, (_, new))| (key, new)))
+            .map(|(key, value)| (key.to_ascii_uppercase(), Some(value.clone())))
+            .chain(diff.removed.keys().map(|key| (key.clone(), None)))
+            .collect();
+        for (key, value) in &set {
+            check_names(env, key)?;
+            check_nul(env, [(key.as_str(), value.as_deref().unwrap_or_default())])?;
+        }
+
+        let now = unix_now();
+        self.update(|versions| {
+            versions.extend(set.into_iter().map(|(key, value)| Version {
+                env: env.to_string(),
+                key,
+                value,
+                created_at: now,
+                expires_at: None,
+            }))
+        })
+        .await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn history_between(
+        &self,
+        env: &str,
+        key: Option<&str>,
+        since: Option<i64>,
+        until: Option<i64>,
+    ) -> EnvelopeResult<Vec<HistoryRow>> {
+        let key = key.map(str::to_ascii_uppercase);
+
+        Ok(self
+            .versions()?
+            .into_iter()
+            .filter(|v| v.env == env && key.as_ref().is_none_or(|key| &v.key == key))
+            .filter(|v| since.is_none_or(|since| v.created_at >= since))
+            .filter(|v| until.is_none_or(|until| v.created_at < until))
+            .map(|v| HistoryRow {
+                env: v.env,
+                key: v.key,
+                value: v.value,
+                created_at: v.created_at,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("envelope-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn version(env: &str, key: &str, value: Option<&str>, created_at: i64) -> Version {
+        Version {
+            env: env.into(),
+            key: key.into(),
+            value: value.map(Into::into),
+            created_at,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_store() {
+        let path = temp_path("json");
+        let store = JsonStore::open(&path);
+        assert!(store.list_environments().await.unwrap().is_empty());
+
+        store
+            .write_versions(vec![
+                version("dev", "HOST", Some("old"), 1),
+                version("dev", "HOST", Some("localhost"), 2),
+                version("dev", "GONE", Some("x"), 1),
+                version("dev", "GONE", None, 2),
+                version("dev", "PORT", Some("80"), 1),
+                version("prod", "PORT", Some("443"), 1),
+            ])
+            .await
+            .unwrap();
+
+        let outcome = store.insert("dev", "host", "example.com").await.unwrap();
+        assert_eq!(Some("localhost"), outcome.previous.as_deref());
+        assert!(outcome.changed);
+        let vars = store
+            .get_vars("dev", &["host".into(), "gone".into(), "none".into()])
+            .await
+            .unwrap();
+        assert_eq!(Some("example.com"), vars["HOST"].as_deref());
+        assert_eq!(None, vars["GONE"]);
+        assert_eq!(None, vars["NONE"]);
+
+        store.delete_var_for_env("dev", "PORT").await.unwrap();
+        store.delete_env("prod").await.unwrap();
+        let keys: Vec<String> = store
+            .list_var_in_env("dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.key)
+            .collect();
+        assert_eq!(vec!["HOST"], keys);
+        assert!(store
+            .list_var_in_env("prod", SortOrder::Asc)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            vec!["dev", "prod"],
+            store
+                .list_environments()
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|env| env.env)
+                .collect::<Vec<_>>()
+        );
+
+        // the whole history is kept, deletions included
+        let history = store
+            .history_between("dev", Some("port"), None, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![Some("80"), None],
+            history
+                .iter()
+                .map(|row| row.value.as_deref())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            2,
+            store
+                .history_between("dev", Some("HOST"), Some(2), None)
+                .await
+                .unwrap()
+                .len()
+        );
+
+        // the file is sorted and readable
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.starts_with("{\n  \"versions\": [\n"));
+        assert!(contents.find("\"GONE\"").unwrap() < contents.find("\"HOST\"").unwrap());
+        assert!(!sibling(&path, "lock").exists());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_same_second() {
+        let path = temp_path("json-second");
+        let store = JsonStore::open(&path);
+
+        store
+            .write_versions(vec![
+                version("dev", "A", Some("1"), 5),
+                version("dev", "B", Some("1"), 5),
+                version("dev", "A", Some("2"), 5),
+                version("dev", "A", None, 4),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![
+                version("dev", "A", None, 4),
+                version("dev", "A", Some("2"), 5),
+                version("dev", "B", Some("1"), 5),
+            ],
+            store.versions().unwrap()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejected_writes() {
+        let path = temp_path("json-rejected");
+        let store = JsonStore::open(&path);
+
+        let err = store.insert("", "A", "1").await.unwrap_err();
+        assert_eq!("env name cannot be empty", err.to_string());
+        let err = store.insert("dev", "A", "a\0b").await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::Constraint(_)));
+        assert!(!path.exists());
+
+        fs::write(&path, "{\"versions\": [").unwrap();
+        let err = store.list_environments().await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::Parse { line: 1, .. }));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lock_file() {
+        let path = temp_path("json-lock");
+        let mut store = JsonStore::open(&path);
+        store.set_write_timeout(Some(Duration::from_millis(50)));
+
+        let lock = LockFile::acquire(&path, None).await.unwrap();
+        let err = store.insert("dev", "A", "1").await.unwrap_err();
+        assert!(matches!(err, EnvelopeError::Busy(_)));
+
+        // a writer waiting for the lock goes on once it is released
+        store.set_write_timeout(Some(Duration::from_secs(5)));
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(lock);
+        };
+        let (inserted, ()) = tokio::join!(store.insert("dev", "A", "1"), release);
+        assert!(inserted.unwrap().changed);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,401 @@
+//! Storage backends of envelope. [`Store`] is the part of the
+//! [`EnvelopeDb`] API that every backend provides, the sqlite
+//! [`EnvelopeDb`] being the default one. [`JsonStore`] keeps the variables
+//! in a JSON file instead, and the `postgres` feature adds [`PgStore`] for a
+//! database shared by a team.
+//!
+//! [`AnyStore::open`] picks the backend from `ENVELOPE_DATABASE_URL`:
+//!
+//! ```
+//! use envelope::store::{AnyStore, Store};
+//! use envelope::SortOrder;
+//!
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> envelope::EnvelopeResult<()> {
+//! # let path = std::env::temp_dir().join(format!("envelope-store-{}.db", std::process::id()));
+//! // the sqlite database at `path` unless ENVELOPE_DATABASE_URL is set
+//! let store = AnyStore::open(&path).await?;
+//! store.insert("dev", "port", "8080").await?;
+//!
+//! let vars = store.list_var_in_env("dev", SortOrder::Asc).await?;
+//! assert_eq!("PORT", vars[0].key);
+//! # drop(store);
+//! # std::fs::remove_file(&path)?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod json;
+#[cfg(feature = "postgres")]
+mod postgres;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::future::Future;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
+use crate::db::{
+    EnvDiff, Environment, EnvelopeDb, EnvelopeResult, EnvironmentRow, HistoryRow, InsertOutcome,
+};
+use crate::error::EnvelopeError;
+use crate::SortOrder;
+
+pub use json::JsonStore;
+#[cfg(feature = "postgres")]
+pub use postgres::{migrate as migrate_postgres, PgStore};
+
+/// Variable holding the url of the database, see [`AnyStore::connect`]
+pub const DATABASE_URL_VAR: &str = "ENVELOPE_DATABASE_URL";
+
+/// Operations every storage backend provides, they behave like the
+/// [`EnvelopeDb`] methods of the same name
+pub trait Store: Sync {
+    /// see [`EnvelopeDb::insert`]
+    fn insert(
+        &self,
+        env: &str,
+        key: &str,
+        value: &str,
+    ) -> impl Future<Output = EnvelopeResult<InsertOutcome>> + Send;
+
+    /// see [`EnvelopeDb::get_vars`]
+    fn get_vars(
+        &self,
+        env: &str,
+        keys: &[String],
+    ) -> impl Future<Output = EnvelopeResult<BTreeMap<String, Option<String>>>> + Send;
+
+    /// see [`EnvelopeDb::list_var_in_env`]
+    fn list_var_in_env(
+        &self,
+        env: &str,
+        order: SortOrder,
+    ) -> impl Future<Output = EnvelopeResult<Vec<EnvironmentRow>>> + Send;
+
+    /// see [`EnvelopeDb::list_environments`]
+    fn list_environments(&self) -> impl Future<Output = EnvelopeResult<Vec<Environment>>> + Send;
+
+    /// see [`EnvelopeDb::check_env_exists`]
+    fn check_env_exists(&self, env: &str) -> impl Future<Output = EnvelopeResult<()>> + Send {
+        async move {
+            match self.list_environments().await?.iter().any(|e| e.env == env) {
+                true => Ok(()),
+                false => Err(EnvelopeError::EnvNotFound(env.to_string())),
+            }
+        }
+    }
+
+    /// see [`EnvelopeDb::delete_var_for_env`]
+    fn delete_var_for_env(
+        &self,
+        env: &str,
+        key: &str,
+    ) -> impl Future<Output = EnvelopeResult<()>> + Send;
+
+    /// see [`EnvelopeDb::delete_env`]
+    fn delete_env(&self, env: &str) -> impl Future<Output = EnvelopeResult<()>> + Send;
+
+    /// see [`EnvelopeDb::apply_diff`]
+    fn apply_diff(
+        &self,
+        env: &str,
+        diff: &EnvDiff,
+    ) -> impl Future<Output = EnvelopeResult<()>> + Send;
+
+    /// see [`EnvelopeDb::history_between`]
+    fn history_between(
+        &self,
+        env: &str,
+        key: Option<&str>,
+        since: Option<i64>,
+        until: Option<i64>,
+    ) -> impl Future<Output = EnvelopeResult<Vec<HistoryRow>>> + Send;
+
+    /// see [`EnvelopeDb::fingerprint`], the digest is the same whatever the
+    /// backend
+    fn fingerprint(&self, env: &str) -> impl Future<Output = EnvelopeResult<String>> + Send {
+        async move {
+            let mut hasher = Sha256::new();
+            for row in self.list_var_in_env(env, SortOrder::Asc).await? {
+                // lengths keep `A=BC` and `AB=C` apart
+                for field in [row.key, row.value] {
+                    hasher.update((field.len() as u64).to_le_bytes());
+                    hasher.update(field);
+                }
+            }
+
+            Ok(format!("{:x}", hasher.finalize()))
+        }
+    }
+}
+
+impl Store for EnvelopeDb {
+    async fn insert(&self, env: &str, key: &str, value: &str) -> EnvelopeResult<InsertOutcome> {
+        EnvelopeDb::insert(self, env, key, value).await
+    }
+
+    async fn get_vars(
+        &self,
+        env: &str,
+        keys: &[String],
+    ) -> EnvelopeResult<BTreeMap<String, Option<String>>> {
+        EnvelopeDb::get_vars(self, env, keys).await
+    }
+
+    async fn list_var_in_env(
+        &self,
+        env: &str,
+        order: SortOrder,
+    ) -> EnvelopeResult<Vec<EnvironmentRow>> {
+        EnvelopeDb::list_var_in_env(self, env, order).await
+    }
+
+    async fn list_environments(&self) -> EnvelopeResult<Vec<Environment>> {
+        EnvelopeDb::list_environments(self).await
+    }
+
+    async fn check_env_exists(&self, env: &str) -> EnvelopeResult<()> {
+        EnvelopeDb::check_env_exists(self, env).await
+    }
+
+    async fn delete_var_for_env(&self, env: &str, key: &str) -> EnvelopeResult<()> {
+        EnvelopeDb::delete_var_for_env(self, env, key).await
+    }
+
+    async fn delete_env(&self, env: &str) -> EnvelopeResult<()> {
+        EnvelopeDb::delete_env(self, env).await
+    }
+
+    async fn apply_diff(&self, env: &str, diff: &EnvDiff) -> EnvelopeResult<()> {
+        EnvelopeDb::apply_diff(self, env, diff).await
+    }
+
+    async fn history_between(
+        &self,
+        env: &str,
+        key: Option<&str>,
+        since: Option<i64>,
+        until: Option<i64>,
+    ) -> EnvelopeResult<Vec<HistoryRow>> {
+        EnvelopeDb::history_between(self, env, key, since, until).await
+    }
+}
+
+/// The backend picked at runtime by [`AnyStore::connect`]
+#[derive(Debug)]
+pub enum AnyStore {
+    Sqlite(EnvelopeDb),
+    Json(JsonStore),
+    #[cfg(feature = "postgres")]
+    Postgres(PgStore),
+}
+
+impl AnyStore {
+    /// name of the backend, for the messages
+    pub fn backend(&self) -> &'static str {
+        match self {
+            AnyStore::Sqlite(_) => "sqlite",
+            AnyStore::Json(_) => "json",
+            #[cfg(feature = "postgres")]
+            AnyStore::Postgres(_) => "postgres",
+        }
+    }
+
+    /// connects to the database named by `ENVELOPE_DATABASE_URL` when it is
+    /// set, opens the database at `path` otherwise, see
+    /// [`AnyStore::connect`]
+    pub async fn open(path: &Path) -> EnvelopeResult<Self> {
+        match env::var(DATABASE_URL_VAR) {
+            Ok(url) if !url.is_empty() => AnyStore::connect(&url).await,
+            _ => AnyStore::connect(&path.to_string_lossy()).await,
+        }
+    }
+
+    /// connects to the postgres database of a `postgres://` or
+    /// `postgresql://` url, which needs the `postgres` feature, and runs its
+    /// migrations. Any other url is the path of a database, with or without
+    /// a `sqlite://` or `json://` scheme: a JSON file if it has the `json`
+    /// scheme or extension, a sqlite database otherwise.
+    pub async fn connect(url: &str) -> EnvelopeResult<Self> {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            return AnyStore::connect_postgres(url).await;
+        }
+        if let Some(path) = url.strip_prefix("json://") {
+            return Ok(AnyStore::Json(JsonStore::open(Path::new(path))));
+        }
+
+        let path = Path::new(url.strip_prefix("sqlite://").unwrap_or(url));
+        match path.extension() {
+            Some(extension) if extension == "json" && !url.starts_with("sqlite://") => {
+                Ok(AnyStore::Json(JsonStore::open(path)))
+            }
+            _ => Ok(AnyStore::Sqlite(EnvelopeDb::open(path).await?)),
+        }
+    }
+
+    #[cfg(feature = "postgres")]
+    async fn connect_postgres(url: &str) -> EnvelopeResult<Self> {
+        Ok(AnyStore::Postgres(PgStore::connect(url).await?))
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    async fn connect_postgres(_url: &str) -> EnvelopeResult<Self> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "envelope was built without postgres support, enable the postgres feature",
+        )
+        .into())
+    }
+}
+
+/// Runs the same method on whichever backend is in use
+macro_rules! dispatch {
+    ($store:expr, $db:ident => $call:expr) => {
+        match $store {
+            AnyStore::Sqlite($db) => $call.await,
+            AnyStore::Json($db) => $call.await,
+            #[cfg(feature = "postgres")]
+            AnyStore::Postgres($db) => $call.await,
+        }
+    };
+}
+
+impl Store for AnyStore {
+    async fn insert(&self, env: &str, key: &str, value: &str) -> EnvelopeResult<InsertOutcome> {
+        dispatch!(self, db => Store::insert(db, env, key, value))
+    }
+
+    async fn get_vars(
+        &self,
+        env: &str,
+        keys: &[String],
+    ) -> EnvelopeResult<BTreeMap<String, Option<String>>> {
+        dispatch!(self, db => Store::get_vars(db, env, keys))
+    }
+
+    async fn list_var_in_env(
+        &self,
+        env: &str,
+        order: SortOrder,
+    ) -> EnvelopeResult<Vec<EnvironmentRow>> {
+        dispatch!(self, db => Store::list_var_in_env(db, env, order))
+    }
+
+    async fn list_environments(&self) -> EnvelopeResult<Vec<Environment>> {
+        dispatch!(self, db => Store::list_environments(db))
+    }
+
+    async fn check_env_exists(&self, env: &str) -> EnvelopeResult<()> {
+        dispatch!(self, db => Store::check_env_exists(db, env))
+    }
+
+    async fn delete_var_for_env(&self, env: &str, key: &str) -> EnvelopeResult<()> {
+        dispatch!(self, db => Store::delete_var_for_env(db, env, key))
+    }
+
+    async fn delete_env(&self, env: &str) -> EnvelopeResult<()> {
+        dispatch!(self, db => Store::delete_env(db, env))
+    }
+
+    async fn apply_diff(&self, env: &str, diff: &EnvDiff) -> EnvelopeResult<()> {
+        dispatch!(self, db => Store::apply_diff(db, env, diff))
+    }
+
+    async fn history_between(
+        &self,
+        env: &str,
+        key: Option<&str>,
+        since: Option<i64>,
+        until: Option<i64>,
+    ) -> EnvelopeResult<Vec<HistoryRow>> {
+        dispatch!(self, db => Store::history_between(db, env, key, since, until))
+    }
+
+    async fn fingerprint(&self, env: &str) -> EnvelopeResult<String> {
+        dispatch!(self, db => Store::fingerprint(db, env))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::test_db;
+
+    #[tokio::test]
+    async fn test_sqlite_store() {
+        let db = test_db().await;
+        sqlx::query(
+            r"INSERT INTO environments (env, key, value, created_at)
+            VALUES
+            ('dev', 'HOST', 'localhost', 1),
+            ('dev', 'PORT', '80', 1),
+            ('prod', 'PORT', '443', 1);",
+        )
+        .execute(db.get_pool())
+        .await
+        .unwrap();
+        let store = AnyStore::Sqlite(db);
+
+        let fingerprint = Store::fingerprint(&store, "dev").await.unwrap();
+        let outcome = Store::insert(&store, "dev", "user", "me").await.unwrap();
+        assert_eq!(None, outcome.previous);
+        assert_ne!(
+            fingerprint,
+            Store::fingerprint(&store, "dev").await.unwrap()
+        );
+
+        let vars = Store::get_vars(&store, "dev", &["PORT".into(), "USER".into()])
+            .await
+            .unwrap();
+        assert_eq!(Some("80"), vars["PORT"].as_deref());
+        assert_eq!(Some("me"), vars["USER"].as_deref());
+
+        Store::delete_var_for_env(&store, "dev", "PORT")
+            .await
+            .unwrap();
+        let keys: Vec<String> = Store::list_var_in_env(&store, "dev", SortOrder::Asc)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.key)
+            .collect();
+        assert_eq!(vec!["HOST", "USER"], keys);
+
+        Store::delete_env(&store, "prod").await.unwrap();
+        assert!(Store::list_var_in_env(&store, "prod", SortOrder::Asc)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(2, Store::list_environments(&store).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let path = env::temp_dir().join(format!("envelope-url-{}.db", std::process::id()));
+        let store = AnyStore::connect(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        assert!(matches!(store, AnyStore::Sqlite(_)));
+        drop(store);
+        std::fs::remove_file(&path).unwrap();
+
+        let store = AnyStore::connect("vars.json").await.unwrap();
+        assert!(matches!(store, AnyStore::Json(_)));
+        let store = AnyStore::connect("json://.envelope").await.unwrap();
+        assert!(matches!(store, AnyStore::Json(store) if store.path() == Path::new(".envelope")));
+    }
+
+    #[cfg(not(feature = "postgres"))]
+    #[tokio::test]
+    async fn test_connect_without_postgres() {
+        let err = AnyStore::connect("postgres://localhost/envelope")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            crate::EnvelopeError::Io(err) if err.kind() == std::io::ErrorKind::Unsupported
+        ));
+    }
+}
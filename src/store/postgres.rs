@@ -0,0 +1,352 @@
+//! The postgres backend, enabled by the `postgres` feature
+
+use std::collections::BTreeMap;
+
+use sea_query::{Alias, Asterisk, Expr, Func, Order, PostgresQueryBuilder, Query, SelectStatement};
+use sea_query_binder::{SqlxBinder, SqlxValues};
+use sqlx::postgres::{PgPool, PgPoolOptions, PgRow};
+use sqlx::Row;
+use tracing::{debug, info};
+
+use super::Store;
+use crate::db::{
+    check_nul, db_error, decode_created_at, EnvDiff, Environment, EnvelopeResult, EnvironmentRow,
+    HistoryRow, InsertOutcome,
+};
+use crate::error::EnvelopeError;
+use crate::SortOrder;
+
+#[derive(Debug, sea_query::Iden)]
+enum Environments {
+    Table,
+    Env,
+    Key,
+    Value,
+    CreatedAt,
+}
+
+/// A [`Store`] keeping the variables in a postgres database, which a team
+/// can share. It only has the environments table of the sqlite database:
+/// locks, descriptions, types, templates and expiring variables are not
+/// available there.
+#[derive(Debug, Clone)]
+pub struct PgStore {
+    db: PgPool,
+}
+
+impl PgStore {
+    /// connects to the database at `url` and creates or updates its tables
+    pub async fn connect(url: &str) -> EnvelopeResult<Self> {
+        // the url is not logged, it may hold a password
+        info!("connecting to postgres");
+        let pool = PgPoolOptions::new().connect(url).await.map_err(db_error)?;
+        migrate(&pool).await?;
+
+        Ok(PgStore::from_pool(pool))
+    }
+
+    /// uses `pool` as the database, the tables must have been created
+    /// beforehand with [`migrate`]
+    pub fn from_pool(pool: PgPool) -> Self {
+        PgStore { db: pool }
+    }
+
+    /// latest version of each variable of `env`, deleted ones included.
+    /// Postgres has no bare columns for the `GROUP BY ... HAVING MAX` of the
+    /// sqlite queries, `DISTINCT ON` keeps the first row of each key instead.
+    fn latest_versions(&self, env: &str) -> SelectStatement {
+        Query::select()
+            .distinct_on([Environments::Env, Environments::Key])
+            .column(Asterisk)
+            .from(Environments::Table)
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .order_by(Environments::Env, Order::Asc)
+            .order_by(Environments::Key, Order::Asc)
+            .order_by(Environments::CreatedAt, Order::Desc)
+            .to_owned()
+    }
+
+    /// inserts a deletion of the keys of `env` matched by `select`, which
+    /// still have a value
+    async fn insert_deletions(&self, mut select: SelectStatement) -> EnvelopeResult<()> {
+        select
+            .column(Environments::Env)
+            .column(Environments::Key)
+            .expr(Expr::val(Option::<String>::None))
+            .from(Environments::Table)
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .group_by_columns([Environments::Env, Environments::Key]);
+
+        let (sql, values) = Query::insert()
+            .into_table(Environments::Table)
+            .columns([Environments::Env, Environments::Key, Environments::Value])
+            .select_from(select)
+            .unwrap()
+            .to_postgres();
+
+        sqlx::query_with(&sql, values)
+            .execute(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        Ok(())
+    }
+}
+
+/// Creates or updates the envelope tables of the postgres database `pool`.
+/// Fails with [`EnvelopeError::MigrationMismatch`] if a newer envelope
+/// migrated it.
+pub async fn migrate(pool: &PgPool) -> EnvelopeResult<()> {
+    let migrator = sqlx::migrate!("./migrations/postgres");
+
+    let migrated: bool = sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+        .fetch_one(pool)
+        .await
+        .map_err(db_error)?;
+    if migrated {
+        let database: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations")
+            .fetch_one(pool)
+            .await
+            .map_err(db_error)?;
+        let known = migrator.iter().map(|m| m.version).max().unwrap_or_default();
+        if let Some(database) = database.filter(|database| *database > known) {
+            return Err(EnvelopeError::MigrationMismatch { database, known });
+        }
+    }
+
+    info!(migrations = migrator.iter().count(), "running migrations");
+    migrator.run(pool).await?;
+
+    Ok(())
+}
+
+impl Store for PgStore {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, value))
+    )]
+    async fn insert(&self, env: &str, key: &str, value: &str) -> EnvelopeResult<InsertOutcome> {
+        check_nul(env, [(key, value)])?;
+
+        let mut tx = self.db.begin().await.map_err(db_error)?;
+
+        let (sql, values) = Query::select()
+            .from(Environments::Table)
+            .column(Environments::Value)
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .and_where(Expr::col(Environments::Key).eq(Func::upper(key)))
+            .order_by(Environments::CreatedAt, Order::Desc)
+            .limit(1)
+            .to_postgres();
+        let latest: Option<(Option<String>,)> = sqlx::query_as_with(&sql, values)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        let previous = latest.and_then(|(value,)| value);
+
+        let (sql, values) = Query::insert()
+            .into_table(Environments::Table)
+            .columns([Environments::Env, Environments::Key, Environments::Value])
+            .values([env.into(), Func::upper(key).into(), value.into()])
+            .unwrap()
+            .to_postgres();
+        sqlx::query_with(&sql, values)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(InsertOutcome {
+            changed: previous.as_deref() != Some(value),
+            previous,
+        })
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn get_vars(
+        &self,
+        env: &str,
+        keys: &[String],
+    ) -> EnvelopeResult<BTreeMap<String, Option<String>>> {
+        let mut vars: BTreeMap<String, Option<String>> =
+            keys.iter().map(|key| (key.to_uppercase(), None)).collect();
+        if vars.is_empty() {
+            return Ok(vars);
+        }
+
+        let latest = self
+            .latest_versions(env)
+            .and_where(Expr::col(Environments::Key).is_in(vars.keys().map(String::as_str)))
+            .to_owned();
+        let (sql, values) = Query::select()
+            .from_subquery(latest, Alias::new("T"))
+            .columns([Environments::Key, Environments::Value])
+            .to_postgres();
+
+        let rows: Vec<(String, Option<String>)> = sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)?;
+        vars.extend(rows);
+
+        Ok(vars)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn list_var_in_env(
+        &self,
+        env: &str,
+        order: SortOrder,
+    ) -> EnvelopeResult<Vec<EnvironmentRow>> {
+        let (sql, values) = Query::select()
+            .from_subquery(self.latest_versions(env), Alias::new("T"))
+            .column(Asterisk)
+            .and_where(Expr::col(Environments::Value).is_not_null())
+            .order_by(Environments::Key, order.to_order())
+            .to_postgres();
+
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn list_environments(&self) -> EnvelopeResult<Vec<Environment>> {
+        let (sql, _) = Query::select()
+            .from(Environments::Table)
+            .column(Environments::Env)
+            .distinct()
+            .order_by(Environments::Env, Order::Asc)
+            .to_postgres();
+
+        sqlx::query_as(&sql)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn delete_var_for_env(&self, env: &str, key: &str) -> EnvelopeResult<()> {
+        let select = Query::select()
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .and_where(Expr::col(Environments::Key).eq(key))
+            .to_owned();
+
+        self.insert_deletions(select).await
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn delete_env(&self, env: &str) -> EnvelopeResult<()> {
+        let select = Query::select()
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .to_owned();
+
+        self.insert_deletions(select).await
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, diff))
+    )]
+    async fn apply_diff(&self, env: &str, diff: &EnvDiff) -> EnvelopeResult<()> {
+        if diff.is_empty() {
+            return Ok(());
+        }
+
+        let set = diff
+            .added
+            .iter()
+            .chain(diff.changed.iter().map(|(key, (_, new))| (key, new)));
+        check_nul(env, set.clone().map(|(k, v)| (k.as_str(), v.as_str())))?;
+
+        let mut insert = Query::insert()
+            .into_table(Environments::Table)
+            .columns([Environments::Env, Environments::Key, Environments::Value])
+            .to_owned();
+        for (key, value) in set {
+            insert
+                .values([env.into(), Func::upper(key).into(), value.into()])
+                .unwrap();
+        }
+        for key in diff.removed.keys() {
+            insert
+                .values([env.into(), key.into(), Option::<String>::None.into()])
+                .unwrap();
+        }
+
+        let (sql, values) = insert.to_postgres();
+        sqlx::query_with(&sql, values)
+            .execute(&self.db)
+            .await
+            .map_err(db_error)?;
+
+        Ok(())
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    async fn history_between(
+        &self,
+        env: &str,
+        key: Option<&str>,
+        since: Option<i64>,
+        until: Option<i64>,
+    ) -> EnvelopeResult<Vec<HistoryRow>> {
+        let mut select = Query::select()
+            .from(Environments::Table)
+            .columns([
+                Environments::Env,
+                Environments::Key,
+                Environments::Value,
+                Environments::CreatedAt,
+            ])
+            .and_where(Expr::col(Environments::Env).eq(env))
+            .order_by_columns([
+                (Environments::Key, Order::Asc),
+                (Environments::CreatedAt, Order::Asc),
+            ])
+            .to_owned();
+        if let Some(key) = key {
+            select.and_where(Expr::col(Environments::Key).eq(Func::upper(key)));
+        }
+        if let Some(since) = since {
+            select.and_where(Expr::col(Environments::CreatedAt).gte(since));
+        }
+        if let Some(until) = until {
+            select.and_where(Expr::col(Environments::CreatedAt).lt(until));
+        }
+
+        let (sql, values) = select.to_postgres();
+        sqlx::query_as_with(&sql, values)
+            .fetch_all(&self.db)
+            .await
+            .map_err(db_error)
+    }
+}
+
+/// `created_at` is a `BIGINT` of unix seconds, as in sqlite
+impl<'r> sqlx::FromRow<'r, PgRow> for EnvironmentRow {
+    fn from_row(row: &'r PgRow) -> sqlx::Result<Self> {
+        Ok(EnvironmentRow {
+            env: row.try_get("env")?,
+            key: row.try_get("key")?,
+            value: row.try_get("value")?,
+            created_at: decode_created_at(row.try_get("created_at")?)?,
+        })
+    }
+}
+
+/// Builds sea-query statements for postgres
+trait ToPostgres {
+    /// returns the sql of the statement and its values, the sql is logged at
+    /// debug level
+    fn to_postgres(&self) -> (String, SqlxValues);
+}
+
+impl<T: SqlxBinder> ToPostgres for T {
+    fn to_postgres(&self) -> (String, SqlxValues) {
+        let (sql, values) = self.build_sqlx(PostgresQueryBuilder);
+        debug!(%sql, "query");
+        (sql, values)
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt::Display;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anstyle::{AnsiColor, Style};
+
+/// Names of environments
+pub const ENV: Style = AnsiColor::Cyan.on_default().bold();
+
+/// Deleted versions of variables
+pub const DELETED: Style = Style::new().dimmed();
+
+/// Variables or values that are added
+pub const ADDED: Style = AnsiColor::Green.on_default();
+
+/// Variables or values that are removed
+pub const REMOVED: Style = AnsiColor::Red.on_default();
+
+/// Warnings printed on stderr
+pub const WARNING: Style = AnsiColor::Yellow.on_default();
+
+/// Whether [`paint`] writes escape codes, see [`set_color`]
+static PAINT: AtomicBool = AtomicBool::new(true);
+
+/// Sets when the output written to [`anstream::stdout`] and
+/// [`anstream::stderr`] is colored. With `auto` it is colored as long as it
+/// goes to a terminal, `NO_COLOR` and `CLICOLOR_FORCE` are honored.
+///
+/// The data written to [`io::stdout`] as is, such as the values of `list`,
+/// is only decorated by [`paint`] when stdout is colored.
+pub fn set_color(when: clap::ColorChoice) {
+    let choice = match when {
+        clap::ColorChoice::Auto => anstream::ColorChoice::Auto,
+        clap::ColorChoice::Always => anstream::ColorChoice::Always,
+        clap::ColorChoice::Never => anstream::ColorChoice::Never,
+    };
+    choice.write_global();
+
+    let paint = anstream::AutoStream::choice(&io::stdout()) != anstream::ColorChoice::Never;
+    PAINT.store(paint, Ordering::Relaxed);
+}
+
+/// Wraps `value` in the escape codes of `style`, unless the output must not
+/// be colored. [`anstream`] streams strip them as well.
+pub fn paint(style: Style, value: impl Display) -> String {
+    match PAINT.load(Ordering::Relaxed) {
+        true => format!("{}{}{}", style.render(), value, style.render_reset()),
+        false => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_paint() {
+        assert_eq!("\x1b[31mgone\x1b[0m", paint(REMOVED, "gone"));
+        assert_eq!("plain", paint(Style::new(), "plain"));
+        assert_eq!(
+            "gone",
+            anstream::adapter::strip_str(&paint(REMOVED, "gone")).to_string()
+        );
+    }
+}
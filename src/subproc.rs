@@ -1,31 +1,141 @@
 use std::{
+    collections::HashMap,
     io::Result,
     process::{Command, ExitStatus},
-    collections::HashMap,
 };
 
 #[derive(Debug)]
-pub struct ChildProcess<'a> {
-    cmd: &'a str,
-    args: &'a [&'a str],
-    envs: HashMap<&'a str, &'a str>,
+pub struct ChildProcess {
+    cmd: String,
+    args: Vec<String>,
+    envs: HashMap<String, String>,
+    remove: Vec<String>,
+    keep: Option<Vec<String>>,
 }
 
-impl<'a> ChildProcess<'a> {
-    pub fn new(cmd: &'a str, args: &'a [&'a str], envs: &'a [(&'a str, &'a str)]) -> Self {
-        let envs = envs.iter().cloned().collect();
+impl ChildProcess {
+    pub fn new(cmd: &str, args: &[&str], envs: &[(&str, &str)]) -> Self {
         ChildProcess {
             cmd: cmd.into(),
-            args,
-            envs,
+            args: args.iter().map(|a| a.to_string()).collect(),
+            envs: envs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            remove: Vec::new(),
+            keep: None,
         }
     }
 
+    /// variables that must not be inherited by the child process
+    pub fn env_remove(mut self, keys: &[&str]) -> Self {
+        self.remove = keys.iter().map(|k| k.to_string()).collect();
+        self
+    }
+
+    /// starts the child process from an empty environment, only the inherited
+    /// variables in `keep` are passed on
+    pub fn pristine(mut self, keep: &[&str]) -> Self {
+        self.keep = Some(keep.iter().map(|k| k.to_string()).collect());
+        self
+    }
+
+    pub fn cmd(&self) -> &str {
+        &self.cmd
+    }
+
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.cmd);
+        if let Some(keep) = &self.keep {
+            cmd.env_clear();
+            for key in keep {
+                if let Some(value) = std::env::var_os(key) {
+                    cmd.env(key, value);
+                }
+            }
+        }
+
+        for key in &self.remove {
+            cmd.env_remove(key);
+        }
+
+        cmd.args(&self.args).envs(&self.envs);
+        cmd
+    }
+
     pub fn run_shell_command(&self) -> Result<ExitStatus> {
-        Command::new(&self.cmd)
-            .args(self.args)
-            .envs(&self.envs)
-            .spawn()?
-            .wait()
+        self.command().spawn()?.wait()
     }
+
+    /// Spawns the child process without waiting for it, the child is killed
+    /// if the returned handle is dropped.
+    ///
+    /// On unix the child leads its own process group, so that it can be
+    /// terminated together with the processes it starts.
+    pub fn spawn_async(&self) -> Result<tokio::process::Child> {
+        let mut cmd = self.command();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            cmd.process_group(0);
+        }
+
+        tokio::process::Command::from(cmd)
+            .kill_on_drop(true)
+            .spawn()
+    }
+
+    /// Hands the terminal over to the child process.
+    ///
+    /// On unix the current process is replaced by the child, so that signals
+    /// and exit codes reach the caller untouched; this only returns on error.
+    /// On windows the child is waited for while Ctrl-C is ignored by envelope,
+    /// the child shares the console and receives it.
+    #[cfg(unix)]
+    pub fn exec(&self) -> Result<ExitStatus> {
+        use std::os::unix::process::CommandExt;
+
+        Err(self.command().exec())
+    }
+
+    /// Hands the terminal over to the child process.
+    ///
+    /// On unix the current process is replaced by the child, so that signals
+    /// and exit codes reach the caller untouched; this only returns on error.
+    /// On windows the child is waited for while Ctrl-C is ignored by envelope,
+    /// the child shares the console and receives it.
+    #[cfg(windows)]
+    pub fn exec(&self) -> Result<ExitStatus> {
+        use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+
+        let mut child = self.command().spawn()?;
+        // SAFETY: passing no handler only toggles whether the process ignores
+        // Ctrl-C, no callback is ever invoked
+        unsafe { SetConsoleCtrlHandler(None, 1) };
+        let status = child.wait();
+        unsafe { SetConsoleCtrlHandler(None, 0) };
+
+        status
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub fn exec(&self) -> Result<ExitStatus> {
+        self.run_shell_command()
+    }
+}
+
+/// Converts the exit status of a child process into the code that envelope
+/// should exit with, processes killed by a signal follow the shell convention
+/// of 128 + signal number
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+
+    status.code().unwrap_or(1)
 }
@@ -0,0 +1,268 @@
+use std::io::{Result, Write};
+
+use anstyle::Style;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// Space between two columns
+const SEPARATOR: &str = "  ";
+
+/// The last column is never shrunk below this width
+const MIN_LAST_WIDTH: usize = 10;
+
+/// How values that do not fit in the last column are rendered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// cut the value and mark it with an ellipsis
+    #[default]
+    Truncate,
+    /// continue the value on the following lines
+    Wrap,
+}
+
+/// Plain text table whose columns are sized to their content, the last column
+/// is fitted to the available width
+#[derive(Debug, Default)]
+pub struct Table {
+    titles: Vec<String>,
+    rows: Vec<Vec<String>>,
+    styles: Vec<Style>,
+}
+
+impl Table {
+    pub fn new<S: Into<String>>(titles: impl IntoIterator<Item = S>) -> Self {
+        Table {
+            titles: titles.into_iter().map(Into::into).collect(),
+            rows: Vec::new(),
+            styles: Vec::new(),
+        }
+    }
+
+    /// Renders the cells of `column` with `style`, titles are left plain
+    pub fn style_column(&mut self, column: usize, style: Style) {
+        if self.styles.len() <= column {
+            self.styles.resize(column + 1, Style::new());
+        }
+        self.styles[column] = style;
+    }
+
+    pub fn add_row<S: Into<String>>(&mut self, row: impl IntoIterator<Item = S>) {
+        self.rows.push(row.into_iter().map(Into::into).collect());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Writes the table, if `width` is given the last column is truncated or
+    /// wrapped so that no line is wider than it
+    pub fn render<W: Write>(
+        &self,
+        w: &mut W,
+        width: Option<usize>,
+        overflow: Overflow,
+    ) -> Result<()> {
+        let columns = self.titles.len();
+        let mut widths: Vec<usize> = self.titles.iter().map(|t| t.width()).collect();
+        for row in &self.rows {
+            for (i, cell) in row.iter().enumerate().take(columns) {
+                let cell_width = cell.lines().map(|l| l.width()).max().unwrap_or(0);
+                widths[i] = widths[i].max(cell_width);
+            }
+        }
+
+        let last_width = match (width, widths.split_last()) {
+            (Some(width), Some((_, others))) => {
+                let used: usize = others.iter().sum::<usize>() + others.len() * SEPARATOR.len();
+                Some(width.saturating_sub(used).max(MIN_LAST_WIDTH))
+            }
+            _ => None,
+        };
+
+        self.render_row(w, &self.titles, &[], &widths, last_width, overflow)?;
+        for row in &self.rows {
+            self.render_row(w, row, &self.styles, &widths, last_width, overflow)?;
+        }
+
+        Ok(())
+    }
+
+    fn render_row<W: Write>(
+        &self,
+        w: &mut W,
+        row: &[String],
+        styles: &[Style],
+        widths: &[usize],
+        last_width: Option<usize>,
+        overflow: Overflow,
+    ) -> Result<()> {
+        let Some((last, cells)) = row.split_last() else {
+            return Ok(());
+        };
+
+        let lines = match (last_width, overflow) {
+            (None, _) => last.lines().map(String::from).collect(),
+            (Some(max), Overflow::Truncate) => {
+                let mut first = last.lines().next().unwrap_or_default().to_string();
+                if last.lines().count() > 1 {
+                    first.push('…');
+                }
+                vec![truncate(&first, max)]
+            }
+            (Some(max), Overflow::Wrap) => last.lines().flat_map(|l| wrap(l, max)).collect(),
+        };
+        let lines = match lines.is_empty() {
+            true => vec![String::new()],
+            false => lines,
+        };
+
+        let style = |column: usize| styles.get(column).copied().unwrap_or_default();
+        for (n, line) in lines.iter().enumerate() {
+            for (column, (cell, width)) in cells.iter().zip(widths).enumerate() {
+                let cell = match n {
+                    0 => cell.as_str(),
+                    _ => "",
+                };
+                let cell = crate::style::paint(style(column), pad(cell, *width));
+                write!(w, "{}{}", cell, SEPARATOR)?;
+            }
+            writeln!(w, "{}", crate::style::paint(style(cells.len()), line))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the width of the terminal attached to stdout, if any
+pub fn terminal_width() -> Option<usize> {
+    terminal_size::terminal_size().map(|(width, _)| width.0 as usize)
+}
+
+/// Pads `value` with spaces up to `width` columns
+fn pad(value: &str, width: usize) -> String {
+    let padding = width.saturating_sub(value.width());
+    format!("{}{}", value, " ".repeat(padding))
+}
+
+/// Cuts `value` so that it fits in `width` columns, an ellipsis marks the
+/// values that have been cut
+fn truncate(value: &str, width: usize) -> String {
+    if value.width() <= width {
+        return value.to_string();
+    }
+
+    let mut truncated = String::new();
+    let mut used = 0;
+    for c in value.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w + 1 > width {
+            break;
+        }
+        truncated.push(c);
+        used += w;
+    }
+    truncated.push('…');
+
+    truncated
+}
+
+/// Splits `value` in lines of at most `width` columns
+fn wrap(value: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    let mut used = 0;
+    for c in value.chars() {
+        let w = c.width().unwrap_or(0);
+        if used + w > width && used > 0 {
+            lines.push(String::new());
+            used = 0;
+        }
+        lines.last_mut().unwrap().push(c);
+        used += w;
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(table: &Table, width: Option<usize>, overflow: Overflow) -> String {
+        let mut output: Vec<u8> = Vec::new();
+        table.render(&mut output, width, overflow).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn table() -> Table {
+        let mut table = Table::new(["ENVIRONMENT", "VARIABLE", "VALUE"]);
+        table.add_row(["dev", "A", "short"]);
+        table.add_row(["dev", "LONG_KEY", "a value that is way too long"]);
+        table
+    }
+
+    #[test]
+    fn test_render_unbounded() {
+        assert_eq!(
+            "ENVIRONMENT  VARIABLE  VALUE\n\
+             dev          A         short\n\
+             dev          LONG_KEY  a value that is way too long\n",
+            render(&table(), None, Overflow::Truncate)
+        );
+    }
+
+    #[test]
+    fn test_render_truncate() {
+        assert_eq!(
+            "ENVIRONMENT  VARIABLE  VALUE\n\
+             dev          A         short\n\
+             dev          LONG_KEY  a value that is w…\n",
+            render(&table(), Some(41), Overflow::Truncate)
+        );
+    }
+
+    #[test]
+    fn test_render_wrap() {
+        assert_eq!(
+            "ENVIRONMENT  VARIABLE  VALUE\n\
+             dev          A         short\n\
+             dev          LONG_KEY  a value that is wa\n\
+             \x20                      y too long\n",
+            render(&table(), Some(41), Overflow::Wrap)
+        );
+    }
+
+    #[test]
+    fn test_render_unicode_width() {
+        let mut table = Table::new(["KEY", "VALUE"]);
+        table.add_row(["日本", "値"]);
+        table.add_row(["A", "🦀🦀🦀🦀🦀🦀"]);
+
+        assert_eq!(
+            "KEY   VALUE\n\
+             日本  値\n\
+             A     🦀🦀🦀🦀…\n",
+            render(&table, Some(16), Overflow::Truncate)
+        );
+    }
+
+    #[test]
+    fn test_render_styled_column() {
+        let mut table = table();
+        table.style_column(0, crate::style::ENV);
+
+        let output = render(&table, None, Overflow::Truncate);
+        assert!(output.contains("\x1b[1m\x1b[36mdev        \x1b[0m  A "));
+        assert_eq!(
+            render(&self::table(), None, Overflow::Truncate),
+            anstream::adapter::strip_str(&output).to_string()
+        );
+    }
+
+    #[test]
+    fn test_truncate_and_wrap() {
+        assert_eq!("abc", truncate("abc", 3));
+        assert_eq!("ab…", truncate("abcd", 3));
+        assert_eq!("🦀…", truncate("🦀🦀", 3));
+        assert_eq!(vec!["ab", "cd", "e"], wrap("abcde", 2));
+        assert_eq!(vec!["🦀", "🦀"], wrap("🦀🦀", 3));
+    }
+}
@@ -0,0 +1,467 @@
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Pane receiving the navigation keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    Envs,
+    Vars,
+}
+
+/// What the text typed in the status line is for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Prompt {
+    /// environment of a variable added while there is none
+    NewEnv,
+    NewKey {
+        env: String,
+    },
+    NewValue {
+        env: String,
+        key: String,
+    },
+    EditValue {
+        env: String,
+        key: String,
+    },
+}
+
+/// Write asked by the user, it runs once confirmed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Write {
+    Set {
+        env: String,
+        key: String,
+        value: String,
+    },
+    Delete {
+        env: String,
+        key: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Normal,
+    /// keys are typed in the filter of the variables
+    Filter,
+    Input {
+        prompt: Prompt,
+        text: String,
+    },
+    Confirm(Write),
+}
+
+/// What the event loop has to do after a key press
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Quit,
+    /// loads the variables of the selected environment
+    Load,
+    Write(Write),
+    /// copies the value to the clipboard of the terminal
+    Copy(String),
+}
+
+/// State of the browser, it knows nothing about the database nor the terminal
+#[derive(Debug)]
+pub struct App {
+    pub envs: Vec<String>,
+    pub env_index: usize,
+    pub vars: Vec<(String, String)>,
+    /// indexes in `vars` of the variables matching the filter
+    pub filtered: Vec<usize>,
+    /// position of the selected variable in `filtered`
+    pub var_index: usize,
+    /// first row of `filtered` on screen, only a window of the variables is
+    /// rendered
+    pub offset: usize,
+    pub filter: String,
+    pub focus: Focus,
+    pub mode: Mode,
+    /// the values of the keys naming a secret are hidden until the user
+    /// asks to see them, see [`crate::secret::is_secret`]
+    pub masked: bool,
+    /// message shown in the status line until the next key press
+    pub status: Option<String>,
+}
+
+impl App {
+    pub fn new(envs: Vec<String>) -> Self {
+        App {
+            envs,
+            env_index: 0,
+            vars: Vec::new(),
+            filtered: Vec::new(),
+            var_index: 0,
+            offset: 0,
+            filter: String::new(),
+            focus: Focus::Envs,
+            mode: Mode::Normal,
+            masked: true,
+            status: None,
+        }
+    }
+
+    pub fn selected_env(&self) -> Option<&str> {
+        self.envs.get(self.env_index).map(String::as_str)
+    }
+
+    pub fn selected_var(&self) -> Option<&(String, String)> {
+        self.filtered.get(self.var_index).map(|&i| &self.vars[i])
+    }
+
+    /// Replaces the environments, the selected one stays selected if it
+    /// still exists
+    pub fn set_envs(&mut self, envs: Vec<String>) {
+        let selected = self.selected_env().map(String::from);
+        self.env_index = selected
+            .and_then(|env| envs.iter().position(|e| *e == env))
+            .unwrap_or(0)
+            .min(envs.len().saturating_sub(1));
+        self.envs = envs;
+    }
+
+    /// Replaces the variables, the selected one stays selected if it still
+    /// matches the filter
+    pub fn set_vars(&mut self, vars: Vec<(String, String)>) {
+        let selected = self.selected_var().map(|(key, _)| key.clone());
+        self.vars = vars;
+        self.apply_filter();
+        if let Some(key) = selected {
+            if let Some(index) = self.filtered.iter().position(|&i| self.vars[i].0 == key) {
+                self.var_index = index;
+            }
+        }
+    }
+
+    /// Keeps the variables whose key contains the filter, ignoring case
+    fn apply_filter(&mut self) {
+        let filter = self.filter.to_uppercase();
+        self.filtered = (0..self.vars.len())
+            .filter(|&i| self.vars[i].0.to_uppercase().contains(&filter))
+            .collect();
+        self.var_index = self.var_index.min(self.filtered.len().saturating_sub(1));
+    }
+
+    /// Moves the window of `height` rows so that the selected variable is
+    /// visible
+    pub fn scroll_to_selection(&mut self, height: usize) {
+        if self.var_index < self.offset {
+            self.offset = self.var_index;
+        } else if height > 0 && self.var_index >= self.offset + height {
+            self.offset = self.var_index + 1 - height;
+        }
+        self.offset = self
+            .offset
+            .min(self.filtered.len().saturating_sub(height.max(1)));
+    }
+
+    pub fn on_key(&mut self, key: KeyEvent) -> Option<Command> {
+        self.status = None;
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Command::Quit);
+        }
+
+        match self.mode.clone() {
+            Mode::Normal => self.on_normal_key(key.code),
+            Mode::Filter => {
+                self.on_filter_key(key.code);
+                None
+            }
+            Mode::Input { prompt, text } => self.on_input_key(key.code, prompt, text),
+            Mode::Confirm(write) => {
+                self.mode = Mode::Normal;
+                match key.code {
+                    KeyCode::Char('y') => Some(Command::Write(write)),
+                    _ => {
+                        self.status = Some("cancelled".into());
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    fn on_normal_key(&mut self, code: KeyCode) -> Option<Command> {
+        match code {
+            KeyCode::Char('q') => return Some(Command::Quit),
+            KeyCode::Esc if !self.filter.is_empty() => {
+                self.filter.clear();
+                self.apply_filter();
+            }
+            KeyCode::Esc => return Some(Command::Quit),
+            KeyCode::Tab | KeyCode::BackTab => {
+                self.focus = match self.focus {
+                    Focus::Envs => Focus::Vars,
+                    Focus::Vars => Focus::Envs,
+                }
+            }
+            KeyCode::Left | KeyCode::Char('h') => self.focus = Focus::Envs,
+            KeyCode::Right | KeyCode::Char('l') => self.focus = Focus::Vars,
+            KeyCode::Up | KeyCode::Char('k') => return self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => return self.move_by(1),
+            KeyCode::PageUp => return self.move_by(-20),
+            KeyCode::PageDown => return self.move_by(20),
+            KeyCode::Home | KeyCode::Char('g') => return self.move_by(isize::MIN),
+            KeyCode::End | KeyCode::Char('G') => return self.move_by(isize::MAX),
+            KeyCode::Char('/') => self.mode = Mode::Filter,
+            KeyCode::Char('m') => self.masked = !self.masked,
+            KeyCode::Char('a') => {
+                let prompt = match self.selected_env() {
+                    Some(env) => Prompt::NewKey { env: env.into() },
+                    None => Prompt::NewEnv,
+                };
+                self.mode = Mode::Input {
+                    prompt,
+                    text: String::new(),
+                };
+            }
+            KeyCode::Char('e') | KeyCode::Enter if self.focus == Focus::Vars => {
+                if let (Some(env), Some((key, value))) = (self.selected_env(), self.selected_var())
+                {
+                    self.mode = Mode::Input {
+                        prompt: Prompt::EditValue {
+                            env: env.into(),
+                            key: key.clone(),
+                        },
+                        text: value.clone(),
+                    };
+                }
+            }
+            KeyCode::Enter => self.focus = Focus::Vars,
+            KeyCode::Char('d') | KeyCode::Delete if self.focus == Focus::Vars => {
+                if let (Some(env), Some((key, _))) = (self.selected_env(), self.selected_var()) {
+                    self.mode = Mode::Confirm(Write::Delete {
+                        env: env.into(),
+                        key: key.clone(),
+                    });
+                }
+            }
+            KeyCode::Char('y') | KeyCode::Char('c') if self.focus == Focus::Vars => {
+                if let Some((key, value)) = self.selected_var().cloned() {
+                    self.status = Some(format!("copied the value of {}", key));
+                    return Some(Command::Copy(value));
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    /// Moves the selection of the focused pane by `delta`, saturating at both
+    /// ends
+    fn move_by(&mut self, delta: isize) -> Option<Command> {
+        let (index, len) = match self.focus {
+            Focus::Envs => (&mut self.env_index, self.envs.len()),
+            Focus::Vars => (&mut self.var_index, self.filtered.len()),
+        };
+        let previous = *index;
+        *index = index
+            .saturating_add_signed(delta)
+            .min(len.saturating_sub(1));
+
+        match self.focus == Focus::Envs && *index != previous {
+            true => {
+                self.var_index = 0;
+                self.offset = 0;
+                Some(Command::Load)
+            }
+            false => None,
+        }
+    }
+
+    fn on_filter_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char(c) => self.filter.push(c),
+            KeyCode::Backspace => {
+                self.filter.pop();
+            }
+            KeyCode::Esc => {
+                self.filter.clear();
+                self.mode = Mode::Normal;
+            }
+            KeyCode::Enter => {
+                self.mode = Mode::Normal;
+                self.focus = Focus::Vars;
+            }
+            _ => {}
+        }
+        self.var_index = 0;
+        self.offset = 0;
+        self.apply_filter();
+    }
+
+    fn on_input_key(&mut self, code: KeyCode, prompt: Prompt, mut text: String) -> Option<Command> {
+        match code {
+            KeyCode::Char(c) => text.push(c),
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                self.status = Some("cancelled".into());
+                return None;
+            }
+            KeyCode::Enter => {
+                self.mode = match prompt {
+                    Prompt::NewEnv | Prompt::NewKey { .. } if text.is_empty() => {
+                        self.status = Some("cancelled".into());
+                        Mode::Normal
+                    }
+                    Prompt::NewEnv => Mode::Input {
+                        prompt: Prompt::NewKey { env: text },
+                        text: String::new(),
+                    },
+                    Prompt::NewKey { env } => Mode::Input {
+                        prompt: Prompt::NewValue { env, key: text },
+                        text: String::new(),
+                    },
+                    Prompt::NewValue { env, key } | Prompt::EditValue { env, key } => {
+                        Mode::Confirm(Write::Set {
+                            env,
+                            key,
+                            value: text,
+                        })
+                    }
+                };
+                return None;
+            }
+            _ => {}
+        }
+
+        self.mode = Mode::Input { prompt, text };
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn press(app: &mut App, keys: &str) -> Option<Command> {
+        let mut command = None;
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\x1b' => KeyCode::Esc,
+                '\t' => KeyCode::Tab,
+                c => KeyCode::Char(c),
+            };
+            command = app.on_key(KeyEvent::from(code));
+        }
+        command
+    }
+
+    fn app() -> App {
+        let mut app = App::new(vec!["dev".into(), "prod".into()]);
+        app.set_vars(
+            (0..100)
+                .map(|i| (format!("KEY_{:02}", i), format!("value {}", i)))
+                .collect(),
+        );
+        app
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut app = app();
+        assert_eq!(Some(Command::Load), press(&mut app, "j"));
+        assert_eq!(Some("prod"), app.selected_env());
+        assert_eq!(None, press(&mut app, "j"));
+
+        press(&mut app, "\tjjjG");
+        assert_eq!("KEY_99", app.selected_var().unwrap().0);
+        press(&mut app, "k");
+        app.scroll_to_selection(10);
+        assert_eq!(89, app.offset);
+        press(&mut app, "g");
+        app.scroll_to_selection(10);
+        assert_eq!((0, 0), (app.var_index, app.offset));
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut app = app();
+        press(&mut app, "/key_4");
+        assert_eq!(10, app.filtered.len());
+        press(&mut app, "2\n");
+        assert_eq!(Mode::Normal, app.mode);
+        assert_eq!("KEY_42", app.selected_var().unwrap().0);
+
+        // the selection survives a reload
+        app.set_vars(vec![
+            ("KEY_1".into(), "1".into()),
+            ("KEY_42".into(), "x".into()),
+        ]);
+        assert_eq!("KEY_42", app.selected_var().unwrap().0);
+
+        press(&mut app, "\x1b");
+        assert_eq!(2, app.filtered.len());
+    }
+
+    #[test]
+    fn test_add_and_edit() {
+        let mut app = app();
+        press(&mut app, "anew\nvalue\n");
+        assert_eq!(
+            Some(Command::Write(Write::Set {
+                env: "dev".into(),
+                key: "new".into(),
+                value: "value".into()
+            })),
+            press(&mut app, "y")
+        );
+
+        assert_eq!(
+            Some(Command::Write(Write::Set {
+                env: "dev".into(),
+                key: "KEY_01".into(),
+                value: "value 1!".into()
+            })),
+            press(&mut app, "\tje!\ny")
+        );
+    }
+
+    #[test]
+    fn test_add_without_envs() {
+        let mut app = App::new(Vec::new());
+        assert_eq!(
+            Some(Command::Write(Write::Set {
+                env: "dev".into(),
+                key: "a".into(),
+                value: String::new()
+            })),
+            press(&mut app, "adev\na\n\ny")
+        );
+    }
+
+    #[test]
+    fn test_delete_confirmation() {
+        let mut app = app();
+        assert_eq!(None, press(&mut app, "\tdn"));
+        assert_eq!(Some("cancelled".to_string()), app.status);
+
+        assert_eq!(
+            Some(Command::Write(Write::Delete {
+                env: "dev".into(),
+                key: "KEY_00".into()
+            })),
+            press(&mut app, "dy")
+        );
+    }
+
+    #[test]
+    fn test_copy_and_mask() {
+        let mut app = app();
+        assert_eq!(None, press(&mut app, "y"));
+        assert_eq!(
+            Some(Command::Copy("value 0".into())),
+            press(&mut app, "\ty")
+        );
+
+        assert!(app.masked);
+        press(&mut app, "m");
+        assert!(!app.masked);
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::{self, Result, Write as _};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use ratatui::DefaultTerminal;
+
+use crate::db::{EnvelopeDb, SortOrder};
+use crate::ops;
+
+mod app;
+mod pick;
+mod ui;
+
+use app::{App, Command, Write};
+pub use pick::pick;
+
+/// Browses the environments and variables of `db` in the terminal until the
+/// user quits
+pub async fn run(db: &EnvelopeDb) -> Result<()> {
+    let mut app = App::new(Vec::new());
+    reload(db, &mut app).await?;
+
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, db, &mut app).await;
+    ratatui::try_restore()?;
+
+    result
+}
+
+async fn event_loop(terminal: &mut DefaultTerminal, db: &EnvelopeDb, app: &mut App) -> Result<()> {
+    loop {
+        terminal.draw(|frame| ui::draw(frame, app))?;
+
+        // a resize only needs the redraw above
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        match app.on_key(key) {
+            None => {}
+            Some(Command::Quit) => return Ok(()),
+            Some(Command::Load) => reload(db, app).await?,
+            Some(Command::Write(write)) => {
+                if let Err(e) = apply(db, &write).await {
+                    app.status = Some(format!("error: {}", e));
+                }
+                reload(db, app).await?;
+            }
+            Some(Command::Copy(value)) => copy(&value)?,
+        }
+    }
+}
+
+/// Reads the environments and the variables of the selected one again
+async fn reload(db: &EnvelopeDb, app: &mut App) -> Result<()> {
+    let envs = db.list_environments().await?;
+    app.set_envs(envs.into_iter().map(|e| e.env).collect());
+
+    let vars = match app.selected_env() {
+        Some(env) => db.list_var_in_env(env, SortOrder::Asc).await?,
+        None => Vec::new(),
+    };
+    app.set_vars(vars.into_iter().map(|row| (row.key, row.value)).collect());
+
+    Ok(())
+}
+
+async fn apply(db: &EnvelopeDb, write: &Write) -> Result<()> {
+    match write {
+        Write::Set { env, key, value } => ops::add_var(db, env, key, value).await.map(drop),
+        Write::Delete { env, key } => ops::delete_var_in_env(db, env, key).await,
+    }
+}
+
+/// Copies `value` to the clipboard with the OSC 52 sequence, which works over
+/// ssh as long as the terminal supports it
+fn copy(value: &str) -> Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", STANDARD.encode(value))?;
+    stdout.flush()
+}
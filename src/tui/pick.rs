@@ -0,0 +1,347 @@
+use std::env;
+use std::io::{self, BufRead, IsTerminal, Result, Write};
+
+use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
+use nucleo_matcher::{Config, Matcher};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::terminal;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::Paragraph;
+use ratatui::{Frame, Terminal, TerminalOptions, Viewport};
+
+use crate::{err, std_err};
+
+/// Most matches shown at once below the query
+const MAX_SHOWN: usize = 10;
+
+/// Lets the user pick one of `items` by typing part of it. Returns None
+/// without asking when stdin is not a terminal or there is nothing to pick
+/// from, fails if the user gives up.
+///
+/// The picker is drawn below the cursor on stderr, so that the output of the
+/// command can still be piped. Terminals that cannot draw it, such as
+/// `TERM=dumb`, get a numbered menu instead.
+pub fn pick(prompt: &str, items: &[String]) -> Result<Option<String>> {
+    if items.is_empty() || !io::stdin().is_terminal() {
+        return Ok(None);
+    }
+
+    let picked = match is_dumb() {
+        true => pick_numbered(&mut io::stdin().lock(), &mut io::stderr(), prompt, items)?,
+        false => pick_fuzzy(prompt, items)?,
+    };
+    match picked {
+        Some(picked) => Ok(Some(picked)),
+        None => Err(std_err!("no {} picked", prompt)),
+    }
+}
+
+fn is_dumb() -> bool {
+    let term = env::var("TERM").unwrap_or_default();
+    term.is_empty() || term == "dumb" || !io::stderr().is_terminal()
+}
+
+/// Ranks `items` by how well they match `query`, best first. Every item is
+/// kept in order when the query is empty.
+fn rank<'a>(matcher: &mut Matcher, query: &str, items: &'a [String]) -> Vec<&'a str> {
+    Pattern::parse(query, CaseMatching::Ignore, Normalization::Smart)
+        .match_list(items.iter().map(String::as_str), matcher)
+        .into_iter()
+        .map(|(item, _)| item)
+        .collect()
+}
+
+/// Outcome of a key press
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    Continue,
+    Done(Option<String>),
+}
+
+struct Picker<'a> {
+    items: &'a [String],
+    matcher: Matcher,
+    query: String,
+    matches: Vec<&'a str>,
+    selected: usize,
+    offset: usize,
+}
+
+impl<'a> Picker<'a> {
+    fn new(items: &'a [String]) -> Self {
+        let mut picker = Picker {
+            items,
+            matcher: Matcher::new(Config::DEFAULT),
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            offset: 0,
+        };
+        picker.update();
+        picker
+    }
+
+    fn update(&mut self) {
+        self.matches = rank(&mut self.matcher, &self.query, self.items);
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Step {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Step::Done(None),
+            KeyCode::Char('c') if ctrl => return Step::Done(None),
+            KeyCode::Enter => {
+                let picked = self.matches.get(self.selected).map(|m| m.to_string());
+                return Step::Done(picked);
+            }
+            KeyCode::Up | KeyCode::BackTab => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Char('p') if ctrl => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down | KeyCode::Tab => self.select_next(),
+            KeyCode::Char('n') if ctrl => self.select_next(),
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                self.update();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.update();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.update();
+            }
+            _ => {}
+        }
+
+        Step::Continue
+    }
+
+    fn select_next(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    fn scroll_to_selection(&mut self, height: usize) {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if height > 0 && self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height;
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame, prompt: &str) {
+        let [input, list] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(frame.area());
+
+        let count = format!("  {}/{}", self.matches.len(), self.items.len());
+        let line = Line::from_iter([
+            format!("{}> ", prompt).bold(),
+            self.query.clone().into(),
+            count.dark_gray(),
+        ]);
+        frame.render_widget(Paragraph::new(line), input);
+
+        let height = list.height as usize;
+        self.scroll_to_selection(height);
+        let lines: Vec<Line> = self
+            .matches
+            .iter()
+            .enumerate()
+            .skip(self.offset)
+            .take(height)
+            .map(|(i, item)| match i == self.selected {
+                true => Line::styled(format!("> {}", item), Style::new().reversed()),
+                false => Line::raw(format!("  {}", item)),
+            })
+            .collect();
+        frame.render_widget(Paragraph::new(lines), list);
+
+        let cursor = prompt.len() + 2 + self.query.chars().count();
+        frame.set_cursor_position((input.x + cursor as u16, input.y));
+    }
+}
+
+/// Falls back to [`pick_numbered`] when the terminal does not report the
+/// position of the cursor, which the inline viewport needs
+fn pick_fuzzy(prompt: &str, items: &[String]) -> Result<Option<String>> {
+    let height = items.len().min(MAX_SHOWN) as u16 + 1;
+    let options = TerminalOptions {
+        viewport: Viewport::Inline(height),
+    };
+
+    terminal::enable_raw_mode()?;
+    let result =
+        Terminal::with_options(CrosstermBackend::new(io::stderr()), options).map(|mut terminal| {
+            let picked = event_loop(&mut terminal, prompt, items);
+            terminal.clear()?;
+            picked
+        });
+    terminal::disable_raw_mode()?;
+
+    match result {
+        Ok(picked) => picked,
+        Err(_) => pick_numbered(&mut io::stdin().lock(), &mut io::stderr(), prompt, items),
+    }
+}
+
+fn event_loop<B: ratatui::backend::Backend>(
+    terminal: &mut Terminal<B>,
+    prompt: &str,
+    items: &[String],
+) -> Result<Option<String>> {
+    let mut picker = Picker::new(items);
+    loop {
+        terminal.draw(|frame| picker.draw(frame, prompt))?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        if let Step::Done(picked) = picker.on_key(key) {
+            return Ok(picked);
+        }
+    }
+}
+
+/// Lists `items` with a number each and reads the number of the picked one,
+/// an empty answer picks nothing
+fn pick_numbered<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    prompt: &str,
+    items: &[String],
+) -> Result<Option<String>> {
+    let width = items.len().to_string().len();
+    for (i, item) in items.iter().enumerate() {
+        writeln!(writer, "{:>width$}) {}", i + 1, item, width = width)?;
+    }
+    write!(writer, "{} [1-{}]: ", prompt, items.len())?;
+    writer.flush()?;
+
+    let mut answer = String::new();
+    reader.read_line(&mut answer)?;
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Ok(None);
+    }
+
+    match answer.parse::<usize>() {
+        Ok(n) if (1..=items.len()).contains(&n) => Ok(Some(items[n - 1].clone())),
+        _ => err!("invalid choice: {}", answer),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    fn items(items: &[&str]) -> Vec<String> {
+        items.iter().map(|i| i.to_string()).collect()
+    }
+
+    fn press(picker: &mut Picker, keys: &str) -> Step {
+        let mut step = Step::Continue;
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                '\x1b' => KeyCode::Esc,
+                '\x08' => KeyCode::Backspace,
+                '\t' => KeyCode::Tab,
+                c => KeyCode::Char(c),
+            };
+            step = picker.on_key(KeyEvent::from(code));
+        }
+        step
+    }
+
+    #[test]
+    fn test_rank() {
+        let mut matcher = Matcher::new(Config::DEFAULT);
+        let keys = items(&["DATABASE_URL", "DEBUG", "API_KEY", "DB_PORT"]);
+
+        assert_eq!(
+            vec!["DATABASE_URL", "DEBUG", "API_KEY", "DB_PORT"],
+            rank(&mut matcher, "", &keys)
+        );
+        assert_eq!("DB_PORT", rank(&mut matcher, "dbp", &keys)[0]);
+        assert_eq!("DATABASE_URL", rank(&mut matcher, "dburl", &keys)[0]);
+        assert!(rank(&mut matcher, "zz", &keys).is_empty());
+    }
+
+    #[test]
+    fn test_picker() {
+        let envs = items(&["dev", "staging", "prod", "prod-eu"]);
+        let mut picker = Picker::new(&envs);
+        assert_eq!(4, picker.matches.len());
+
+        assert_eq!(Step::Continue, press(&mut picker, "prod"));
+        assert_eq!(vec!["prod", "prod-eu"], picker.matches);
+        assert_eq!(
+            Step::Done(Some("prod-eu".into())),
+            press(&mut picker, "\t\t\n")
+        );
+
+        let mut picker = Picker::new(&envs);
+        press(&mut picker, "stg\x08\x08\x08");
+        assert_eq!(4, picker.matches.len());
+        assert_eq!(Step::Done(Some("dev".into())), press(&mut picker, "\n"));
+
+        let mut picker = Picker::new(&envs);
+        assert_eq!(Step::Done(None), press(&mut picker, "zz\n"));
+        assert_eq!(Step::Done(None), press(&mut picker, "\x1b"));
+    }
+
+    #[test]
+    fn test_draw() {
+        let keys: Vec<String> = (0..50).map(|i| format!("KEY_{:02}", i)).collect();
+        let mut picker = Picker::new(&keys);
+        press(&mut picker, &"\t".repeat(20));
+
+        let mut terminal = Terminal::new(TestBackend::new(30, 5)).unwrap();
+        terminal.draw(|frame| picker.draw(frame, "key")).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        let lines: Vec<String> = (0..5)
+            .map(|y| {
+                let line: String = (0..30).map(|x| buffer[(x, y)].symbol()).collect();
+                line.trim_end().to_string()
+            })
+            .collect();
+        assert_eq!("key>   50/50", lines[0]);
+        assert_eq!(vec!["  KEY_18", "  KEY_19", "> KEY_20"], lines[2..]);
+    }
+
+    #[test]
+    fn test_pick_numbered() {
+        let envs = items(&["dev", "prod"]);
+        let mut output = Vec::new();
+        let picked = pick_numbered(&mut "2\n".as_bytes(), &mut output, "environment", &envs);
+        assert_eq!(Some("prod".to_string()), picked.unwrap());
+        assert_eq!(
+            "1) dev\n2) prod\nenvironment [1-2]: ",
+            String::from_utf8(output).unwrap()
+        );
+
+        let picked = pick_numbered(&mut "\n".as_bytes(), &mut Vec::new(), "environment", &envs);
+        assert_eq!(None, picked.unwrap());
+        assert!(
+            pick_numbered(&mut "3\n".as_bytes(), &mut Vec::new(), "environment", &envs).is_err()
+        );
+        assert!(pick_numbered(
+            &mut "dev\n".as_bytes(),
+            &mut Vec::new(),
+            "environment",
+            &envs
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::widgets::{Block, List, ListState, Paragraph, Row, Table, TableState};
+use ratatui::Frame;
+use unicode_width::UnicodeWidthStr;
+
+use crate::secret;
+
+use super::app::{App, Focus, Mode, Prompt, Write};
+
+/// Rows taken by the borders and the header of the variables table
+const TABLE_CHROME: u16 = 3;
+
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let [main, status] =
+        Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+    let envs_width = app.envs.iter().map(|e| e.width()).max().unwrap_or(0);
+    let envs_width = envs_width.clamp(12, 30) as u16 + 4;
+    let [envs, vars] =
+        Layout::horizontal([Constraint::Length(envs_width), Constraint::Min(1)]).areas(main);
+
+    draw_envs(frame, app, envs);
+    draw_vars(frame, app, vars);
+    frame.render_widget(Paragraph::new(status_line(app)), status);
+}
+
+fn block(title: String, focused: bool) -> Block<'static> {
+    let block = Block::bordered().title(title);
+    match focused {
+        true => block.border_style(Style::new().fg(Color::Cyan)),
+        false => block,
+    }
+}
+
+fn draw_envs(frame: &mut Frame, app: &App, area: Rect) {
+    let block = block("environments".into(), app.focus == Focus::Envs);
+    if app.envs.is_empty() {
+        let empty = Paragraph::new("none yet,\npress a to add\na variable").block(block);
+        frame.render_widget(empty, area);
+        return;
+    }
+
+    let list = List::new(app.envs.iter().map(String::as_str))
+        .block(block)
+        .highlight_style(Style::new().reversed());
+    let mut state = ListState::default().with_selected(Some(app.env_index));
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+fn draw_vars(frame: &mut Frame, app: &mut App, area: Rect) {
+    let mut title = match app.selected_env() {
+        Some(env) => format!("{} ({}/{})", env, app.filtered.len(), app.vars.len()),
+        None => "variables".into(),
+    };
+    if !app.filter.is_empty() {
+        title.push_str(&format!(" /{}", app.filter));
+    }
+    let block = block(title, app.focus == Focus::Vars);
+
+    // only the rows on screen are built, environments may hold thousands
+    let height = area.height.saturating_sub(TABLE_CHROME) as usize;
+    app.scroll_to_selection(height);
+    let window: Vec<&(String, String)> = app
+        .filtered
+        .iter()
+        .skip(app.offset)
+        .take(height)
+        .map(|&i| &app.vars[i])
+        .collect();
+
+    let key_width = window.iter().map(|(k, _)| k.width()).max().unwrap_or(0);
+    let rows = window
+        .iter()
+        .map(|(key, value)| Row::new([key.clone(), shown(app, key, value)]));
+
+    let widths = [
+        Constraint::Length(key_width.max(3) as u16),
+        Constraint::Min(1),
+    ];
+    let table = Table::new(rows, widths)
+        .header(Row::new(["KEY", "VALUE"]).bold())
+        .block(block)
+        .row_highlight_style(Style::new().reversed());
+    let selected = (!window.is_empty()).then(|| app.var_index - app.offset);
+    let mut state = TableState::default().with_selected(selected);
+    frame.render_stateful_widget(table, area, &mut state);
+}
+
+/// Values spanning several lines are cut after the first one
+fn first_line(value: &str) -> String {
+    match value.split_once('\n') {
+        Some((first, _)) => format!("{}…", first),
+        None => value.to_string(),
+    }
+}
+
+/// The value of `key` as displayed, [`secret::MASK`] while the secrets are
+/// masked and `key` names one, see [`secret::is_secret`]
+fn shown(app: &App, key: &str, value: &str) -> String {
+    match app.masked && secret::is_secret(key) {
+        true => secret::MASK.to_string(),
+        false => first_line(value),
+    }
+}
+
+fn status_line(app: &App) -> String {
+    match &app.mode {
+        Mode::Normal => match &app.status {
+            Some(status) => status.clone(),
+            None => format!(
+                "q quit  / filter  a add  e edit  d delete  y copy  m {} secrets",
+                if app.masked { "show" } else { "hide" }
+            ),
+        },
+        Mode::Filter => format!("/{}▏", app.filter),
+        Mode::Input { prompt, text } => {
+            let label = match prompt {
+                Prompt::NewEnv => "environment".to_string(),
+                Prompt::NewKey { env } => format!("new key in {}", env),
+                Prompt::NewValue { key, .. } | Prompt::EditValue { key, .. } => {
+                    format!("value of {}", key)
+                }
+            };
+            format!("{}: {}▏", label, text)
+        }
+        Mode::Confirm(Write::Set { env, key, value }) => {
+            format!("set {}={} in {}? (y/n)", key, shown(app, key, value), env)
+        }
+        Mode::Confirm(Write::Delete { env, key }) => {
+            format!("delete {} from {}? (y/n)", key, env)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn render(app: &mut App, width: u16, height: u16) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|frame| draw(frame, app)).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        let mut lines = Vec::new();
+        for y in 0..height {
+            let line: String = (0..width)
+                .map(|x| buffer[(x, y)].symbol().to_string())
+                .collect();
+            lines.push(line.trim_end().to_string());
+        }
+        lines.join("\n")
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut app = App::new(vec!["dev".into(), "prod".into()]);
+        app.set_vars(
+            (0..5000)
+                .map(|i| (format!("KEY_{:04}", i), format!("value {}", i)))
+                .collect(),
+        );
+
+        let screen = render(&mut app, 80, 10);
+        assert!(screen.contains("│dev"));
+        assert!(screen.contains("dev (5000/5000)"));
+        assert!(screen.contains("KEY_0000"));
+        assert!(!screen.contains("value 0"));
+        assert!(screen.ends_with("m show secrets"));
+
+        app.masked = false;
+        app.focus = Focus::Vars;
+        app.var_index = 4999;
+        let screen = render(&mut app, 80, 10);
+        assert!(screen.contains("KEY_4999 value 4999"));
+        assert!(!screen.contains("KEY_0000"));
+        assert_eq!(4994, app.offset);
+    }
+
+    #[test]
+    fn test_draw_secrets() {
+        let mut app = App::new(vec!["dev".into()]);
+        app.set_vars(vec![
+            ("DB_PASSWORD".into(), "hunter2".into()),
+            ("PORT".into(), "80".into()),
+        ]);
+
+        let screen = render(&mut app, 80, 8);
+        assert!(screen.contains("DB_PASSWORD ********"));
+        assert!(screen.contains("PORT        80"));
+        assert!(!screen.contains("hunter2"));
+
+        app.masked = false;
+        let screen = render(&mut app, 80, 8);
+        assert!(screen.contains("DB_PASSWORD hunter2"));
+        assert!(screen.ends_with("m hide secrets"));
+    }
+
+    #[test]
+    fn test_draw_empty() {
+        let mut app = App::new(Vec::new());
+        let screen = render(&mut app, 60, 8);
+        assert!(screen.contains("none yet"));
+        assert!(screen.contains("variables"));
+
+        // too small to show anything but it must not panic
+        render(&mut app, 4, 2);
+    }
+}
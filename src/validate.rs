@@ -0,0 +1,556 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::db::EnvDiff;
+use crate::reference::SecretRef;
+
+/// Key of [`Schema::required`] whose keys every environment requires
+pub const ALL_ENVS: &str = "*";
+
+/// Rule the value of a variable must follow
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rule {
+    NonEmpty,
+    /// `scheme://rest`, such as `postgres://localhost/db`
+    Url,
+    Integer,
+    /// one of the listed values exactly
+    OneOf(Vec<String>),
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rule::NonEmpty => write!(f, "non-empty"),
+            Rule::Url => write!(f, "url"),
+            Rule::Integer => write!(f, "integer"),
+            Rule::OneOf(_) => write!(f, "one-of"),
+        }
+    }
+}
+
+impl Rule {
+    /// Describes why the value of `key` breaks the rule, None if it does not
+    fn check(&self, key: &str, value: &str) -> Option<String> {
+        let broken = match self {
+            Rule::NonEmpty => value.is_empty(),
+            Rule::Url => !is_url(value),
+            Rule::Integer => value.parse::<i64>().is_err(),
+            Rule::OneOf(allowed) => !allowed.iter().any(|a| a == value),
+        };
+        if !broken {
+            return None;
+        }
+
+        Some(match self {
+            Rule::NonEmpty => format!("{} is empty", key),
+            Rule::Url => format!("{} is not a URL", key),
+            Rule::Integer => format!("{} is not an integer", key),
+            Rule::OneOf(allowed) => format!("{} is not one of {}", key, allowed.join(", ")),
+        })
+    }
+}
+
+fn is_url(value: &str) -> bool {
+    let Some((scheme, rest)) = value.split_once("://") else {
+        return false;
+    };
+
+    scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        && !rest.is_empty()
+        && !rest.contains(char::is_whitespace)
+}
+
+/// Regex the whole key of a variable must match
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyPattern {
+    pattern: String,
+    regex: Regex,
+}
+
+impl TryFrom<String> for KeyPattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        let regex = Regex::new(&format!("^(?:{})$", pattern))?;
+        Ok(KeyPattern { pattern, regex })
+    }
+}
+
+impl KeyPattern {
+    pub fn is_match(&self, key: &str) -> bool {
+        self.regex.is_match(key)
+    }
+
+    /// A key following the pattern close to `key`, if one is found: `key` in
+    /// SCREAMING_SNAKE_CASE, prefixed by the literal start of the pattern
+    /// when needed
+    pub fn example_for(&self, key: &str) -> Option<String> {
+        let screaming: String = key
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() {
+                true => c.to_ascii_uppercase(),
+                false => '_',
+            })
+            .collect();
+        let prefix: String = self
+            .pattern
+            .trim_start_matches('^')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect();
+
+        [screaming.clone(), format!("{}{}", prefix, screaming)]
+            .into_iter()
+            .find(|candidate| self.is_match(candidate))
+    }
+
+    /// Describes why `key` does not follow the pattern, None if it does
+    fn check(&self, key: &str) -> Option<String> {
+        if self.is_match(key) {
+            return None;
+        }
+
+        Some(match self.example_for(key) {
+            Some(example) => format!(
+                "{} does not match {}, such as {}",
+                key, self.pattern, example
+            ),
+            None => format!("{} does not match {}", key, self.pattern),
+        })
+    }
+}
+
+/// Type a variable is annotated with, its values must parse as such but are
+/// stored as given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    Int,
+    /// `true`, `false`, `1`, `0`, `yes` or `no`, in any case
+    Bool,
+    Url,
+    /// integer from 1 to 65535
+    Port,
+    /// a secret reference resolved when the variable is read, see
+    /// [`crate::reference`]
+    Reference,
+}
+
+impl ValueType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValueType::Int => "int",
+            ValueType::Bool => "bool",
+            ValueType::Url => "url",
+            ValueType::Port => "port",
+            ValueType::Reference => "reference",
+        }
+    }
+
+    /// Whether `value` parses as the type
+    pub fn accepts(&self, value: &str) -> bool {
+        match self {
+            ValueType::Int => value.parse::<i64>().is_ok(),
+            ValueType::Bool => ["true", "false", "1", "0", "yes", "no"]
+                .iter()
+                .any(|b| b.eq_ignore_ascii_case(value)),
+            ValueType::Url => is_url(value),
+            ValueType::Port => value.parse::<u16>().is_ok_and(|port| port > 0),
+            ValueType::Reference => matches!(SecretRef::parse(value), Ok(Some(_))),
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ValueType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "int" => Ok(ValueType::Int),
+            "bool" => Ok(ValueType::Bool),
+            "url" => Ok(ValueType::Url),
+            "port" => Ok(ValueType::Port),
+            "reference" => Ok(ValueType::Reference),
+            _ => Err(format!("unknown type {}", s)),
+        }
+    }
+}
+
+/// A variable breaking the schema. Values are left out of the message, they
+/// may be secrets.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    pub key: String,
+    /// `required` or the name of the broken [`Rule`]
+    pub rule: String,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (rule: {})", self.message, self.rule)
+    }
+}
+
+/// The `[schema]` section of the project config
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    /// keys required by each environment, the ones of [`ALL_ENVS`] are
+    /// required by every environment
+    #[serde(default)]
+    pub required: BTreeMap<String, Vec<String>>,
+    /// rule the value of each key follows, whichever its environment
+    #[serde(default)]
+    pub rules: BTreeMap<String, Rule>,
+    /// pattern the keys of each environment follow, the one of [`ALL_ENVS`]
+    /// applies to the environments without their own
+    #[serde(default)]
+    pub key_pattern: BTreeMap<String, KeyPattern>,
+}
+
+impl Schema {
+    pub fn is_empty(&self) -> bool {
+        self.required.is_empty() && self.rules.is_empty() && self.key_pattern.is_empty()
+    }
+
+    /// Uppercases the keys, like they are on insert
+    pub fn normalize(&mut self) {
+        for keys in self.required.values_mut() {
+            for key in keys.iter_mut() {
+                *key = key.to_ascii_uppercase();
+            }
+        }
+        self.rules = std::mem::take(&mut self.rules)
+            .into_iter()
+            .map(|(key, rule)| (key.to_ascii_uppercase(), rule))
+            .collect();
+    }
+
+    /// Checks `value` against the rule of `key`, if any
+    pub fn check_var(&self, key: &str, value: &str) -> Option<Violation> {
+        let key = key.to_ascii_uppercase();
+        let rule = self.rules.get(&key)?;
+        let message = rule.check(&key, value)?;
+
+        Some(Violation {
+            rule: rule.to_string(),
+            key,
+            message,
+        })
+    }
+
+    /// Checks `key` against the key pattern of `env`, if any
+    pub fn check_key(&self, env: &str, key: &str) -> Option<Violation> {
+        let pattern = self
+            .key_pattern
+            .get(env)
+            .or_else(|| self.key_pattern.get(ALL_ENVS))?;
+
+        Some(Violation {
+            key: key.to_string(),
+            rule: "key-pattern".into(),
+            message: pattern.check(key)?,
+        })
+    }
+
+    /// Checks the variables of `env`, the required ones must be set and every
+    /// one must follow its rule and the key pattern
+    pub fn check_env(&self, env: &str, vars: &BTreeMap<String, String>) -> Vec<Violation> {
+        let mut seen = BTreeSet::new();
+        let mut violations: Vec<Violation> = [ALL_ENVS, env]
+            .iter()
+            .filter_map(|e| self.required.get(*e))
+            .flatten()
+            .filter(|key| !vars.contains_key(*key) && seen.insert(*key))
+            .map(|key| Violation {
+                key: key.clone(),
+                rule: "required".into(),
+                message: format!("{} is required in {}", key, env),
+            })
+            .collect();
+
+        violations.extend(vars.keys().filter_map(|key| self.check_key(env, key)));
+        violations.extend(
+            vars.iter()
+                .filter_map(|(key, value)| self.check_var(key, value)),
+        );
+        violations
+    }
+
+    /// Checks the keys that `diff` adds to `env` and the values it adds or
+    /// changes, the other variables of the environment are left alone
+    pub fn check_diff(&self, env: &str, diff: &EnvDiff) -> Vec<Violation> {
+        let added = diff.added.iter();
+        let changed = diff.changed.iter().map(|(key, (_, new))| (key, new));
+
+        let mut violations: Vec<Violation> = diff
+            .added
+            .keys()
+            .filter_map(|key| self.check_key(env, key))
+            .collect();
+        violations.extend(
+            added
+                .chain(changed)
+                .filter_map(|(key, value)| self.check_var(key, value)),
+        );
+        violations
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn broken(rule: Rule, value: &str) -> Option<String> {
+        rule.check("KEY", value)
+    }
+
+    #[test]
+    fn test_non_empty() {
+        assert_eq!(None, broken(Rule::NonEmpty, "x"));
+        assert_eq!(None, broken(Rule::NonEmpty, " "));
+        assert_eq!(Some("KEY is empty".into()), broken(Rule::NonEmpty, ""));
+    }
+
+    #[test]
+    fn test_url() {
+        for url in [
+            "https://example.com",
+            "postgres://user:pw@localhost:5432/db",
+            "redis+sentinel://host",
+        ] {
+            assert_eq!(None, broken(Rule::Url, url), "{}", url);
+        }
+        for url in [
+            "",
+            "example.com",
+            "://host",
+            "1http://host",
+            "http://",
+            "http://a b",
+        ] {
+            assert_eq!(
+                Some("KEY is not a URL".into()),
+                broken(Rule::Url, url),
+                "{}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn test_integer() {
+        for n in ["0", "8080", "-1", "+5"] {
+            assert_eq!(None, broken(Rule::Integer, n), "{}", n);
+        }
+        for n in ["", "1.5", "12a", " 1", "99999999999999999999"] {
+            assert_eq!(
+                Some("KEY is not an integer".into()),
+                broken(Rule::Integer, n),
+                "{}",
+                n
+            );
+        }
+    }
+
+    #[test]
+    fn test_one_of() {
+        let rule = Rule::OneOf(vec!["debug".into(), "info".into()]);
+        assert_eq!(None, broken(rule.clone(), "info"));
+        assert_eq!(
+            Some("KEY is not one of debug, info".into()),
+            broken(rule.clone(), "INFO")
+        );
+        assert!(broken(rule, "").is_some());
+    }
+
+    #[test]
+    fn test_value_type() {
+        let accepted = [
+            (ValueType::Int, vec!["0", "-12", "8080"]),
+            (
+                ValueType::Bool,
+                vec!["true", "FALSE", "1", "0", "yes", "No"],
+            ),
+            (ValueType::Url, vec!["https://example.com"]),
+            (ValueType::Port, vec!["1", "8080", "65535"]),
+            (
+                ValueType::Reference,
+                vec!["op://Dev/db/password", "ssm:///my/param"],
+            ),
+        ];
+        for (ty, values) in accepted {
+            for value in values {
+                assert!(ty.accepts(value), "{} {}", ty, value);
+            }
+        }
+
+        let rejected = [
+            (ValueType::Int, vec!["", "1.0", "ten"]),
+            (ValueType::Bool, vec!["", "y", "on", "2"]),
+            (ValueType::Url, vec!["example.com"]),
+            (ValueType::Port, vec!["0", "65536", "-1", "http"]),
+            (ValueType::Reference, vec!["hunter2", "op://Dev/db"]),
+        ];
+        for (ty, values) in rejected {
+            for value in values {
+                assert!(!ty.accepts(value), "{} {}", ty, value);
+            }
+        }
+
+        for ty in [
+            ValueType::Int,
+            ValueType::Bool,
+            ValueType::Url,
+            ValueType::Port,
+            ValueType::Reference,
+        ] {
+            assert_eq!(Ok(ty), ty.as_str().parse());
+        }
+        assert!("none".parse::<ValueType>().is_err());
+    }
+
+    fn schema() -> Schema {
+        let mut schema = Schema {
+            required: BTreeMap::from([
+                (ALL_ENVS.into(), vec!["host".into()]),
+                ("prod".into(), vec!["DSN".into(), "HOST".into()]),
+            ]),
+            rules: BTreeMap::from([("port".into(), Rule::Integer)]),
+            key_pattern: BTreeMap::new(),
+        };
+        schema.normalize();
+        schema
+    }
+
+    #[test]
+    fn test_check_var() {
+        let schema = schema();
+        assert_eq!(None, schema.check_var("PORT", "80"));
+        assert_eq!(None, schema.check_var("OTHER", "x"));
+
+        let violation = schema.check_var("port", "http").unwrap();
+        assert_eq!("PORT", violation.key);
+        assert_eq!(
+            "PORT is not an integer (rule: integer)",
+            violation.to_string()
+        );
+    }
+
+    #[test]
+    fn test_check_env() {
+        let schema = schema();
+        let vars = BTreeMap::from([("PORT".to_string(), "x".to_string())]);
+
+        let messages = |env| -> Vec<String> {
+            schema
+                .check_env(env, &vars)
+                .iter()
+                .map(ToString::to_string)
+                .collect()
+        };
+        assert_eq!(
+            vec![
+                "HOST is required in dev (rule: required)",
+                "PORT is not an integer (rule: integer)"
+            ],
+            messages("dev")
+        );
+        assert_eq!(
+            vec![
+                "HOST is required in prod (rule: required)",
+                "DSN is required in prod (rule: required)",
+                "PORT is not an integer (rule: integer)"
+            ],
+            messages("prod")
+        );
+    }
+
+    #[test]
+    fn test_check_diff() {
+        let schema = schema();
+        let diff = EnvDiff {
+            added: BTreeMap::from([("PORT".into(), "x".into())]),
+            removed: BTreeMap::from([("HOST".into(), "h".into())]),
+            changed: BTreeMap::new(),
+        };
+        assert_eq!(1, schema.check_diff("dev", &diff).len());
+
+        let diff = EnvDiff {
+            changed: BTreeMap::from([("PORT".into(), ("x".into(), "80".into()))]),
+            ..Default::default()
+        };
+        assert!(schema.check_diff("dev", &diff).is_empty());
+    }
+
+    fn pattern(pattern: &str) -> KeyPattern {
+        KeyPattern::try_from(pattern.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_key_pattern() {
+        let app = pattern("^APP_[A-Z0-9_]+$");
+        assert!(app.is_match("APP_PORT"));
+        assert!(!app.is_match("PORT"));
+        assert_eq!(Some("APP_DB_HOST".into()), app.example_for("db-host"));
+        assert_eq!(
+            Some("PORT does not match ^APP_[A-Z0-9_]+$, such as APP_PORT".into()),
+            app.check("PORT")
+        );
+
+        // the whole key must match
+        let screaming = pattern("[A-Z_]+");
+        assert!(!screaming.is_match("APP_port"));
+        assert_eq!(
+            Some("port does not match [A-Z_]+, such as PORT".into()),
+            screaming.check("port")
+        );
+        assert_eq!(
+            Some("A1 does not match [A-Z_]+".into()),
+            screaming.check("A1")
+        );
+
+        assert!(KeyPattern::try_from("(".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_check_key() {
+        let mut schema = schema();
+        schema.key_pattern = BTreeMap::from([
+            (ALL_ENVS.into(), pattern("APP_[A-Z_]+")),
+            ("legacy".into(), pattern(".*")),
+        ]);
+
+        assert_eq!(None, schema.check_key("dev", "APP_HOST"));
+        assert_eq!(None, schema.check_key("legacy", "HOST"));
+        assert_eq!(
+            "HOST does not match APP_[A-Z_]+, such as APP_HOST (rule: key-pattern)",
+            schema.check_key("dev", "HOST").unwrap().to_string()
+        );
+
+        let diff = EnvDiff {
+            added: BTreeMap::from([("HOST".into(), "h".into())]),
+            changed: BTreeMap::from([("OLD".into(), ("a".into(), "b".into()))]),
+            ..Default::default()
+        };
+        assert_eq!(1, schema.check_diff("dev", &diff).len());
+        assert!(schema.check_diff("legacy", &diff).is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+const ENVELOPE: &str = env!("CARGO_BIN_EXE_envelope");
+
+fn envelope_at(dir: &Path, url: &str, args: &[&str]) -> Output {
+    Command::new(ENVELOPE)
+        .args(args)
+        .current_dir(dir)
+        .env("ENVELOPE_DATABASE_URL", url)
+        .output()
+        .unwrap()
+}
+
+fn envelope(dir: &Path, args: &[&str]) -> Output {
+    Command::new(ENVELOPE)
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[test]
+fn test_database_url() {
+    let dir = std::env::temp_dir().join(format!("envelope-backends-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let url = format!("json://{}", dir.join("vars.json").display());
+
+    stdout(&envelope_at(&dir, &url, &["add", "dev", "port", "80"]));
+    stdout(&envelope_at(
+        &dir,
+        &url,
+        &["add", "dev", "host", "localhost"],
+    ));
+    assert_eq!("dev\n", stdout(&envelope_at(&dir, &url, &["list"])));
+    assert_eq!(
+        "HOST=localhost\nPORT=80\n",
+        stdout(&envelope_at(&dir, &url, &["list", "dev"]))
+    );
+
+    stdout(&envelope_at(
+        &dir,
+        &url,
+        &["delete", "-e", "dev", "-k", "host"],
+    ));
+    assert_eq!(
+        "PORT=80\n",
+        stdout(&envelope_at(&dir, &url, &["list", "dev"]))
+    );
+    let history = stdout(&envelope_at(&dir, &url, &["history", "dev", "host"]));
+    assert!(history.ends_with(" HOST (deleted)\n"), "{}", history);
+    // the variables went to the file, not to a sqlite database
+    assert!(!dir.join(".envelope").exists());
+
+    let output = envelope_at(&dir, &url, &["lock", "dev"]);
+    assert!(!output.status.success());
+    assert_eq!(
+        "error: only add, delete, history and list run on the json backend, the other commands need the sqlite database\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // a sqlite url runs every command
+    let url = format!("sqlite://{}", dir.join("vars.db").display());
+    stdout(&envelope_at(&dir, &url, &["add", "dev", "port", "80"]));
+    stdout(&envelope_at(&dir, &url, &["lock", "dev"]));
+    assert!(dir.join("vars.db").exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_json_backend() {
+    let dir = std::env::temp_dir().join(format!("envelope-json-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = envelope(&dir, &["--backend", "json", "list"]);
+    assert_eq!(Some(3), output.status.code());
+    stdout(&envelope(&dir, &["--backend", "json", "init"]));
+    stdout(&envelope(
+        &dir,
+        &["--backend", "json", "add", "dev", "port", "80"],
+    ));
+    assert!(!dir.join(".envelope").exists());
+
+    // the file convert writes is read back
+    stdout(&envelope(&dir, &["convert", "--to", "sqlite"]));
+    assert_eq!("PORT=80\n", stdout(&envelope(&dir, &["list", "dev"])));
+
+    let output = Command::new(ENVELOPE)
+        .args(["list", "dev"])
+        .current_dir(&dir)
+        .env("ENVELOPE_PATH", dir.join(".envelope.json"))
+        .output()
+        .unwrap();
+    assert_eq!("PORT=80\n", stdout(&output));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,99 @@
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const ENVELOPE: &str = env!("CARGO_BIN_EXE_envelope");
+
+fn workdir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("envelope-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn envelope(dir: &Path, args: &[&str]) {
+    let status = Command::new(ENVELOPE)
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Completes `line` with the bash completions loaded and returns the
+/// candidates, the cursor is at the end of the line
+fn complete(dir: &Path, line: &str) -> Vec<String> {
+    let bin = Path::new(ENVELOPE).parent().unwrap();
+    let script = format!(
+        "source <(envelope completions bash)\n\
+         COMP_LINE='{line}'\n\
+         read -ra COMP_WORDS <<< \"$COMP_LINE\"\n\
+         [[ $COMP_LINE == *' ' ]] && COMP_WORDS+=('')\n\
+         COMP_CWORD=$(( ${{#COMP_WORDS[@]}} - 1 ))\n\
+         _envelope_dynamic envelope \"${{COMP_WORDS[COMP_CWORD]}}\" \"${{COMP_WORDS[COMP_CWORD-1]}}\"\n\
+         printf '%s\\n' \"${{COMPREPLY[@]}}\""
+    );
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    let output = Command::new("bash")
+        .args(["--norc", "--noprofile", "-c", &script])
+        .current_dir(dir)
+        .env("PATH", path)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn test_complete_envs_and_keys() {
+    let dir = workdir("complete");
+    envelope(&dir, &["init"]);
+    envelope(&dir, &["add", "dev", "db_url", "x"]);
+    envelope(&dir, &["add", "dev", "db_port", "5432"]);
+    envelope(&dir, &["add", "prod", "api_key", "y"]);
+
+    assert_eq!(vec!["dev", "prod"], complete(&dir, "envelope list "));
+    assert_eq!(vec!["prod"], complete(&dir, "envelope add p"));
+    assert_eq!(
+        vec!["DB_PORT", "DB_URL"],
+        complete(&dir, "envelope add dev ")
+    );
+    assert_eq!(vec!["DB_URL"], complete(&dir, "envelope history dev DB_U"));
+    assert_eq!(
+        vec!["dev", "prod"],
+        complete(&dir, "envelope export --format csv -e ")
+    );
+    assert_eq!(
+        vec!["API_KEY"],
+        complete(&dir, "envelope delete -e prod -k ")
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_complete_without_db() {
+    let dir = workdir("complete-no-db");
+
+    let output = Command::new(ENVELOPE)
+        .args(["__complete", "envs"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,58 @@
+use std::path::Path;
+use std::process::{Command, Output};
+use std::thread;
+
+const ENVELOPE: &str = env!("CARGO_BIN_EXE_envelope");
+
+const WRITERS: usize = 8;
+const WRITES: usize = 5;
+
+fn envelope(dir: &Path, args: &[&str]) -> Output {
+    Command::new(ENVELOPE)
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+/// envelope processes writing to the same database at once all succeed, the
+/// lock contention is retried instead of failing with "database is locked"
+#[test]
+fn test_concurrent_writers() {
+    let dir = std::env::temp_dir().join(format!("envelope-concurrency-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    assert!(envelope(&dir, &["init"]).status.success());
+
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|writer| {
+            let dir = dir.clone();
+            thread::spawn(move || {
+                for write in 0..WRITES {
+                    let key = format!("KEY_{}_{}", writer, write);
+                    let output = envelope(&dir, &["add", "dev", &key, "value"]);
+                    assert!(
+                        output.status.success(),
+                        "{}: {}",
+                        key,
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    assert!(envelope(&dir, &["export", "dev"]).status.success());
+    let exported = std::fs::read_to_string(dir.join(".env")).unwrap();
+    for writer in 0..WRITERS {
+        for write in 0..WRITES {
+            let line = format!("KEY_{}_{}=value", writer, write);
+            assert!(exported.lines().any(|l| l == line), "{}", exported);
+        }
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,83 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+const ENVELOPE: &str = env!("CARGO_BIN_EXE_envelope");
+
+fn envelope(dir: &Path, args: &[&str]) -> Output {
+    Command::new(ENVELOPE)
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_exit_codes() {
+    let dir = std::env::temp_dir().join(format!("envelope-errors-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let output = envelope(&dir, &["list", "dev"]);
+    assert_eq!(Some(3), output.status.code());
+    assert_eq!(
+        "error: envelope is not initialized in current directory\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert!(envelope(&dir, &["init"]).status.success());
+    assert!(envelope(&dir, &["add", "dev", "key", "value"])
+        .status
+        .success());
+
+    let output = envelope(&dir, &["list", "prod"]);
+    assert_eq!(Some(4), output.status.code());
+    assert_eq!(
+        "error: env prod does not exist\n",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output = envelope(&dir, &["rename", "-e", "dev", "other", "key"]);
+    assert_eq!(Some(4), output.status.code());
+
+    std::fs::write(dir.join("envelope.toml"), "[schema]\nrequired = 1\n").unwrap();
+    let output = envelope(&dir, &["check", "dev"]);
+    assert_eq!(Some(8), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("error: envelope.toml:2: "));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_flatten_needs_yes() {
+    let dir = std::env::temp_dir().join(format!("envelope-flatten-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    assert!(envelope(&dir, &["init"]).status.success());
+    assert!(envelope(&dir, &["add", "dev", "key", "a"]).status.success());
+    // versions of a key are one second apart at least
+    std::thread::sleep(std::time::Duration::from_secs(1));
+    assert!(envelope(&dir, &["add", "dev", "key", "b"]).status.success());
+
+    // nobody can confirm, the history is kept
+    for args in [
+        &["flatten", "dev"][..],
+        &["flatten", "dev", "--before", "0"],
+    ] {
+        let output = envelope(&dir, args);
+        assert!(!output.status.success());
+        assert_eq!(
+            "error: refusing to run destructively without --yes in non-interactive mode\n",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let output = envelope(&dir, &["history", "dev"]);
+    assert_eq!(2, String::from_utf8_lossy(&output.stdout).lines().count());
+
+    assert!(envelope(&dir, &["--yes", "flatten", "dev"])
+        .status
+        .success());
+    let output = envelope(&dir, &["history", "dev"]);
+    assert_eq!(1, String::from_utf8_lossy(&output.stdout).lines().count());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
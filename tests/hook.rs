@@ -0,0 +1,103 @@
+#![cfg(unix)]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+const ENVELOPE: &str = env!("CARGO_BIN_EXE_envelope");
+
+/// Creates a project directory with an initialized envelope database and a
+/// subdirectory
+fn workdir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("envelope-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+
+    envelope(&dir, &["init"]);
+    envelope(&dir, &["add", "dev", "key", "value"]);
+
+    dir
+}
+
+fn envelope(dir: &PathBuf, args: &[&str]) {
+    let status = Command::new(ENVELOPE)
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+/// Runs `script` in a bash session with the hook installed, the hook is
+/// triggered explicitly since there is no prompt in a non interactive shell
+fn bash(dir: &PathBuf, script: &str) -> String {
+    let script = format!("eval \"$('{}' hook bash)\"\n{}", ENVELOPE, script);
+    let output = Command::new("bash")
+        .args(["--norc", "--noprofile", "-c", &script])
+        .current_dir(dir)
+        .env_remove("ENVELOPE_LOADED_VARS")
+        .env_remove("ENVELOPE_HOOK_STATE")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_hook_inactive() {
+    let dir = workdir("hook-inactive");
+
+    let output = bash(&dir, "_envelope_hook\necho \"${KEY-unset}\"");
+    assert_eq!("unset\n", output);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_hook_load_unload() {
+    let dir = workdir("hook-load-unload");
+    envelope(&dir, &["activate", "dev"]);
+
+    let output = bash(
+        &dir,
+        r#"export OTHER=kept
+cd sub && _envelope_hook
+echo "sub: ${KEY-unset}"
+cd / && _envelope_hook
+echo "outside: ${KEY-unset} ${ENVELOPE_LOADED_VARS-unset} ${OTHER-unset}"
+cd - > /dev/null && _envelope_hook
+echo "back: ${KEY-unset}"
+"#,
+    );
+    assert_eq!(
+        "sub: value\noutside: unset unset kept\nback: value\n",
+        output
+    );
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_hook_deactivate() {
+    let dir = workdir("hook-deactivate");
+    envelope(&dir, &["activate", "dev"]);
+
+    let output = bash(
+        &dir,
+        &format!(
+            r#"_envelope_hook
+echo "active: ${{KEY-unset}}"
+'{}' deactivate && _envelope_hook
+echo "inactive: ${{KEY-unset}}"
+"#,
+            ENVELOPE
+        ),
+    );
+    assert_eq!("active: value\ninactive: unset\n", output);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
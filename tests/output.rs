@@ -0,0 +1,47 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+const ENVELOPE: &str = env!("CARGO_BIN_EXE_envelope");
+
+fn envelope(dir: &Path, args: &[&str]) -> Output {
+    Command::new(ENVELOPE)
+        .args(args)
+        .current_dir(dir)
+        .env_remove("CLICOLOR_FORCE")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_piped_values_kept() {
+    let dir = std::env::temp_dir().join(format!("envelope-output-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    assert!(envelope(&dir, &["init"]).status.success());
+    assert!(envelope(&dir, &["add", "dev", "k", "a\x1b[31mb"])
+        .status
+        .success());
+
+    // stdout is a pipe, the values are written as stored
+    let output = envelope(&dir, &["list", "dev"]);
+    assert_eq!("K=a\x1b[31mb\n", String::from_utf8_lossy(&output.stdout));
+
+    let output = envelope(&dir, &["history", "dev"]);
+    assert!(
+        String::from_utf8_lossy(&output.stdout).ends_with(" K=a\x1b[31mb\n"),
+        "{:?}",
+        output
+    );
+
+    // the decorations are not colored in a pipe
+    let output = envelope(&dir, &["list"]);
+    assert_eq!("dev\n", String::from_utf8_lossy(&output.stdout));
+    let output = envelope(&dir, &["--color", "always", "list"]);
+    assert_eq!(
+        "\x1b[1m\x1b[36mdev\x1b[0m\n",
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
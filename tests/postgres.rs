@@ -0,0 +1,163 @@
+//! Runs against the postgres database of `ENVELOPE_TEST_POSTGRES_URL`, the
+//! tests pass without doing anything when it is not set:
+//!
+//! ```sh
+//! ENVELOPE_TEST_POSTGRES_URL=postgres://localhost/envelope_test cargo test --features postgres
+//! ```
+#![cfg(feature = "postgres")]
+
+use envelope::store::{AnyStore, PgStore, Store};
+use envelope::{EnvDiff, EnvelopeDb, EnvelopeError, SortOrder};
+use sqlx::PgPool;
+
+/// connects to the test database, the environments of `name` left by a
+/// previous run are removed
+async fn connect(name: &str) -> Option<(PgStore, PgPool)> {
+    let url = std::env::var("ENVELOPE_TEST_POSTGRES_URL").ok()?;
+    let pool = PgPool::connect(&url).await.unwrap();
+    envelope::store::migrate_postgres(&pool).await.unwrap();
+    sqlx::query("DELETE FROM environments WHERE env LIKE $1")
+        .bind(format!("{}-%", name))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    Some((PgStore::from_pool(pool.clone()), pool))
+}
+
+fn pairs(rows: Vec<envelope::EnvironmentRow>) -> Vec<(String, String)> {
+    rows.into_iter().map(|row| (row.key, row.value)).collect()
+}
+
+#[tokio::test]
+async fn test_postgres_store() {
+    let Some((store, pool)) = connect("store").await else {
+        return;
+    };
+    // versions are keyed by the second they were written at, the older ones
+    // are inserted directly
+    sqlx::query(
+        r"INSERT INTO environments (env, key, value, created_at)
+        VALUES
+        ('store-dev', 'HOST', 'old', 1),
+        ('store-dev', 'HOST', 'localhost', 2),
+        ('store-dev', 'PORT', '80', 1),
+        ('store-dev', 'GONE', 'x', 1),
+        ('store-dev', 'GONE', NULL, 2),
+        ('store-prod', 'PORT', '443', 1)",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let outcome = store.insert("store-dev", "user", "me").await.unwrap();
+    assert_eq!(None, outcome.previous);
+    assert!(outcome.changed);
+    assert_eq!(
+        vec![
+            ("HOST".into(), "localhost".into()),
+            ("PORT".into(), "80".into()),
+            ("USER".into(), "me".into()),
+        ],
+        pairs(
+            store
+                .list_var_in_env("store-dev", SortOrder::Asc)
+                .await
+                .unwrap()
+        )
+    );
+    assert_eq!(
+        "USER",
+        store
+            .list_var_in_env("store-dev", SortOrder::Desc)
+            .await
+            .unwrap()[0]
+            .key
+    );
+
+    let vars = store
+        .get_vars("store-dev", &["host".into(), "gone".into(), "none".into()])
+        .await
+        .unwrap();
+    assert_eq!(Some("localhost"), vars["HOST"].as_deref());
+    assert_eq!(None, vars["GONE"]);
+    assert_eq!(None, vars["NONE"]);
+
+    store.delete_var_for_env("store-dev", "PORT").await.unwrap();
+    store.delete_env("store-prod").await.unwrap();
+    assert_eq!(
+        2,
+        pairs(
+            store
+                .list_var_in_env("store-dev", SortOrder::Asc)
+                .await
+                .unwrap()
+        )
+        .len()
+    );
+    assert!(store
+        .list_var_in_env("store-prod", SortOrder::Asc)
+        .await
+        .unwrap()
+        .is_empty());
+    let envs: Vec<String> = store
+        .list_environments()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|env| env.env)
+        .filter(|env| env.starts_with("store-"))
+        .collect();
+    assert_eq!(vec!["store-dev", "store-prod"], envs);
+}
+
+#[tokio::test]
+async fn test_postgres_constraints() {
+    let Some((store, _)) = connect("constraints").await else {
+        return;
+    };
+
+    let err = store.insert("", "A", "1").await.unwrap_err();
+    assert_eq!("env name cannot be empty", err.to_string());
+    let err = store
+        .insert("constraints-dev", "A", "a\0b")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, EnvelopeError::Constraint(_)));
+}
+
+/// the same variables have the same fingerprint in both backends
+#[tokio::test]
+async fn test_postgres_matches_sqlite() {
+    let Some((store, _)) = connect("same").await else {
+        return;
+    };
+    let path = std::env::temp_dir().join(format!("envelope-pg-{}.db", std::process::id()));
+    let sqlite = EnvelopeDb::open(&path).await.unwrap();
+
+    let diff = EnvDiff::between(
+        Default::default(),
+        [("A".into(), "1".into()), ("B".into(), "é".into())].into(),
+    );
+    store.apply_diff("same-dev", &diff).await.unwrap();
+    sqlite.apply_diff("same-dev", &diff).await.unwrap();
+    assert_eq!(
+        Store::fingerprint(&sqlite, "same-dev").await.unwrap(),
+        store.fingerprint("same-dev").await.unwrap()
+    );
+    drop(sqlite);
+    std::fs::remove_file(&path).unwrap();
+
+    let url = std::env::var("ENVELOPE_TEST_POSTGRES_URL").unwrap();
+    let store = AnyStore::connect(&url).await.unwrap();
+    assert!(matches!(store, AnyStore::Postgres(_)));
+    assert_eq!(
+        vec![("A".into(), "1".into()), ("B".into(), "é".into())],
+        pairs(
+            store
+                .list_var_in_env("same-dev", SortOrder::Asc)
+                .await
+                .unwrap()
+        )
+    );
+}
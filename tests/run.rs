@@ -0,0 +1,135 @@
+#![cfg(unix)]
+
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ENVELOPE: &str = env!("CARGO_BIN_EXE_envelope");
+
+/// Creates an empty directory with an initialized envelope database
+fn workdir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("envelope-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    envelope(&dir, &["init"]);
+    envelope(&dir, &["add", "dev", "key", "value"]);
+
+    dir
+}
+
+fn envelope(dir: &PathBuf, args: &[&str]) {
+    let status = Command::new(ENVELOPE)
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn test_run_exit_code() {
+    let dir = workdir("exit-code");
+
+    let status = Command::new(ENVELOPE)
+        .args([
+            "run",
+            "dev",
+            "--",
+            "sh",
+            "-c",
+            "[ \"$KEY\" = value ] && exit 7",
+        ])
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+
+    assert_eq!(Some(7), status.code());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_run_sigint() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let dir = workdir("sigint");
+
+    let mut child = Command::new(ENVELOPE)
+        .args(["run", "dev", "--", "sleep", "30"])
+        .current_dir(&dir)
+        .stdin(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    // give envelope the time to load the environment and start the command
+    thread::sleep(Duration::from_millis(500));
+
+    let start = Instant::now();
+    let kill = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+
+    // `sleep` itself must have received the signal and terminated
+    let status = child.wait().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(Some(2), status.signal());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// Runs `envelope run ARGS -- sh -c 'echo $KEY'` and returns what it printed
+fn run_echo(dir: &PathBuf, args: &[&str], envs: &[(&str, &str)]) -> String {
+    let output = Command::new(ENVELOPE)
+        .arg("run")
+        .args(args)
+        .args(["--", "sh", "-c", "echo \"$KEY\""])
+        .envs(envs.iter().copied())
+        .env_remove("KEY")
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_run_fallback_dotenv() {
+    let dir = std::env::temp_dir().join(format!("envelope-fallback-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(".env"), "# comment\nexport KEY=\"dotenv\"\n").unwrap();
+
+    let status = Command::new(ENVELOPE)
+        .args(["run", "--", "true"])
+        .current_dir(&dir)
+        .env_remove("ENVELOPE_FALLBACK_DOTENV")
+        .stderr(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+
+    assert_eq!("dotenv\n", run_echo(&dir, &["--fallback-dotenv"], &[]));
+    assert_eq!(
+        "dotenv\n",
+        run_echo(&dir, &[], &[("ENVELOPE_FALLBACK_DOTENV", "1")])
+    );
+
+    // a database always wins over the dotenv file, unless --dotenv-only
+    envelope(&dir, &["init"]);
+    envelope(&dir, &["add", "dev", "key", "database"]);
+    assert_eq!(
+        "database\n",
+        run_echo(&dir, &["dev", "--fallback-dotenv"], &[])
+    );
+    assert_eq!("dotenv\n", run_echo(&dir, &["--dotenv-only"], &[]));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}